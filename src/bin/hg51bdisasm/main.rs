@@ -0,0 +1,34 @@
+use std::env;
+use std::fs;
+
+use anyhow::{bail, Result};
+
+use siena::cpu_hg51b::instruction::Instruction;
+
+fn main() -> Result<()> {
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() < 2 {
+        bail!("Syntax: {} <filename> [offset (hex)]", args[0]);
+    }
+
+    let f = fs::read(&args[1])?;
+    let offset = if args.len() > 2 {
+        usize::from_str_radix(args[2].trim_start_matches("0x"), 16)?
+    } else {
+        0
+    };
+    if offset >= f.len() {
+        bail!("Offset beyond end of file");
+    }
+
+    // Programs are executed from 512 byte (256 instruction) pages,
+    // so print the page-relative PC along with the file offset.
+    let mut fiter = f.into_iter().skip(offset);
+    let mut pos = offset;
+    while let Ok(ins) = Instruction::decode(&mut fiter) {
+        println!("{:06X} {:02X} {}", pos, ((pos - offset) / 2) & 0xFF, ins);
+        pos += ins.len();
+    }
+    Ok(())
+}
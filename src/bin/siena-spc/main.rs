@@ -0,0 +1,12 @@
+#[cfg(not(feature = "apu_blargg"))]
+mod player;
+
+#[cfg(not(feature = "apu_blargg"))]
+fn main() -> anyhow::Result<()> {
+    player::main()
+}
+
+#[cfg(feature = "apu_blargg")]
+fn main() -> anyhow::Result<()> {
+    anyhow::bail!("siena-spc requires Siena's own APU, build without the apu_blargg feature")
+}
@@ -0,0 +1,145 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use clap::Parser;
+
+use siena::frontend::pacing::AudioQueue;
+use siena::frontend::sdl::SDLAudioSink;
+use siena::snes::apu::apu::Apu;
+use siena::snes::apu::spc::{SpcFile, SpcPlayer};
+
+/// Length to play files without a length tag for, in seconds
+const DEFAULT_LENGTH: u32 = 180;
+
+/// Samples (per channel) rendered at once
+const CHUNK_SAMPLES: usize = 512;
+
+/// Audio buffered ahead during playback, in samples (per channel)
+const PLAYBACK_BUFFER: usize = 4096;
+
+#[derive(Parser)]
+#[command(
+    about = "Siena - SPC music player",
+    author = "Thomas <thomas@thomasw.dev>",
+    long_about = None)]
+struct Args {
+    /// SPC file to play
+    filename: String,
+
+    /// Render to a WAV file rather than playing
+    #[arg(long)]
+    wav: Option<PathBuf>,
+
+    /// Length to play, in seconds (default: from the ID666 tags)
+    #[arg(long)]
+    seconds: Option<u32>,
+
+    /// SPC700 (APU) IPL to load
+    #[arg(long, default_value = "spc700.rom")]
+    spc_ipl: String,
+}
+
+/// Renders the audio of an SPC file, fading out at the end
+struct Renderer {
+    player: SpcPlayer,
+    /// Samples (per channel) rendered and remaining
+    position: usize,
+    length: usize,
+    fade: usize,
+}
+
+impl Renderer {
+    /// Renders the next chunk of audio, or None at the end
+    fn next_chunk(&mut self) -> Result<Option<Vec<i16>>> {
+        let count = CHUNK_SAMPLES.min(self.length - self.position);
+        if count == 0 {
+            return Ok(None);
+        }
+
+        let mut out = vec![0; count * 2];
+        self.player.render(&mut out)?;
+
+        let fade_start = self.length - self.fade;
+        for (i, frame) in out.chunks_exact_mut(2).enumerate() {
+            let pos = self.position + i;
+            if pos >= fade_start {
+                let gain = (self.length - pos) as f32 / self.fade as f32;
+                for s in frame {
+                    *s = (f32::from(*s) * gain) as i16;
+                }
+            }
+        }
+        self.position += count;
+        Ok(Some(out))
+    }
+}
+
+pub fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let spc = SpcFile::load(&PathBuf::from(&args.filename))
+        .with_context(|| format!("Failed to load {}", args.filename))?;
+    let ipl = fs::read(&args.spc_ipl)
+        .with_context(|| format!("Failed to load SPC700 IPL ROM from {}", &args.spc_ipl))?;
+
+    if let Some(tags) = &spc.tags {
+        println!("Title:  {}", tags.song_title);
+        println!("Game:   {}", tags.game_title);
+        println!("Artist: {}", tags.artist);
+        println!("Dumper: {} ({})", tags.dumper, tags.dump_date);
+        if !tags.comments.is_empty() {
+            println!("        {}", tags.comments);
+        }
+    }
+
+    // The fade out only applies to the length from the tags
+    let (length, fade) = match (args.seconds, &spc.tags) {
+        (Some(s), _) => (s * 1000, 0),
+        (None, Some(tags)) if tags.length > 0 => (tags.length * 1000 + tags.fade, tags.fade),
+        _ => (DEFAULT_LENGTH * 1000, 0),
+    };
+    let ms_samples = |ms: u32| ms as usize * Apu::SAMPLE_RATE as usize / 1000;
+    println!("Length: {}.{:03}s", length / 1000, length % 1000);
+
+    let mut renderer = Renderer {
+        player: SpcPlayer::new(&ipl, &spc),
+        position: 0,
+        length: ms_samples(length),
+        fade: ms_samples(fade),
+    };
+
+    if let Some(filename) = &args.wav {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: Apu::SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut wav = hound::WavWriter::create(filename, spec)?;
+        while let Some(chunk) = renderer.next_chunk()? {
+            for s in chunk {
+                wav.write_sample(s)?;
+            }
+        }
+        wav.finalize()?;
+        println!("Rendered to {:?}", filename);
+        return Ok(());
+    }
+
+    let queue = Arc::new(Mutex::new(AudioQueue::new(2)));
+    let _audio = SDLAudioSink::init_queue(Arc::clone(&queue))?;
+    while let Some(chunk) = renderer.next_chunk()? {
+        while queue.lock().unwrap().get_level() > PLAYBACK_BUFFER {
+            sleep(Duration::from_millis(10));
+        }
+        queue.lock().unwrap().push(&chunk);
+    }
+    while queue.lock().unwrap().get_level() > 0 {
+        sleep(Duration::from_millis(10));
+    }
+    Ok(())
+}
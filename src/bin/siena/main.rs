@@ -1,24 +1,43 @@
 use std::fs;
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::mem::discriminant;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::SystemTime;
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::{Context, Result};
 use clap::Parser;
 use memmap::MmapMut;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
 
 use siena::frontend::channel::ChannelRenderer;
+use siena::frontend::filter::{Filter, FilterChain, Picture};
 use siena::frontend::gif::Gif;
-use siena::frontend::sdl::{SDLAudioSink, SDLEventPump, SDLRenderer};
-use siena::frontend::Renderer;
+use siena::frontend::pacing::{AudioPacer, AudioQueue, FramePacer, FrameSamples, PacingMode};
+use siena::frontend::recorder::{AudioFormat, Recorder};
+use siena::frontend::sdl::{SDLAudioSink, SDLEventPump, SDLImageWindow, SDLRenderer};
+use siena::frontend::{ColorProfile, Renderer};
+#[cfg(not(feature = "apu_blargg"))]
+use siena::snes::apu::apu::Apu;
+#[cfg(feature = "apu_blargg")]
+use siena::snes::apu_blargg::Apu;
+use siena::snes::boarddb::BoardDatabase;
 use siena::snes::bus::mainbus::BusTrace;
 use siena::snes::cartridge::{Cartridge, Mapper, VideoFormat};
+use siena::snes::coprocessor::bsx::flash::MemoryPack;
+use siena::snes::coprocessor::msu1::Msu1;
+use siena::snes::coprocessor::BatteryRam;
 use siena::snes::emulator::Emulator;
 use siena::snes::joypad::{Button, JoypadEvent};
-use siena::snes::ppu::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use siena::snes::patch;
+use siena::snes::ppu::debug::DebugView;
+use siena::snes::ppu::ppu::{RenderMode, SCREEN_HEIGHT, SCREEN_WIDTH};
+use siena::snes::ppu::state::{
+    BPP, DBG_BACKDROP, DBG_BG1, DBG_BG2, DBG_BG3, DBG_BG4, DBG_COLORMATH, DBG_OBJ, DBG_OBJ_PRIO0,
+    DBG_OBJ_PRIO1, DBG_OBJ_PRIO2, DBG_OBJ_PRIO3, DBG_WINDOWS,
+};
 
 /// Maps an SDL keycode to a controller input for a specific controller.
 fn map_keycode(keycode: Keycode) -> Option<(usize, Button)> {
@@ -40,13 +59,135 @@ fn map_keycode(keycode: Keycode) -> Option<(usize, Button)> {
     }
 }
 
+/// Maps keys to PPU debug layer mask bits
+fn map_dbg_layer(keycode: Keycode) -> Option<(u16, &'static str)> {
+    match keycode {
+        Keycode::F1 => Some((DBG_BG1, "BG1")),
+        Keycode::F2 => Some((DBG_BG2, "BG2")),
+        Keycode::F3 => Some((DBG_BG3, "BG3")),
+        Keycode::F4 => Some((DBG_BG4, "BG4")),
+        Keycode::F5 => Some((DBG_OBJ, "OBJ")),
+        Keycode::F6 => Some((DBG_OBJ_PRIO0, "OBJ priority 0")),
+        Keycode::F7 => Some((DBG_OBJ_PRIO1, "OBJ priority 1")),
+        Keycode::F8 => Some((DBG_OBJ_PRIO2, "OBJ priority 2")),
+        Keycode::F9 => Some((DBG_OBJ_PRIO3, "OBJ priority 3")),
+        Keycode::F10 => Some((DBG_COLORMATH, "Color math")),
+        Keycode::F11 => Some((DBG_WINDOWS, "Windows")),
+        Keycode::F12 => Some((DBG_BACKDROP, "Backdrop")),
+
+        _ => None,
+    }
+}
+
+/// Interval at which battery backed RAM kept by co-processors
+/// is written to the save file.
+const SAVE_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Battery backed RAM that is written to a save file
+enum SaveSource {
+    /// RAM kept by a co-processor
+    CoProcessor,
+    /// SRAM of a Sufami Turbo slot
+    SufamiSlot(usize),
+}
+
+/// A save file for battery backed RAM, with the contents of the last write.
+struct SaveFile {
+    source: SaveSource,
+    filename: PathBuf,
+    last: Vec<u8>,
+}
+
+/// Gets the battery backed RAM of a save source
+fn battery_ram<'a>(cartridge: &'a Cartridge, source: &SaveSource) -> Option<&'a dyn BatteryRam> {
+    match source {
+        SaveSource::CoProcessor => cartridge.co_battery_ram(),
+        SaveSource::SufamiSlot(slot) => cartridge.co_sufami.as_ref()?.slots[*slot]
+            .as_ref()
+            .map(|s| s as &dyn BatteryRam),
+    }
+}
+
+/// Writes battery backed RAM to the save file, if it changed since
+/// the last write.
+fn flush_battery_ram(cartridge: &Cartridge, save: &mut SaveFile) -> Result<()> {
+    let Some(bram) = battery_ram(cartridge, &save.source) else {
+        return Ok(());
+    };
+    let data = bram.save_battery_ram();
+    if data != save.last {
+        fs::write(&save.filename, &data)?;
+        save.last = data;
+    }
+    Ok(())
+}
+
+/// Writes the Satellaview memory pack back to its image, if it was modified.
+fn flush_bsx_pack(cartridge: &mut Cartridge, filename: &Path) -> Result<()> {
+    let Some(bsx) = cartridge.co_bsx.as_mut() else {
+        return Ok(());
+    };
+    if let Some(data) = bsx.take_modified_pack() {
+        fs::write(filename, data)?;
+    }
+    Ok(())
+}
+
+/// Paces the emulation thread, as selected by `PacingMode`
+enum Pacer {
+    /// Run as fast as possible, or paced by the presentation (vsync)
+    Unpaced,
+    Sleep(FramePacer),
+    Audio(AudioPacer),
+}
+
 /// Signals the main thread can send to the emulation thread.
 enum EmuThreadSignal {
     Quit,
+    FlushSave,
     DumpState,
+    ExportSpc,
     ToggleVerbose,
     ToggleVerboseSPC,
     ToggleVerboseGSU,
+    ToggleVerboseCx4,
+    ToggleLayer(u16, &'static str),
+    RenderDebugViews(Vec<DebugView>),
+    ExportDebugViews(Vec<DebugView>),
+    /// Records every frame with its audio, pushing the audio to the
+    /// specified pacer (if any) to play it while recording.
+    StartRecording(Recorder, Option<AudioPacer>),
+    StopRecording,
+}
+
+/// A debug view of PPU memory and the window it is shown in
+/// (created once the first image arrives).
+struct DebugWindow {
+    view: DebugView,
+    window: Option<SDLImageWindow>,
+}
+
+/// Opens the specified debug view, replacing an open view of the same
+/// kind, or closes it if it is already open.
+fn toggle_debug_view(windows: &mut Vec<DebugWindow>, view: DebugView) {
+    match windows
+        .iter()
+        .position(|w| discriminant(&w.view) == discriminant(&view))
+    {
+        Some(i) if windows[i].view == view => {
+            windows.remove(i);
+        }
+        Some(i) => windows[i].view = view,
+        None => windows.push(DebugWindow { view, window: None }),
+    }
+}
+
+/// Gets the open debug view of the same kind as the specified view
+fn get_debug_view(windows: &[DebugWindow], view: DebugView) -> Option<DebugView> {
+    windows
+        .iter()
+        .find(|w| discriminant(&w.view) == discriminant(&view))
+        .map(|w| w.view)
 }
 
 #[derive(Parser)]
@@ -98,14 +239,32 @@ struct Args {
     #[arg(long)]
     mapper: Option<Mapper>,
 
-    /// Override frame rate limit (0 = unlimited)
+    /// Override frame rate limit, for sleep pacing (0 = unlimited)
     #[arg(long)]
     fps: Option<u64>,
 
+    /// Frame pacing strategy
+    #[arg(long, value_enum, default_value_t = PacingMode::Sleep)]
+    pacing: PacingMode,
+
     /// Override video format
     #[arg(long)]
     videoformat: Option<VideoFormat>,
 
+    /// PPU renderer. The dot renderer is slower, but shows
+    /// mid-scanline raster effects.
+    #[arg(long, value_enum, default_value_t = RenderMode::Scanline)]
+    renderer: RenderMode,
+
+    /// Conversion of SNES colors to display colors
+    #[arg(long, value_enum, default_value_t = ColorProfile::Linear)]
+    color_profile: ColorProfile,
+
+    /// Video filter to apply before presenting. May be specified
+    /// multiple times to apply several filters, in order.
+    #[arg(long, value_enum)]
+    filter: Vec<Filter>,
+
     /// Co-processor ROM to load (if needed)
     #[arg(short, long)]
     corom: Option<String>,
@@ -113,36 +272,135 @@ struct Args {
     /// SPC700 (APU) IPL to load
     #[arg(long, default_value = "spc700.rom")]
     spc_ipl: String,
+
+    /// IPS/BPS/UPS patch to apply to the ROM (can be repeated).
+    /// If not specified, patches next to the ROM with the same
+    /// name are applied.
+    #[arg(long)]
+    patch: Vec<String>,
+
+    /// Additional board database (JSON) to load. Entries in this
+    /// database take precedence over the built-in database.
+    #[arg(long)]
+    boarddb: Option<String>,
+
+    /// Satellaview memory pack image to insert (BS-X cartridge only).
+    /// Changes to the memory pack are written back to the image.
+    #[arg(long)]
+    bsx_pack: Option<String>,
+
+    /// Directory to receive Satellaview broadcasts from (BS-X cartridge only)
+    #[arg(long)]
+    bsx_broadcast: Option<String>,
+
+    /// Sufami Turbo cartridge to insert in slot A. The ROM is loaded
+    /// as Sufami Turbo BIOS.
+    #[arg(long)]
+    sufami_a: Option<String>,
+
+    /// Sufami Turbo cartridge to insert in slot B. The ROM is loaded
+    /// as Sufami Turbo BIOS.
+    #[arg(long)]
+    sufami_b: Option<String>,
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
 
     // Set up the display and events
-    let mut display = SDLRenderer::new(SCREEN_WIDTH, SCREEN_HEIGHT)?;
+    let mut display = if args.pacing == PacingMode::Vsync {
+        SDLRenderer::new_vsync(SCREEN_WIDTH, SCREEN_HEIGHT)?
+    } else {
+        SDLRenderer::new(SCREEN_WIDTH, SCREEN_HEIGHT)?
+    };
+    display.set_filters(FilterChain::new(&args.filter));
     let mut displaychannel = ChannelRenderer::new(SCREEN_WIDTH, SCREEN_HEIGHT)?;
+    displaychannel.set_color_profile(args.color_profile);
     let framereceiver = displaychannel.get_receiver();
+    // With vsync, emulation waits for each frame to be presented
+    let lossless = displaychannel.get_lossless_flag();
+    lossless.store(args.pacing == PacingMode::Vsync, Ordering::Relaxed);
     let eventpump = SDLEventPump::new();
 
     // Initialize cartridge
-    let f = fs::read(&args.filename)?;
+    let mut f = fs::read(&args.filename)?;
+    let patches = if args.patch.is_empty() {
+        patch::find_patches(&PathBuf::from(&args.filename))
+    } else {
+        args.patch.iter().map(PathBuf::from).collect()
+    };
+    for patch_filename in patches {
+        println!("Applying patch {:?}", patch_filename);
+        let p = fs::read(&patch_filename)?;
+        f = patch::apply_patch(&f, &p)
+            .with_context(|| format!("Failed to apply patch {:?}", patch_filename))?;
+    }
     let f_co = if let Some(filename) = args.corom {
         Some(fs::read(filename)?)
     } else {
         None
     };
-    let cartridge = if args.mapper.is_none() {
-        let mut c = Cartridge::load(&f, f_co.as_deref())?;
-        println!("Cartridge: {}", &c);
-
-        if c.has_ram() {
-            // Determine filename of save file
-            let save_filename = {
-                let mut path = PathBuf::from(&args.filename);
-                path.set_extension("srm");
-                path
+    // Save files for battery backed RAM not kept in `Cartridge::ram`
+    let mut saves: Vec<SaveFile> = vec![];
+    let mut cartridge = if args.sufami_a.is_some() || args.sufami_b.is_some() {
+        let slot_a = args.sufami_a.as_ref().map(fs::read).transpose()?;
+        let slot_b = args.sufami_b.as_ref().map(fs::read).transpose()?;
+        let mut c = Cartridge::load_sufami(&f, slot_a.as_deref(), slot_b.as_deref())?;
+
+        // Each slot has its own save file, next to the slot image
+        for (slot, filename) in [&args.sufami_a, &args.sufami_b].into_iter().enumerate() {
+            let Some(filename) = filename else {
+                continue;
             };
+            let Some(bram) = c.co_sufami.as_mut().unwrap().slots[slot].as_mut() else {
+                continue;
+            };
+            if bram.battery_ram_size() == 0 {
+                continue;
+            }
+            let mut save_filename = PathBuf::from(filename);
+            save_filename.set_extension("srm");
+            if save_filename.exists() {
+                bram.load_battery_ram(&fs::read(&save_filename)?);
+                println!("Loaded save file {:?}", save_filename);
+            }
+            saves.push(SaveFile {
+                source: SaveSource::SufamiSlot(slot),
+                filename: save_filename,
+                last: bram.save_battery_ram(),
+            });
+        }
+
+        c
+    } else if args.mapper.is_none() {
+        let mut db = BoardDatabase::embedded();
+        if let Some(filename) = &args.boarddb {
+            db.merge(BoardDatabase::load(&PathBuf::from(filename))?);
+        }
+        let mut c = Cartridge::load_with_db(&f, &[], f_co.as_deref(), &db)?;
+        println!("Cartridge: {}", &c);
 
+        // Determine filename of save file
+        let save_filename = {
+            let mut path = PathBuf::from(&args.filename);
+            path.set_extension("srm");
+            path
+        };
+
+        if let Some(bram) = c.co_battery_ram_mut() {
+            // Co-processor keeps the RAM, load it from the save file
+            // and periodically write it back.
+            if save_filename.exists() {
+                let data = fs::read(&save_filename)?;
+                bram.load_battery_ram(&data);
+                println!("Loaded save file {:?}", save_filename);
+            }
+            saves.push(SaveFile {
+                source: SaveSource::CoProcessor,
+                filename: save_filename,
+                last: bram.save_battery_ram(),
+            });
+        } else if c.has_ram() {
             // Initialize memory-mapped save file
             let savef = fs::OpenOptions::new()
                 .read(true)
@@ -160,25 +418,72 @@ fn main() -> Result<()> {
     } else {
         Cartridge::load_nohdr(&f, args.mapper.unwrap())?
     };
+    let bsx_pack = args.bsx_pack.as_ref().map(PathBuf::from);
+    if let Some(bsx) = cartridge.co_bsx.as_mut() {
+        if let Some(filename) = &bsx_pack {
+            bsx.insert_pack(MemoryPack::new(&fs::read(filename).with_context(|| {
+                format!("Failed to load memory pack {:?}", filename)
+            })?));
+            println!("Inserted memory pack {:?}", filename);
+        }
+        bsx.set_broadcast_dir(args.bsx_broadcast.as_ref().map(PathBuf::from));
+    }
+    cartridge.co_msu1 = Msu1::open(&PathBuf::from(&args.filename))?;
+    if cartridge.co_msu1.is_some() {
+        println!("MSU-1 enabled");
+    }
     let fn_title = cartridge.get_title_clean();
 
     // Load SPC700 IPL ROM
     let apu_ipl = fs::read(&args.spc_ipl)
         .with_context(|| format!("Failed to load SPC700 IPL ROM from {}", &args.spc_ipl))?;
 
-    let mut emulator =
-        Emulator::<ChannelRenderer>::new(cartridge, &apu_ipl, displaychannel, args.videoformat)?;
+    let mut emulator = Emulator::<ChannelRenderer>::new(
+        cartridge,
+        &apu_ipl,
+        displaychannel,
+        args.videoformat,
+        args.renderer,
+    )?;
     emulator.set_joypad_sticky(args.sticky);
     emulator.set_trace_bus(args.trace_bus);
     emulator.set_verbose_spc(args.spc_verbose);
     emulator.set_verbose_cpu(args.verbose);
     emulator.set_trace_apu_comm(args.trace_apu_comm);
-    if let Some(fps) = args.fps {
-        emulator.set_fps_limit(fps);
-    }
+    // Clocks in the cartridge start at the host time and run on emulated
+    // time from there (save states keep their own time)
+    emulator.set_time(
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("Timetravel detected")
+            .as_secs() as i64,
+    );
 
     // Initialize audio
-    let _audio = SDLAudioSink::init(emulator.get_apu());
+    let audio_queue = Arc::new(Mutex::new(AudioQueue::new(2)));
+    let mut audio = if args.pacing == PacingMode::Audio {
+        SDLAudioSink::init_queue(Arc::clone(&audio_queue))
+    } else {
+        SDLAudioSink::init(emulator.get_apu())
+    };
+    let videoformat = emulator.get_videoformat();
+
+    // Set up frame pacing
+    let mut pacer = match (args.pacing, args.fps) {
+        (PacingMode::Sleep, Some(0)) | (PacingMode::Vsync, _) => Pacer::Unpaced,
+        (PacingMode::Sleep, Some(fps)) => Pacer::Sleep(FramePacer::with_fps(fps)),
+        (PacingMode::Audio, _) if audio.is_ok() => Pacer::Audio(AudioPacer::new(
+            audio_queue,
+            videoformat,
+            Apu::SAMPLE_RATE,
+            2,
+        )),
+        (PacingMode::Audio, _) => {
+            println!("No audio output, falling back to sleep pacing");
+            Pacer::Sleep(FramePacer::new(videoformat))
+        }
+        (PacingMode::Sleep, None) => Pacer::Sleep(FramePacer::new(videoformat)),
+    };
 
     // Load and deserialize state file
     if let Some(state_filename) = args.state {
@@ -190,47 +495,199 @@ fn main() -> Result<()> {
     // Joypad event channels
     let joypad_senders = emulator.get_joypad_senders()?;
 
-    // Spin up emulation thread and communication channel
+    // Spin up emulation thread and communication channels
     let (emuthread_tx, emuthread_rx) = crossbeam_channel::unbounded();
-    let emuthread = thread::spawn(move || loop {
-        // Handle signals from main thread
-        match emuthread_rx.try_recv() {
-            Ok(EmuThreadSignal::Quit) => break,
-            Ok(EmuThreadSignal::DumpState) => {
-                let filename = format!(
-                    "state_{}.json",
-                    SystemTime::now()
+    let (debug_tx, debug_rx) = crossbeam_channel::unbounded();
+    let emu_title = fn_title.clone();
+    let emuthread = thread::spawn(move || -> Result<()> {
+        let mut frame_samples = FrameSamples::new(videoformat, Apu::SAMPLE_RATE);
+        let mut avi_recording: Option<(Recorder, Option<AudioPacer>)> = None;
+        loop {
+            // Handle signals from main thread
+            match emuthread_rx.try_recv() {
+                Ok(EmuThreadSignal::Quit) => break,
+                Ok(EmuThreadSignal::FlushSave) => {
+                    for save in saves.iter_mut() {
+                        flush_battery_ram(emulator.get_cartridge(), save)?;
+                    }
+                    if let Some(filename) = &bsx_pack {
+                        flush_bsx_pack(emulator.get_cartridge_mut(), filename)?;
+                    }
+                }
+                Ok(EmuThreadSignal::DumpState) => {
+                    let filename = format!(
+                        "state_{}.json",
+                        SystemTime::now()
+                            .duration_since(SystemTime::UNIX_EPOCH)
+                            .expect("Timetravel detected")
+                            .as_secs()
+                    );
+                    let file = fs::File::create(&filename).unwrap();
+                    match emulator.dump_state(&file) {
+                        Ok(()) => println!("State dumped to {}", filename),
+                        Err(e) => println!("Failed to dump state: {:?}", e),
+                    }
+                }
+                #[cfg(not(feature = "apu_blargg"))]
+                Ok(EmuThreadSignal::ExportSpc) => {
+                    fs::create_dir_all("spc/")?;
+                    let timestamp = SystemTime::now()
                         .duration_since(SystemTime::UNIX_EPOCH)
                         .expect("Timetravel detected")
-                        .as_secs()
-                );
-                let file = fs::File::create(&filename).unwrap();
-                match emulator.dump_state(&file) {
-                    Ok(()) => println!("State dumped to {}", filename),
-                    Err(e) => println!("Failed to dump state: {:?}", e),
+                        .as_secs();
+                    let filename = PathBuf::from(format!("spc/{}_{}.spc", emu_title, timestamp));
+                    match emulator.save_spc(timestamp as i64).save(&filename) {
+                        Ok(()) => println!("APU state exported to {:?}", filename),
+                        Err(e) => println!("Failed to export APU state: {:?}", e),
+                    }
+                }
+                Ok(EmuThreadSignal::ToggleVerbose) => emulator.toggle_verbose_cpu(),
+                #[cfg(not(feature = "apu_blargg"))]
+                Ok(EmuThreadSignal::ToggleVerboseSPC) => {
+                    emulator.toggle_verbose_spc();
+                }
+                Ok(EmuThreadSignal::ToggleVerboseGSU) => {
+                    emulator.toggle_verbose_gsu();
+                }
+                Ok(EmuThreadSignal::ToggleVerboseCx4) => {
+                    emulator.toggle_verbose_cx4();
+                }
+                Ok(EmuThreadSignal::RenderDebugViews(views)) => {
+                    for view in views {
+                        debug_tx.send((view, emulator.render_debug_view(view)))?;
+                    }
+                }
+                Ok(EmuThreadSignal::ExportDebugViews(views)) => {
+                    fs::create_dir_all("debug/")?;
+                    let timestamp = SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .expect("Timetravel detected")
+                        .as_secs();
+                    for view in views {
+                        let filename = PathBuf::from(format!(
+                            "debug/{}_{}_{}.png",
+                            emu_title, view, timestamp
+                        ));
+                        match emulator
+                            .render_debug_view(view)
+                            .and_then(|img| img.save_png(&filename))
+                        {
+                            Ok(()) => println!("Exported {} to {:?}", view, filename),
+                            Err(e) => println!("Failed to export {}: {:?}", view, e),
+                        }
+                    }
+                }
+                Ok(EmuThreadSignal::ToggleLayer(mask, name)) => {
+                    emulator.toggle_dbg_layer(mask);
+                    if emulator.get_dbg_layermask() & mask != 0 {
+                        println!("{} disabled", name);
+                    } else {
+                        println!("{} enabled", name);
+                    }
+                }
+                Ok(EmuThreadSignal::StartRecording(rec, monitor)) => {
+                    avi_recording = Some((rec, monitor));
+                }
+                Ok(EmuThreadSignal::StopRecording) => {
+                    if let Some((rec, _)) = avi_recording.take() {
+                        rec.finish()?;
+                        println!("Recording finished");
+                    }
                 }
+                _ => (),
             }
-            Ok(EmuThreadSignal::ToggleVerbose) => emulator.toggle_verbose_cpu(),
-            #[cfg(not(feature = "apu_blargg"))]
-            Ok(EmuThreadSignal::ToggleVerboseSPC) => {
-                emulator.toggle_verbose_spc();
+
+            emulator.run_frame().unwrap();
+
+            // Render the audio of the frame here when pacing by audio or
+            // recording, so it is exactly the audio produced during it.
+            let samples = if matches!(pacer, Pacer::Audio(_)) || avi_recording.is_some() {
+                let mut samples = vec![0; frame_samples.next_frame() * 2];
+                emulator.render_audio(&mut samples);
+                samples
+            } else {
+                vec![]
+            };
+
+            if let Some((rec, monitor)) = avi_recording.as_mut() {
+                let (buffer, _) = emulator.get_frame();
+                rec.add_frame(&buffer, SCREEN_WIDTH, &samples)?;
+                if let Some(monitor) = monitor {
+                    monitor.push(&samples);
+                }
             }
-            Ok(EmuThreadSignal::ToggleVerboseGSU) => {
-                emulator.toggle_verbose_gsu();
+
+            match &mut pacer {
+                Pacer::Unpaced => (),
+                Pacer::Sleep(p) => p.wait(),
+                Pacer::Audio(p) => {
+                    p.push(&samples);
+                    p.wait();
+                }
             }
-            _ => (),
         }
 
-        emulator.tick().unwrap();
+        if let Some((rec, _)) = avi_recording.take() {
+            rec.finish()?;
+            println!("Recording finished");
+        }
+
+        // Write out save file on exit
+        for save in saves.iter_mut() {
+            flush_battery_ram(emulator.get_cartridge(), save)?;
+            println!("Saved {:?}", save.filename);
+        }
+        if saves.is_empty() && emulator.get_cartridge().has_ram() {
+            emulator.get_cartridge().ram.flush()?;
+        }
+        if let Some(filename) = &bsx_pack {
+            flush_bsx_pack(emulator.get_cartridge_mut(), filename)?;
+        }
+        Ok(())
     });
 
     // Presentation / event thread below
     let mut recording: Option<Gif> = None;
+    let mut avi_recording = false;
+    let mut last_save_flush = Instant::now();
+    let mut debug_windows: Vec<DebugWindow> = vec![];
     'mainloop: loop {
         let frame = framereceiver.recv()?;
-        display.update_from(Arc::clone(&frame))?;
+        if last_save_flush.elapsed() >= SAVE_FLUSH_INTERVAL {
+            emuthread_tx.send(EmuThreadSignal::FlushSave)?;
+            last_save_flush = Instant::now();
+        }
+        display.update_from(Arc::clone(&frame.buffer), frame.height)?;
         if let Some(rec) = recording.as_mut() {
-            rec.add(&frame)?;
+            rec.add(&frame.buffer)?;
+        }
+
+        // Update debug views
+        while let Ok((view, result)) = debug_rx.try_recv() {
+            let Some(w) = debug_windows.iter_mut().find(|w| w.view == view) else {
+                // Closed in the meantime
+                continue;
+            };
+            match result {
+                Ok(img) => {
+                    let title = format!("Siena - {}", view);
+                    if w.window.as_ref().map(|w| w.get_size()) != Some((img.width, img.height)) {
+                        w.window = Some(SDLImageWindow::new(&title, img.width, img.height)?);
+                    }
+                    let window = w.window.as_mut().unwrap();
+                    window.set_title(&title)?;
+                    window.update(&img.pixels)?;
+                }
+                Err(e) => {
+                    println!("{}: {}", view, e);
+                    debug_windows.retain(|w| w.view != view);
+                }
+            }
+        }
+        if !debug_windows.is_empty() {
+            emuthread_tx.send(EmuThreadSignal::RenderDebugViews(
+                debug_windows.iter().map(|w| w.view).collect(),
+            ))?;
         }
 
         while let Some(event) = eventpump.poll() {
@@ -242,6 +699,121 @@ fn main() -> Result<()> {
                 }
                 | Event::Quit { .. } => break 'mainloop,
 
+                // Window closed
+                Event::Window {
+                    window_id,
+                    win_event: WindowEvent::Close,
+                    ..
+                } => {
+                    if window_id == display.get_window_id() {
+                        break 'mainloop;
+                    }
+                    debug_windows.retain(|w| {
+                        w.window.as_ref().map(|w| w.get_window_id()) != Some(window_id)
+                    });
+                }
+
+                // Debug views: tiles (2/4/8bpp)
+                Event::KeyDown {
+                    keycode: Some(k @ (Keycode::Num1 | Keycode::Num2 | Keycode::Num3)),
+                    ..
+                } => {
+                    let bpp = match k {
+                        Keycode::Num1 => BPP::Two,
+                        Keycode::Num2 => BPP::Four,
+                        _ => BPP::Eight,
+                    };
+                    let palette = match get_debug_view(&debug_windows, DebugView::Tiles(bpp, 0)) {
+                        Some(DebugView::Tiles(_, palette)) => palette,
+                        _ => 0,
+                    };
+                    toggle_debug_view(&mut debug_windows, DebugView::Tiles(bpp, palette));
+                }
+
+                // Debug views: select tile palette
+                Event::KeyDown {
+                    keycode: Some(k @ (Keycode::PageUp | Keycode::PageDown)),
+                    ..
+                } => {
+                    if let Some(DebugView::Tiles(bpp, palette)) =
+                        get_debug_view(&debug_windows, DebugView::Tiles(BPP::Two, 0))
+                    {
+                        let palettes = (256 / usize::from(bpp.entries_per_palette())) as u8;
+                        let palette = if k == Keycode::PageUp {
+                            palette.wrapping_add(1) % palettes
+                        } else {
+                            palette.checked_sub(1).unwrap_or(palettes - 1)
+                        };
+                        toggle_debug_view(&mut debug_windows, DebugView::Tiles(bpp, palette));
+                    }
+                }
+
+                // Debug views: BG tilemaps (cycles through BG1-4)
+                Event::KeyDown {
+                    keycode: Some(Keycode::Num4),
+                    ..
+                } => match get_debug_view(&debug_windows, DebugView::Tilemap(0)) {
+                    Some(DebugView::Tilemap(3)) => {
+                        toggle_debug_view(&mut debug_windows, DebugView::Tilemap(3))
+                    }
+                    Some(DebugView::Tilemap(bg)) => {
+                        toggle_debug_view(&mut debug_windows, DebugView::Tilemap(bg + 1))
+                    }
+                    _ => toggle_debug_view(&mut debug_windows, DebugView::Tilemap(0)),
+                },
+
+                // Debug views: palette
+                Event::KeyDown {
+                    keycode: Some(Keycode::Num5),
+                    ..
+                } => toggle_debug_view(&mut debug_windows, DebugView::Palette),
+
+                // Debug views: sprites
+                Event::KeyDown {
+                    keycode: Some(Keycode::Num6),
+                    ..
+                } => toggle_debug_view(&mut debug_windows, DebugView::Sprites),
+
+                // Debug views: export open views to PNG
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    ..
+                } => {
+                    emuthread_tx.send(EmuThreadSignal::ExportDebugViews(
+                        debug_windows.iter().map(|w| w.view).collect(),
+                    ))?;
+                }
+
+                // Screenshot, at native resolution or (with shift) as presented
+                Event::KeyDown {
+                    keycode: Some(Keycode::S),
+                    keymod,
+                    ..
+                } => {
+                    let picture = if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        display.screenshot()?
+                    } else {
+                        Picture::from_displaybuffer(&frame.buffer, SCREEN_WIDTH, frame.height)
+                            .to_native()
+                    };
+                    fs::create_dir_all("screenshots/")?;
+                    let filename = PathBuf::from(format!(
+                        "screenshots/{}_{}.png",
+                        fn_title,
+                        SystemTime::now()
+                            .duration_since(SystemTime::UNIX_EPOCH)
+                            .expect("Timetravel detected")
+                            .as_millis()
+                    ));
+                    match picture.save_png(&filename) {
+                        Ok(()) => println!(
+                            "Screenshot ({}x{}) saved to {:?}",
+                            picture.width, picture.height, filename
+                        ),
+                        Err(e) => println!("Failed to save screenshot: {:?}", e),
+                    }
+                }
+
                 // Dump state
                 Event::KeyDown {
                     keycode: Some(Keycode::D),
@@ -250,6 +822,14 @@ fn main() -> Result<()> {
                     emuthread_tx.send(EmuThreadSignal::DumpState)?;
                 }
 
+                // Export APU state as SPC file
+                Event::KeyDown {
+                    keycode: Some(Keycode::M),
+                    ..
+                } => {
+                    emuthread_tx.send(EmuThreadSignal::ExportSpc)?;
+                }
+
                 // Toggle S-CPU verbose
                 Event::KeyDown {
                     keycode: Some(Keycode::Num0),
@@ -274,6 +854,14 @@ fn main() -> Result<()> {
                     emuthread_tx.send(EmuThreadSignal::ToggleVerboseGSU)?;
                 }
 
+                // Toggle Cx4 verbose
+                Event::KeyDown {
+                    keycode: Some(Keycode::Num7),
+                    ..
+                } => {
+                    emuthread_tx.send(EmuThreadSignal::ToggleVerboseCx4)?;
+                }
+
                 // Start/stop recording
                 Event::KeyDown {
                     keycode: Some(Keycode::Q),
@@ -291,7 +879,7 @@ fn main() -> Result<()> {
                         );
                         recording = Some(Gif::new(
                             SCREEN_WIDTH,
-                            SCREEN_HEIGHT,
+                            frame.height,
                             50,
                             fs::File::create(&filename)?,
                         )?);
@@ -302,6 +890,62 @@ fn main() -> Result<()> {
                     }
                 }
 
+                // Start/stop lossless video/audio recording
+                Event::KeyDown {
+                    keycode: Some(Keycode::V),
+                    ..
+                } => {
+                    if avi_recording {
+                        emuthread_tx.send(EmuThreadSignal::StopRecording)?;
+                        if let Ok(audio) = audio.as_mut() {
+                            audio.lock().set_redirect(None);
+                        }
+                        avi_recording = false;
+                    } else {
+                        fs::create_dir_all("recordings/")?;
+                        let filename = format!(
+                            "recordings/{}_{}.avi",
+                            fn_title,
+                            SystemTime::now()
+                                .duration_since(SystemTime::UNIX_EPOCH)
+                                .expect("Timetravel detected")
+                                .as_secs()
+                        );
+                        let rec = Recorder::new(
+                            Path::new(&filename),
+                            SCREEN_WIDTH,
+                            frame.height,
+                            videoformat,
+                            AudioFormat {
+                                sample_rate: Apu::SAMPLE_RATE,
+                                channels: 2,
+                            },
+                        )?;
+                        // Unless paced by audio, the audio device renders from the
+                        // APU itself. Play the audio rendered for the recording
+                        // instead, so it is not consumed twice.
+                        let monitor = match audio.as_mut() {
+                            Ok(audio) if args.pacing != PacingMode::Audio => {
+                                let queue = Arc::new(Mutex::new(AudioQueue::new(2)));
+                                audio.lock().set_redirect(Some(Arc::clone(&queue)));
+                                Some(AudioPacer::new(queue, videoformat, Apu::SAMPLE_RATE, 2))
+                            }
+                            _ => None,
+                        };
+                        emuthread_tx.send(EmuThreadSignal::StartRecording(rec, monitor))?;
+                        avi_recording = true;
+                        println!("Started recording to {}", filename);
+                    }
+                }
+
+                // Toggle PPU layers/effects
+                Event::KeyDown {
+                    keycode: Some(k), ..
+                } if map_dbg_layer(k).is_some() => {
+                    let (mask, name) = map_dbg_layer(k).unwrap();
+                    emuthread_tx.send(EmuThreadSignal::ToggleLayer(mask, name))?;
+                }
+
                 // Controller input
                 Event::KeyDown {
                     keycode: Some(k), ..
@@ -322,7 +966,11 @@ fn main() -> Result<()> {
         }
     }
 
+    // Stop waiting for frames to be presented, then unblock the emulation
+    // thread if it is waiting to hand over a frame
+    lossless.store(false, Ordering::Relaxed);
+    while framereceiver.try_recv().is_ok() {}
+
     emuthread_tx.send(EmuThreadSignal::Quit)?;
-    emuthread.join().unwrap();
-    Ok(())
+    emuthread.join().unwrap()
 }
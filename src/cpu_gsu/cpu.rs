@@ -15,7 +15,7 @@ pub const CACHE_LINES: usize = 32;
 pub const CACHE_LINE_SIZE: usize = 16;
 pub const CACHE_SIZE: usize = CACHE_LINES * CACHE_LINE_SIZE;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum GsuMap {
     SuperFX1,
     SuperFX2,
@@ -0,0 +1,885 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::instruction::*;
+use super::regs::{regaddr, RegisterFile, CONSTANTS, REG24_MASK};
+
+use crate::tickable::{Tickable, Ticks};
+
+/// Address on the HG51B external bus (24-bit)
+pub type Hg51bAddress = u32;
+
+/// Amount of 24-bit words in the data ROM
+pub const DATA_ROM_WORDS: usize = 1024;
+
+/// Size of the data ROM dump in bytes
+pub const DATA_ROM_SIZE: usize = DATA_ROM_WORDS * 3;
+
+/// Size of the data RAM in bytes
+pub const DATA_RAM_SIZE: usize = 3 * 1024;
+
+/// Instructions per cache page
+pub const CACHE_PAGE_SIZE: usize = 256;
+
+/// Amount of cache pages
+pub const CACHE_PAGES: usize = 2;
+
+/// Call stack depth
+const STACK_DEPTH: usize = 8;
+
+/// Instruction cache state
+#[derive(Serialize, Deserialize, Default)]
+pub struct CacheState {
+    /// Cache load requested by S-CPU
+    pub enable: bool,
+    /// Currently active page
+    pub page: usize,
+    /// Page locks
+    pub lock: [bool; CACHE_PAGES],
+    /// Bus addresses currently loaded in each page
+    pub address: [Option<Hg51bAddress>; CACHE_PAGES],
+    /// Program ROM base address
+    pub base: Hg51bAddress,
+    /// Program bank to start at
+    pub pb: u16,
+    /// Program counter to start at
+    pub pc: u8,
+}
+
+/// DMA state
+#[derive(Serialize, Deserialize, Default)]
+pub struct DmaState {
+    pub enable: bool,
+    pub source: Hg51bAddress,
+    pub target: Hg51bAddress,
+    pub length: u16,
+}
+
+/// Pending external bus access (triggered through BUSROM/BUSRAM)
+#[derive(Serialize, Deserialize, Default)]
+pub struct BusState {
+    pub enable: bool,
+    pub reading: bool,
+    pub writing: bool,
+    pub pending: Ticks,
+    pub address: Hg51bAddress,
+}
+
+/// S-CPU facing I/O state
+#[derive(Serialize, Deserialize)]
+pub struct IoState {
+    /// Locked up by an illegal DMA
+    pub lock: bool,
+    pub halt: bool,
+    /// IRQ disable
+    pub irq: bool,
+    /// 0 = 2 ROMs, 1 = 1 ROM
+    pub rom: bool,
+    pub vector: [u8; 32],
+    /// ROM wait states
+    pub wait_rom: Ticks,
+    /// RAM wait states
+    pub wait_ram: Ticks,
+    pub suspend: bool,
+    /// Suspend duration, 0 = until resumed
+    pub suspend_duration: Ticks,
+    pub cache: CacheState,
+    pub dma: DmaState,
+    pub bus: BusState,
+}
+
+impl Default for IoState {
+    fn default() -> Self {
+        Self {
+            lock: false,
+            halt: true,
+            irq: false,
+            rom: true,
+            vector: [0; 32],
+            wait_rom: 3,
+            wait_ram: 3,
+            suspend: false,
+            suspend_duration: 0,
+            cache: CacheState::default(),
+            dma: DmaState::default(),
+            bus: BusState::default(),
+        }
+    }
+}
+
+/// Hitachi HG51B169 (Capcom Cx4)
+#[derive(Serialize, Deserialize)]
+pub struct CpuHg51b {
+    pub verbose: bool,
+    pub regs: RegisterFile,
+    pub io: IoState,
+    pub cycles: Ticks,
+
+    /// Cartridge ROM as seen on the external bus (moved over on state load)
+    #[serde(skip)]
+    pub rom: Vec<u8>,
+    pub rom_mask: usize,
+
+    /// Internal data ROM (24-bit words)
+    pub data_rom: Vec<u32>,
+
+    /// Internal data RAM
+    pub data_ram: Vec<u8>,
+
+    /// Instruction cache, two pages of 256 instructions
+    pub program_ram: Vec<u16>,
+
+    stack: [u32; STACK_DEPTH],
+}
+
+impl CpuHg51b {
+    pub fn new(rom: &[u8], rom_mask: usize) -> Self {
+        Self {
+            verbose: false,
+            regs: RegisterFile::new(),
+            io: IoState::default(),
+            cycles: 0,
+            rom: rom.to_owned(),
+            rom_mask,
+            data_rom: vec![0; DATA_ROM_WORDS],
+            data_ram: vec![0; DATA_RAM_SIZE],
+            program_ram: vec![0; CACHE_PAGE_SIZE * CACHE_PAGES],
+            stack: [0; STACK_DEPTH],
+        }
+    }
+
+    /// Loads the internal data ROM from a dump of 24-bit little endian words.
+    pub fn load_data_rom(&mut self, rom: &[u8]) {
+        assert_eq!(rom.len(), DATA_ROM_SIZE);
+        self.data_rom = Vec::from_iter(
+            rom.chunks_exact(3)
+                .map(|w| w[0] as u32 | (w[1] as u32) << 8 | (w[2] as u32) << 16),
+        );
+    }
+
+    pub fn dump_state(&self) -> String {
+        format!("{}\n --> {}", self.regs, self.peek_next_instr())
+    }
+
+    /// Decodes the next instruction at PC in the active cache page
+    pub fn peek_next_instr(&self) -> Instruction {
+        Instruction::from_opcode(
+            self.program_ram[self.io.cache.page * CACHE_PAGE_SIZE + self.regs.pc as usize],
+        )
+    }
+
+    fn is_rom(addr: Hg51bAddress) -> bool {
+        addr & 0x408000 != 0
+    }
+
+    fn is_ram(addr: Hg51bAddress) -> bool {
+        addr & 0x40E000 == 0x006000
+    }
+
+    /// Wait states for a bus access to the given address
+    fn wait_states(&self, addr: Hg51bAddress) -> Ticks {
+        if Self::is_rom(addr) {
+            1 + self.io.wait_rom
+        } else if Self::is_ram(addr) {
+            1 + self.io.wait_ram
+        } else {
+            1
+        }
+    }
+
+    /// Translates a data RAM address, mirroring the upper 1 KB.
+    fn dram_addr(addr: u32) -> usize {
+        let addr = (addr & 0xFFF) as usize;
+        if addr >= DATA_RAM_SIZE {
+            addr - 0x400
+        } else {
+            addr
+        }
+    }
+
+    /// Reads from the external bus
+    pub fn read_bus(&self, addr: Hg51bAddress) -> u8 {
+        if Self::is_rom(addr) {
+            // LoROM
+            let bank = ((addr >> 16) & 0x7F) as usize;
+            return self.rom[(bank * 0x8000 + (addr as usize & 0x7FFF)) & self.rom_mask];
+        }
+        if Self::is_ram(addr) && (addr & 0xFFF) < DATA_RAM_SIZE as u32 {
+            return self.data_ram[(addr & 0xFFF) as usize];
+        }
+        0
+    }
+
+    /// Writes to the external bus
+    pub fn write_bus(&mut self, addr: Hg51bAddress, val: u8) {
+        if Self::is_ram(addr) && (addr & 0xFFF) < DATA_RAM_SIZE as u32 {
+            self.data_ram[(addr & 0xFFF) as usize] = val;
+        }
+    }
+
+    /// Advances the clock, completing pending bus accesses.
+    fn step(&mut self, ticks: Ticks) {
+        self.cycles += ticks;
+
+        if !self.io.bus.enable {
+            return;
+        }
+        if self.io.bus.pending > ticks {
+            self.io.bus.pending -= ticks;
+            return;
+        }
+
+        self.io.bus.enable = false;
+        self.io.bus.pending = 0;
+        if self.io.bus.reading {
+            self.io.bus.reading = false;
+            self.regs.mdr = self.read_bus(self.io.bus.address) as u32;
+        }
+        if self.io.bus.writing {
+            self.io.bus.writing = false;
+            self.write_bus(self.io.bus.address, self.regs.mdr as u8);
+        }
+    }
+
+    /// Starts an external bus access at MAR
+    fn start_bus(&mut self, write: bool, wait: Ticks) {
+        self.io.bus = BusState {
+            enable: true,
+            reading: !write,
+            writing: write,
+            pending: 1 + wait,
+            address: self.regs.mar,
+        };
+    }
+
+    /// Reads from the register file
+    pub fn read_reg(&mut self, reg: u8) -> u32 {
+        match reg {
+            regaddr::A => self.regs.a,
+            regaddr::MULH => (self.regs.mul >> 24) as u32 & REG24_MASK,
+            regaddr::MULL => self.regs.mul as u32 & REG24_MASK,
+            regaddr::MDR => self.regs.mdr,
+            regaddr::ROM => self.regs.rom,
+            regaddr::RAM => self.regs.ram,
+            regaddr::MAR => self.regs.mar,
+            regaddr::DPR => self.regs.dpr,
+            regaddr::PC => self.regs.pc as u32,
+            regaddr::P => self.regs.p as u32,
+            regaddr::BUS_ROM => {
+                self.start_bus(false, self.io.wait_rom);
+                0
+            }
+            regaddr::BUS_RAM => {
+                self.start_bus(false, self.io.wait_ram);
+                0
+            }
+            regaddr::CONST_START..=regaddr::CONST_END => {
+                CONSTANTS[(reg - regaddr::CONST_START) as usize]
+            }
+            regaddr::GPR_START..=regaddr::GPR_END => {
+                self.regs.gpr[(reg - regaddr::GPR_START) as usize]
+            }
+            _ => 0,
+        }
+    }
+
+    /// Writes to the register file
+    pub fn write_reg(&mut self, reg: u8, val: u32) {
+        let val = val & REG24_MASK;
+        match reg {
+            regaddr::A => self.regs.a = val,
+            regaddr::MULH => {
+                self.regs.mul = (self.regs.mul & 0xFFFFFF) | ((val as u64) << 24);
+            }
+            regaddr::MULL => {
+                self.regs.mul = (self.regs.mul & 0xFFFFFF000000) | val as u64;
+            }
+            regaddr::MDR => self.regs.mdr = val,
+            regaddr::ROM => self.regs.rom = val,
+            regaddr::RAM => self.regs.ram = val,
+            regaddr::MAR => self.regs.mar = val,
+            regaddr::DPR => self.regs.dpr = val,
+            regaddr::PC => self.regs.pc = val as u8,
+            regaddr::P => self.regs.p = (val & 0x7FFF) as u16,
+            regaddr::BUS_ROM => self.start_bus(true, self.io.wait_rom),
+            regaddr::BUS_RAM => self.start_bus(true, self.io.wait_ram),
+            regaddr::GPR_START..=regaddr::GPR_END => {
+                self.regs.gpr[(reg - regaddr::GPR_START) as usize] = val
+            }
+            _ => (),
+        }
+    }
+
+    fn read_operand(&mut self, operand: Operand) -> u32 {
+        match operand {
+            Operand::Reg(r) => self.read_reg(r),
+            Operand::Imm(i) => i,
+        }
+    }
+
+    fn push(&mut self) {
+        self.stack.copy_within(0..(STACK_DEPTH - 1), 1);
+        self.stack[0] = (self.regs.pb as u32) << 8 | self.regs.pc as u32;
+    }
+
+    fn pull(&mut self) {
+        let pc = self.stack[0];
+        self.stack.copy_within(1.., 0);
+        self.stack[STACK_DEPTH - 1] = 0;
+        self.regs.pb = ((pc >> 8) & 0x7FFF) as u16;
+        self.regs.pc = pc as u8;
+    }
+
+    /// Stops execution and raises an interrupt (if enabled)
+    fn halt(&mut self) {
+        self.io.halt = true;
+        if !self.io.irq {
+            self.regs.i = true;
+        }
+    }
+
+    /// Makes sure the program bank is cached. Returns false if
+    /// it cannot be cached because both pages are locked.
+    fn cache(&mut self) -> bool {
+        let address = (self.io.cache.base + (self.regs.pb as u32) * 512) & REG24_MASK;
+        self.io.cache.enable = false;
+
+        // Try the current page, then the other page
+        if self.io.cache.address[self.io.cache.page] == Some(address) {
+            return true;
+        }
+        self.io.cache.page ^= 1;
+        if self.io.cache.address[self.io.cache.page] == Some(address) {
+            return true;
+        }
+
+        // Load into the first unlocked page
+        if self.io.cache.lock[self.io.cache.page] {
+            self.io.cache.page ^= 1;
+        }
+        if self.io.cache.lock[self.io.cache.page] {
+            return false;
+        }
+
+        let page = self.io.cache.page;
+        self.io.cache.address[page] = Some(address);
+        let mut addr = address;
+        for offset in 0..CACHE_PAGE_SIZE {
+            self.step(self.wait_states(addr));
+            let l = self.read_bus(addr);
+            let h = self.read_bus(addr.wrapping_add(1) & REG24_MASK);
+            self.program_ram[page * CACHE_PAGE_SIZE + offset] = l as u16 | (h as u16) << 8;
+            addr = addr.wrapping_add(2) & REG24_MASK;
+        }
+        true
+    }
+
+    /// Executes a DMA transfer requested by the S-CPU
+    fn dma(&mut self) {
+        for offset in 0..(self.io.dma.length as u32) {
+            let source = (self.io.dma.source + offset) & REG24_MASK;
+            let target = (self.io.dma.target + offset) & REG24_MASK;
+
+            if (Self::is_rom(source) && Self::is_rom(target))
+                || (Self::is_ram(source) && Self::is_ram(target))
+            {
+                // Locks up until the S-CPU intervenes
+                self.io.lock = true;
+                return;
+            }
+
+            self.step(self.wait_states(source));
+            let val = self.read_bus(source);
+            self.step(self.wait_states(target));
+            self.write_bus(target, val);
+        }
+        self.io.dma.enable = false;
+    }
+
+    /// Advances PC, moving to the second cache page at the end of a page.
+    fn advance(&mut self) {
+        self.regs.pc = self.regs.pc.wrapping_add(1);
+        if self.regs.pc != 0 {
+            return;
+        }
+        if self.io.cache.page == 1 {
+            return self.halt();
+        }
+        self.io.cache.page = 1;
+        if self.io.cache.lock[1] {
+            return self.halt();
+        }
+        self.regs.pb = self.regs.p;
+        if !self.cache() {
+            self.halt();
+        }
+    }
+
+    fn alu_add(&mut self, x: u32, y: u32) -> u32 {
+        let z = x + y;
+        self.regs.set_nz(z);
+        self.regs.c = z > REG24_MASK;
+        self.regs.v = !(x ^ y) & (x ^ z) & 0x800000 != 0;
+        z & REG24_MASK
+    }
+
+    fn alu_sub(&mut self, x: u32, y: u32) -> u32 {
+        let z = x.wrapping_sub(y);
+        self.regs.set_nz(z);
+        self.regs.c = x >= y;
+        self.regs.v = (x ^ y) & (x ^ z) & 0x800000 != 0;
+        z & REG24_MASK
+    }
+
+    fn alu_logic(&mut self, z: u32) -> u32 {
+        let z = z & REG24_MASK;
+        self.regs.set_nz(z);
+        z
+    }
+
+    fn op_alu(&mut self, op: AluOp, shift: u8, operand: Operand) {
+        let a = (self.regs.a << shift) & REG24_MASK;
+        let val = self.read_operand(operand) & REG24_MASK;
+
+        self.regs.a = match op {
+            AluOp::CMPR => {
+                self.alu_sub(val, a);
+                return;
+            }
+            AluOp::CMP => {
+                self.alu_sub(a, val);
+                return;
+            }
+            AluOp::ADD => self.alu_add(a, val),
+            AluOp::SUBR => self.alu_sub(val, a),
+            AluOp::SUB => self.alu_sub(a, val),
+            AluOp::XNOR => self.alu_logic(!a ^ val),
+            AluOp::XOR => self.alu_logic(a ^ val),
+            AluOp::AND => self.alu_logic(a & val),
+            AluOp::OR => self.alu_logic(a | val),
+        };
+    }
+
+    fn op_shift(&mut self, op: ShiftOp, operand: Operand) {
+        let mut s = self.read_operand(operand);
+        if s > 24 {
+            s = 0;
+        }
+        let a = self.regs.a;
+        let result = match op {
+            ShiftOp::SHR => a >> s,
+            ShiftOp::ASR => ((((a << 8) as i32) >> 8) >> s) as u32,
+            ShiftOp::ROR => (a >> s) | (a.checked_shl(24 - s).unwrap_or(0)),
+            ShiftOp::SHL => a << s,
+        };
+        self.regs.a = self.alu_logic(result);
+    }
+
+    fn op_mul(&mut self, operand: Operand) {
+        let sx = |v: u32| (((v << 8) as i32) >> 8) as i64;
+        let val = self.read_operand(operand);
+        self.regs.mul = (sx(self.regs.a) * sx(val)) as u64 & 0xFFFF_FFFF_FFFF;
+    }
+
+    fn ram_addr(&self, addr: RamAddr) -> usize {
+        match addr {
+            RamAddr::A => Self::dram_addr(self.regs.a),
+            RamAddr::Dpr(i) => Self::dram_addr(self.regs.dpr + i as u32),
+        }
+    }
+
+    fn jump(&mut self, cond: Condition, far: bool, target: u8, call: bool) {
+        let take = match cond {
+            Condition::Always => true,
+            Condition::Z => self.regs.z,
+            Condition::C => self.regs.c,
+            Condition::N => self.regs.n,
+            Condition::V => self.regs.v,
+        };
+        if !take {
+            return;
+        }
+        if call {
+            self.push();
+        }
+        if far {
+            self.regs.pb = self.regs.p;
+        }
+        self.regs.pc = target;
+        self.step(2);
+    }
+
+    fn execute_instruction(&mut self, instr: &Instruction) {
+        match instr.op {
+            Op::Nop => (),
+            Op::Jmp { cond, far, target } => self.jump(cond, far, target, false),
+            Op::Jsr { cond, far, target } => self.jump(cond, far, target, true),
+            Op::Rts => {
+                self.pull();
+                self.step(2);
+            }
+            Op::Wait => {
+                if self.io.bus.enable {
+                    self.step(self.io.bus.pending);
+                }
+            }
+            Op::Skip { flag, take } => {
+                let f = match flag {
+                    SkipFlag::V => self.regs.v,
+                    SkipFlag::C => self.regs.c,
+                    SkipFlag::Z => self.regs.z,
+                    SkipFlag::N => self.regs.n,
+                };
+                if f == take {
+                    self.advance();
+                    self.step(1);
+                }
+            }
+            Op::IncMar => self.regs.mar = (self.regs.mar + 1) & REG24_MASK,
+            Op::Alu { op, shift, operand } => self.op_alu(op, shift, operand),
+            Op::Mul(operand) => self.op_mul(operand),
+            Op::Shift { op, operand } => self.op_shift(op, operand),
+            Op::Sxb => {
+                let a = (self.regs.a as u8 as i8) as i32 as u32;
+                self.regs.a = self.alu_logic(a);
+            }
+            Op::Sxw => {
+                let a = (self.regs.a as u16 as i16) as i32 as u32;
+                self.regs.a = self.alu_logic(a);
+            }
+            Op::Ld { dest, operand } => {
+                let val = self.read_operand(operand);
+                match dest {
+                    LdDest::A => self.regs.a = val,
+                    LdDest::MDR => self.regs.mdr = val,
+                    LdDest::MAR => self.regs.mar = val,
+                    LdDest::P => self.regs.p = (val & 0x7FFF) as u16,
+                }
+            }
+            Op::LdPl(v) => self.regs.p = (self.regs.p & 0x7F00) | v as u16,
+            Op::LdPh(v) => self.regs.p = (self.regs.p & 0x00FF) | ((v as u16 & 0x7F) << 8),
+            Op::St { reg, src } => {
+                let val = match src {
+                    StSrc::A => self.regs.a,
+                    StSrc::MDR => self.regs.mdr,
+                };
+                self.write_reg(reg, val);
+            }
+            Op::Rdram { byte, addr } => {
+                let shift = byte * 8;
+                let val = self.data_ram[self.ram_addr(addr)] as u32;
+                self.regs.ram = (self.regs.ram & !(0xFF << shift)) | (val << shift);
+            }
+            Op::Wrram { byte, addr } => {
+                let a = self.ram_addr(addr);
+                self.data_ram[a] = (self.regs.ram >> (byte * 8)) as u8;
+            }
+            Op::Rdrom(Operand::Reg(_)) => {
+                self.regs.rom = self.data_rom[self.regs.a as usize % DATA_ROM_WORDS];
+            }
+            Op::Rdrom(Operand::Imm(i)) => {
+                self.regs.rom = self.data_rom[i as usize % DATA_ROM_WORDS];
+            }
+            Op::Swap(r) => std::mem::swap(&mut self.regs.a, &mut self.regs.gpr[r as usize]),
+            Op::Clear => {
+                self.regs.a = 0;
+                self.regs.p = 0;
+                self.regs.ram = 0;
+                self.regs.dpr = 0;
+            }
+            Op::Halt => self.halt(),
+        }
+    }
+
+    /// Executes one instruction
+    fn execute(&mut self) {
+        if !self.cache() {
+            return self.halt();
+        }
+
+        let instr = self.peek_next_instr();
+        if self.verbose {
+            println!("{}", self.dump_state());
+        }
+
+        self.advance();
+        self.step(1);
+        self.execute_instruction(&instr);
+    }
+
+    /// Indicates whether the CPU is running (for the status register)
+    pub fn running(&self) -> bool {
+        self.busy() || !self.io.halt
+    }
+
+    /// Indicates whether the CPU is busy with a cache load, DMA or
+    /// bus access (for the status register)
+    pub fn busy(&self) -> bool {
+        self.io.cache.enable || self.io.dma.enable || self.io.bus.pending > 0
+    }
+
+    /// Interrupt line towards the S-CPU
+    pub fn get_int(&self) -> bool {
+        self.regs.i
+    }
+
+    /// S-CPU read from the I/O area ($7C00-$7FFF)
+    pub fn read_io(&self, addr: usize) -> u8 {
+        let io = &self.io;
+        match addr {
+            0x7F40..=0x7F42 => (io.dma.source >> ((addr - 0x7F40) * 8)) as u8,
+            0x7F43..=0x7F44 => (io.dma.length >> ((addr - 0x7F43) * 8)) as u8,
+            0x7F45..=0x7F47 => (io.dma.target >> ((addr - 0x7F45) * 8)) as u8,
+            0x7F48 => io.cache.page as u8,
+            0x7F49..=0x7F4B => (io.cache.base >> ((addr - 0x7F49) * 8)) as u8,
+            0x7F4C => io.cache.lock[0] as u8 | (io.cache.lock[1] as u8) << 1,
+            0x7F4D..=0x7F4E => (io.cache.pb >> ((addr - 0x7F4D) * 8)) as u8,
+            0x7F4F => io.cache.pc,
+            0x7F50 => io.wait_ram as u8 | (io.wait_rom as u8) << 4,
+            0x7F51 => io.irq as u8,
+            0x7F52 => io.rom as u8,
+            0x7F53..=0x7F5F => {
+                io.suspend as u8
+                    | (self.regs.i as u8) << 1
+                    | (self.running() as u8) << 6
+                    | (self.busy() as u8) << 7
+            }
+            0x7F60..=0x7F7F => io.vector[addr & 0x1F],
+            0x7F80..=0x7FAF | 0x7FC0..=0x7FEF => {
+                let r = addr & 0x3F;
+                (self.regs.gpr[r / 3] >> ((r % 3) * 8)) as u8
+            }
+            _ => 0,
+        }
+    }
+
+    /// S-CPU write to the I/O area ($7C00-$7FFF)
+    pub fn write_io(&mut self, addr: usize, val: u8) {
+        let set_byte = |reg: &mut u32, byte: usize| {
+            *reg = (*reg & !(0xFF << (byte * 8))) | ((val as u32) << (byte * 8));
+        };
+
+        match addr {
+            0x7F40..=0x7F42 => set_byte(&mut self.io.dma.source, addr - 0x7F40),
+            0x7F43..=0x7F44 => {
+                let mut l = self.io.dma.length as u32;
+                set_byte(&mut l, addr - 0x7F43);
+                self.io.dma.length = l as u16;
+            }
+            0x7F45..=0x7F47 => {
+                set_byte(&mut self.io.dma.target, addr - 0x7F45);
+                if addr == 0x7F47 && self.io.halt {
+                    self.io.dma.enable = true;
+                }
+            }
+            0x7F48 => {
+                self.io.cache.page = (val & 1) as usize;
+                if self.io.halt {
+                    self.io.cache.enable = true;
+                }
+            }
+            0x7F49..=0x7F4B => set_byte(&mut self.io.cache.base, addr - 0x7F49),
+            0x7F4C => {
+                self.io.cache.lock[0] = val & 0x01 != 0;
+                self.io.cache.lock[1] = val & 0x02 != 0;
+            }
+            0x7F4D..=0x7F4E => {
+                let mut pb = self.io.cache.pb as u32;
+                set_byte(&mut pb, addr - 0x7F4D);
+                self.io.cache.pb = (pb & 0x7FFF) as u16;
+            }
+            0x7F4F => {
+                self.io.cache.pc = val;
+                if self.io.halt {
+                    // Start execution
+                    self.io.halt = false;
+                    self.regs.pb = self.io.cache.pb;
+                    self.regs.pc = self.io.cache.pc;
+                }
+            }
+            0x7F50 => {
+                self.io.wait_ram = (val & 0x07) as Ticks;
+                self.io.wait_rom = ((val >> 4) & 0x07) as Ticks;
+            }
+            0x7F51 => {
+                self.io.irq = val & 1 != 0;
+                if self.io.irq {
+                    self.regs.i = false;
+                }
+            }
+            0x7F52 => self.io.rom = val & 1 != 0,
+            0x7F53 => {
+                self.io.lock = false;
+                self.io.halt = true;
+            }
+            0x7F55..=0x7F5C => {
+                // Suspend indefinitely (7F55) or for 32 - 224 cycles
+                self.io.suspend = true;
+                self.io.suspend_duration = (addr - 0x7F55) * 32;
+            }
+            0x7F5D => self.io.suspend = false,
+            0x7F5E => self.regs.i = false,
+            0x7F60..=0x7F7F => self.io.vector[addr & 0x1F] = val,
+            0x7F80..=0x7FAF | 0x7FC0..=0x7FEF => {
+                let r = addr & 0x3F;
+                set_byte(&mut self.regs.gpr[r / 3], r % 3);
+            }
+            _ => (),
+        }
+    }
+
+    /// S-CPU read from data RAM
+    pub fn read_dram(&self, addr: usize) -> Option<u8> {
+        self.data_ram.get(addr & 0xFFF).copied()
+    }
+
+    /// S-CPU write to data RAM
+    pub fn write_dram(&mut self, addr: usize, val: u8) -> Option<()> {
+        self.data_ram.get_mut(addr & 0xFFF).map(|v| *v = val)
+    }
+}
+
+impl Tickable for CpuHg51b {
+    fn tick(&mut self, _ticks: Ticks) -> Result<Ticks> {
+        self.cycles = 0;
+
+        if self.io.lock || (self.io.suspend && self.io.suspend_duration == 0) {
+            // Idle, but finish outstanding bus access
+            if self.io.bus.enable {
+                self.step(1);
+            }
+        } else if self.io.suspend {
+            self.step(self.io.suspend_duration);
+            self.io.suspend_duration = 0;
+            self.io.suspend = false;
+        } else if self.io.cache.enable {
+            self.cache();
+        } else if self.io.dma.enable {
+            self.dma();
+        } else if self.io.halt {
+            if self.io.bus.enable {
+                self.step(1);
+            }
+        } else {
+            self.execute();
+        }
+
+        Ok(self.cycles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cpu(code: &[u16]) -> CpuHg51b {
+        // Program at $00:8000, LoROM
+        let mut rom = vec![0; 0x10000];
+        for (i, op) in code.iter().enumerate() {
+            rom[i * 2] = *op as u8;
+            rom[i * 2 + 1] = (*op >> 8) as u8;
+        }
+        let mut c = CpuHg51b::new(&rom, rom.len() - 1);
+        c.write_io(0x7F49, 0x00);
+        c.write_io(0x7F4A, 0x80);
+        c.write_io(0x7F4B, 0x00);
+        c.write_io(0x7F4D, 0);
+        c.write_io(0x7F4E, 0);
+        c.write_io(0x7F4F, 0);
+        c
+    }
+
+    fn run(c: &mut CpuHg51b, steps: usize) {
+        for _ in 0..steps {
+            c.tick(1).unwrap();
+        }
+    }
+
+    #[test]
+    fn start_halt_irq() {
+        // NOP, HALT
+        let mut c = cpu(&[0x0000, 0xFC00]);
+        assert!(c.running());
+        run(&mut c, 2);
+        assert!(!c.running());
+        assert!(c.get_int());
+        assert_eq!(c.read_io(0x7F5E) & 0x02, 0x02);
+        c.write_io(0x7F5E, 0);
+        assert!(!c.get_int());
+    }
+
+    #[test]
+    fn alu_flags() {
+        // LD A,#FF; ADD A<<16,#01; CMP A,#00
+        let mut c = cpu(&[0x64FF, 0x8701, 0x5400]);
+        run(&mut c, 2);
+        assert_eq!(c.regs.a, 0xFF0001);
+        assert!(c.regs.n);
+        assert!(!c.regs.c);
+        run(&mut c, 1);
+        assert!(c.regs.c);
+        assert!(!c.regs.z);
+    }
+
+    #[test]
+    fn jsr_rts() {
+        // JSR $03; HALT; NOP; LD A,#12; RTS
+        let mut c = cpu(&[0x2803, 0xFC00, 0x0000, 0x6412, 0x3C00]);
+        run(&mut c, 3);
+        assert_eq!(c.regs.a, 0x12);
+        assert_eq!(c.regs.pc, 1);
+        run(&mut c, 1);
+        assert!(c.io.halt);
+    }
+
+    #[test]
+    fn data_ram() {
+        // LD A,#34; ST RAM,A; WRRAM 0,DPR+#10; RDRAM 2,DPR+#10
+        let mut c = cpu(&[0x6434, 0xE00C, 0xEC10, 0x6E10]);
+        run(&mut c, 4);
+        assert_eq!(c.data_ram[0x10], 0x34);
+        assert_eq!(c.read_dram(0x6010), Some(0x34));
+        assert_eq!(c.regs.ram, 0x340034);
+    }
+
+    #[test]
+    fn bus_read() {
+        // LD A,BUSROM; WAIT
+        let mut c = cpu(&[0x602E, 0x1C00]);
+        c.regs.mar = 0x008000;
+        run(&mut c, 1);
+        assert!(c.busy());
+        run(&mut c, 1);
+        assert!(!c.busy());
+        assert_eq!(c.regs.mdr, 0x2E);
+    }
+
+    #[test]
+    fn data_rom() {
+        // RDROM #001
+        let mut c = cpu(&[0x7401]);
+        let mut drom = vec![0; DATA_ROM_SIZE];
+        drom[3..6].copy_from_slice(&[0x56, 0x34, 0x12]);
+        c.load_data_rom(&drom);
+        run(&mut c, 1);
+        assert_eq!(c.regs.rom, 0x123456);
+    }
+
+    #[test]
+    fn state_without_rom() {
+        let c = cpu(&[0x602E, 0x1C00]);
+        let json = serde_json::to_string(&c).unwrap();
+        let c: CpuHg51b = serde_json::from_str(&json).unwrap();
+        assert!(c.rom.is_empty());
+    }
+
+    #[test]
+    fn gpr_io() {
+        let mut c = cpu(&[]);
+        c.write_io(0x7F83, 0x11);
+        c.write_io(0x7F84, 0x22);
+        c.write_io(0x7F85, 0x33);
+        assert_eq!(c.regs.gpr[1], 0x332211);
+        assert_eq!(c.read_io(0x7FC4), 0x22);
+    }
+}
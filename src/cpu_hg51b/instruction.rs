@@ -0,0 +1,457 @@
+use std::fmt;
+
+use anyhow::Result;
+use strum::Display;
+use thiserror::Error;
+
+use super::regs::reg_name;
+
+/// Length of an instruction in bytes
+pub const INSTRUCTION_LEN: usize = 2;
+
+/// Shift amounts selectable for A in ALU instructions
+const ALU_SHIFTS: [u8; 4] = [0, 1, 8, 16];
+
+#[derive(Debug, Error)]
+enum DecodeErr {
+    #[error("End of instruction stream")]
+    EndOfStream,
+}
+
+/// Branch condition of JMP/JSR
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Display)]
+pub enum Condition {
+    #[strum(serialize = "")]
+    Always,
+    #[strum(serialize = "EQ,")]
+    Z,
+    #[strum(serialize = "GE,")]
+    C,
+    #[strum(serialize = "MI,")]
+    N,
+    #[strum(serialize = "VS,")]
+    V,
+}
+
+/// Flag tested by SKIP
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Display)]
+pub enum SkipFlag {
+    V,
+    C,
+    Z,
+    N,
+}
+
+/// Second operand of an ALU instruction
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Operand {
+    /// Register file address
+    Reg(u8),
+    /// Immediate value
+    Imm(u32),
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Reg(r) => write!(f, "{}", reg_name(*r)),
+            Operand::Imm(i) => write!(f, "#{:02X}", i),
+        }
+    }
+}
+
+/// Data RAM address source for RDRAM/WRRAM
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RamAddr {
+    /// Address in A
+    A,
+    /// DPR + immediate
+    Dpr(u8),
+}
+
+impl fmt::Display for RamAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RamAddr::A => write!(f, "A"),
+            RamAddr::Dpr(i) => write!(f, "DPR+#{:02X}", i),
+        }
+    }
+}
+
+/// Destination of the LD instruction
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Display)]
+pub enum LdDest {
+    A,
+    MDR,
+    MAR,
+    P,
+}
+
+/// Source of the ST instruction
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Display)]
+pub enum StSrc {
+    A,
+    MDR,
+}
+
+/// Accumulator-based ALU operations (A << shift, operand)
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Display)]
+pub enum AluOp {
+    /// Compare operand with A
+    CMPR,
+    /// Compare A with operand
+    CMP,
+    ADD,
+    /// Reverse subtract (operand - A)
+    SUBR,
+    SUB,
+    XNOR,
+    XOR,
+    AND,
+    OR,
+}
+
+/// Shift/rotate operations on A
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Display)]
+pub enum ShiftOp {
+    SHR,
+    ASR,
+    ROR,
+    SHL,
+}
+
+/// Decoded instruction operation
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Op {
+    Nop,
+    Jmp {
+        cond: Condition,
+        far: bool,
+        target: u8,
+    },
+    Jsr {
+        cond: Condition,
+        far: bool,
+        target: u8,
+    },
+    Rts,
+    Wait,
+    Skip {
+        flag: SkipFlag,
+        take: bool,
+    },
+    IncMar,
+    Alu {
+        op: AluOp,
+        shift: u8,
+        operand: Operand,
+    },
+    Mul(Operand),
+    Shift {
+        op: ShiftOp,
+        operand: Operand,
+    },
+    Sxb,
+    Sxw,
+    Ld {
+        dest: LdDest,
+        operand: Operand,
+    },
+    LdPl(u8),
+    LdPh(u8),
+    St {
+        reg: u8,
+        src: StSrc,
+    },
+    Rdram {
+        byte: u8,
+        addr: RamAddr,
+    },
+    Wrram {
+        byte: u8,
+        addr: RamAddr,
+    },
+    Rdrom(Operand),
+    Swap(u8),
+    Clear,
+    Halt,
+}
+
+/// A decoded instruction
+#[derive(Debug, Copy, Clone)]
+pub struct Instruction {
+    pub opcode: u16,
+    pub op: Op,
+}
+
+impl Instruction {
+    /// Decodes a single opcode.
+    pub fn from_opcode(opcode: u16) -> Instruction {
+        let hi = (opcode >> 8) as u8;
+        let lo = opcode as u8;
+        let reg = lo & 0x7F;
+        let far = hi & 0x02 != 0;
+        let shift = ALU_SHIFTS[(hi & 0x03) as usize];
+        let sel = hi & 0x03;
+
+        let alu = |op, imm: bool| Op::Alu {
+            op,
+            shift,
+            operand: if imm {
+                Operand::Imm(lo as u32)
+            } else {
+                Operand::Reg(reg)
+            },
+        };
+        let shft = |op, imm: bool| Op::Shift {
+            op,
+            operand: if imm {
+                Operand::Imm((lo & 0x1F) as u32)
+            } else {
+                Operand::Reg(reg)
+            },
+        };
+        let jmp = |cond| Op::Jmp {
+            cond,
+            far,
+            target: lo,
+        };
+        let jsr = |cond| Op::Jsr {
+            cond,
+            far,
+            target: lo,
+        };
+
+        let op = match hi >> 2 {
+            0x02 => jmp(Condition::Always),
+            0x03 => jmp(Condition::Z),
+            0x04 => jmp(Condition::C),
+            0x05 => jmp(Condition::N),
+            0x06 => jmp(Condition::V),
+            0x07 => Op::Wait,
+            0x09 => Op::Skip {
+                flag: [SkipFlag::V, SkipFlag::C, SkipFlag::Z, SkipFlag::N][sel as usize],
+                take: lo & 1 != 0,
+            },
+            0x0A => jsr(Condition::Always),
+            0x0B => jsr(Condition::Z),
+            0x0C => jsr(Condition::C),
+            0x0D => jsr(Condition::N),
+            0x0E => jsr(Condition::V),
+            0x0F => Op::Rts,
+            0x10 => Op::IncMar,
+            0x12 => alu(AluOp::CMPR, false),
+            0x13 => alu(AluOp::CMPR, true),
+            0x14 => alu(AluOp::CMP, false),
+            0x15 => alu(AluOp::CMP, true),
+            0x16 if sel == 1 => Op::Sxb,
+            0x16 if sel == 2 => Op::Sxw,
+            0x18 => Op::Ld {
+                dest: [LdDest::A, LdDest::MDR, LdDest::MAR, LdDest::P][sel as usize],
+                operand: Operand::Reg(reg),
+            },
+            0x19 => Op::Ld {
+                dest: [LdDest::A, LdDest::MDR, LdDest::MAR, LdDest::P][sel as usize],
+                operand: Operand::Imm(lo as u32),
+            },
+            0x1A if sel != 3 => Op::Rdram {
+                byte: sel,
+                addr: RamAddr::A,
+            },
+            0x1B if sel != 3 => Op::Rdram {
+                byte: sel,
+                addr: RamAddr::Dpr(lo),
+            },
+            0x1C => Op::Rdrom(Operand::Reg(0)),
+            0x1D => Op::Rdrom(Operand::Imm((opcode & 0x3FF) as u32)),
+            0x1F if sel == 0 => Op::LdPl(lo),
+            0x1F if sel == 1 => Op::LdPh(lo & 0x7F),
+            0x20 => alu(AluOp::ADD, false),
+            0x21 => alu(AluOp::ADD, true),
+            0x22 => alu(AluOp::SUBR, false),
+            0x23 => alu(AluOp::SUBR, true),
+            0x24 => alu(AluOp::SUB, false),
+            0x25 => alu(AluOp::SUB, true),
+            0x26 => Op::Mul(Operand::Reg(reg)),
+            0x27 => Op::Mul(Operand::Imm(lo as u32)),
+            0x28 => alu(AluOp::XNOR, false),
+            0x29 => alu(AluOp::XNOR, true),
+            0x2A => alu(AluOp::XOR, false),
+            0x2B => alu(AluOp::XOR, true),
+            0x2C => alu(AluOp::AND, false),
+            0x2D => alu(AluOp::AND, true),
+            0x2E => alu(AluOp::OR, false),
+            0x2F => alu(AluOp::OR, true),
+            0x30 => shft(ShiftOp::SHR, false),
+            0x31 => shft(ShiftOp::SHR, true),
+            0x32 => shft(ShiftOp::ASR, false),
+            0x33 => shft(ShiftOp::ASR, true),
+            0x34 => shft(ShiftOp::ROR, false),
+            0x35 => shft(ShiftOp::ROR, true),
+            0x36 => shft(ShiftOp::SHL, false),
+            0x37 => shft(ShiftOp::SHL, true),
+            0x38 if sel == 0 => Op::St { reg, src: StSrc::A },
+            0x38 if sel == 1 => Op::St {
+                reg,
+                src: StSrc::MDR,
+            },
+            0x3A if sel != 3 => Op::Wrram {
+                byte: sel,
+                addr: RamAddr::A,
+            },
+            0x3B if sel != 3 => Op::Wrram {
+                byte: sel,
+                addr: RamAddr::Dpr(lo),
+            },
+            0x3C => Op::Swap(lo & 0x0F),
+            0x3E => Op::Clear,
+            0x3F => Op::Halt,
+
+            // Everything else (including the unknown opcodes) behaves as NOP
+            _ => Op::Nop,
+        };
+
+        Instruction { opcode, op }
+    }
+
+    /// Try to decode a single instruction from an iterator.
+    pub fn decode(stream: &mut impl Iterator<Item = u8>) -> Result<Instruction> {
+        let mut opcode = stream.next().ok_or(DecodeErr::EndOfStream)? as u16;
+        opcode |= (stream.next().ok_or(DecodeErr::EndOfStream)? as u16) << 8;
+
+        Ok(Self::from_opcode(opcode))
+    }
+
+    pub fn len(&self) -> usize {
+        INSTRUCTION_LEN
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{:04X}] ", self.opcode)?;
+        let farstr = |far| if far { " (far)" } else { "" };
+        match self.op {
+            Op::Nop => write!(f, "NOP"),
+            Op::Jmp { cond, far, target } => {
+                write!(f, "JMP   {}${:02X}{}", cond, target, farstr(far))
+            }
+            Op::Jsr { cond, far, target } => {
+                write!(f, "JSR   {}${:02X}{}", cond, target, farstr(far))
+            }
+            Op::Rts => write!(f, "RTS"),
+            Op::Wait => write!(f, "WAIT"),
+            Op::Skip { flag, take } => write!(f, "SKIP  {},{}", flag, take as u8),
+            Op::IncMar => write!(f, "INC   MAR"),
+            Op::Alu { op, shift, operand } => {
+                if shift == 0 {
+                    write!(f, "{:<5} A,{}", op.to_string(), operand)
+                } else {
+                    write!(f, "{:<5} A<<{},{}", op.to_string(), shift, operand)
+                }
+            }
+            Op::Mul(operand) => write!(f, "MUL   A,{}", operand),
+            Op::Shift { op, operand } => write!(f, "{:<5} A,{}", op.to_string(), operand),
+            Op::Sxb => write!(f, "SXB   A"),
+            Op::Sxw => write!(f, "SXW   A"),
+            Op::Ld { dest, operand } => write!(f, "LD    {},{}", dest, operand),
+            Op::LdPl(v) => write!(f, "LD    PL,#{:02X}", v),
+            Op::LdPh(v) => write!(f, "LD    PH,#{:02X}", v),
+            Op::St { reg, src } => write!(f, "ST    {},{}", reg_name(reg), src),
+            Op::Rdram { byte, addr } => write!(f, "RDRAM {},{}", byte, addr),
+            Op::Wrram { byte, addr } => write!(f, "WRRAM {},{}", byte, addr),
+            Op::Rdrom(Operand::Reg(_)) => write!(f, "RDROM A"),
+            Op::Rdrom(Operand::Imm(i)) => write!(f, "RDROM #{:03X}", i),
+            Op::Swap(r) => write!(f, "SWAP  A,R{}", r),
+            Op::Clear => write!(f, "CLEAR"),
+            Op::Halt => write!(f, "HALT"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn op(opcode: u16) -> Op {
+        Instruction::from_opcode(opcode).op
+    }
+
+    #[test]
+    fn decode_jumps() {
+        assert_eq!(
+            op(0x0812),
+            Op::Jmp {
+                cond: Condition::Always,
+                far: false,
+                target: 0x12
+            }
+        );
+        assert_eq!(
+            op(0x0E34),
+            Op::Jmp {
+                cond: Condition::Z,
+                far: true,
+                target: 0x34
+            }
+        );
+        assert_eq!(
+            op(0x3A56),
+            Op::Jsr {
+                cond: Condition::V,
+                far: true,
+                target: 0x56
+            }
+        );
+        assert_eq!(op(0x3C00), Op::Rts);
+    }
+
+    #[test]
+    fn decode_alu() {
+        assert_eq!(
+            op(0x8161),
+            Op::Alu {
+                op: AluOp::ADD,
+                shift: 1,
+                operand: Operand::Reg(0x61)
+            }
+        );
+        assert_eq!(
+            op(0x57FF),
+            Op::Alu {
+                op: AluOp::CMP,
+                shift: 16,
+                operand: Operand::Imm(0xFF)
+            }
+        );
+        assert_eq!(
+            op(0xC4FF),
+            Op::Shift {
+                op: ShiftOp::SHR,
+                operand: Operand::Imm(0x1F)
+            }
+        );
+    }
+
+    #[test]
+    fn decode_misc() {
+        assert_eq!(op(0x7723), Op::Rdrom(Operand::Imm(0x323)));
+        assert_eq!(
+            op(0x6D10),
+            Op::Rdram {
+                byte: 1,
+                addr: RamAddr::Dpr(0x10)
+            }
+        );
+        assert_eq!(
+            op(0xE160),
+            Op::St {
+                reg: 0x60,
+                src: StSrc::MDR
+            }
+        );
+        assert_eq!(op(0xF00F), Op::Swap(15));
+        assert_eq!(op(0xFC00), Op::Halt);
+        assert_eq!(op(0x0000), Op::Nop);
+    }
+}
@@ -0,0 +1,3 @@
+pub mod cpu;
+pub mod instruction;
+pub mod regs;
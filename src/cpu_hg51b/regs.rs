@@ -0,0 +1,159 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Mask for a 24-bit register
+pub const REG24_MASK: u32 = 0xFFFFFF;
+
+/// Amount of general purpose registers
+pub const GPR_COUNT: usize = 16;
+
+/// Register file addresses, as used by the LD/ST instructions
+/// and the register operands of ALU instructions.
+pub mod regaddr {
+    /// Accumulator
+    pub const A: u8 = 0x00;
+    /// Multiplier result, upper 24 bits
+    pub const MULH: u8 = 0x01;
+    /// Multiplier result, lower 24 bits
+    pub const MULL: u8 = 0x02;
+    /// Bus memory data register
+    pub const MDR: u8 = 0x03;
+    /// Data ROM read buffer
+    pub const ROM: u8 = 0x08;
+    /// Data RAM read/write buffer
+    pub const RAM: u8 = 0x0C;
+    /// Bus memory address register
+    pub const MAR: u8 = 0x13;
+    /// Data RAM pointer
+    pub const DPR: u8 = 0x1C;
+    /// Program counter
+    pub const PC: u8 = 0x20;
+    /// Page register
+    pub const P: u8 = 0x28;
+    /// Access triggers a bus access to ROM at MAR
+    pub const BUS_ROM: u8 = 0x2E;
+    /// Access triggers a bus access to RAM at MAR
+    pub const BUS_RAM: u8 = 0x2F;
+    /// Start of the constants area
+    pub const CONST_START: u8 = 0x50;
+    /// End of the constants area
+    pub const CONST_END: u8 = 0x5F;
+    /// First general purpose register
+    pub const GPR_START: u8 = 0x60;
+    /// Last general purpose register
+    pub const GPR_END: u8 = 0x6F;
+}
+
+/// Hardwired constants in the register space (0x50 - 0x5F)
+pub const CONSTANTS: [u32; 16] = [
+    0x000000, 0xFFFFFF, 0x00FF00, 0xFF0000, 0x00FFFF, 0xFFFF00, 0x800000, 0x7FFFFF, 0x008000,
+    0x007FFF, 0xFF7FFF, 0xFFFF7F, 0x010000, 0xFEFFFF, 0x000100, 0x00FEFF,
+];
+
+/// Returns a printable name for a register address
+pub fn reg_name(reg: u8) -> String {
+    match reg {
+        regaddr::A => "A".to_string(),
+        regaddr::MULH => "MULH".to_string(),
+        regaddr::MULL => "MULL".to_string(),
+        regaddr::MDR => "MDR".to_string(),
+        regaddr::ROM => "ROM".to_string(),
+        regaddr::RAM => "RAM".to_string(),
+        regaddr::MAR => "MAR".to_string(),
+        regaddr::DPR => "DPR".to_string(),
+        regaddr::PC => "PC".to_string(),
+        regaddr::P => "P".to_string(),
+        regaddr::BUS_ROM => "BUSROM".to_string(),
+        regaddr::BUS_RAM => "BUSRAM".to_string(),
+        regaddr::CONST_START..=regaddr::CONST_END => {
+            format!("#{:06X}", CONSTANTS[(reg - regaddr::CONST_START) as usize])
+        }
+        regaddr::GPR_START..=regaddr::GPR_END => format!("R{}", reg - regaddr::GPR_START),
+        _ => format!("?{:02X}", reg),
+    }
+}
+
+/// Complete HG51B register file
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct RegisterFile {
+    /// Program bank (15-bit)
+    pub pb: u16,
+    /// Program counter (within the current cache page)
+    pub pc: u8,
+    /// Page register (15-bit), loaded into PB on far jumps
+    pub p: u16,
+
+    /// Accumulator (24-bit)
+    pub a: u32,
+    /// Multiplier result (48-bit)
+    pub mul: u64,
+    /// Bus memory data register (24-bit)
+    pub mdr: u32,
+    /// Bus memory address register (24-bit)
+    pub mar: u32,
+    /// Data ROM read buffer (24-bit)
+    pub rom: u32,
+    /// Data RAM buffer (24-bit)
+    pub ram: u32,
+    /// Data RAM pointer (24-bit)
+    pub dpr: u32,
+    /// General purpose registers (24-bit)
+    pub gpr: [u32; GPR_COUNT],
+
+    /// Negative flag
+    pub n: bool,
+    /// Zero flag
+    pub z: bool,
+    /// Carry flag
+    pub c: bool,
+    /// Overflow flag
+    pub v: bool,
+    /// Interrupt flag
+    pub i: bool,
+}
+
+impl RegisterFile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets N and Z according to a 24-bit result
+    pub fn set_nz(&mut self, val: u32) {
+        self.n = val & 0x800000 != 0;
+        self.z = val & REG24_MASK == 0;
+    }
+}
+
+impl fmt::Display for RegisterFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "PB:{:04X} PC:{:02X} P:{:04X} A:{:06X} MUL:{:012X} MDR:{:06X} MAR:{:06X} ROM:{:06X} RAM:{:06X} DPR:{:06X} {}{}{}{}{}",
+            self.pb,
+            self.pc,
+            self.p,
+            self.a,
+            self.mul,
+            self.mdr,
+            self.mar,
+            self.rom,
+            self.ram,
+            self.dpr,
+            if self.n { 'N' } else { 'n' },
+            if self.z { 'Z' } else { 'z' },
+            if self.c { 'C' } else { 'c' },
+            if self.v { 'V' } else { 'v' },
+            if self.i { 'I' } else { 'i' },
+        )?;
+        for (i, r) in self.gpr.iter().enumerate() {
+            if i % 8 == 0 {
+                writeln!(f)?;
+            } else {
+                write!(f, " ")?;
+            }
+            write!(f, "R{:<2}:{:06X}", i, r)?;
+        }
+        Ok(())
+    }
+}
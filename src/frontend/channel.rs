@@ -1,21 +1,44 @@
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 use std::sync::Arc;
 
 use anyhow::Result;
 use crossbeam_channel::{Receiver, Sender, TrySendError};
 
-use super::{new_displaybuffer, DisplayBuffer, Renderer};
+use super::{new_displaybuffer, ColorProfile, DisplayBuffer, Renderer};
+
+/// A completed frame, as sent over the channel.
+pub struct Frame {
+    pub buffer: DisplayBuffer,
+    /// Height of the picture in the buffer
+    pub height: usize,
+}
 
 /// A renderer that feeds it display buffer back over a channel.
 pub struct ChannelRenderer {
     displaybuffer: DisplayBuffer,
-    sender: Sender<DisplayBuffer>,
-    receiver: Receiver<DisplayBuffer>,
+    active_height: usize,
+    color_profile: ColorProfile,
+    /// Wait for the receiver rather than dropping frames
+    lossless: Arc<AtomicBool>,
+    sender: Sender<Frame>,
+    receiver: Receiver<Frame>,
 }
 
 impl ChannelRenderer {
-    pub fn get_receiver(&mut self) -> Receiver<DisplayBuffer> {
+    pub fn get_receiver(&mut self) -> Receiver<Frame> {
         self.receiver.clone()
     }
+
+    /// Gets a flag that, when set, makes the renderer wait for the
+    /// receiver to accept every frame rather than dropping frames
+    /// (e.g. when paced by vsync).
+    pub fn get_lossless_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.lossless)
+    }
+
+    pub fn set_color_profile(&mut self, profile: ColorProfile) {
+        self.color_profile = profile;
+    }
 }
 
 impl Renderer for ChannelRenderer {
@@ -24,6 +47,9 @@ impl Renderer for ChannelRenderer {
         let (sender, receiver) = crossbeam_channel::bounded(1);
         Ok(Self {
             displaybuffer: new_displaybuffer(width, height),
+            active_height: height,
+            color_profile: ColorProfile::default(),
+            lossless: Arc::new(AtomicBool::new(false)),
             sender,
             receiver,
         })
@@ -33,6 +59,14 @@ impl Renderer for ChannelRenderer {
         Arc::clone(&self.displaybuffer)
     }
 
+    fn set_active_height(&mut self, height: usize) {
+        self.active_height = height;
+    }
+
+    fn get_color_profile(&self) -> ColorProfile {
+        self.color_profile
+    }
+
     /// Renders changes to screen
     fn update(&mut self) -> Result<()> {
         // Copy the current buffer as fresh backbuffer so it is possible to
@@ -40,7 +74,25 @@ impl Renderer for ChannelRenderer {
         let new_buffer = self.displaybuffer.clone();
         let buffer = std::mem::replace(&mut self.displaybuffer, new_buffer);
 
-        match self.sender.try_send(buffer) {
+        if self.lossless.load(Ordering::Relaxed) {
+            // Send a snapshot, so the receiver gets the frame exactly
+            // as it was completed.
+            let snapshot = Arc::new(
+                buffer
+                    .iter()
+                    .map(|b| AtomicU8::new(b.load(Ordering::Acquire)))
+                    .collect(),
+            );
+            return Ok(self.sender.send(Frame {
+                buffer: snapshot,
+                height: self.active_height,
+            })?);
+        }
+
+        match self.sender.try_send(Frame {
+            buffer,
+            height: self.active_height,
+        }) {
             Err(TrySendError::Full(_)) => Ok(()),
             e => Ok(e?),
         }
@@ -0,0 +1,673 @@
+//! Video filters, applied to a picture between the PPU output and
+//! presentation. Filters run entirely on the CPU.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+use anyhow::Result;
+
+use super::{Color, DisplayBuffer};
+
+/// Intensity of the dark lines produced by the scanlines filter
+const SCANLINE_INTENSITY: u32 = 60;
+
+/// hqx similarity thresholds for Y, U and V
+const HQX_THRESHOLD: (i32, i32, i32) = (48, 7, 6);
+
+/// Phase advance of the NTSC color subcarrier per low-res pixel, in
+/// degrees. A pixel takes 4 master cycles, a subcarrier period takes 6.
+const NTSC_PIXEL_PHASE: f32 = 240.0;
+/// Phase advance of the NTSC color subcarrier per scanline, in degrees.
+/// A scanline takes 1364 master cycles.
+const NTSC_LINE_PHASE: f32 = 120.0;
+/// Samples beyond the edges of a line the NTSC decoder needs
+const NTSC_MARGIN: isize = 4;
+
+/// An RGB888 picture
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Picture {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>,
+}
+
+impl Picture {
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        assert_eq!(pixels.len(), width * height);
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// A black picture
+    pub fn blank(width: usize, height: usize) -> Self {
+        Self::new(width, height, vec![(0, 0, 0); width * height])
+    }
+
+    /// Copies the top `height` lines of a display buffer that is
+    /// `width` pixels wide
+    pub fn from_displaybuffer(buffer: &DisplayBuffer, width: usize, height: usize) -> Self {
+        let pixels = buffer[..(width * height * 4)]
+            .chunks_exact(4)
+            .map(|p| {
+                (
+                    p[2].load(Ordering::Acquire),
+                    p[1].load(Ordering::Acquire),
+                    p[0].load(Ordering::Acquire),
+                )
+            })
+            .collect();
+        Self::new(width, height, pixels)
+    }
+
+    /// Converts the picture to the format of a display buffer
+    pub fn to_bgrx(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|&(r, g, b)| [b, g, r, 0])
+            .collect()
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: Color) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    /// Gets a pixel, clamping the coordinates to the edges
+    fn get_clamped(&self, x: isize, y: isize) -> Color {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let y = y.clamp(0, self.height as isize - 1) as usize;
+        self.get(x, y)
+    }
+
+    fn line(&self, y: usize) -> &[Color] {
+        &self.pixels[(y * self.width)..((y + 1) * self.width)]
+    }
+
+    /// Returns the factors by which the picture is doubled horizontally
+    /// and vertically. The PPU outputs low resolution pictures as
+    /// doubled pixels, to fit the high resolution frame buffer.
+    pub fn get_doubling(&self) -> (usize, usize) {
+        let h = if self.width & 1 == 0 && self.pixels.chunks_exact(2).all(|p| p[0] == p[1]) {
+            2
+        } else {
+            1
+        };
+        let v = if self.height & 1 == 0
+            && (0..self.height)
+                .step_by(2)
+                .all(|y| self.line(y) == self.line(y + 1))
+        {
+            2
+        } else {
+            1
+        };
+        (h, v)
+    }
+
+    /// Reduces a doubled picture to the resolution the PPU produced it in
+    pub fn to_native(&self) -> Self {
+        let (h, v) = self.get_doubling();
+        let (width, height) = (self.width / h, self.height / v);
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| self.get(x * h, y * v))
+            .collect();
+        Self::new(width, height, pixels)
+    }
+
+    /// Encodes the picture as PNG
+    pub fn write_png(&self, out: impl Write) -> Result<()> {
+        let mut encoder = png::Encoder::new(out, self.width.try_into()?, self.height.try_into()?);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        let data: Vec<u8> = self
+            .pixels
+            .iter()
+            .flat_map(|&(r, g, b)| [r, g, b])
+            .collect();
+        writer.write_image_data(&data)?;
+        Ok(())
+    }
+
+    /// Writes the picture to a PNG file
+    pub fn save_png(&self, filename: &Path) -> Result<()> {
+        self.write_png(BufWriter::new(File::create(filename)?))
+    }
+}
+
+/// Available video filters
+#[derive(Debug, Eq, PartialEq, Copy, Clone, clap::ValueEnum)]
+pub enum Filter {
+    /// Nearest neighbour scaling by 2
+    Nearest2x,
+    /// Nearest neighbour scaling by 3
+    Nearest3x,
+    /// Nearest neighbour scaling by 4
+    Nearest4x,
+    /// Scale2x (EPX) pixel art scaler
+    Scale2x,
+    /// Scale3x pixel art scaler
+    Scale3x,
+    /// Scaling by 2, smoothing edges like hqx
+    Hq2x,
+    /// Doubles the lines, darkening every other line
+    Scanlines,
+    /// NTSC composite video artifacts
+    Composite,
+    /// NTSC RF modulator artifacts
+    Rf,
+}
+
+impl Filter {
+    pub fn apply(&self, p: &Picture) -> Picture {
+        match self {
+            Self::Nearest2x => nearest(p, 2),
+            Self::Nearest3x => nearest(p, 3),
+            Self::Nearest4x => nearest(p, 4),
+            Self::Scale2x => scale2x(p),
+            Self::Scale3x => scale3x(p),
+            Self::Hq2x => hq2x(p),
+            Self::Scanlines => scanlines(p),
+            Self::Composite => ntsc(p, NtscSignal::Composite),
+            Self::Rf => ntsc(p, NtscSignal::Rf),
+        }
+    }
+}
+
+/// A chain of filters, applied in order
+#[derive(Debug, Clone, Default)]
+pub struct FilterChain {
+    filters: Vec<Filter>,
+}
+
+impl FilterChain {
+    pub fn new(filters: &[Filter]) -> Self {
+        Self {
+            filters: filters.to_vec(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// Filters a picture as output by the PPU. Doubled pictures are
+    /// reduced to their native resolution first.
+    pub fn apply(&self, p: &Picture) -> Picture {
+        let native = p.to_native();
+        self.filters.iter().fold(native, |p, f| f.apply(&p))
+    }
+}
+
+/// Nearest neighbour scaling by an integer factor
+pub fn nearest(p: &Picture, factor: usize) -> Picture {
+    let (width, height) = (p.width * factor, p.height * factor);
+    let pixels = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .map(|(x, y)| p.get(x / factor, y / factor))
+        .collect();
+    Picture::new(width, height, pixels)
+}
+
+/// Writes the NxN block of pixels produced from source pixel (x, y)
+/// into a picture N times the size of the source picture.
+fn put_block<const N: usize>(
+    out: &mut [Color],
+    width: usize,
+    x: usize,
+    y: usize,
+    block: [[Color; N]; N],
+) {
+    for (by, row) in block.iter().enumerate() {
+        let start = (y * N + by) * width * N + x * N;
+        out[start..(start + N)].copy_from_slice(row);
+    }
+}
+
+/// Scale2x (also known as EPX)
+pub fn scale2x(p: &Picture) -> Picture {
+    let mut out = vec![(0, 0, 0); p.width * p.height * 4];
+    for y in 0..p.height {
+        for x in 0..p.width {
+            let (x, y) = (x as isize, y as isize);
+            let b = p.get_clamped(x, y - 1);
+            let d = p.get_clamped(x - 1, y);
+            let e = p.get_clamped(x, y);
+            let f = p.get_clamped(x + 1, y);
+            let h = p.get_clamped(x, y + 1);
+
+            let block = if b != h && d != f {
+                [
+                    [if d == b { d } else { e }, if b == f { f } else { e }],
+                    [if d == h { d } else { e }, if h == f { f } else { e }],
+                ]
+            } else {
+                [[e; 2]; 2]
+            };
+            put_block(&mut out, p.width, x as usize, y as usize, block);
+        }
+    }
+    Picture::new(p.width * 2, p.height * 2, out)
+}
+
+/// Scale3x
+pub fn scale3x(p: &Picture) -> Picture {
+    let mut out = vec![(0, 0, 0); p.width * p.height * 9];
+    for y in 0..p.height {
+        for x in 0..p.width {
+            let (x, y) = (x as isize, y as isize);
+            let a = p.get_clamped(x - 1, y - 1);
+            let b = p.get_clamped(x, y - 1);
+            let c = p.get_clamped(x + 1, y - 1);
+            let d = p.get_clamped(x - 1, y);
+            let e = p.get_clamped(x, y);
+            let f = p.get_clamped(x + 1, y);
+            let g = p.get_clamped(x - 1, y + 1);
+            let h = p.get_clamped(x, y + 1);
+            let i = p.get_clamped(x + 1, y + 1);
+
+            let block = if b != h && d != f {
+                [
+                    [
+                        if d == b { d } else { e },
+                        if (d == b && e != c) || (b == f && e != a) {
+                            b
+                        } else {
+                            e
+                        },
+                        if b == f { f } else { e },
+                    ],
+                    [
+                        if (d == b && e != g) || (d == h && e != a) {
+                            d
+                        } else {
+                            e
+                        },
+                        e,
+                        if (b == f && e != i) || (h == f && e != c) {
+                            f
+                        } else {
+                            e
+                        },
+                    ],
+                    [
+                        if d == h { d } else { e },
+                        if (d == h && e != i) || (h == f && e != g) {
+                            h
+                        } else {
+                            e
+                        },
+                        if h == f { f } else { e },
+                    ],
+                ]
+            } else {
+                [[e; 3]; 3]
+            };
+            put_block(&mut out, p.width, x as usize, y as usize, block);
+        }
+    }
+    Picture::new(p.width * 3, p.height * 3, out)
+}
+
+fn to_yuv((r, g, b): Color) -> (i32, i32, i32) {
+    let (r, g, b) = (i32::from(r), i32::from(g), i32::from(b));
+    (
+        (r + g + b) >> 2,
+        128 + ((r - b) >> 2),
+        128 + ((2 * g - r - b) >> 3),
+    )
+}
+
+/// Compares colors the way hqx does, in YUV space
+fn yuv_similar(a: Color, b: Color) -> bool {
+    let (ay, au, av) = to_yuv(a);
+    let (by, bu, bv) = to_yuv(b);
+    (ay - by).abs() <= HQX_THRESHOLD.0
+        && (au - bu).abs() <= HQX_THRESHOLD.1
+        && (av - bv).abs() <= HQX_THRESHOLD.2
+}
+
+/// Weighted average of colors
+fn blend<const N: usize>(colors: [(Color, u32); N]) -> Color {
+    let total: u32 = colors.iter().map(|(_, w)| w).sum();
+    let channel = |f: fn(Color) -> u8| {
+        (colors
+            .iter()
+            .map(|&(c, w)| u32::from(f(c)) * w)
+            .sum::<u32>()
+            / total) as u8
+    };
+    (channel(|c| c.0), channel(|c| c.1), channel(|c| c.2))
+}
+
+/// Scaling by 2 in the style of hq2x: edges are detected like scale2x,
+/// but comparing colors by similarity in YUV space, and interpolated
+/// rather than copied.
+pub fn hq2x(p: &Picture) -> Picture {
+    let mut out = vec![(0, 0, 0); p.width * p.height * 4];
+    for y in 0..p.height {
+        for x in 0..p.width {
+            let (x, y) = (x as isize, y as isize);
+            let b = p.get_clamped(x, y - 1);
+            let d = p.get_clamped(x - 1, y);
+            let e = p.get_clamped(x, y);
+            let f = p.get_clamped(x + 1, y);
+            let h = p.get_clamped(x, y + 1);
+
+            // Interpolate a corner towards its two neighbours if they
+            // form an edge through the corner.
+            let corner = |n1: Color, n2: Color, o1: Color, o2: Color| {
+                if yuv_similar(n1, n2) && !yuv_similar(n1, o1) && !yuv_similar(n2, o2) {
+                    blend([(e, 2), (n1, 1), (n2, 1)])
+                } else {
+                    e
+                }
+            };
+            let block = [
+                [corner(d, b, f, h), corner(b, f, h, d)],
+                [corner(h, d, b, f), corner(f, h, d, b)],
+            ];
+            put_block(&mut out, p.width, x as usize, y as usize, block);
+        }
+    }
+    Picture::new(p.width * 2, p.height * 2, out)
+}
+
+/// Doubles every line, darkening the second copy
+pub fn scanlines(p: &Picture) -> Picture {
+    let dark = |c: Color| {
+        blend([
+            (c, SCANLINE_INTENSITY),
+            ((0, 0, 0), 100 - SCANLINE_INTENSITY),
+        ])
+    };
+    let mut pixels = Vec::with_capacity(p.pixels.len() * 2);
+    for y in 0..p.height {
+        pixels.extend_from_slice(p.line(y));
+        pixels.extend(p.line(y).iter().map(|&c| dark(c)));
+    }
+    Picture::new(p.width, p.height * 2, pixels)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum NtscSignal {
+    Composite,
+    Rf,
+}
+
+impl NtscSignal {
+    /// Luma filter taps. Three samples cover an entire number of
+    /// subcarrier cycles, so averaging them cancels out the chroma.
+    /// RF has less bandwidth, so it is filtered twice.
+    fn luma_taps(&self) -> &'static [f32] {
+        match self {
+            Self::Composite => &[1.0, 1.0, 1.0],
+            Self::Rf => &[1.0, 2.0, 3.0, 2.0, 1.0],
+        }
+    }
+
+    /// Width of the chroma demodulation window, in samples
+    fn chroma_width(&self) -> isize {
+        match self {
+            Self::Composite => 3,
+            Self::Rf => 6,
+        }
+    }
+
+    /// Amplitude of the noise in the signal
+    fn noise(&self) -> f32 {
+        match self {
+            Self::Composite => 0.0,
+            Self::Rf => 0.02,
+        }
+    }
+}
+
+/// Deterministic noise in the range -1.0 - 1.0
+fn noise(x: usize, y: usize) -> f32 {
+    let mut h = (x as u32).wrapping_mul(0x9E37_79B9) ^ (y as u32).wrapping_mul(0x85EB_CA6B);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2C1B_3C6D);
+    h ^= h >> 12;
+    (h & 0xFFFF) as f32 / 32768.0 - 1.0
+}
+
+/// Simulates the artifacts of the NTSC video signal: the picture is
+/// encoded into a composite signal, with chroma modulated onto the color
+/// subcarrier, and decoded again with limited bandwidth. Luma and chroma
+/// partially bleed into each other, causing color fringes at sharp
+/// edges (like blargg's snes_ntsc).
+fn ntsc(p: &Picture, signal: NtscSignal) -> Picture {
+    // The phase step depends on the horizontal resolution of the picture
+    let pixel_phase = (NTSC_PIXEL_PHASE * 256.0 / p.width as f32).to_radians();
+    let line_phase = NTSC_LINE_PHASE.to_radians();
+    let mut pixels = Vec::with_capacity(p.pixels.len());
+
+    for y in 0..p.height {
+        // Subcarrier (cos, sin) for every sample of the line
+        let carrier: Vec<(f32, f32)> = (-NTSC_MARGIN..(p.width as isize + NTSC_MARGIN))
+            .map(|x| (x as f32 * pixel_phase + y as f32 * line_phase).sin_cos())
+            .map(|(sin, cos)| (cos, sin))
+            .collect();
+        let carrier_at = |x: isize| carrier[(x + NTSC_MARGIN) as usize];
+
+        // Encode, extending the line at both ends with its edge pixels
+        let composite: Vec<f32> = (-NTSC_MARGIN..(p.width as isize + NTSC_MARGIN))
+            .map(|x| {
+                let (r, g, b) = p.get_clamped(x, y as isize);
+                let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+                let luma = 0.299 * r + 0.587 * g + 0.114 * b;
+                let i = 0.596 * r - 0.274 * g - 0.322 * b;
+                let q = 0.211 * r - 0.523 * g + 0.312 * b;
+                let (cos, sin) = carrier_at(x);
+                let noise = signal.noise() * noise(x.max(0) as usize, y);
+                luma + i * cos + q * sin + noise
+            })
+            .collect();
+        let sample = |x: isize| composite[(x + NTSC_MARGIN) as usize];
+
+        // Decode
+        let luma_taps = signal.luma_taps();
+        let luma_center = (luma_taps.len() / 2) as isize;
+        let luma_total: f32 = luma_taps.iter().sum();
+        let chroma_width = signal.chroma_width();
+        for x in 0..(p.width as isize) {
+            let luma = luma_taps
+                .iter()
+                .enumerate()
+                .map(|(i, t)| t * sample(x + i as isize - luma_center))
+                .sum::<f32>()
+                / luma_total;
+
+            let (mut i, mut q) = (0.0, 0.0);
+            for n in (x - chroma_width / 2)..(x - chroma_width / 2 + chroma_width) {
+                let chroma = sample(n) - luma;
+                let (cos, sin) = carrier_at(n);
+                i += chroma * cos;
+                q += chroma * sin;
+            }
+            let (i, q) = (i * 2.0 / chroma_width as f32, q * 2.0 / chroma_width as f32);
+
+            let to_u8 = |v: f32| (v * 255.0).round().clamp(0.0, 255.0) as u8;
+            pixels.push((
+                to_u8(luma + 0.956 * i + 0.621 * q),
+                to_u8(luma - 0.272 * i - 0.647 * q),
+                to_u8(luma - 1.106 * i + 1.703 * q),
+            ));
+        }
+    }
+    Picture::new(p.width, p.height, pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const R: Color = (0xFF, 0, 0);
+    const K: Color = (0, 0, 0);
+
+    fn picture(width: usize, height: usize, f: impl Fn(usize, usize) -> Color) -> Picture {
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| f(x, y))
+            .collect();
+        Picture::new(width, height, pixels)
+    }
+
+    /// A red triangle below the diagonal
+    fn diagonal(size: usize) -> Picture {
+        picture(size, size, |x, y| if x <= y { R } else { K })
+    }
+
+    #[test]
+    fn displaybuffer_roundtrip() {
+        let p = picture(4, 2, |x, y| (x as u8, y as u8, 0x80));
+        let buffer = super::super::new_displaybuffer(4, 3);
+        for (i, b) in p.to_bgrx().into_iter().enumerate() {
+            buffer[i].store(b, Ordering::Release);
+        }
+        assert_eq!(Picture::from_displaybuffer(&buffer, 4, 2), p);
+    }
+
+    #[test]
+    fn to_native() {
+        let p = diagonal(4);
+        let doubled = nearest(&p, 2);
+        assert_eq!(doubled.get_doubling(), (2, 2));
+        assert_eq!(doubled.to_native(), p);
+        assert_eq!(p.get_doubling(), (1, 1));
+        assert_eq!(p.to_native(), p);
+
+        // Only doubled vertically (high-res)
+        let hires = picture(4, 4, |x, y| if x == y / 2 { R } else { K });
+        assert_eq!(hires.get_doubling(), (1, 2));
+        assert_eq!(hires.to_native().height, 2);
+    }
+
+    #[test]
+    fn png() {
+        let p = diagonal(4);
+        let mut out = vec![];
+        p.write_png(&mut out).unwrap();
+
+        let decoder = png::Decoder::new(out.as_slice());
+        let mut reader = decoder.read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).unwrap();
+        assert_eq!((info.width, info.height), (4, 4));
+        assert_eq!(info.color_type, png::ColorType::Rgb);
+        assert_eq!(&data[..6], &[0xFF, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn nearest_scale() {
+        let p = diagonal(4);
+        let out = nearest(&p, 3);
+        assert_eq!((out.width, out.height), (12, 12));
+        for y in 0..12 {
+            for x in 0..12 {
+                assert_eq!(out.get(x, y), p.get(x / 3, y / 3));
+            }
+        }
+    }
+
+    #[test]
+    fn scale2x_diagonal() {
+        let out = scale2x(&diagonal(4));
+        assert_eq!((out.width, out.height), (8, 8));
+        // The staircase is smoothed into a straight diagonal
+        assert_eq!(out.get(2, 2), R);
+        assert_eq!(out.get(3, 2), K);
+        assert_eq!(out.get(2, 3), R);
+        // Nearest would give the (black) pixel above the edge
+        assert_eq!(out.get(2, 1), R);
+        assert_eq!(nearest(&diagonal(4), 2).get(2, 1), K);
+
+        // Flat areas are unchanged
+        let flat = picture(3, 3, |_, _| R);
+        assert_eq!(scale2x(&flat), nearest(&flat, 2));
+    }
+
+    #[test]
+    fn scale3x_diagonal() {
+        let out = scale3x(&diagonal(4));
+        assert_eq!((out.width, out.height), (12, 12));
+        assert_eq!(out.get(3, 2), R);
+        assert_eq!(out.get(5, 3), K);
+        let flat = picture(3, 3, |_, _| R);
+        assert_eq!(scale3x(&flat), nearest(&flat, 3));
+    }
+
+    #[test]
+    fn hq2x_diagonal() {
+        let out = hq2x(&diagonal(4));
+        assert_eq!((out.width, out.height), (8, 8));
+        // The edge is interpolated
+        assert_eq!(out.get(2, 1), (0x7F, 0, 0));
+        // Flat areas are unchanged
+        assert_eq!(out.get(0, 7), R);
+        assert_eq!(out.get(7, 0), K);
+        let flat = picture(3, 3, |_, _| R);
+        assert_eq!(hq2x(&flat), nearest(&flat, 2));
+    }
+
+    #[test]
+    fn scanlines_darken() {
+        let p = picture(2, 2, |_, _| (200, 100, 50));
+        let out = scanlines(&p);
+        assert_eq!((out.width, out.height), (2, 4));
+        assert_eq!(out.get(0, 0), (200, 100, 50));
+        assert_eq!(out.get(0, 1), (120, 60, 30));
+        assert_eq!(out.get(1, 2), (200, 100, 50));
+    }
+
+    #[test]
+    fn ntsc_flat() {
+        // Flat colors survive the signal, apart from rounding
+        for color in [(0x80, 0x80, 0x80), (0xFF, 0xFF, 0xFF), (0x20, 0x80, 0xC0)] {
+            let p = picture(16, 2, |_, _| color);
+            let out = ntsc(&p, NtscSignal::Composite);
+            assert_eq!((out.width, out.height), (16, 2));
+            for &(r, g, b) in &out.pixels {
+                assert!((i32::from(r) - i32::from(color.0)).abs() <= 2);
+                assert!((i32::from(g) - i32::from(color.1)).abs() <= 2);
+                assert!((i32::from(b) - i32::from(color.2)).abs() <= 2);
+            }
+        }
+    }
+
+    #[test]
+    fn ntsc_artifacts() {
+        // Sharp luma edges produce color fringes
+        let p = picture(16, 1, |x, _| if x < 8 { (0xFF, 0xFF, 0xFF) } else { K });
+        let out = ntsc(&p, NtscSignal::Composite);
+        let (r, g, b) = out.get(8, 0);
+        assert!(r != g || g != b);
+
+        // RF is noisy, but deterministic
+        let flat = picture(16, 2, |_, _| (0x80, 0x80, 0x80));
+        assert_ne!(
+            ntsc(&flat, NtscSignal::Rf),
+            ntsc(&flat, NtscSignal::Composite)
+        );
+        assert_eq!(ntsc(&flat, NtscSignal::Rf), ntsc(&flat, NtscSignal::Rf));
+    }
+
+    #[test]
+    fn chain() {
+        let p = nearest(&diagonal(4), 2);
+        let chain = FilterChain::new(&[Filter::Scale2x, Filter::Scanlines]);
+        let out = chain.apply(&p);
+        assert_eq!((out.width, out.height), (8, 16));
+        assert_eq!(FilterChain::default().apply(&p), diagonal(4));
+    }
+}
@@ -26,7 +26,8 @@ impl Gif {
     }
 
     pub fn add(&mut self, frame: &DisplayBuffer) -> Result<()> {
-        let mut rframe: Vec<u8> = frame
+        // Frames may be larger than the recording, crop them
+        let mut rframe: Vec<u8> = frame[..(self.width * self.height * 4)]
             .chunks_exact(4)
             .flat_map(|a| {
                 [
@@ -1,5 +1,8 @@
 pub mod channel;
+pub mod filter;
 pub mod gif;
+pub mod pacing;
+pub mod recorder;
 pub mod sdl;
 pub mod test;
 
@@ -21,6 +24,19 @@ fn new_displaybuffer(width: usize, height: usize) -> DisplayBuffer {
 /// RGB888 format
 pub type Color = (u8, u8, u8);
 
+/// Conversion of the SNES' RGB555 colors to host colors
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone, clap::ValueEnum)]
+pub enum ColorProfile {
+    /// 5-bit intensities shifted to 8-bit, peaking at 0xF8
+    #[default]
+    Linear,
+    /// 5-bit intensities expanded to the full 8-bit range by
+    /// replicating the upper bits into the lower bits
+    BitReplicate,
+    /// Gamma corrected to resemble a CRT television
+    Crt,
+}
+
 pub trait Renderer {
     /// Creates a new renderer with a screen of the given size
     fn new(width: usize, height: usize) -> Result<Self>
@@ -32,6 +48,15 @@ pub trait Renderer {
 
     /// Gets a reference to the (lockable) back buffer
     fn get_buffer(&mut self) -> DisplayBuffer;
+
+    /// Sets the height of the picture in the back buffer, which
+    /// may be less than the height of the buffer itself.
+    fn set_active_height(&mut self, height: usize);
+
+    /// Color profile the picture should be produced in
+    fn get_color_profile(&self) -> ColorProfile {
+        ColorProfile::default()
+    }
 }
 
 pub struct NullRenderer {}
@@ -47,4 +72,6 @@ impl Renderer for NullRenderer {
     fn get_buffer(&mut self) -> DisplayBuffer {
         unreachable!()
     }
+
+    fn set_active_height(&mut self, _height: usize) {}
 }
@@ -0,0 +1,276 @@
+//! Frame pacing, which runs the emulation at the speed of the emulated
+//! system. The emulation core itself runs as fast as it is ticked.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::snes::cartridge::VideoFormat;
+
+/// Strategy to pace the emulation with
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone, clap::ValueEnum)]
+pub enum PacingMode {
+    /// Sleep until the next frame is due
+    #[default]
+    Sleep,
+    /// Wait for each frame to be presented, in sync with the
+    /// refresh rate of the display
+    Vsync,
+    /// Keep the audio buffer filled to a target level
+    Audio,
+}
+
+/// Emulated frame rate, as a fraction (rate / scale frames per second)
+pub fn frame_rate(videoformat: VideoFormat) -> (u64, u64) {
+    (
+        videoformat.master_clock() as u64,
+        videoformat.frame_cycles() as u64,
+    )
+}
+
+/// Paces frames by sleeping until the next frame is due.
+pub struct FramePacer {
+    frametime: Duration,
+    next: Instant,
+}
+
+impl FramePacer {
+    /// Amount of frames emulation may fall behind before pacing
+    /// gives up catching up.
+    const MAX_LAG_FRAMES: u32 = 4;
+
+    /// Paces at the frame rate of the emulated system
+    pub fn new(videoformat: VideoFormat) -> Self {
+        let (rate, scale) = frame_rate(videoformat);
+        Self::with_frametime(Duration::from_nanos(scale * 1_000_000_000 / rate))
+    }
+
+    /// Paces at the specified frame rate
+    pub fn with_fps(fps: u64) -> Self {
+        Self::with_frametime(Duration::from_nanos(1_000_000_000 / fps))
+    }
+
+    fn with_frametime(frametime: Duration) -> Self {
+        Self {
+            frametime,
+            next: Instant::now() + frametime,
+        }
+    }
+
+    pub fn get_frametime(&self) -> Duration {
+        self.frametime
+    }
+
+    /// Waits until the next frame is due. Deadlines are absolute, so
+    /// sleep inaccuracy does not accumulate.
+    pub fn wait(&mut self) {
+        let now = Instant::now();
+        if self.next > now {
+            sleep(self.next - now);
+        } else if now - self.next > self.frametime * Self::MAX_LAG_FRAMES {
+            // Too far behind (e.g. paused by the debugger), start over
+            self.next = now;
+        }
+        self.next += self.frametime;
+    }
+}
+
+/// Counts the audio samples the emulated system produces per frame.
+/// Frames do not span a whole number of samples, so counts alternate
+/// such that they do not drift from the frame rate over time.
+pub struct FrameSamples {
+    videoformat: VideoFormat,
+    sample_rate: u64,
+    /// Frames and samples (per channel) counted in total
+    frames: u64,
+    samples: u64,
+}
+
+impl FrameSamples {
+    pub fn new(videoformat: VideoFormat, sample_rate: u32) -> Self {
+        Self {
+            videoformat,
+            sample_rate: u64::from(sample_rate),
+            frames: 0,
+            samples: 0,
+        }
+    }
+
+    /// Amount of samples (per channel) the emulated system produces in
+    /// the next frame.
+    pub fn next_frame(&mut self) -> usize {
+        let (rate, scale) = frame_rate(self.videoformat);
+        self.frames += 1;
+        let total = self.frames * self.sample_rate * scale / rate;
+        let count = total - self.samples;
+        self.samples = total;
+        count as usize
+    }
+}
+
+/// Interleaved audio samples waiting to be played
+pub struct AudioQueue {
+    samples: VecDeque<i16>,
+    channels: usize,
+}
+
+impl AudioQueue {
+    pub fn new(channels: usize) -> Self {
+        Self {
+            samples: VecDeque::new(),
+            channels,
+        }
+    }
+
+    /// Queued samples, per channel
+    pub fn get_level(&self) -> usize {
+        self.samples.len() / self.channels
+    }
+
+    pub fn push(&mut self, samples: &[i16]) {
+        self.samples.extend(samples);
+    }
+
+    /// Fills the output with queued samples. If not enough samples are
+    /// queued, the remainder is filled with silence.
+    pub fn pop(&mut self, out: &mut [i16]) {
+        let count = out.len().min(self.samples.len());
+        for (o, s) in out.iter_mut().zip(self.samples.drain(..count)) {
+            *o = s;
+        }
+        out[count..].fill(0);
+    }
+}
+
+/// Paces frames by the level of the audio output buffer. Emulation waits
+/// while the buffer is above the target level. Audio is resampled slightly
+/// to steer the buffer towards the target level (dynamic rate control), so
+/// differences between the emulated and the audio device clock do not cause
+/// buffer underruns (crackle).
+pub struct AudioPacer {
+    queue: Arc<Mutex<AudioQueue>>,
+    channels: usize,
+    sample_rate: u64,
+
+    /// Target buffer level, in samples per channel
+    target: usize,
+
+    /// Position of the resampler between the last and the next input sample
+    position: f64,
+    /// Last input sample, per channel
+    last: Vec<i16>,
+}
+
+impl AudioPacer {
+    /// Maximum deviation of the resampling ratio
+    const MAX_DEVIATION: f64 = 0.005;
+    /// Target buffer level, in frames
+    const TARGET_FRAMES: u64 = 3;
+
+    pub fn new(
+        queue: Arc<Mutex<AudioQueue>>,
+        videoformat: VideoFormat,
+        sample_rate: u32,
+        channels: usize,
+    ) -> Self {
+        let (rate, scale) = frame_rate(videoformat);
+        let sample_rate = u64::from(sample_rate);
+        Self {
+            queue,
+            channels,
+            sample_rate,
+            target: (sample_rate * scale * Self::TARGET_FRAMES / rate) as usize,
+            position: 0.0,
+            last: vec![0; channels],
+        }
+    }
+
+    /// Resampling ratio (output samples per input sample) for the
+    /// specified buffer level
+    fn get_ratio(&self, level: usize) -> f64 {
+        let fill = (level as f64 / self.target as f64).min(2.0);
+        1.0 + Self::MAX_DEVIATION * (1.0 - fill)
+    }
+
+    /// Adds the audio of a frame to the buffer, resampled by the
+    /// dynamic rate control.
+    pub fn push(&mut self, samples: &[i16]) {
+        let ratio = self.get_ratio(self.queue.lock().unwrap().get_level());
+        let step = 1.0 / ratio;
+
+        let mut out = Vec::with_capacity((samples.len() as f64 * ratio) as usize + self.channels);
+        for frame in samples.chunks_exact(self.channels) {
+            while self.position < 1.0 {
+                for (last, &next) in self.last.iter().zip(frame) {
+                    let v = f64::from(*last) + (f64::from(next) - f64::from(*last)) * self.position;
+                    out.push(v.round() as i16);
+                }
+                self.position += step;
+            }
+            self.position -= 1.0;
+            self.last.copy_from_slice(frame);
+        }
+
+        self.queue.lock().unwrap().push(&out);
+    }
+
+    /// Waits until the buffer drained to the target level
+    pub fn wait(&self) {
+        let poll = Duration::from_secs(1) / self.sample_rate as u32 * 64;
+        while self.queue.lock().unwrap().get_level() > self.target {
+            sleep(poll);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn audio_queue_underrun() {
+        let mut q = AudioQueue::new(2);
+        q.push(&[1, 2, 3, 4]);
+        assert_eq!(q.get_level(), 2);
+
+        let mut out = [0x55; 6];
+        q.pop(&mut out);
+        assert_eq!(out, [1, 2, 3, 4, 0, 0]);
+        assert_eq!(q.get_level(), 0);
+    }
+
+    #[test]
+    fn audio_frame_samples() {
+        let mut s = FrameSamples::new(VideoFormat::NTSC, 32000);
+        let counts: Vec<usize> = (0..60098).map(|_| s.next_frame()).collect();
+        assert!(counts.iter().all(|&c| c == 532 || c == 533));
+
+        // No drift over time
+        let (rate, scale) = frame_rate(VideoFormat::NTSC);
+        let total: usize = counts.iter().sum();
+        assert_eq!(total as u64, 60098 * 32000 * scale / rate);
+    }
+
+    #[test]
+    fn audio_rate_control() {
+        let queue = Arc::new(Mutex::new(AudioQueue::new(2)));
+        let mut p = AudioPacer::new(Arc::clone(&queue), VideoFormat::NTSC, 32000, 2);
+        let input = vec![100; 1000 * 2];
+
+        // Empty buffer: produce more samples to fill it up
+        p.push(&input);
+        let low = queue.lock().unwrap().get_level();
+        assert!(low > 1000 && low <= 1006);
+
+        // Full buffer: produce fewer samples
+        queue.lock().unwrap().push(&vec![0; p.target * 2 * 2]);
+        let before = queue.lock().unwrap().get_level();
+        p.push(&input);
+        let high = queue.lock().unwrap().get_level() - before;
+        assert!((994..1000).contains(&high));
+
+        // At the target level, the ratio is 1
+        assert_eq!(p.get_ratio(p.target), 1.0);
+    }
+}
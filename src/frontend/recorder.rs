@@ -0,0 +1,484 @@
+//! Lossless recording of video and audio to AVI files, with
+//! uncompressed RGB video and PCM audio.
+
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+
+use anyhow::Result;
+
+use super::DisplayBuffer;
+use crate::snes::cartridge::VideoFormat;
+
+/// AVI 1.0 files are limited to 4GB, start a new file before that.
+const MAX_SEGMENT_SIZE: u64 = 0xF000_0000;
+
+/// avih flag: the file has an index
+const AVIF_HASINDEX: u32 = 0x10;
+/// idx1 flag: the chunk is a keyframe
+const AVIIF_KEYFRAME: u32 = 0x10;
+
+const VIDEO_CHUNK: &[u8; 4] = b"00db";
+const AUDIO_CHUNK: &[u8; 4] = b"01wb";
+
+/// Format of the recorded audio (16-bit signed PCM)
+#[derive(Debug, Clone, Copy)]
+pub struct AudioFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl AudioFormat {
+    fn block_align(&self) -> u32 {
+        u32::from(self.channels) * 2
+    }
+}
+
+/// Writes an AVI file with an uncompressed RGB video stream and a
+/// PCM audio stream.
+pub struct AviWriter<W: Write + Seek> {
+    out: W,
+    width: usize,
+    height: usize,
+    audio: AudioFormat,
+
+    /// Position of the 'movi' FOURCC, which idx1 offsets are relative to
+    movi_pos: u64,
+    /// Chunks written, for the index: FOURCC, offset, size
+    index: Vec<([u8; 4], u32, u32)>,
+    frames: u32,
+    audio_blocks: u32,
+
+    // Positions of fields to fill in when finishing the file
+    pos_total_frames: u64,
+    pos_video_length: u64,
+    pos_audio_length: u64,
+}
+
+impl<W: Write + Seek> AviWriter<W> {
+    /// Creates a new AVI file, with a frame rate of `rate / scale`
+    /// frames per second.
+    pub fn new(
+        mut out: W,
+        width: usize,
+        height: usize,
+        rate: u32,
+        scale: u32,
+        audio: AudioFormat,
+    ) -> Result<Self> {
+        let frame_size = Self::frame_size(width, height);
+        let (w, h) = (u32::try_from(width)?, u32::try_from(height)?);
+
+        out.write_all(b"RIFF")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(b"AVI ")?;
+
+        out.write_all(b"LIST")?;
+        out.write_all(
+            &(4 + (8 + 56) + (12 + (8 + 56) + (8 + 40)) + (12 + (8 + 56) + (8 + 16)) as u32)
+                .to_le_bytes(),
+        )?;
+        out.write_all(b"hdrl")?;
+
+        // Main header
+        let pos_total_frames = out.stream_position()? + 8 + 16;
+        Self::write_chunk(
+            &mut out,
+            b"avih",
+            &[
+                (1_000_000u64 * u64::from(scale) / u64::from(rate)) as u32,
+                (u64::from(frame_size) * u64::from(rate) / u64::from(scale)) as u32
+                    + audio.sample_rate * audio.block_align(),
+                0,
+                AVIF_HASINDEX,
+                0, // total frames
+                0,
+                2, // streams
+                frame_size,
+                w,
+                h,
+                0,
+                0,
+                0,
+                0,
+            ],
+        )?;
+
+        // Video stream
+        out.write_all(b"LIST")?;
+        out.write_all(&(4 + (8 + 56) + (8 + 40) as u32).to_le_bytes())?;
+        out.write_all(b"strl")?;
+        let pos_video_length = out.stream_position()? + 8 + 32;
+        Self::write_chunk(
+            &mut out,
+            b"strh",
+            &[
+                u32::from_le_bytes(*b"vids"),
+                u32::from_le_bytes(*b"DIB "),
+                0,
+                0, // priority, language
+                0,
+                scale,
+                rate,
+                0,
+                0, // length
+                frame_size,
+                u32::MAX, // quality: default
+                0,
+                0,             // frame: left, top
+                w | (h << 16), // frame: right, bottom
+            ],
+        )?;
+        // BITMAPINFOHEADER, bottom-up 24-bit RGB
+        Self::write_chunk(
+            &mut out,
+            b"strf",
+            &[40, w, h, 1 | (24 << 16), 0, frame_size, 0, 0, 0, 0],
+        )?;
+
+        // Audio stream
+        out.write_all(b"LIST")?;
+        out.write_all(&(4 + (8 + 56) + (8 + 16) as u32).to_le_bytes())?;
+        out.write_all(b"strl")?;
+        let pos_audio_length = out.stream_position()? + 8 + 32;
+        Self::write_chunk(
+            &mut out,
+            b"strh",
+            &[
+                u32::from_le_bytes(*b"auds"),
+                0,
+                0,
+                0,
+                0,
+                audio.block_align(),
+                audio.sample_rate * audio.block_align(),
+                0,
+                0, // length
+                audio.sample_rate * audio.block_align(),
+                u32::MAX,
+                audio.block_align(),
+                0,
+                0,
+            ],
+        )?;
+        // WAVEFORMAT, PCM
+        Self::write_chunk(
+            &mut out,
+            b"strf",
+            &[
+                1 | (u32::from(audio.channels) << 16),
+                audio.sample_rate,
+                audio.sample_rate * audio.block_align(),
+                audio.block_align() | (16 << 16),
+            ],
+        )?;
+
+        out.write_all(b"LIST")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        let movi_pos = out.stream_position()?;
+        out.write_all(b"movi")?;
+
+        Ok(Self {
+            out,
+            width,
+            height,
+            audio,
+            movi_pos,
+            index: vec![],
+            frames: 0,
+            audio_blocks: 0,
+            pos_total_frames,
+            pos_video_length,
+            pos_audio_length,
+        })
+    }
+
+    /// Size of a frame in bytes, with lines padded to 4 bytes
+    fn frame_size(width: usize, height: usize) -> u32 {
+        (((width * 3 + 3) & !3) * height) as u32
+    }
+
+    fn write_chunk(out: &mut W, fourcc: &[u8; 4], fields: &[u32]) -> Result<()> {
+        out.write_all(fourcc)?;
+        out.write_all(&(fields.len() as u32 * 4).to_le_bytes())?;
+        for f in fields {
+            out.write_all(&f.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn write_data(&mut self, fourcc: &[u8; 4], data: &[u8]) -> Result<()> {
+        let offset = self.out.stream_position()? - self.movi_pos;
+        self.index
+            .push((*fourcc, u32::try_from(offset)?, u32::try_from(data.len())?));
+
+        self.out.write_all(fourcc)?;
+        self.out.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.out.write_all(data)?;
+        if data.len() & 1 != 0 {
+            self.out.write_all(&[0])?;
+        }
+        Ok(())
+    }
+
+    /// Size of the file so far, in bytes
+    pub fn get_size(&mut self) -> Result<u64> {
+        Ok(self.out.stream_position()? + self.index.len() as u64 * 16 + 8)
+    }
+
+    pub fn get_frame_count(&self) -> u32 {
+        self.frames
+    }
+
+    /// Adds a video frame, from a display buffer of the given width
+    pub fn add_frame(&mut self, buffer: &DisplayBuffer, buffer_width: usize) -> Result<()> {
+        let stride = (self.width * 3 + 3) & !3;
+        let mut data = vec![0; stride * self.height];
+
+        // Lines are stored bottom to top, pixels in BGR order
+        for (y, line) in data.chunks_exact_mut(stride).rev().enumerate() {
+            for x in 0..self.width {
+                let src = (y * buffer_width + x) * 4;
+                for c in 0..3 {
+                    line[x * 3 + c] = buffer[src + c].load(Ordering::Acquire);
+                }
+            }
+        }
+        self.write_data(VIDEO_CHUNK, &data)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Adds interleaved audio samples
+    pub fn add_audio(&mut self, samples: &[i16]) -> Result<()> {
+        let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        self.write_data(AUDIO_CHUNK, &data)?;
+        self.audio_blocks += (samples.len() / usize::from(self.audio.channels)) as u32;
+        Ok(())
+    }
+
+    /// Writes the index and completes the headers
+    pub fn finish(mut self) -> Result<W> {
+        let movi_end = self.out.stream_position()?;
+        self.out.write_all(b"idx1")?;
+        self.out
+            .write_all(&(self.index.len() as u32 * 16).to_le_bytes())?;
+        for (fourcc, offset, size) in &self.index {
+            self.out.write_all(fourcc)?;
+            self.out.write_all(&AVIIF_KEYFRAME.to_le_bytes())?;
+            self.out.write_all(&offset.to_le_bytes())?;
+            self.out.write_all(&size.to_le_bytes())?;
+        }
+        let end = self.out.stream_position()?;
+
+        for (pos, val) in [
+            (4, end - 8),
+            (self.movi_pos - 4, movi_end - self.movi_pos),
+            (self.pos_total_frames, self.frames.into()),
+            (self.pos_video_length, self.frames.into()),
+            (self.pos_audio_length, self.audio_blocks.into()),
+        ] {
+            self.out.seek(SeekFrom::Start(pos))?;
+            self.out.write_all(&u32::try_from(val)?.to_le_bytes())?;
+        }
+        self.out.seek(SeekFrom::Start(end))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Records emulator output to AVI files, frame by frame with the audio
+/// the emulated system produced during each frame. Recordings that exceed
+/// the size limit of AVI files are continued in a new file.
+pub struct Recorder {
+    avi: Option<AviWriter<BufWriter<File>>>,
+    filename: PathBuf,
+    segment: usize,
+
+    width: usize,
+    height: usize,
+    videoformat: VideoFormat,
+    audioformat: AudioFormat,
+}
+
+impl Recorder {
+    pub fn new(
+        filename: &Path,
+        width: usize,
+        height: usize,
+        videoformat: VideoFormat,
+        audioformat: AudioFormat,
+    ) -> Result<Self> {
+        let mut rec = Self {
+            avi: None,
+            filename: filename.to_path_buf(),
+            segment: 0,
+            width,
+            height,
+            videoformat,
+            audioformat,
+        };
+        rec.avi = Some(rec.create_segment()?);
+        Ok(rec)
+    }
+
+    /// Frame rate, as a fraction (rate / scale frames per second)
+    pub fn get_frame_rate(videoformat: VideoFormat) -> (u32, u32) {
+        (
+            videoformat.master_clock() as u32,
+            videoformat.frame_cycles() as u32,
+        )
+    }
+
+    fn create_segment(&self) -> Result<AviWriter<BufWriter<File>>> {
+        let filename = if self.segment == 0 {
+            self.filename.clone()
+        } else {
+            let stem = self.filename.file_stem().unwrap_or_default();
+            self.filename
+                .with_file_name(format!("{}_{}.avi", stem.to_string_lossy(), self.segment))
+        };
+        let (rate, scale) = Self::get_frame_rate(self.videoformat);
+        AviWriter::new(
+            BufWriter::new(File::create(filename)?),
+            self.width,
+            self.height,
+            rate,
+            scale,
+            self.audioformat,
+        )
+    }
+
+    /// Adds a video frame, with the interleaved audio samples the
+    /// emulated system produced during it.
+    pub fn add_frame(
+        &mut self,
+        buffer: &DisplayBuffer,
+        buffer_width: usize,
+        samples: &[i16],
+    ) -> Result<()> {
+        let avi = self.avi.as_mut().unwrap();
+        let frame_size = AviWriter::<BufWriter<File>>::frame_size(self.width, self.height);
+        if avi.get_size()? + u64::from(frame_size) * 2 > MAX_SEGMENT_SIZE {
+            self.avi.take().unwrap().finish()?;
+            self.segment += 1;
+            self.avi = Some(self.create_segment()?);
+        }
+
+        let avi = self.avi.as_mut().unwrap();
+        avi.add_frame(buffer, buffer_width)?;
+        avi.add_audio(samples)?;
+        Ok(())
+    }
+
+    /// Completes the recording
+    pub fn finish(mut self) -> Result<()> {
+        self.avi.take().unwrap().finish()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::new_displaybuffer;
+    use crate::frontend::pacing::FrameSamples;
+
+    use std::io::Cursor;
+
+    const AUDIO: AudioFormat = AudioFormat {
+        sample_rate: 32000,
+        channels: 2,
+    };
+
+    fn u32_at(data: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+    }
+
+    fn find(data: &[u8], fourcc: &[u8; 4]) -> usize {
+        data.windows(4).position(|w| w == fourcc).unwrap()
+    }
+
+    #[test]
+    fn avi_structure() {
+        let buffer = new_displaybuffer(4, 2);
+        // Top-left pixel red (BGRX), bottom-right blue
+        buffer[2].store(0xFF, Ordering::Relaxed);
+        buffer[(4 + 2) * 4].store(0xFF, Ordering::Relaxed);
+
+        let (rate, scale) = Recorder::get_frame_rate(VideoFormat::NTSC);
+        let mut avi = AviWriter::new(Cursor::new(vec![]), 3, 2, rate, scale, AUDIO).unwrap();
+        for _ in 0..3 {
+            avi.add_frame(&buffer, 4).unwrap();
+            avi.add_audio(&[1, 2, 3, 4]).unwrap();
+        }
+        let data = avi.finish().unwrap().into_inner();
+
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(u32_at(&data, 4) as usize, data.len() - 8);
+        assert_eq!(&data[8..12], b"AVI ");
+
+        // Main header: 60.0988 Hz, 3 frames
+        let avih = find(&data, b"avih") + 8;
+        assert_eq!(u32_at(&data, avih), 16639);
+        assert_eq!(u32_at(&data, avih + 16), 3);
+        assert_eq!(u32_at(&data, avih + 32), 3);
+        assert_eq!(u32_at(&data, avih + 36), 2);
+
+        // Stream lengths
+        let vids = find(&data, b"vids");
+        assert_eq!(u32_at(&data, vids + 20), scale);
+        assert_eq!(u32_at(&data, vids + 24), rate);
+        assert_eq!(u32_at(&data, vids + 32), 3);
+        let auds = find(&data, b"auds");
+        assert_eq!(u32_at(&data, auds + 32), 6);
+
+        // First video frame, bottom-up, BGR, lines padded to 12 bytes
+        let movi = find(&data, b"movi");
+        assert_eq!(&data[movi + 4..movi + 8], VIDEO_CHUNK);
+        assert_eq!(u32_at(&data, movi + 8), 24);
+        let frame = &data[movi + 12..movi + 36];
+        assert_eq!(frame[..12], [0, 0, 0, 0, 0, 0, 0xFF, 0, 0, 0, 0, 0]);
+        assert_eq!(frame[12..15], [0, 0, 0xFF]);
+
+        // Index
+        let idx1 = find(&data, b"idx1");
+        assert_eq!(u32_at(&data, idx1 + 4), 6 * 16);
+        assert_eq!(&data[idx1 + 8..idx1 + 12], VIDEO_CHUNK);
+        assert_eq!(u32_at(&data, idx1 + 16), 4);
+        assert_eq!(&data[idx1 + 24..idx1 + 28], AUDIO_CHUNK);
+        assert_eq!(u32_at(&data, movi - 4) as usize, idx1 - movi);
+    }
+
+    #[test]
+    fn frame_rate() {
+        let (rate, scale) = Recorder::get_frame_rate(VideoFormat::NTSC);
+        assert_eq!(format!("{:.4}", rate as f64 / scale as f64), "60.0988");
+        let (rate, scale) = Recorder::get_frame_rate(VideoFormat::PAL);
+        assert_eq!(format!("{:.4}", rate as f64 / scale as f64), "50.0070");
+    }
+
+    #[test]
+    fn frame_audio() {
+        let dir = std::env::temp_dir().join("siena_recorder_test.avi");
+        let buffer = new_displaybuffer(2, 2);
+        let mut rec = Recorder::new(&dir, 2, 2, VideoFormat::PAL, AUDIO).unwrap();
+
+        // 1 second, with the audio of each frame written as is
+        let mut frame_samples = FrameSamples::new(VideoFormat::PAL, AUDIO.sample_rate);
+        for _ in 0..50 {
+            let samples = vec![1; frame_samples.next_frame() * 2];
+            rec.add_frame(&buffer, 2, &samples).unwrap();
+        }
+        rec.finish().unwrap();
+
+        let data = std::fs::read(&dir).unwrap();
+        std::fs::remove_file(&dir).unwrap();
+        let auds = find(&data, b"auds");
+        assert_eq!(u32_at(&data, auds + 32), 31995);
+        let movi = find(&data, b"movi");
+        assert_eq!(&data[movi + 4 + 8 + 16..movi + 4 + 8 + 16 + 4], AUDIO_CHUNK);
+        assert_eq!(u32_at(&data, movi + 4 + 8 + 16 + 4), 639 * 4);
+    }
+}
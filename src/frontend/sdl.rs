@@ -9,11 +9,14 @@ use sdl2::audio::AudioDevice;
 use sdl2::audio::AudioSpecDesired;
 use sdl2::event::Event;
 use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
 use sdl2::render::{Canvas, Texture};
 use sdl2::video::Window;
 use sdl2::{EventPump, Sdl};
 
-use super::{new_displaybuffer, DisplayBuffer, Renderer};
+use super::filter::{FilterChain, Picture};
+use super::pacing::AudioQueue;
+use super::{new_displaybuffer, Color, ColorProfile, DisplayBuffer, Renderer};
 
 #[cfg(not(feature = "apu_blargg"))]
 use crate::snes::apu::apu::Apu;
@@ -43,8 +46,16 @@ pub struct SDLRenderer {
     texture: Texture,
     displaybuffer: DisplayBuffer,
     width: usize,
-    #[allow(dead_code)]
     height: usize,
+    active_height: usize,
+    color_profile: ColorProfile,
+
+    filters: FilterChain,
+    /// Texture for filtered pictures, with its size
+    filter_texture: Option<(Texture, usize, usize)>,
+    /// Area of the texture holding the last picture, and its height
+    /// in the display buffer
+    last_picture: (Rect, usize),
 
     fps_count: u64,
     fps_time: Instant,
@@ -53,16 +64,42 @@ pub struct SDLRenderer {
 impl SDLRenderer {
     const BPP: usize = 4;
 
-    pub fn update_from(&mut self, buffer: DisplayBuffer) -> Result<()> {
-        // This is safe because SDL will only read from the transmuted
-        // buffer. Worst case is a garbled display.
-        let sdl_displaybuffer = unsafe { std::mem::transmute::<&[AtomicU8], &[u8]>(&buffer) };
-        self.texture
-            .update(None, &sdl_displaybuffer, self.width * Self::BPP)?;
-        self.canvas.clear();
-        self.canvas
-            .copy(&self.texture, None, None)
-            .map_err(|e| anyhow!(e))?;
+    pub fn get_window_id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    pub fn set_color_profile(&mut self, profile: ColorProfile) {
+        self.color_profile = profile;
+    }
+
+    /// Sets the video filters to apply before presenting
+    pub fn set_filters(&mut self, filters: FilterChain) {
+        self.filters = filters;
+    }
+
+    /// Presents the given buffer, with a picture of `height` lines.
+    /// Pictures shorter than the screen are letterboxed.
+    pub fn update_from(&mut self, buffer: DisplayBuffer, height: usize) -> Result<()> {
+        let height = height.min(self.height);
+        self.last_picture = if self.filters.is_empty() {
+            // This is safe because SDL will only read from the transmuted
+            // buffer. Worst case is a garbled display.
+            let sdl_displaybuffer = unsafe { std::mem::transmute::<&[AtomicU8], &[u8]>(&buffer) };
+            self.texture
+                .update(None, &sdl_displaybuffer, self.width * Self::BPP)?;
+            (Rect::new(0, 0, self.width as u32, height as u32), height)
+        } else {
+            let picture = self
+                .filters
+                .apply(&Picture::from_displaybuffer(&buffer, self.width, height));
+            self.update_filter_texture(&picture)?;
+            (
+                Rect::new(0, 0, picture.width as u32, picture.height as u32),
+                height,
+            )
+        };
+
+        self.draw()?;
         self.canvas.present();
 
         self.fps_count += 1;
@@ -78,11 +115,72 @@ impl SDLRenderer {
 
         Ok(())
     }
-}
 
-impl Renderer for SDLRenderer {
-    /// Creates a new renderer with a screen of the given size
-    fn new(width: usize, height: usize) -> Result<Self> {
+    /// Draws the last picture to the canvas, scaled to the window
+    fn draw(&mut self) -> Result<()> {
+        let (src, height) = self.last_picture;
+        let texture = match &self.filter_texture {
+            Some((texture, _, _)) if !self.filters.is_empty() => texture,
+            _ => &self.texture,
+        };
+
+        let (out_w, out_h) = self.canvas.output_size().map_err(|e| anyhow!(e))?;
+        let dst_h = out_h as usize * height / self.height;
+        let dst = Rect::new(
+            0,
+            ((out_h as usize - dst_h) / 2) as i32,
+            out_w,
+            dst_h as u32,
+        );
+        self.canvas.clear();
+        self.canvas
+            .copy(texture, src, dst)
+            .map_err(|e| anyhow!(e))?;
+        Ok(())
+    }
+
+    /// Captures the last picture as presented: filtered and scaled to
+    /// the size of the window.
+    pub fn screenshot(&mut self) -> Result<Picture> {
+        self.draw()?;
+        let (width, height) = self.canvas.output_size().map_err(|e| anyhow!(e))?;
+        let data = self
+            .canvas
+            .read_pixels(None, PixelFormatEnum::RGB24)
+            .map_err(|e| anyhow!(e))?;
+        let pixels = data.chunks_exact(3).map(|p| (p[0], p[1], p[2])).collect();
+        Ok(Picture::new(width as usize, height as usize, pixels))
+    }
+
+    /// Copies a filtered picture to the filter texture, (re)creating
+    /// it if the size of the picture changed.
+    fn update_filter_texture(&mut self, picture: &Picture) -> Result<()> {
+        if !matches!(self.filter_texture, Some((_, w, h)) if (w, h) == (picture.width, picture.height))
+        {
+            if let Some((texture, _, _)) = self.filter_texture.take() {
+                // Textures are not destroyed on drop with 'unsafe_textures'
+                unsafe { texture.destroy() };
+            }
+            let texture = self.canvas.texture_creator().create_texture_streaming(
+                PixelFormatEnum::RGB888,
+                picture.width.try_into()?,
+                picture.height.try_into()?,
+            )?;
+            self.filter_texture = Some((texture, picture.width, picture.height));
+        }
+
+        let (texture, _, _) = self.filter_texture.as_mut().unwrap();
+        texture.update(None, &picture.to_bgrx(), picture.width * Self::BPP)?;
+        Ok(())
+    }
+
+    /// Creates a new renderer with a screen of the given size, which
+    /// waits for vertical sync when presenting
+    pub fn new_vsync(width: usize, height: usize) -> Result<Self> {
+        Self::create(width, height, true)
+    }
+
+    fn create(width: usize, height: usize, vsync: bool) -> Result<Self> {
         SDL.with(|cell| {
             let sdls = cell.borrow_mut();
             let video_subsystem = sdls.context.video().map_err(|e| anyhow!(e))?;
@@ -95,7 +193,11 @@ impl Renderer for SDLRenderer {
                 .position_centered()
                 .build()?;
 
-            let canvas = window.into_canvas().accelerated().build()?;
+            let mut builder = window.into_canvas().accelerated();
+            if vsync {
+                builder = builder.present_vsync();
+            }
+            let canvas = builder.build()?;
             println!("Rendering driver: {:?}", canvas.info().name);
             let texture_creator = canvas.texture_creator();
             let texture = texture_creator.create_texture_streaming(
@@ -110,19 +212,110 @@ impl Renderer for SDLRenderer {
                 displaybuffer: new_displaybuffer(width, height),
                 width,
                 height,
+                active_height: height,
+                color_profile: ColorProfile::default(),
+                filters: FilterChain::default(),
+                filter_texture: None,
+                last_picture: (Rect::new(0, 0, width as u32, height as u32), height),
                 fps_count: 0,
                 fps_time: Instant::now(),
             })
         })
     }
+}
+
+impl Renderer for SDLRenderer {
+    /// Creates a new renderer with a screen of the given size
+    fn new(width: usize, height: usize) -> Result<Self> {
+        Self::create(width, height, false)
+    }
 
     fn get_buffer(&mut self) -> DisplayBuffer {
         Arc::clone(&self.displaybuffer)
     }
 
+    fn set_active_height(&mut self, height: usize) {
+        self.active_height = height;
+    }
+
+    fn get_color_profile(&self) -> ColorProfile {
+        self.color_profile
+    }
+
     /// Renders changes to screen
     fn update(&mut self) -> Result<()> {
-        self.update_from(Arc::clone(&self.displaybuffer))
+        self.update_from(Arc::clone(&self.displaybuffer), self.active_height)
+    }
+}
+
+/// A window displaying an RGB888 image, such as a debug view
+pub struct SDLImageWindow {
+    canvas: Canvas<Window>,
+    texture: Texture,
+    width: usize,
+    height: usize,
+}
+
+impl SDLImageWindow {
+    /// Largest size at which images are initially shown, in pixels
+    const MAX_SIZE: usize = 1024;
+
+    pub fn new(title: &str, width: usize, height: usize) -> Result<Self> {
+        SDL.with(|cell| {
+            let sdls = cell.borrow_mut();
+            let video_subsystem = sdls.context.video().map_err(|e| anyhow!(e))?;
+
+            // Scale up small images
+            let scale = (Self::MAX_SIZE / 2 / width.max(height)).max(1);
+            let window = video_subsystem
+                .window(
+                    title,
+                    (width * scale).try_into()?,
+                    (height * scale).try_into()?,
+                )
+                .resizable()
+                .build()?;
+
+            let canvas = window.into_canvas().accelerated().build()?;
+            let texture_creator = canvas.texture_creator();
+            let texture = texture_creator.create_texture_streaming(
+                PixelFormatEnum::RGB888,
+                width.try_into()?,
+                height.try_into()?,
+            )?;
+
+            Ok(Self {
+                canvas,
+                texture,
+                width,
+                height,
+            })
+        })
+    }
+
+    pub fn get_window_id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    pub fn get_size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    pub fn set_title(&mut self, title: &str) -> Result<()> {
+        Ok(self.canvas.window_mut().set_title(title)?)
+    }
+
+    /// Displays an image of the size of the window
+    pub fn update(&mut self, pixels: &[Color]) -> Result<()> {
+        let data: Vec<u8> = pixels.iter().flat_map(|&(r, g, b)| [b, g, r, 0]).collect();
+        self.texture
+            .update(None, &data, self.width * SDLRenderer::BPP)?;
+        self.canvas.clear();
+        self.canvas
+            .copy(&self.texture, None, None)
+            .map_err(|e| anyhow!(e))?;
+        self.canvas.present();
+        Ok(())
     }
 }
 
@@ -140,33 +333,69 @@ impl SDLEventPump {
     }
 }
 
+/// Where an audio sink gets its samples from
+enum AudioSource {
+    /// Rendered from the APU as needed
+    Apu(Arc<Mutex<Apu>>),
+    /// Produced in advance by the emulation
+    Queue(Arc<Mutex<AudioQueue>>),
+}
+
 pub struct SDLAudioSink {
-    apu: Arc<Mutex<Apu>>,
+    source: AudioSource,
+    /// Queue to play from instead of the source, while the emulation
+    /// produces the audio (e.g. for recording)
+    redirect: Option<Arc<Mutex<AudioQueue>>>,
 }
 
 impl AudioCallback for SDLAudioSink {
     type Channel = i16;
 
     fn callback(&mut self, out: &mut [i16]) {
-        let mut apu = self.apu.lock().unwrap();
-        apu.render(out);
+        if let Some(queue) = &self.redirect {
+            queue.lock().unwrap().pop(out);
+            return;
+        }
+
+        match &self.source {
+            AudioSource::Apu(apu) => apu.lock().unwrap().render(out),
+            AudioSource::Queue(queue) => queue.lock().unwrap().pop(out),
+        }
     }
 }
 
 impl SDLAudioSink {
-    /// Creates a new audiosink
+    /// Plays audio from the specified queue rather than the source the
+    /// sink was created with, or returns to the source (`None`).
+    pub fn set_redirect(&mut self, redirect: Option<Arc<Mutex<AudioQueue>>>) {
+        self.redirect = redirect;
+    }
+
+    /// Creates a new audiosink, rendering audio from the APU
     pub fn init(apu: Arc<Mutex<Apu>>) -> Result<AudioDevice<SDLAudioSink>> {
+        Self::open(AudioSource::Apu(apu))
+    }
+
+    /// Creates a new audiosink, playing audio from a queue
+    pub fn init_queue(queue: Arc<Mutex<AudioQueue>>) -> Result<AudioDevice<SDLAudioSink>> {
+        Self::open(AudioSource::Queue(queue))
+    }
+
+    fn open(source: AudioSource) -> Result<AudioDevice<SDLAudioSink>> {
         SDL.with(|cell| {
             let sdls = cell.borrow_mut();
             let audio_subsystem = sdls.context.audio().map_err(|e| anyhow!(e))?;
             let spec = AudioSpecDesired {
-                freq: Some(32000),
+                freq: Some(Apu::SAMPLE_RATE as i32),
                 channels: Some(2),
                 samples: Some(128),
             };
 
             let device = audio_subsystem
-                .open_playback(None, &spec, |_spec| Self { apu })
+                .open_playback(None, &spec, |_spec| Self {
+                    source,
+                    redirect: None,
+                })
                 .map_err(|e| anyhow!(e))?;
             device.resume();
             Ok(device)
@@ -15,6 +15,8 @@ use std::sync::Arc;
 pub struct TestRenderer {
     buffer: DisplayBuffer,
     state: TDS,
+    width: usize,
+    active_height: usize,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -40,6 +42,8 @@ impl TestRenderer {
                     iter::repeat_with(|| AtomicU8::new(0)).take(width * height * 4),
                 )),
                 state: Rc::clone(&state),
+                width,
+                active_height: height,
             },
             state,
         )
@@ -56,12 +60,15 @@ impl Renderer for TestRenderer {
     }
 
     fn update(&mut self) -> Result<()> {
+        // Only the active picture is considered
+        let active = &self.buffer[..(self.width * self.active_height * 4)];
+
         let mut hasher = Sha256::new();
         hasher.update(
             // Shuffle them around into the old format, BEFORE
             // everything started using 32-bit RGB, which was just
             // 24-bit R, G, B.
-            self.buffer
+            active
                 .iter()
                 .map(|a| a.load(Ordering::Acquire))
                 .chunks(4)
@@ -78,7 +85,7 @@ impl Renderer for TestRenderer {
             1
         };
 
-        let all_black = self.buffer.iter().all(|c| c.load(Ordering::Acquire) == 0);
+        let all_black = active.iter().all(|c| c.load(Ordering::Acquire) == 0);
 
         self.state.set(TestRendererState {
             hash: hash.into(),
@@ -91,4 +98,8 @@ impl Renderer for TestRenderer {
     fn get_buffer(&mut self) -> DisplayBuffer {
         Arc::clone(&self.buffer)
     }
+
+    fn set_active_height(&mut self, height: usize) {
+        self.active_height = height;
+    }
 }
@@ -1,6 +1,7 @@
 pub mod bus;
 pub mod cpu_65816;
 pub mod cpu_gsu;
+pub mod cpu_hg51b;
 pub mod cpu_sm83;
 pub mod cpu_spc700;
 pub mod cpu_upd77c25;
@@ -1,4 +1,4 @@
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 
 use anyhow::Result;
 use colored::*;
@@ -7,9 +7,11 @@ use serde::{Deserialize, Serialize};
 use crate::bus::{Address, BusMember};
 use crate::cpu_spc700::cpu::{CpuSpc700, SpcAddress};
 use crate::cpu_spc700::regs::Register;
+use crate::snes::coprocessor::msu1::Msu1Audio;
 use crate::tickable::{Tickable, Ticks};
 
 use super::apubus::Apubus;
+use super::spc::{SpcFile, SPC_DSP_REGS, SPC_EXTRA_RAM_SIZE, SPC_RAM_SIZE};
 
 /// Type for the CPU <-> APU communication ports
 pub type ApuPorts = Arc<RwLock<InnerApuPorts>>;
@@ -44,12 +46,19 @@ pub struct Apu {
 
     /// Main CPU communication ports
     pub ports: ApuPorts,
+
+    /// MSU-1 audio to mix into the output
+    #[serde(skip)]
+    msu1: Option<Arc<Mutex<Msu1Audio>>>,
 }
 
 impl Apu {
     const IPL_ENTRYPOINT: SpcAddress = 0xFFC0;
     const IPL_SIZE: usize = 64;
 
+    /// Output sample rate
+    pub const SAMPLE_RATE: u32 = 32000;
+
     pub fn new(ipl: &[u8], verbose: bool) -> Self {
         assert_eq!(ipl.len(), Self::IPL_SIZE);
 
@@ -67,6 +76,7 @@ impl Apu {
             spc_master_credit: 0,
             verbose,
             ports,
+            msu1: None,
         }
     }
 
@@ -75,11 +85,51 @@ impl Apu {
         Arc::clone(&self.ports)
     }
 
+    /// Sets the MSU-1 audio to mix into the output
+    pub fn set_msu1(&mut self, audio: Option<Arc<Mutex<Msu1Audio>>>) {
+        self.msu1 = audio;
+    }
+
+    /// Restores the state of the APU from an SPC file
+    pub fn load_spc(&mut self, spc: &SpcFile) {
+        self.cpu.regs.write(Register::PC, spc.pc);
+        self.cpu.regs.write(Register::A, spc.a.into());
+        self.cpu.regs.write(Register::X, spc.x.into());
+        self.cpu.regs.write(Register::Y, spc.y.into());
+        self.cpu.regs.write(Register::PSW, spc.psw.into());
+        self.cpu.regs.write(Register::SP, spc.sp.into());
+        self.cpu.bus.load_spc(spc);
+        self.spc_cycles_taken = 0;
+        self.spc_master_credit = 0;
+    }
+
+    /// Captures the state of the APU as an SPC file, without tags
+    pub fn save_spc(&self) -> SpcFile {
+        let mut spc = SpcFile {
+            pc: self.cpu.regs.read(Register::PC),
+            a: self.cpu.regs.read8(Register::A),
+            x: self.cpu.regs.read8(Register::X),
+            y: self.cpu.regs.read8(Register::Y),
+            psw: self.cpu.regs.read8(Register::PSW),
+            sp: self.cpu.regs.read8(Register::SP),
+            ram: vec![0; SPC_RAM_SIZE],
+            dsp: [0; SPC_DSP_REGS],
+            extra_ram: [0; SPC_EXTRA_RAM_SIZE],
+            tags: None,
+        };
+        self.cpu.bus.save_spc(&mut spc);
+        spc
+    }
+
     pub fn render(&mut self, out: &mut [i16]) {
         // Stub until DSP is implemented
         for i in 0..out.len() {
             out[i] = 0;
         }
+
+        if let Some(msu1) = &self.msu1 {
+            msu1.lock().unwrap().mix(out, Self::SAMPLE_RATE);
+        }
     }
 }
 
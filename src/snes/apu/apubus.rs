@@ -8,6 +8,7 @@ use crate::cpu_spc700::cpu::{SpcAddress, SPC_ADDRESS_MASK};
 use crate::tickable::{Tickable, Ticks};
 
 use super::apu::ApuPorts;
+use super::spc::{SpcFile, SPC_EXTRA_RAM_SIZE};
 use super::timers::{Timer, APU_TIMERS};
 
 const APU_RAM_SIZE: usize = 64 * 1024;
@@ -59,6 +60,59 @@ impl Apubus {
             dsp_stub: [0; 0x80],
         }
     }
+
+    /// Reads an S-DSP register
+    pub fn read_dsp(&self, reg: u8) -> u8 {
+        self.dsp_stub[usize::from(reg & 0x7F)]
+    }
+
+    /// Restores RAM, I/O registers and the S-DSP from an SPC file
+    pub(super) fn load_spc(&mut self, spc: &SpcFile) {
+        self.ram.copy_from_slice(&spc.ram);
+        self.dsp_stub = spc.dsp;
+
+        // Control register ($00F1)
+        let control = self.ram[0x00F1];
+        self.timers_enabled = control & 0x07;
+        self.rom_mapped = control & (1 << 7) != 0;
+        if self.rom_mapped {
+            // RAM hidden by the IPL ROM is stored separately
+            self.ram[(APU_RAM_SIZE - SPC_EXTRA_RAM_SIZE)..].copy_from_slice(&spc.extra_ram);
+        }
+
+        self.dsp_addr = usize::from(self.ram[0x00F2]);
+        for t in 0..APU_TIMERS {
+            self.timers[t].reset();
+            self.timers[t].set_top(self.ram[0x00FA + t]);
+            self.timers[t].set_cnt(self.ram[0x00FD + t]);
+        }
+
+        // Values the S-CPU wrote to the ports
+        let mut ports = self.ports.write().unwrap();
+        ports.apu.copy_from_slice(&self.ram[0x00F4..0x00F8]);
+    }
+
+    /// Stores RAM, I/O registers and the S-DSP in an SPC file
+    pub(super) fn save_spc(&self, spc: &mut SpcFile) {
+        spc.ram.copy_from_slice(&self.ram);
+        spc.dsp = self.dsp_stub;
+
+        let hidden = APU_RAM_SIZE - SPC_EXTRA_RAM_SIZE;
+        spc.extra_ram.copy_from_slice(&self.ram[hidden..]);
+        if self.rom_mapped {
+            spc.ram[hidden..].copy_from_slice(&self.rom);
+        }
+
+        // I/O registers as the SPC700 reads them
+        spc.ram[0x00F1] = self.timers_enabled | if self.rom_mapped { 1 << 7 } else { 0 };
+        spc.ram[0x00F2] = self.dsp_addr as u8;
+        spc.ram[0x00F3] = self.read_dsp(self.dsp_addr as u8);
+        spc.ram[0x00F4..0x00F8].copy_from_slice(&self.ports.read().unwrap().apu);
+        for t in 0..APU_TIMERS {
+            spc.ram[0x00FA + t] = self.timers[t].get_top();
+            spc.ram[0x00FD + t] = self.timers[t].peek_cnt();
+        }
+    }
 }
 
 impl Bus<SpcAddress> for Apubus {
@@ -1,3 +1,4 @@
 pub mod apu;
 pub mod apubus;
+pub mod spc;
 pub mod timers;
@@ -0,0 +1,418 @@
+//! SPC snapshot files (`.spc`), which contain the state of the APU
+//! while playing a piece of music, with ID666 tags.
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+
+use crate::tickable::Ticks;
+
+use super::apu::Apu;
+use crate::snes::coprocessor::spc7110::rtc::civil_from_days;
+
+const SPC_SIGNATURE: &[u8] = b"SNES-SPC700 Sound File Data v0.30";
+const SPC_FILE_SIZE: usize = 0x10200;
+
+const OFFSET_HAS_ID666: usize = 0x23;
+const OFFSET_REGS: usize = 0x25;
+const OFFSET_ID666: usize = 0x2E;
+const OFFSET_RAM: usize = 0x100;
+const OFFSET_DSP: usize = 0x10100;
+const OFFSET_EXTRA_RAM: usize = 0x101C0;
+
+/// Value at `OFFSET_HAS_ID666` if the file contains ID666 tags
+const HAS_ID666: u8 = 26;
+
+pub const SPC_RAM_SIZE: usize = 64 * 1024;
+pub const SPC_DSP_REGS: usize = 0x80;
+/// Size of the RAM that can be hidden by the IPL ROM
+pub const SPC_EXTRA_RAM_SIZE: usize = 64;
+
+/// ID666 tags, describing the music in an SPC file
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Id666 {
+    pub song_title: String,
+    pub game_title: String,
+    pub dumper: String,
+    pub comments: String,
+    /// Date the file was dumped (MM/DD/YYYY)
+    pub dump_date: String,
+    /// Length of the music before fading out, in seconds
+    pub length: u32,
+    /// Length of the fade out, in milliseconds
+    pub fade: u32,
+    pub artist: String,
+}
+
+impl Id666 {
+    /// Reads a text field, up to the first NUL
+    fn read_str(data: &[u8]) -> String {
+        let end = data.iter().position(|&c| c == 0).unwrap_or(data.len());
+        String::from_utf8_lossy(&data[..end]).trim().to_string()
+    }
+
+    /// Reads a number stored as text
+    fn read_num(data: &[u8]) -> u32 {
+        Self::read_str(data).parse().unwrap_or(0)
+    }
+
+    /// Reads a little endian number
+    fn read_bin(data: &[u8]) -> u32 {
+        data.iter()
+            .rev()
+            .fold(0, |acc, &b| (acc << 8) | u32::from(b))
+    }
+
+    /// Decodes ID666 tags in either the text or binary format. The format
+    /// is not marked in the file, but the text format only contains digits
+    /// in the length fields.
+    fn parse(data: &[u8]) -> Self {
+        let field = |offset: usize, len: usize| &data[(offset - OFFSET_ID666)..][..len];
+        let is_text = field(0xA9, 8).iter().all(|&c| c == 0 || c.is_ascii_digit())
+            && field(0x9E, 11)
+                .iter()
+                .all(|&c| c == 0 || c.is_ascii_digit() || c == b'/');
+
+        let mut tags = Self {
+            song_title: Self::read_str(field(0x2E, 32)),
+            game_title: Self::read_str(field(0x4E, 32)),
+            dumper: Self::read_str(field(0x6E, 16)),
+            comments: Self::read_str(field(0x7E, 32)),
+            ..Default::default()
+        };
+        if is_text {
+            tags.dump_date = Self::read_str(field(0x9E, 11));
+            tags.length = Self::read_num(field(0xA9, 3));
+            tags.fade = Self::read_num(field(0xAC, 5));
+            tags.artist = Self::read_str(field(0xB1, 32));
+        } else {
+            let date = field(0x9E, 4);
+            if date.iter().any(|&b| b != 0) {
+                tags.dump_date = format!(
+                    "{:02}/{:02}/{:04}",
+                    date[1],
+                    date[0],
+                    Self::read_bin(&date[2..4])
+                );
+            }
+            tags.length = Self::read_bin(field(0xA9, 3));
+            tags.fade = Self::read_bin(field(0xAC, 4));
+            tags.artist = Self::read_str(field(0xB0, 32));
+        }
+        tags
+    }
+
+    /// Writes a text field, truncated or padded with NULs
+    fn write_str(data: &mut [u8], s: &str) {
+        let bytes = s.as_bytes();
+        let len = bytes.len().min(data.len());
+        data.fill(0);
+        data[..len].copy_from_slice(&bytes[..len]);
+    }
+
+    /// Encodes the tags in the text format
+    fn write(&self, data: &mut [u8]) {
+        let mut field = |offset: usize, len: usize, s: &str| {
+            Self::write_str(&mut data[(offset - OFFSET_ID666)..][..len], s)
+        };
+        field(0x2E, 32, &self.song_title);
+        field(0x4E, 32, &self.game_title);
+        field(0x6E, 16, &self.dumper);
+        field(0x7E, 32, &self.comments);
+        field(0x9E, 11, &self.dump_date);
+        field(0xA9, 3, &self.length.min(999).to_string());
+        field(0xAC, 5, &self.fade.min(99999).to_string());
+        field(0xB1, 32, &self.artist);
+    }
+
+    /// Formats a date (Unix time, in seconds) as a dump date (MM/DD/YYYY)
+    pub fn format_date(time: i64) -> String {
+        let (year, month, day) = civil_from_days(time.div_euclid(86400));
+        format!("{:02}/{:02}/{:04}", month, day, year)
+    }
+}
+
+/// Contents of an SPC file
+#[derive(Debug, Clone)]
+pub struct SpcFile {
+    pub pc: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub psw: u8,
+    pub sp: u8,
+    /// APU RAM, including the I/O registers at $00F0-$00FF
+    pub ram: Vec<u8>,
+    pub dsp: [u8; SPC_DSP_REGS],
+    /// RAM at $FFC0-$FFFF, which is hidden if the IPL ROM is mapped
+    pub extra_ram: [u8; SPC_EXTRA_RAM_SIZE],
+    pub tags: Option<Id666>,
+}
+
+impl SpcFile {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < SPC_FILE_SIZE || !data.starts_with(SPC_SIGNATURE) {
+            bail!("Not an SPC file");
+        }
+
+        let regs = &data[OFFSET_REGS..];
+        Ok(Self {
+            pc: u16::from_le_bytes([regs[0], regs[1]]),
+            a: regs[2],
+            x: regs[3],
+            y: regs[4],
+            psw: regs[5],
+            sp: regs[6],
+            ram: data[OFFSET_RAM..][..SPC_RAM_SIZE].to_vec(),
+            dsp: data[OFFSET_DSP..][..SPC_DSP_REGS].try_into()?,
+            extra_ram: data[OFFSET_EXTRA_RAM..][..SPC_EXTRA_RAM_SIZE].try_into()?,
+            tags: (data[OFFSET_HAS_ID666] == HAS_ID666)
+                .then(|| Id666::parse(&data[OFFSET_ID666..OFFSET_RAM])),
+        })
+    }
+
+    pub fn load(filename: &Path) -> Result<Self> {
+        Self::parse(&fs::read(filename)?)
+    }
+
+    /// Encodes the SPC file, with tags in the ID666 text format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = vec![0; SPC_FILE_SIZE];
+        data[..SPC_SIGNATURE.len()].copy_from_slice(SPC_SIGNATURE);
+        data[0x21] = 26;
+        data[0x22] = 26;
+        data[OFFSET_HAS_ID666] = if self.tags.is_some() { HAS_ID666 } else { 27 };
+        // Minor version
+        data[0x24] = 30;
+
+        data[OFFSET_REGS..][..2].copy_from_slice(&self.pc.to_le_bytes());
+        data[OFFSET_REGS + 2..][..5].copy_from_slice(&[self.a, self.x, self.y, self.psw, self.sp]);
+        if let Some(tags) = &self.tags {
+            tags.write(&mut data[OFFSET_ID666..OFFSET_RAM]);
+        }
+        data[OFFSET_RAM..][..SPC_RAM_SIZE].copy_from_slice(&self.ram);
+        data[OFFSET_DSP..][..SPC_DSP_REGS].copy_from_slice(&self.dsp);
+        data[OFFSET_EXTRA_RAM..][..SPC_EXTRA_RAM_SIZE].copy_from_slice(&self.extra_ram);
+        data
+    }
+
+    pub fn save(&self, filename: &Path) -> Result<()> {
+        Ok(fs::write(filename, self.to_bytes())?)
+    }
+}
+
+/// Plays an SPC file on the APU, without the rest of the system
+pub struct SpcPlayer {
+    apu: Apu,
+    /// SPC700 cycles run ahead of the rendered audio
+    cycles: Ticks,
+}
+
+impl SpcPlayer {
+    /// SPC700 clock, in Hz
+    const CLOCK: Ticks = 1_024_000;
+
+    pub fn new(ipl: &[u8], spc: &SpcFile) -> Self {
+        let mut apu = Apu::new(ipl, false);
+        apu.load_spc(spc);
+        Self { apu, cycles: 0 }
+    }
+
+    pub fn get_apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    /// Runs the APU for the duration of the output buffer (interleaved
+    /// stereo samples) and renders its audio into it.
+    pub fn render(&mut self, out: &mut [i16]) -> Result<()> {
+        let samples = out.len() / 2;
+        let target = samples * Self::CLOCK / Apu::SAMPLE_RATE as Ticks;
+        while self.cycles < target {
+            self.apu.cpu.step().map(|c| self.cycles += c)?;
+        }
+        self.cycles -= target;
+        self.apu.render(out);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cpu_spc700::regs::Register;
+
+    /// Builds an SPC file running the specified program at $0200
+    fn spc_file(program: &[u8], tags: &[(usize, &[u8])]) -> Vec<u8> {
+        let mut data = vec![0; SPC_FILE_SIZE];
+        data[..SPC_SIGNATURE.len()].copy_from_slice(SPC_SIGNATURE);
+        data[0x21] = 26;
+        data[0x22] = 26;
+        data[OFFSET_HAS_ID666] = HAS_ID666;
+        data[0x24] = 30;
+        data[OFFSET_REGS..][..7].copy_from_slice(&[0x00, 0x02, 0x11, 0x22, 0x33, 0x02, 0xEF]);
+        data[OFFSET_RAM + 0x200..][..program.len()].copy_from_slice(program);
+        for &(offset, value) in tags {
+            data[offset..][..value.len()].copy_from_slice(value);
+        }
+        data
+    }
+
+    #[test]
+    fn parse_regs() {
+        let spc = SpcFile::parse(&spc_file(&[0xAB], &[])).unwrap();
+        assert_eq!(spc.pc, 0x0200);
+        assert_eq!(
+            (spc.a, spc.x, spc.y, spc.psw, spc.sp),
+            (0x11, 0x22, 0x33, 0x02, 0xEF)
+        );
+        assert_eq!(spc.ram[0x200], 0xAB);
+
+        assert!(SpcFile::parse(&[0; SPC_FILE_SIZE]).is_err());
+        assert!(SpcFile::parse(&spc_file(&[], &[])[..0x1000]).is_err());
+    }
+
+    #[test]
+    fn parse_id666_text() {
+        let spc = SpcFile::parse(&spc_file(
+            &[],
+            &[
+                (0x2E, b"Song"),
+                (0x4E, b"Game"),
+                (0x6E, b"Dumper"),
+                (0x9E, b"01/02/1995"),
+                (0xA9, b"180"),
+                (0xAC, b"10000"),
+                (0xB1, b"Artist"),
+            ],
+        ))
+        .unwrap();
+        let tags = spc.tags.unwrap();
+        assert_eq!(tags.song_title, "Song");
+        assert_eq!(tags.game_title, "Game");
+        assert_eq!(tags.dumper, "Dumper");
+        assert_eq!(tags.dump_date, "01/02/1995");
+        assert_eq!(tags.length, 180);
+        assert_eq!(tags.fade, 10000);
+        assert_eq!(tags.artist, "Artist");
+    }
+
+    #[test]
+    fn parse_id666_binary() {
+        let spc = SpcFile::parse(&spc_file(
+            &[],
+            &[
+                (0x2E, b"Song"),
+                (0x9E, &[2, 1, 0xCB, 0x07]),
+                (0xA9, &[0xB4, 0x00, 0x00]),
+                (0xAC, &[0x10, 0x27, 0x00, 0x00]),
+                (0xB0, b"Artist"),
+            ],
+        ))
+        .unwrap();
+        let tags = spc.tags.unwrap();
+        assert_eq!(tags.song_title, "Song");
+        assert_eq!(tags.dump_date, "01/02/1995");
+        assert_eq!(tags.length, 180);
+        assert_eq!(tags.fade, 10000);
+        assert_eq!(tags.artist, "Artist");
+    }
+
+    #[test]
+    fn write_roundtrip() {
+        let data = spc_file(
+            &[0x12, 0x34],
+            &[
+                (0x2E, b"Song"),
+                (0x4E, b"Game"),
+                (0x9E, b"01/02/1995"),
+                (0xA9, b"180"),
+                (0xAC, b"10000"),
+                (0xB1, b"Artist"),
+                (OFFSET_DSP + 0x5C, &[0xAA]),
+                (OFFSET_EXTRA_RAM + 1, &[0xBB]),
+            ],
+        );
+        let spc = SpcFile::parse(&data).unwrap();
+        assert_eq!(spc.to_bytes(), data);
+
+        // Long fields are truncated
+        let mut spc = spc;
+        spc.tags.as_mut().unwrap().song_title = "X".repeat(40);
+        let spc = SpcFile::parse(&spc.to_bytes()).unwrap();
+        assert_eq!(spc.tags.unwrap().song_title, "X".repeat(32));
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(Id666::format_date(0), "01/01/1970");
+        assert_eq!(Id666::format_date(9187 * 86400 + 3600), "02/26/1995");
+    }
+
+    #[test]
+    fn load_into_apu() {
+        // Program: MOV $F4, A ; INC A ; BRA -5
+        let mut data = spc_file(&[0xC4, 0xF4, 0xBC, 0x2F, 0xFB], &[]);
+        // Timer 0 enabled with a target of 2, IPL ROM unmapped
+        data[OFFSET_RAM + 0xF1] = 0x01;
+        data[OFFSET_RAM + 0xFA] = 2;
+        data[OFFSET_RAM + 0xF5] = 0x5A;
+        data[OFFSET_DSP + 0x0C] = 0x7F;
+        data[OFFSET_RAM + 0xFFC0] = 0x88;
+        data[OFFSET_EXTRA_RAM] = 0x99;
+        let spc = SpcFile::parse(&data).unwrap();
+
+        let mut player = SpcPlayer::new(&[0; 64], &spc);
+        let apu = player.get_apu();
+        assert_eq!(apu.cpu.regs.read(Register::PC), 0x0200);
+        assert_eq!(apu.cpu.regs.read(Register::SP), 0xEF);
+        assert_eq!(apu.ports.read().unwrap().apu[1], 0x5A);
+        assert_eq!(apu.cpu.bus.read_dsp(0x0C), 0x7F);
+        // IPL ROM is not mapped, so the extra RAM is not used
+        assert_eq!(apu.cpu.bus.read(0xFFC0), 0x88);
+
+        let mut out = [0; 64];
+        player.render(&mut out).unwrap();
+        let apu = player.get_apu();
+        assert_ne!(apu.ports.read().unwrap().cpu[0], 0);
+        // Timer 0 runs at 8 KHz, ticking its counter every 2 periods
+        let cnt = apu.cpu.bus.read(0xFD);
+        assert!((3..=5).contains(&cnt), "{}", cnt);
+    }
+
+    #[test]
+    fn save_from_apu() {
+        let mut data = spc_file(&[0xC4, 0xF4, 0xBC, 0x2F, 0xFB], &[]);
+        // IPL ROM mapped, hiding the extra RAM
+        data[OFFSET_RAM + 0xF1] = 0x81;
+        data[OFFSET_RAM + 0xF2] = 0x0C;
+        data[OFFSET_RAM + 0xFA] = 2;
+        data[OFFSET_RAM + 0xF5] = 0x5A;
+        data[OFFSET_DSP + 0x0C] = 0x7F;
+        data[OFFSET_EXTRA_RAM] = 0x99;
+        let spc = SpcFile::parse(&data).unwrap();
+
+        let player = SpcPlayer::new(&[0xEE; 64], &spc);
+        let saved = player.get_apu().save_spc();
+        assert_eq!(saved.pc, 0x0200);
+        assert_eq!(
+            (saved.a, saved.x, saved.y, saved.psw, saved.sp),
+            (0x11, 0x22, 0x33, 0x02, 0xEF)
+        );
+        assert_eq!(saved.dsp, spc.dsp);
+        assert_eq!(saved.ram[0xF1], 0x81);
+        assert_eq!(saved.ram[0xF2], 0x0C);
+        assert_eq!(saved.ram[0xF3], 0x7F);
+        assert_eq!(saved.ram[0xF5], 0x5A);
+        assert_eq!(saved.ram[0xFA], 2);
+        assert_eq!(saved.ram[0x200..0x205], spc.ram[0x200..0x205]);
+        // The IPL ROM shows in RAM, the RAM below it is stored separately
+        assert_eq!(saved.ram[0xFFC0], 0xEE);
+        assert_eq!(saved.extra_ram[0], 0x99);
+
+        // Loads back to the same state
+        let player = SpcPlayer::new(&[0xEE; 64], &saved);
+        assert_eq!(player.get_apu().save_spc().to_bytes(), saved.to_bytes());
+    }
+}
@@ -62,6 +62,19 @@ impl Timer {
         self.top = usize::from(top)
     }
 
+    pub fn get_top(&self) -> u8 {
+        self.top as u8
+    }
+
+    pub fn set_cnt(&mut self, cnt: u8) {
+        self.cnt.set(cnt & 0x0F);
+    }
+
+    /// Gets the counter without resetting it
+    pub fn peek_cnt(&self) -> u8 {
+        self.cnt.get()
+    }
+
     pub fn get_cnt(&self) -> u8 {
         let v = self.cnt.get();
         self.cnt.set(0);
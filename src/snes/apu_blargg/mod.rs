@@ -1,10 +1,12 @@
 use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use snes_spc::{SnesSpc, SpcTime};
 
 use crate::bus::{Address, BusMember};
+use crate::snes::coprocessor::msu1::Msu1Audio;
 use crate::tickable::{Tickable, Ticks};
 
 /// The SNES Audio Processing Unit
@@ -15,6 +17,10 @@ pub struct Apu {
     spc: Option<RefCell<SnesSpc>>,
 
     ticks: Ticks,
+
+    /// MSU-1 audio to mix into the output
+    #[serde(skip)]
+    msu1: Option<Arc<Mutex<Msu1Audio>>>,
 }
 
 impl Apu {
@@ -22,6 +28,9 @@ impl Apu {
 
     const IPL_SIZE: usize = 64;
 
+    /// Output sample rate
+    pub const SAMPLE_RATE: u32 = 32000;
+
     pub fn new(ipl: &[u8], _verbose: bool) -> Self {
         assert_eq!(ipl.len(), Self::IPL_SIZE);
 
@@ -31,13 +40,23 @@ impl Apu {
         Self {
             spc: Some(RefCell::new(spc)),
             ticks: 0,
+            msu1: None,
         }
     }
 
+    /// Sets the MSU-1 audio to mix into the output
+    pub fn set_msu1(&mut self, audio: Option<Arc<Mutex<Msu1Audio>>>) {
+        self.msu1 = audio;
+    }
+
     pub fn render(&mut self, out: &mut [i16]) {
         if let Some(spc) = &self.spc {
             spc.borrow_mut().play(out).unwrap();
         }
+
+        if let Some(msu1) = &self.msu1 {
+            msu1.lock().unwrap().mix(out, Self::SAMPLE_RATE);
+        }
     }
 }
 
@@ -0,0 +1,194 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::cartridge::{CoProcessor, Mapper};
+use crate::cpu_gsu::cpu::GsuMap;
+
+/// Board database shipped with the emulator. Its entries are keyed by
+/// header title only for now; checksums of the known SuperFX, SA-1 and
+/// special-board cartridges are still to be added.
+const EMBEDDED_DB: &str = include_str!("boards.json");
+
+/// Description of a cartridge board. All fields except the name
+/// are optional; anything that is not specified is detected
+/// from the cartridge header as usual.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct BoardEntry {
+    /// Name of the game/board
+    pub name: String,
+
+    /// SHA-256 of the ROM (without copier header), as hex string
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+
+    /// CRC32 of the ROM (without copier header), as hex string
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crc32: Option<String>,
+
+    /// Title as in the cartridge header, used if no hashes match
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+
+    /// Board type (mapper)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mapper: Option<Mapper>,
+
+    /// Cartridge (battery backed) RAM size, in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ram_size: Option<usize>,
+
+    /// Co-processor on the board
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coprocessor: Option<CoProcessor>,
+
+    /// SuperFX: GSU memory map
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gsu_map: Option<GsuMap>,
+
+    /// SuperFX: GSU (shared) RAM size, in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gsu_ram_size: Option<usize>,
+}
+
+/// Database of cartridge boards, for games that cannot be
+/// (fully) detected by their header.
+#[derive(Debug, Clone, Default)]
+pub struct BoardDatabase {
+    entries: Vec<BoardEntry>,
+}
+
+impl BoardDatabase {
+    /// Creates an empty database
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the database that is embedded in the emulator
+    pub fn embedded() -> Self {
+        Self::from_json(EMBEDDED_DB).expect("Embedded board database is invalid")
+    }
+
+    /// Parses a database from JSON
+    pub fn from_json(json: &str) -> Result<Self> {
+        let entries: Vec<BoardEntry> = serde_json::from_str(json)?;
+        for e in &entries {
+            for (field, size) in [("ram_size", e.ram_size), ("gsu_ram_size", e.gsu_ram_size)] {
+                if let Some(size) = size.filter(|s| !s.is_power_of_two()) {
+                    bail!(
+                        "Board \"{}\": {} must be a non-zero power of two, got {}",
+                        e.name,
+                        field,
+                        size
+                    );
+                }
+            }
+        }
+        Ok(Self { entries })
+    }
+
+    /// Loads a database from a JSON file
+    pub fn load(filename: &Path) -> Result<Self> {
+        let json = fs::read_to_string(filename)
+            .with_context(|| format!("Failed to read board database {:?}", filename))?;
+        Self::from_json(&json)
+            .with_context(|| format!("Failed to parse board database {:?}", filename))
+    }
+
+    /// Adds the entries of another database. Entries of the other
+    /// database take precedence over existing entries.
+    pub fn merge(&mut self, other: BoardDatabase) {
+        let mut entries = other.entries;
+        entries.append(&mut self.entries);
+        self.entries = entries;
+    }
+
+    pub fn entries(&self) -> &[BoardEntry] {
+        &self.entries
+    }
+
+    /// Finds the board for a ROM (without copier header). Matches
+    /// on SHA-256 first, then CRC32 and finally the header title.
+    pub fn lookup(&self, rom: &[u8], title: &str) -> Option<&BoardEntry> {
+        let sha256 = hex_string(&Sha256::digest(rom));
+        if let Some(e) = self.entries.iter().find(|e| {
+            e.sha256
+                .as_ref()
+                .is_some_and(|h| h.eq_ignore_ascii_case(&sha256))
+        }) {
+            return Some(e);
+        }
+
+        let crc32 = format!("{:08X}", crc32fast::hash(rom));
+        if let Some(e) = self.entries.iter().find(|e| {
+            e.crc32
+                .as_ref()
+                .is_some_and(|h| h.eq_ignore_ascii_case(&crc32))
+        }) {
+            return Some(e);
+        }
+
+        self.entries
+            .iter()
+            .find(|e| e.title.as_ref().is_some_and(|t| t.trim() == title.trim()))
+    }
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn embedded() {
+        let db = BoardDatabase::embedded();
+        let e = db.lookup(&[], "STAR FOX").unwrap();
+        assert_eq!(e.mapper, Some(Mapper::SuperFXMC1));
+        assert_eq!(e.gsu_ram_size, Some(0x8000));
+        assert!(db.lookup(&[], "NOT A GAME").is_none());
+    }
+
+    #[test]
+    fn invalid_ram_size() {
+        for json in [
+            r#"[{ "name": "Test", "ram_size": 0 }]"#,
+            r#"[{ "name": "Test", "ram_size": 3000 }]"#,
+            r#"[{ "name": "Test", "gsu_ram_size": 0 }]"#,
+        ] {
+            assert!(BoardDatabase::from_json(json).is_err());
+        }
+        assert!(BoardDatabase::from_json(r#"[{ "name": "Test", "ram_size": 2048 }]"#).is_ok());
+    }
+
+    #[test]
+    fn lookup_order() {
+        let rom = b"ROM DATA";
+        let mut db = BoardDatabase::from_json(&format!(
+            r#"[
+                {{ "name": "by title", "title": "GAME", "ram_size": 1 }},
+                {{ "name": "by crc", "crc32": "{:08x}", "ram_size": 2 }}
+            ]"#,
+            crc32fast::hash(rom)
+        ))
+        .unwrap();
+        assert_eq!(db.lookup(rom, "GAME").unwrap().name, "by crc");
+        assert_eq!(db.lookup(b"OTHER", "GAME").unwrap().name, "by title");
+
+        db.merge(
+            BoardDatabase::from_json(&format!(
+                r#"[{{ "name": "by sha", "sha256": "{}", "coprocessor": "OBC1" }}]"#,
+                hex_string(&Sha256::digest(rom))
+            ))
+            .unwrap(),
+        );
+        let e = db.lookup(rom, "GAME").unwrap();
+        assert_eq!(e.name, "by sha");
+        assert_eq!(e.coprocessor, Some(CoProcessor::OBC1));
+    }
+}
@@ -9,7 +9,7 @@ use crate::bus::{Address, Bus, BusMember, ADDRESS_MASK};
 use crate::frontend::Renderer;
 use crate::snes::cartridge::{Cartridge, VideoFormat};
 use crate::snes::joypad::{Joypad, JOYPAD_COUNT};
-use crate::snes::ppu::ppu::PPU;
+use crate::snes::ppu::ppu::{RenderMode, PPU};
 use crate::tickable::{Tickable, Ticks};
 
 #[cfg(not(feature = "apu_blargg"))]
@@ -244,11 +244,8 @@ impl<TRenderer> Mainbus<TRenderer>
 where
     TRenderer: Renderer,
 {
-    /// Start of auto joypad read (in PPU cycles)
-    const AUTOJOY_START: Ticks =
-        ((PPU::<TRenderer>::VBLANK_START * PPU::<TRenderer>::CYCLES_PER_SCANLINE) + 34);
-    /// End of auto joypad read (in PPU cycles)
-    const AUTOJOY_END: Ticks = Self::AUTOJOY_START + (4224 / 4);
+    /// Duration of auto joypad read (in PPU cycles)
+    const AUTOJOY_CYCLES: Ticks = 4224 / 4;
 
     pub fn new(
         cartridge: Cartridge,
@@ -257,9 +254,12 @@ where
         joypads: [Joypad; JOYPAD_COUNT],
         apu_ipl: &[u8],
         apu_verbose: bool,
-        fps: u64,
         videoformat: VideoFormat,
+        render_mode: RenderMode,
     ) -> Self {
+        let mut apu = Apu::new(apu_ipl, apu_verbose);
+        apu.set_msu1(cartridge.co_msu1.as_ref().map(|m| m.get_audio()));
+
         Self {
             cartridge,
             wram: vec![0; WRAM_SIZE],
@@ -268,8 +268,8 @@ where
             hdmaen: 0,
             joypads: Some(joypads),
 
-            ppu: PPU::<TRenderer>::new(renderer, fps, videoformat),
-            apu: Arc::new(Mutex::new(Apu::new(apu_ipl, apu_verbose))),
+            ppu: PPU::<TRenderer>::new(renderer, videoformat, render_mode),
+            apu: Arc::new(Mutex::new(apu)),
 
             memsel: 0,
             wmadd: Cell::new(0),
@@ -299,6 +299,12 @@ where
         Arc::clone(&self.apu)
     }
 
+    /// Start of auto joypad read (in PPU cycles), which moves along
+    /// with the start of V-blank.
+    fn autojoy_start(&self) -> Ticks {
+        (self.ppu.get_vblank_start() * PPU::<TRenderer>::CYCLES_PER_SCANLINE) + 34
+    }
+
     fn gdma_run(&mut self, chmask: u8) {
         for ch in 0..DMA_CHANNELS {
             if chmask & (1 << ch) == 0 {
@@ -560,8 +566,8 @@ where
 
                     // Auto joypad read busy
                     if self.nmitimen & (1 << 0) != 0
-                        && self.ppu.get_cycles() >= Self::AUTOJOY_START
-                        && self.ppu.get_cycles() < Self::AUTOJOY_END
+                        && self.ppu.get_cycles() >= self.autojoy_start()
+                        && self.ppu.get_cycles() < self.autojoy_start() + Self::AUTOJOY_CYCLES
                     {
                         result |= 1 << 0;
                     }
@@ -942,7 +948,7 @@ where
         }
 
         // Advance joypad shift register for auto joypad read
-        if self.ppu.get_cycles() >= Self::AUTOJOY_START && self.nmitimen & (1 << 0) != 0 {
+        if self.ppu.get_cycles() >= self.autojoy_start() && self.nmitimen & (1 << 0) != 0 {
             if !self.autojoy_advance {
                 let joypads = self.joypads.as_ref().unwrap();
                 for j in joypads {
@@ -996,8 +1002,8 @@ mod tests {
             joypads,
             &[0; 64],
             false,
-            0,
             VideoFormat::PAL,
+            RenderMode::Scanline,
         )
     }
 
@@ -6,14 +6,26 @@ use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 use serde::{Deserialize, Serialize};
 use strum::Display;
+use thiserror::Error;
 
+use super::boarddb::BoardDatabase;
+use super::copier::{self, CopierHeader};
+use super::coprocessor::bsx::{self, BsxRegion, BSX};
+use super::coprocessor::cx4::Cx4;
 use super::coprocessor::dsp1::DSP1;
+use super::coprocessor::msu1::Msu1;
+use super::coprocessor::obc1::{self, OBC1};
 use super::coprocessor::sa1::SA1;
 use super::coprocessor::sgb::SuperGameboy;
+use super::coprocessor::spc7110::{self, SPC7110};
+use super::coprocessor::sufami::{SufamiSlot, SufamiTurbo};
 use super::coprocessor::superfx::SuperFX;
+use super::coprocessor::BatteryRam;
 
 use crate::bus::{Address, BusMember};
 use crate::cpu_gsu::cpu::GsuMap;
+use crate::cpu_hg51b::cpu as cx4_cpu;
+use crate::tickable::Ticks;
 
 const HDR_TITLE_OFFSET: usize = 0x00;
 const HDR_TITLE_SIZE: usize = 21;
@@ -24,15 +36,118 @@ const HDR_RAMSIZE_OFFSET: usize = 0x18;
 const HDR_DESTINATION_OFFSET: usize = 0x19;
 const HDR_CHECKSUM_OFFSET: usize = 0x1C;
 const HDR_ICHECKSUM_OFFSET: usize = 0x1E;
-const HDR_LEN: usize = 0x1F;
+/// Offset of the (emulation mode) reset vector relative to the header
+const HDR_RESET_VECTOR_OFFSET: usize = 0x3C;
 const RAM_SIZE: usize = 0x20000;
 
+/// Possible header locations: LoROM, HiROM, ExHiROM
+const HEADER_CANDIDATES: [usize; 3] = [0x7FC0, 0xFFC0, 0x40FFC0];
+
+/// Minimum score for a header candidate to be accepted
+const MIN_HEADER_SCORE: i32 = 6;
+
+/// Bonus for a header candidate at the location suggested
+/// by the copier header
+const COPIER_HINT_SCORE: i32 = 2;
+
+/// Errors that can occur while loading a cartridge
+#[derive(Debug, Error)]
+pub enum CartridgeError {
+    #[error("Illogical cartridge file size: 0x{0:08X}")]
+    IllogicalSize(usize),
+    #[error("Could not locate cartridge header, candidates:\n{}", fmt_scores(.0))]
+    NoHeader(Vec<HeaderScore>),
+    #[error("Unknown map mode in header: {0:02X}")]
+    UnknownMapMode(u8),
+    #[error("Unknown chipset in header: {0:02X}")]
+    UnknownChipset(u8),
+    #[error("Unknown SuperFX game \"{0}\" (CRC32 {1:08X}), add it to the board database")]
+    UnknownSuperFX(String, u32),
+    #[error("{0} co-processor requires a ROM, please specify using --corom")]
+    MissingCoRom(&'static str),
+    #[error("{0} co-processor ROM has an invalid size: {1} bytes, expected {2}")]
+    InvalidCoRomSize(&'static str, usize, usize),
+    #[error("Cannot determine mapper for map mode {0:?} with co-processor {1:?}")]
+    UnknownMapper(MapMode, Option<CoProcessor>),
+}
+
+fn fmt_scores(scores: &[HeaderScore]) -> String {
+    scores
+        .iter()
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Score of a possible header location, broken down per heuristic
+/// so the detection can be diagnosed.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct HeaderScore {
+    /// Offset of the header in the ROM image
+    pub offset: usize,
+    /// Checksum and complement validity
+    pub checksum: i32,
+    /// Map mode byte consistency with the header location
+    pub mapmode: i32,
+    /// Reset vector pointing into ROM
+    pub reset_vector: i32,
+    /// Plausibility of the first opcode at the reset vector
+    pub opcode: i32,
+    /// Title is printable ASCII (or JIS X 0201 katakana)
+    pub title: i32,
+}
+
+impl HeaderScore {
+    pub fn total(&self) -> i32 {
+        self.checksum + self.mapmode + self.reset_vector + self.opcode + self.title
+    }
+}
+
+impl fmt::Display for HeaderScore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "0x{:06X}: score {} (checksum {}, map mode {}, reset vector {}, opcode {}, title {})",
+            self.offset,
+            self.total(),
+            self.checksum,
+            self.mapmode,
+            self.reset_vector,
+            self.opcode,
+            self.title
+        )
+    }
+}
+
 #[derive(Copy, Clone, Display, Serialize, Deserialize, clap::ValueEnum)]
 pub enum VideoFormat {
     PAL,
     NTSC,
 }
 
+impl VideoFormat {
+    /// Master clock frequency, in Hz
+    pub fn master_clock(&self) -> Ticks {
+        match self {
+            // 6 * 315/88 MHz
+            VideoFormat::NTSC => 21_477_273,
+            // 4.8 * 4.43361875 MHz
+            VideoFormat::PAL => 21_281_370,
+        }
+    }
+
+    /// Average length of a (non-interlaced) frame, in master cycles.
+    pub fn frame_cycles(&self) -> Ticks {
+        match self {
+            // 262 scanlines of 1364 cycles, with a short scanline
+            // (1360 cycles) every other frame (60.0988 Hz).
+            VideoFormat::NTSC => 262 * 1364 - 2,
+            // 312 scanlines of 1364 cycles (50.0070 Hz).
+            VideoFormat::PAL => 312 * 1364,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq, FromPrimitive)]
 pub enum Chipset {
     RomOnly = 0,
@@ -42,9 +157,10 @@ pub enum Chipset {
     RomRamCo = 4,
     RomRamCoBat = 5,
     RomCoBat = 6,
+    RomRamCoBatRtc = 9,
 }
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, FromPrimitive)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, FromPrimitive, Serialize, Deserialize)]
 pub enum CoProcessor {
     DSPx = 0,
     SuperFX = 1,
@@ -53,6 +169,12 @@ pub enum CoProcessor {
     SDD1 = 4,
     SRTC = 5,
     SuperGameboy = 14,
+    /// Custom chip, see chipset sub-type
+    Custom = 15,
+    /// Capcom Cx4 (custom, sub-type 0x10)
+    Cx4,
+    /// Epson SPC7110 (custom, sub-type 0x00)
+    SPC7110,
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, FromPrimitive)]
@@ -61,6 +183,7 @@ pub enum MapMode {
     HiROM = 1,
     SA1 = 3,
     ExHiROM = 5,
+    SPC7110 = 10,
 }
 
 #[derive(
@@ -85,6 +208,11 @@ pub enum Mapper {
     SuperFX2,
     SA1,
     SuperGameboy,
+    OBC1,
+    Cx4,
+    SPC7110,
+    BSX,
+    SufamiTurbo,
 }
 
 pub fn empty_ram() -> MmapMut {
@@ -116,6 +244,21 @@ pub struct Cartridge {
     /// DSP-1 co-processor
     pub co_dsp1: Option<DSP1>,
 
+    /// OBC1 object controller
+    pub co_obc1: Option<OBC1>,
+
+    /// Cx4 co-processor
+    pub co_cx4: Option<Cx4>,
+
+    /// SPC7110 co-processor
+    pub co_spc7110: Option<SPC7110>,
+
+    /// Satellaview BS-X base cartridge
+    pub co_bsx: Option<BSX>,
+
+    /// Sufami Turbo adapter
+    pub co_sufami: Option<SufamiTurbo>,
+
     /// SuperFX co-processor
     pub co_superfx: Option<SuperFX>,
 
@@ -126,6 +269,10 @@ pub struct Cartridge {
     // TODO serialization
     #[serde(skip)]
     pub co_sgb: Option<SuperGameboy>,
+
+    /// MSU-1 audio/data chip (file backed, moved over on state load)
+    #[serde(skip)]
+    pub co_msu1: Option<Msu1>,
 }
 
 impl Cartridge {
@@ -186,19 +333,49 @@ impl Cartridge {
         (1 << self.rom[self.header_offset + HDR_ROMSIZE_OFFSET]) * 1024
     }
 
+    /// Returns the size of the cartridge RAM, in bytes.
     pub fn get_ram_size(&self) -> usize {
+        if self.ram_mask == 0 {
+            0
+        } else {
+            self.ram_mask + 1
+        }
+    }
+
+    /// Returns the size of the cartridge RAM according to the header.
+    fn get_header_ram_size(&self) -> usize {
         match self.get_chipset() {
             Chipset::RomOnly | Chipset::RomCo | Chipset::RomCoBat => 0,
-            _ => (1 << self.rom[self.header_offset + HDR_RAMSIZE_OFFSET]) * 1024,
+            _ => 1usize
+                .checked_shl(self.rom[self.header_offset + HDR_RAMSIZE_OFFSET].into())
+                .map_or(usize::MAX, |s| s.saturating_mul(1024)),
         }
     }
 
+    /// Chipset sub-type for custom co-processors, which lives in
+    /// the extended header right before the title.
+    fn get_chipset_subtype(&self) -> u8 {
+        self.rom[self.header_offset - 1]
+    }
+
     fn get_coprocessor(&self) -> Option<CoProcessor> {
         match self.get_chipset() {
-            Chipset::RomCo | Chipset::RomRamCo | Chipset::RomRamCoBat | Chipset::RomCoBat => Some(
-                CoProcessor::from_u8(self.rom[self.header_offset + HDR_CHIPSET_OFFSET] >> 4)
-                    .unwrap(),
-            ),
+            Chipset::RomCo
+            | Chipset::RomRamCo
+            | Chipset::RomRamCoBat
+            | Chipset::RomCoBat
+            | Chipset::RomRamCoBatRtc => {
+                match CoProcessor::from_u8(self.rom[self.header_offset + HDR_CHIPSET_OFFSET] >> 4)
+                    .unwrap()
+                {
+                    CoProcessor::Custom => match self.get_chipset_subtype() {
+                        0x00 => Some(CoProcessor::SPC7110),
+                        0x10 => Some(CoProcessor::Cx4),
+                        _ => Some(CoProcessor::Custom),
+                    },
+                    c => Some(c),
+                }
+            }
             _ => None,
         }
     }
@@ -207,6 +384,13 @@ impl Cartridge {
         self.ram_mask != 0
     }
 
+    fn has_battery(&self) -> bool {
+        matches!(
+            self.get_chipset(),
+            Chipset::RomRamBat | Chipset::RomRamCoBat | Chipset::RomCoBat | Chipset::RomRamCoBatRtc
+        )
+    }
+
     pub fn get_video_format(&self) -> VideoFormat {
         match self.rom[self.header_offset + HDR_DESTINATION_OFFSET] {
             0x00 // Japan
@@ -218,14 +402,168 @@ impl Cartridge {
         }
     }
 
-    fn probe_header(hdr: &[u8]) -> bool {
-        let csum1: u16 =
-            (hdr[HDR_CHECKSUM_OFFSET + 0] as u16) | (hdr[HDR_CHECKSUM_OFFSET + 1] as u16) << 8;
-        let csum2: u16 =
-            (hdr[HDR_ICHECKSUM_OFFSET + 0] as u16) | (hdr[HDR_ICHECKSUM_OFFSET + 1] as u16) << 8;
-        return csum1 == (csum2 ^ 0xFFFF)
-            && Self::parse_title(&hdr[HDR_TITLE_OFFSET..(HDR_TITLE_OFFSET + HDR_TITLE_SIZE)])
-                .is_ok();
+    /// Scores a possible header location in a ROM image (without copier header).
+    /// Returns None if the ROM is too small to contain a header at that location.
+    pub fn score_header(rom: &[u8], offset: usize) -> Option<HeaderScore> {
+        if offset + HDR_RESET_VECTOR_OFFSET + 2 > rom.len() {
+            return None;
+        }
+        let hdr = &rom[offset..];
+        let mut score = HeaderScore {
+            offset,
+            checksum: 0,
+            mapmode: 0,
+            reset_vector: 0,
+            opcode: 0,
+            title: 0,
+        };
+
+        // Checksum + complement
+        let csum = u16::from_le_bytes([hdr[HDR_CHECKSUM_OFFSET], hdr[HDR_CHECKSUM_OFFSET + 1]]);
+        let icsum = u16::from_le_bytes([hdr[HDR_ICHECKSUM_OFFSET], hdr[HDR_ICHECKSUM_OFFSET + 1]]);
+        if csum == icsum ^ 0xFFFF {
+            score.checksum += 4;
+            let sum = rom.iter().fold(0u16, |acc, &b| acc.wrapping_add(b as u16));
+            if sum == csum {
+                score.checksum += 2;
+            }
+        }
+
+        // Map mode should match the location of the header
+        let mapmode = hdr[HDR_MAPMODE_OFFSET];
+        if mapmode & 0xE0 == 0x20 {
+            score.mapmode += 1;
+            if matches!(
+                (offset, MapMode::from_u8(mapmode & 0x0F)),
+                (0x7FC0, Some(MapMode::LoROM | MapMode::SA1))
+                    | (0xFFC0, Some(MapMode::HiROM | MapMode::SPC7110))
+                    | (0x40FFC0, Some(MapMode::ExHiROM))
+            ) {
+                score.mapmode += 3;
+            }
+        }
+
+        // Reset vector should point into ROM
+        let reset = u16::from_le_bytes([
+            hdr[HDR_RESET_VECTOR_OFFSET],
+            hdr[HDR_RESET_VECTOR_OFFSET + 1],
+        ]) as usize;
+        if reset >= 0x8000 {
+            score.reset_vector += 3;
+
+            // The first instruction executed should be sensible
+            let bank_base = offset & !0xFFFF;
+            let code_offset = if offset & 0xFFFF == 0x7FC0 {
+                bank_base + (reset - 0x8000)
+            } else {
+                bank_base + reset
+            };
+            score.opcode = match rom.get(code_offset) {
+                // SEI, CLC, SEC, STZ abs, JMP abs, JML long
+                Some(0x78 | 0x18 | 0x38 | 0x9C | 0x4C | 0x5C) => 4,
+                // LDA/LDX/LDY imm, REP, SEP, JSR abs, JSL long, XCE
+                Some(0xA9 | 0xA2 | 0xA0 | 0xC2 | 0xE2 | 0x20 | 0x22 | 0xFB) => 2,
+                // BRK, COP, STP, WDM, SBC long,X
+                Some(0x00 | 0x02 | 0xDB | 0x42 | 0xFF) => -4,
+                _ => 0,
+            };
+        } else {
+            score.reset_vector -= 4;
+        }
+
+        // Title should be readable
+        if hdr[HDR_TITLE_OFFSET..(HDR_TITLE_OFFSET + HDR_TITLE_SIZE)]
+            .iter()
+            .all(|&c| (0x20..=0x7E).contains(&c) || (0xA0..=0xDF).contains(&c) || c == 0)
+        {
+            score.title += 2;
+        }
+
+        Some(score)
+    }
+
+    /// Scores all possible header locations in a ROM image (without copier header).
+    pub fn score_headers(rom: &[u8]) -> Vec<HeaderScore> {
+        HEADER_CANDIDATES
+            .into_iter()
+            .filter_map(|offset| Self::score_header(rom, offset))
+            .collect()
+    }
+
+    /// Finds the most likely header location in a ROM image (without copier header).
+    pub fn detect_header(rom: &[u8]) -> Result<HeaderScore, CartridgeError> {
+        Self::detect_header_hinted(rom, None)
+    }
+
+    /// Finds the most likely header location in a ROM image (without copier header),
+    /// preferring the location suggested by a copier header.
+    pub fn detect_header_hinted(
+        rom: &[u8],
+        hint: Option<usize>,
+    ) -> Result<HeaderScore, CartridgeError> {
+        let scores = Self::score_headers(rom);
+        let total = |s: &HeaderScore| {
+            if Some(s.offset) == hint {
+                s.total() + COPIER_HINT_SCORE
+            } else {
+                s.total()
+            }
+        };
+        // Highest score wins, first candidate on a tie
+        let best = scores.iter().filter(|s| total(s) >= MIN_HEADER_SCORE).fold(
+            None,
+            |best: Option<HeaderScore>, s| match best {
+                Some(b) if total(&b) >= total(s) => Some(b),
+                _ => Some(*s),
+            },
+        );
+        best.ok_or(CartridgeError::NoHeader(scores))
+    }
+
+    /// Detects HiROM images that were dumped interleaved by a copier. These
+    /// have their header at the LoROM location, but with a HiROM map mode, and
+    /// a proper HiROM header after de-interleaving.
+    pub fn is_interleaved(rom: &[u8]) -> bool {
+        if rom.is_empty() || rom.len() & 0xFFFF != 0 {
+            return false;
+        }
+        match Self::detect_header(rom) {
+            Ok(h)
+                if h.offset == 0x7FC0
+                    && MapMode::from_u8(rom[h.offset + HDR_MAPMODE_OFFSET] & 0x0F)
+                        == Some(MapMode::HiROM) => {}
+            _ => return false,
+        }
+        Self::detect_header(&copier::deinterleave(rom)).is_ok_and(|h| h.offset == 0xFFC0)
+    }
+
+    /// Determines the size of a copier header from the file size
+    pub fn copier_header_size(len: usize) -> Result<usize, CartridgeError> {
+        match len % 1024 {
+            0 => Ok(0),
+            0x200 => Ok(0x200),
+            _ => Err(CartridgeError::IllogicalSize(len)),
+        }
+    }
+
+    /// Checks if the fields in the header are understood
+    fn validate_header(&self) -> Result<(), CartridgeError> {
+        let mapmode = self.rom[self.header_offset + HDR_MAPMODE_OFFSET];
+        if MapMode::from_u8(mapmode & 0x0F).is_none() {
+            return Err(CartridgeError::UnknownMapMode(mapmode));
+        }
+        let chipset = self.rom[self.header_offset + HDR_CHIPSET_OFFSET];
+        if chipset & 0x0F != 0x0A && Chipset::from_u8(chipset & 0x0F).is_none() {
+            return Err(CartridgeError::UnknownChipset(chipset));
+        }
+        if !matches!(
+            self.get_chipset(),
+            Chipset::RomOnly | Chipset::RomRam | Chipset::RomRamBat
+        ) && CoProcessor::from_u8(chipset >> 4).is_none()
+        {
+            return Err(CartridgeError::UnknownChipset(chipset));
+        }
+        Ok(())
     }
 
     /// Loads a cartridge.
@@ -236,45 +574,92 @@ impl Cartridge {
 
     /// Loads a cartridge and a save.
     /// Fails if it cannot find the cartridge header.
-    pub fn load_with_save(rom: &[u8], _save: &[u8], co_rom: Option<&[u8]>) -> Result<Self> {
-        let load_offset = match rom.len() % 1024 {
-            0 => 0,
-            0x200 => {
-                println!("Cartridge contains 0x200 bytes of weird header");
-                0x200
-            }
-            _ => panic!("Illogical cartridge file size: 0x{:08X}", rom.len()),
+    pub fn load_with_save(rom: &[u8], save: &[u8], co_rom: Option<&[u8]>) -> Result<Self> {
+        Self::load_with_db(rom, save, co_rom, &BoardDatabase::embedded())
+    }
+
+    /// Loads a cartridge and a save, using the specified board database
+    /// to override settings detected from the header.
+    /// Fails if it cannot find the cartridge header.
+    pub fn load_with_db(
+        rom: &[u8],
+        _save: &[u8],
+        co_rom: Option<&[u8]>,
+        db: &BoardDatabase,
+    ) -> Result<Self> {
+        let load_offset = Self::copier_header_size(rom.len())?;
+        let copier = if load_offset > 0 {
+            let copier = CopierHeader::parse(&rom[..load_offset]);
+            println!("Copier header: {}", copier);
+            if copier.multi_file {
+                println!("WARNING! Image is split into multiple files, only this part is loaded");
+            }
+            Some(copier)
+        } else {
+            None
         };
         let rom = &rom[load_offset..];
 
-        let mut header_offset = None;
-        for possible_offset in [0x7FC0, 0xFFC0] {
-            if (possible_offset + HDR_LEN) > rom.len() {
-                continue;
-            }
-            if Self::probe_header(&rom[possible_offset..]) {
-                println!("Cartridge header at 0x{:06X}", possible_offset);
-                header_offset = Some(possible_offset);
-                break;
-            }
+        let deinterleaved;
+        let rom = if Self::is_interleaved(rom) {
+            println!("Interleaved HiROM image detected, de-interleaving");
+            deinterleaved = copier::deinterleave(rom);
+            &deinterleaved
+        } else {
+            rom
+        };
+
+        for score in Self::score_headers(rom) {
+            println!("Header candidate {}", score);
         }
+        let header = Self::detect_header_hinted(rom, copier.and_then(|c| c.header_offset_hint()))?;
+        println!("Cartridge header at 0x{:06X}", header.offset);
 
         let mut c = Self {
             rom: Vec::from(rom),
             ram: MmapMut::map_anon(RAM_SIZE)?,
-            header_offset: header_offset.expect("Could not locate header"),
+            header_offset: header.offset,
             ram_mask: 0,
             rom_mask: 0,
             co_dsp1: None,
+            co_obc1: None,
+            co_cx4: None,
+            co_spc7110: None,
+            co_bsx: None,
+            co_sufami: None,
+            co_msu1: None,
             co_superfx: None,
             co_sa1: None,
             co_sgb: None,
             mapper: Mapper::LoROM,
         };
 
+        c.validate_header()?;
+
+        let board = db.lookup(rom, &c.get_title());
+        if let Some(board) = board {
+            println!("Board database entry: {}", board.name);
+        }
+        let coprocessor = board
+            .and_then(|b| b.coprocessor)
+            .or_else(|| c.get_coprocessor());
+
         // ROM/RAM masks
-        if c.get_ram_size() > 0 {
-            c.ram_mask = c.get_ram_size() - 1;
+        let header_ram_size = c.get_header_ram_size();
+        let ram_size = match (
+            board.and_then(|b| b.ram_size),
+            copier.and_then(|h| h.sram_size),
+        ) {
+            (Some(size), _) => size,
+            // Implausible size in the cartridge header, go by the copier header
+            (None, Some(size)) if header_ram_size > RAM_SIZE => {
+                println!("Using SRAM size from copier header");
+                size
+            }
+            _ => header_ram_size,
+        };
+        if ram_size > 0 {
+            c.ram_mask = ram_size - 1;
         }
         if c.get_rom_size() != c.rom.len() {
             println!(
@@ -298,32 +683,74 @@ impl Cartridge {
         );
 
         // Detect / initialize co-processor
-        match c.get_coprocessor() {
+        match coprocessor {
             Some(CoProcessor::DSPx) => {
                 println!("DSP-1 co-processor detected");
                 if let Some(rom) = co_rom {
                     c.co_dsp1 = Some(DSP1::new());
                     c.co_dsp1.as_mut().unwrap().load_rom_combined(rom);
                 } else {
-                    panic!("DSP-1 co-processor requires a ROM, please specify using --corom");
+                    return Err(CartridgeError::MissingCoRom("DSP-1").into());
                 }
                 // TODO detect DSP-2, DSP-3, DSP-4
             }
+            Some(CoProcessor::OBC1) => {
+                println!("OBC1 co-processor detected");
+                c.co_obc1 = Some(OBC1::new());
+                // The OBC1 addresses all of its SRAM, regardless of the
+                // size in the header
+                c.ram_mask = c.ram_mask.max(obc1::SRAM_SIZE - 1);
+            }
+            Some(CoProcessor::Cx4) => {
+                println!("Cx4 co-processor detected");
+                if let Some(rom) = co_rom {
+                    if rom.len() != cx4_cpu::DATA_ROM_SIZE {
+                        return Err(CartridgeError::InvalidCoRomSize(
+                            "Cx4",
+                            rom.len(),
+                            cx4_cpu::DATA_ROM_SIZE,
+                        )
+                        .into());
+                    }
+                    c.co_cx4 = Some(Cx4::new(&c.rom, c.rom_mask));
+                    c.co_cx4.as_mut().unwrap().load_data_rom(rom);
+                } else {
+                    return Err(CartridgeError::MissingCoRom("Cx4").into());
+                }
+            }
+            Some(CoProcessor::SPC7110) => {
+                println!("SPC7110 co-processor detected");
+                // Split off the data ROM, which follows the program ROM.
+                // Padding is not applied to the data ROM.
+                let mut data_rom = c.rom.split_off(spc7110::PROGRAM_ROM_SIZE.min(c.rom.len()));
+                data_rom.truncate(rom.len().saturating_sub(spc7110::PROGRAM_ROM_SIZE));
+                c.rom_mask = Self::saturate_mask(c.rom.len() - 1);
+                println!(
+                    "Program ROM: {} KB, data ROM: {} KB",
+                    c.rom.len() / 1024,
+                    data_rom.len() / 1024
+                );
+                c.co_spc7110 = Some(SPC7110::new(
+                    data_rom,
+                    c.get_chipset() == Chipset::RomRamCoBatRtc,
+                ));
+            }
             Some(CoProcessor::SuperFX) => {
                 println!("SuperFX co-processor detected");
-                let (sfx_map, map, ram_mask) = match c.get_title().as_str() {
-                    "DIRT RACER" => (GsuMap::SuperFX1, Mapper::SuperFX1, 0x1FFFF),
-                    "DIRT TRAX FX" => (GsuMap::SuperFX1, Mapper::SuperFX1, 0x1FFFF),
-                    "DOOM" => (GsuMap::SuperFX2, Mapper::SuperFX2, 0xFFFF),
-                    "FX SKIING NINTENDO 96" => (GsuMap::SuperFX2, Mapper::SuperFX2, 0x3FFFF),
-                    "STAR FOX" => (GsuMap::SuperFX1, Mapper::SuperFXMC1, 0x7FFF),
-                    "STARFOX2" => (GsuMap::SuperFX2, Mapper::SuperFX2, 0xFFFF),
-                    "Stunt Race FX" => (GsuMap::SuperFX1, Mapper::SuperFX1, 0xFFFFF),
-                    "SUPER FX TEST" => (GsuMap::SuperFX2, Mapper::SuperFX2, 0xFFFFF),
-                    "VORTEX" => (GsuMap::SuperFX1, Mapper::SuperFX1, 0x7FFF),
-                    "YOSHI'S ISLAND" => (GsuMap::SuperFX2, Mapper::SuperFX2, 0x7FFF),
-                    _ => panic!("Unknown SuperFX game \"{}\"", c.get_title()),
+                let Some((sfx_map, map, ram_size)) = board.and_then(|b| {
+                    Some((
+                        b.gsu_map?,
+                        b.mapper.unwrap_or(Mapper::SuperFX1),
+                        b.gsu_ram_size?,
+                    ))
+                }) else {
+                    return Err(CartridgeError::UnknownSuperFX(
+                        c.get_title(),
+                        crc32fast::hash(rom),
+                    )
+                    .into());
                 };
+                let ram_mask = ram_size - 1;
                 println!(
                     "Cartridge map {:?}, GSU map {:?}, shared RAM mask {:06X}",
                     map, sfx_map, ram_mask
@@ -333,14 +760,14 @@ impl Cartridge {
             }
             Some(CoProcessor::SA1) => {
                 println!("SA-1 co-processor detected");
-                c.co_sa1 = Some(SA1::new(rom, c.rom_mask));
+                c.co_sa1 = Some(SA1::new(rom, c.rom_mask, c.get_ram_size()));
             }
             Some(CoProcessor::SuperGameboy) => {
                 println!("Super Gameboy detected");
                 if let Some(rom) = co_rom {
                     c.co_sgb = Some(SuperGameboy::new(rom)?);
                 } else {
-                    panic!("Please specify Gameboy ROM using --corom");
+                    return Err(CartridgeError::MissingCoRom("Super Gameboy").into());
                 }
             }
             Some(c) => println!("Warning: unimplemented co-processor: {:?}", c),
@@ -348,30 +775,42 @@ impl Cartridge {
         }
 
         // TODO refactor header to its own struct
-        c.mapper = match (c.get_map(), c.get_coprocessor()) {
-            (MapMode::LoROM, None) => Mapper::LoROM,
-            (MapMode::HiROM, None) => Mapper::HiROM,
-            (MapMode::LoROM, Some(CoProcessor::DSPx)) => Mapper::LoROMDSP1,
-            (MapMode::HiROM, Some(CoProcessor::DSPx)) => Mapper::HiROMDSP1,
-            (_, Some(CoProcessor::SuperFX)) => c.mapper,
-            (MapMode::SA1, Some(CoProcessor::SA1)) => Mapper::SA1,
-            (MapMode::LoROM, Some(CoProcessor::SuperGameboy)) => Mapper::SuperGameboy,
-            _ => panic!("Cannot determine mapper"),
+        c.mapper = if let Some(mapper) = board.and_then(|b| b.mapper) {
+            mapper
+        } else {
+            match (c.get_map(), coprocessor) {
+                (MapMode::LoROM, None) => Mapper::LoROM,
+                (MapMode::HiROM, None) => Mapper::HiROM,
+                (MapMode::LoROM, Some(CoProcessor::DSPx)) => Mapper::LoROMDSP1,
+                (MapMode::HiROM, Some(CoProcessor::DSPx)) => Mapper::HiROMDSP1,
+                (_, Some(CoProcessor::SuperFX)) => c.mapper,
+                (MapMode::SA1, Some(CoProcessor::SA1)) => Mapper::SA1,
+                (MapMode::LoROM, Some(CoProcessor::SuperGameboy)) => Mapper::SuperGameboy,
+                (MapMode::LoROM, Some(CoProcessor::OBC1)) => Mapper::OBC1,
+                (MapMode::LoROM, Some(CoProcessor::Cx4)) => Mapper::Cx4,
+                (MapMode::SPC7110, Some(CoProcessor::SPC7110)) => Mapper::SPC7110,
+                (m, co) => return Err(CartridgeError::UnknownMapper(m, co).into()),
+            }
         };
         println!("Selected mapper: {}", c.mapper);
+
+        if c.mapper == Mapper::BSX {
+            println!("Satellaview BS-X cartridge detected");
+            c.co_bsx = Some(BSX::new());
+            c.ram_mask = bsx::SRAM_SIZE - 1;
+        }
         Ok(c)
     }
 
     /// Loads a cartridge but does not do header detection
     pub fn load_nohdr(rom: &[u8], mapper: Mapper) -> Result<Self> {
-        let load_offset = match rom.len() % 1024 {
-            0 => 0,
-            0x200 => {
-                println!("Cartridge contains 0x200 bytes of weird header");
-                0x200
-            }
-            _ => panic!("Illogical cartridge file size: 0x{:08X}", rom.len()),
-        };
+        let load_offset = Self::copier_header_size(rom.len())?;
+        if load_offset > 0 {
+            println!(
+                "Cartridge contains 0x{:X} bytes of weird header",
+                load_offset
+            );
+        }
         let rom = &rom[load_offset..];
         let rom_mask = Self::saturate_mask(rom.len() - 1);
 
@@ -384,6 +823,19 @@ impl Cartridge {
             ram_mask: RAM_SIZE - 1,
             rom_mask,
             co_dsp1: None,
+            co_obc1: None,
+            co_cx4: None,
+            co_spc7110: None,
+            co_bsx: if mapper == Mapper::BSX {
+                Some(BSX::new())
+            } else {
+                None
+            },
+            co_sufami: if mapper == Mapper::SufamiTurbo {
+                Some(SufamiTurbo::default())
+            } else {
+                None
+            },
             co_sa1: None,
             co_superfx: if mapper == Mapper::SuperFX1 {
                 Some(SuperFX::new(rom, GsuMap::SuperFX1, 0x1FFFF))
@@ -391,6 +843,7 @@ impl Cartridge {
                 None
             },
             co_sgb: None,
+            co_msu1: None,
         };
         c.rom.resize(rom_mask + 1, 0xFF);
 
@@ -402,6 +855,34 @@ impl Cartridge {
         Ok(c)
     }
 
+    /// Loads the Sufami Turbo BIOS with cartridges in slot A and/or B
+    pub fn load_sufami(bios: &[u8], slot_a: Option<&[u8]>, slot_b: Option<&[u8]>) -> Result<Self> {
+        let mut c = Self::load_nohdr(bios, Mapper::SufamiTurbo)?;
+        c.ram_mask = 0;
+        if c.rom.len() >= 0x8000 {
+            c.header_offset = 0x7FC0;
+        }
+
+        let load_slot = |name: &str, rom: Option<&[u8]>| -> Result<Option<SufamiSlot>> {
+            let Some(rom) = rom else {
+                return Ok(None);
+            };
+            let slot = SufamiSlot::new(rom)?;
+            println!(
+                "Sufami Turbo slot {}: \"{}\", {} KB SRAM",
+                name,
+                slot.get_title(),
+                slot.battery_ram_size() / 1024
+            );
+            Ok(Some(slot))
+        };
+        c.co_sufami = Some(SufamiTurbo::new(
+            load_slot("A", slot_a)?,
+            load_slot("B", slot_b)?,
+        ));
+        Ok(c)
+    }
+
     /// Creates an empty new cartridge (for tests)
     /// Does not do header detection
     pub fn new_empty() -> Result<Self> {
@@ -413,6 +894,12 @@ impl Cartridge {
             ram_mask: RAM_SIZE - 1,
             rom_mask: usize::MAX,
             co_dsp1: None,
+            co_obc1: None,
+            co_cx4: None,
+            co_spc7110: None,
+            co_bsx: None,
+            co_sufami: None,
+            co_msu1: None,
             co_sa1: None,
             co_superfx: None,
             co_sgb: None,
@@ -785,6 +1272,127 @@ impl Cartridge {
         }
     }
 
+    fn read_obc1(&self, fulladdr: Address) -> Option<u8> {
+        let (bank, addr) = ((fulladdr >> 16) as usize, (fulladdr & 0xFFFF) as usize);
+        match (bank, addr) {
+            // OBC1 (SRAM with register window at $7FF0-$7FF7)
+            (0x00..=0x3F | 0x80..=0xBF, 0x6000..=0x7FFF) => {
+                let obc1 = self.co_obc1.as_ref().unwrap();
+                Some(obc1.read(&self.ram, addr))
+            }
+
+            _ => self.read_lorom(fulladdr),
+        }
+    }
+
+    fn write_obc1(&mut self, fulladdr: Address, val: u8) -> Option<()> {
+        let (bank, addr) = ((fulladdr >> 16) as usize, (fulladdr & 0xFFFF) as usize);
+        match (bank, addr) {
+            // OBC1 (SRAM with register window at $7FF0-$7FF7)
+            (0x00..=0x3F | 0x80..=0xBF, 0x6000..=0x7FFF) => {
+                let obc1 = self.co_obc1.as_mut().unwrap();
+                Some(obc1.write(&mut self.ram, addr, val))
+            }
+
+            _ => self.write_lorom(fulladdr, val),
+        }
+    }
+
+    fn read_cx4(&self, fulladdr: Address) -> Option<u8> {
+        let (bank, addr) = ((fulladdr >> 16) as usize, (fulladdr & 0xFFFF) as usize);
+        match (bank, addr) {
+            // Cx4 data RAM and I/O
+            (0x00..=0x3F | 0x80..=0xBF, 0x6000..=0x7FFF) => {
+                self.co_cx4.as_ref().unwrap().read(fulladdr)
+            }
+
+            _ => self.read_lorom(fulladdr),
+        }
+    }
+
+    fn write_cx4(&mut self, fulladdr: Address, val: u8) -> Option<()> {
+        let (bank, addr) = ((fulladdr >> 16) as usize, (fulladdr & 0xFFFF) as usize);
+        match (bank, addr) {
+            // Cx4 data RAM and I/O
+            (0x00..=0x3F | 0x80..=0xBF, 0x6000..=0x7FFF) => {
+                self.co_cx4.as_mut().unwrap().write(fulladdr, val)
+            }
+
+            _ => self.write_lorom(fulladdr, val),
+        }
+    }
+
+    fn read_spc7110(&self, fulladdr: Address) -> Option<u8> {
+        let (bank, addr) = ((fulladdr >> 16) as usize, (fulladdr & 0xFFFF) as usize);
+        let spc = self.co_spc7110.as_ref().unwrap();
+        match (bank, addr) {
+            // SRAM (if enabled through $4830)
+            (0x00..=0x3F | 0x80..=0xBF, 0x6000..=0x7FFF) => {
+                if self.has_ram() && spc.sram_enabled() {
+                    Some(self.ram[(addr - 0x6000) & self.ram_mask])
+                } else {
+                    None
+                }
+            }
+
+            // Program ROM (HiROM)
+            (0x00..=0x0F | 0x80..=0x8F, 0x8000..=0xFFFF) | (0xC0..=0xCF, _) => {
+                Some(self.rom[((bank & 0x0F) * 0x10000 + addr) & self.rom_mask])
+            }
+
+            // I/O and data ROM
+            _ => spc.read(fulladdr),
+        }
+    }
+
+    fn write_spc7110(&mut self, fulladdr: Address, val: u8) -> Option<()> {
+        let (bank, addr) = ((fulladdr >> 16) as usize, (fulladdr & 0xFFFF) as usize);
+        match (bank, addr) {
+            // SRAM (if enabled through $4830)
+            (0x00..=0x3F | 0x80..=0xBF, 0x6000..=0x7FFF) => {
+                if self.has_ram() && self.co_spc7110.as_ref().unwrap().sram_enabled() {
+                    Some(self.ram[(addr - 0x6000) & self.ram_mask] = val)
+                } else {
+                    None
+                }
+            }
+
+            // I/O
+            _ => self.co_spc7110.as_mut().unwrap().write(fulladdr, val),
+        }
+    }
+
+    fn read_sufami(&self, fulladdr: Address) -> Option<u8> {
+        let (bank, addr) = ((fulladdr >> 16) as usize, (fulladdr & 0xFFFF) as usize);
+        match (bank, addr) {
+            // BIOS
+            (0x00..=0x1F | 0x80..=0x9F, 0x8000..=0xFFFF) => {
+                Some(self.rom[((bank & 0x1F) * 0x8000 + (addr - 0x8000)) & self.rom_mask])
+            }
+
+            // Slots
+            _ => self.co_sufami.as_ref().unwrap().read(fulladdr),
+        }
+    }
+
+    fn read_bsx(&self, fulladdr: Address) -> Option<u8> {
+        let bsx = self.co_bsx.as_ref().unwrap();
+        match bsx.map(fulladdr)? {
+            BsxRegion::Bios(offset) => Some(self.rom[offset & self.rom_mask]),
+            BsxRegion::Sram(offset) => Some(self.ram[offset & self.ram_mask]),
+            _ => bsx.read(fulladdr),
+        }
+    }
+
+    fn write_bsx(&mut self, fulladdr: Address, val: u8) -> Option<()> {
+        let bsx = self.co_bsx.as_mut().unwrap();
+        match bsx.map(fulladdr)? {
+            BsxRegion::Bios(_) => None,
+            BsxRegion::Sram(offset) => Some(self.ram[offset & self.ram_mask] = val),
+            _ => bsx.write(fulladdr, val),
+        }
+    }
+
     pub fn get_int(&mut self) -> bool {
         if let Some(sfx) = self.co_superfx.as_mut() {
             return sfx.get_int();
@@ -792,6 +1400,9 @@ impl Cartridge {
         if let Some(sa1) = self.co_sa1.as_mut() {
             return sa1.get_int();
         }
+        if let Some(cx4) = self.co_cx4.as_ref() {
+            return cx4.get_int();
+        }
 
         false
     }
@@ -799,6 +1410,59 @@ impl Cartridge {
     pub fn set_ram_buffer(&mut self, ram: MmapMut) {
         self.ram = ram;
     }
+
+    /// Sets the current date and time (Unix time, in seconds) for
+    /// co-processors that keep or receive the time (SPC7110 RTC,
+    /// Satellaview receiver).
+    pub fn set_time(&mut self, time: i64) {
+        if let Some(spc) = self.co_spc7110.as_mut() {
+            spc.set_time(time);
+        }
+        if let Some(bsx) = self.co_bsx.as_mut() {
+            bsx.set_time(time);
+        }
+    }
+
+    /// Advances the time of co-processors that keep or receive the
+    /// time by the specified amount of seconds.
+    pub fn advance_time(&mut self, secs: i64) {
+        if let Some(spc) = self.co_spc7110.as_mut() {
+            spc.advance_time(secs);
+        }
+        if let Some(bsx) = self.co_bsx.as_mut() {
+            bsx.advance_time(secs);
+        }
+    }
+
+    /// Returns the co-processor that keeps the battery backed RAM
+    /// of this cartridge, if any. If this returns None, the battery
+    /// backed RAM (if any) is `ram`.
+    pub fn co_battery_ram(&self) -> Option<&dyn BatteryRam> {
+        if !self.has_battery() {
+            return None;
+        }
+        if let Some(sfx) = self.co_superfx.as_ref() {
+            return Some(sfx);
+        }
+        if let Some(sa1) = self.co_sa1.as_ref() {
+            return Some(sa1);
+        }
+        None
+    }
+
+    /// Mutable version of `co_battery_ram()`.
+    pub fn co_battery_ram_mut(&mut self) -> Option<&mut dyn BatteryRam> {
+        if !self.has_battery() {
+            return None;
+        }
+        if let Some(sfx) = self.co_superfx.as_mut() {
+            return Some(sfx);
+        }
+        if let Some(sa1) = self.co_sa1.as_mut() {
+            return Some(sa1);
+        }
+        None
+    }
 }
 
 impl fmt::Display for Cartridge {
@@ -818,6 +1482,10 @@ impl fmt::Display for Cartridge {
 
 impl BusMember<Address> for Cartridge {
     fn read(&self, fulladdr: Address) -> Option<u8> {
+        if let Some(v) = self.co_msu1.as_ref().and_then(|m| m.read(fulladdr)) {
+            return Some(v);
+        }
+
         match self.mapper {
             Mapper::LoROM => self.read_lorom(fulladdr),
             Mapper::HiROM => self.read_hirom(fulladdr),
@@ -828,10 +1496,21 @@ impl BusMember<Address> for Cartridge {
             Mapper::SuperFX2 => self.read_superfx2(fulladdr),
             Mapper::SA1 => self.co_sa1.as_ref().unwrap().read(fulladdr),
             Mapper::SuperGameboy => self.read_sgb(fulladdr),
+            Mapper::OBC1 => self.read_obc1(fulladdr),
+            Mapper::Cx4 => self.read_cx4(fulladdr),
+            Mapper::SPC7110 => self.read_spc7110(fulladdr),
+            Mapper::BSX => self.read_bsx(fulladdr),
+            Mapper::SufamiTurbo => self.read_sufami(fulladdr),
         }
     }
 
     fn write(&mut self, fulladdr: Address, val: u8) -> Option<()> {
+        if let Some(msu1) = self.co_msu1.as_mut() {
+            if msu1.write(fulladdr, val).is_some() {
+                return Some(());
+            }
+        }
+
         match self.mapper {
             Mapper::LoROM => self.write_lorom(fulladdr, val),
             Mapper::HiROM => self.write_hirom(fulladdr, val),
@@ -842,6 +1521,287 @@ impl BusMember<Address> for Cartridge {
             Mapper::SuperFX2 => self.write_superfx2(fulladdr, val),
             Mapper::SA1 => self.co_sa1.as_mut().unwrap().write(fulladdr, val),
             Mapper::SuperGameboy => self.write_sgb(fulladdr, val),
+            Mapper::OBC1 => self.write_obc1(fulladdr, val),
+            Mapper::Cx4 => self.write_cx4(fulladdr, val),
+            Mapper::SPC7110 => self.write_spc7110(fulladdr, val),
+            Mapper::BSX => self.write_bsx(fulladdr, val),
+            Mapper::SufamiTurbo => self.co_sufami.as_mut().unwrap().write(fulladdr, val),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a minimal ROM image with a header at the given offset
+    fn build_rom(size: usize, hdr_offset: usize, mapmode: u8) -> Vec<u8> {
+        let mut rom = vec![0; size];
+        let hdr = &mut rom[hdr_offset..];
+        hdr[..HDR_TITLE_SIZE].copy_from_slice(b"HEADER TEST          ");
+        hdr[HDR_MAPMODE_OFFSET] = mapmode;
+        hdr[HDR_ROMSIZE_OFFSET] = size.ilog2() as u8 - 10;
+        hdr[HDR_DESTINATION_OFFSET] = 0x01;
+        hdr[HDR_RESET_VECTOR_OFFSET..(HDR_RESET_VECTOR_OFFSET + 2)]
+            .copy_from_slice(&0x8000u16.to_le_bytes());
+
+        // SEI at the reset vector
+        if mapmode & 1 == 0 {
+            rom[hdr_offset & !0xFFFF] = 0x78;
+        } else {
+            rom[(hdr_offset & !0xFFFF) + 0x8000] = 0x78;
+        }
+
+        // Fix up the checksum
+        let hdr = &mut rom[hdr_offset..];
+        hdr[HDR_CHECKSUM_OFFSET..(HDR_CHECKSUM_OFFSET + 4)]
+            .copy_from_slice(&[0x00, 0x00, 0xFF, 0xFF]);
+        let sum = rom.iter().fold(0u16, |acc, &b| acc.wrapping_add(b as u16));
+        let hdr = &mut rom[hdr_offset..];
+        hdr[HDR_CHECKSUM_OFFSET..(HDR_CHECKSUM_OFFSET + 2)].copy_from_slice(&sum.to_le_bytes());
+        hdr[HDR_ICHECKSUM_OFFSET..(HDR_ICHECKSUM_OFFSET + 2)]
+            .copy_from_slice(&(sum ^ 0xFFFF).to_le_bytes());
+        rom
+    }
+
+    #[test]
+    fn detect_lorom() {
+        let rom = build_rom(0x20000, 0x7FC0, 0x20);
+        let hdr = Cartridge::detect_header(&rom).unwrap();
+        assert_eq!(hdr.offset, 0x7FC0);
+        assert_eq!(
+            hdr,
+            HeaderScore {
+                offset: 0x7FC0,
+                checksum: 6,
+                mapmode: 4,
+                reset_vector: 3,
+                opcode: 4,
+                title: 2,
+            }
+        );
+
+        let c = Cartridge::load(&rom, None).unwrap();
+        assert_eq!(c.mapper, Mapper::LoROM);
+        assert_eq!(c.get_title(), "HEADER TEST");
+    }
+
+    #[test]
+    fn detect_hirom() {
+        let rom = build_rom(0x20000, 0xFFC0, 0x21);
+        let scores = Cartridge::score_headers(&rom);
+        assert_eq!(scores.len(), 2);
+        assert!(scores[0].total() < MIN_HEADER_SCORE);
+        assert_eq!(Cartridge::detect_header(&rom).unwrap().offset, 0xFFC0);
+
+        let c = Cartridge::load(&rom, None).unwrap();
+        assert_eq!(c.mapper, Mapper::HiROM);
+    }
+
+    #[test]
+    fn copier_header() {
+        let mut rom = vec![0; 0x200];
+        rom.extend(build_rom(0x20000, 0x7FC0, 0x20));
+        let c = Cartridge::load(&rom, None).unwrap();
+        assert_eq!(c.get_title(), "HEADER TEST");
+    }
+
+    #[test]
+    fn copier_sram_hint() {
+        let mut rom = build_rom(0x20000, 0x7FC0, 0x20);
+        rom[0x7FC0 + HDR_CHIPSET_OFFSET] = 0x02;
+        rom[0x7FC0 + HDR_RAMSIZE_OFFSET] = 0x01;
+        let mut swc = vec![0; copier::COPIER_HEADER_SIZE];
+        swc[2] = 1 << 2;
+        swc[8..11].copy_from_slice(&[0xAA, 0xBB, 0x04]);
+        swc.extend(&rom);
+
+        // Plausible cartridge header takes precedence
+        let c = Cartridge::load(&swc, None).unwrap();
+        assert_eq!(c.get_ram_size(), 2 * 1024);
+
+        // Garbage in the cartridge header
+        swc[0x200 + 0x7FC0 + HDR_RAMSIZE_OFFSET] = 0xF0;
+        let c = Cartridge::load(&swc, None).unwrap();
+        assert_eq!(c.get_ram_size(), 8 * 1024);
+        swc[2] = 0;
+        let c = Cartridge::load(&swc, None).unwrap();
+        assert_eq!(c.get_ram_size(), 32 * 1024);
+    }
+
+    #[test]
+    fn obc1_ram_size() {
+        let mut rom = build_rom(0x20000, 0x7FC0, 0x20);
+        rom[0x7FC0 + HDR_CHIPSET_OFFSET] = 0x25;
+        rom[0x7FC0 + HDR_RAMSIZE_OFFSET] = 0x01;
+        let mut c = Cartridge::load(&rom, None).unwrap();
+        assert!(c.co_obc1.is_some());
+        assert_eq!(c.get_ram_size(), 8 * 1024);
+
+        // Register window at the top of SRAM
+        c.set_ram_buffer(MmapMut::map_anon(c.get_ram_size()).unwrap());
+        c.write(0x007FF6, 0x7F);
+        c.write(0x007FF0, 0xAA);
+        assert_eq!(c.read(0x007FF0), Some(0xAA));
+        assert_eq!(c.read(0x007FF6), Some(0x7F));
+    }
+
+    #[test]
+    fn board_override() {
+        let rom = build_rom(0x20000, 0x7FC0, 0x20);
+        let db = BoardDatabase::from_json(
+            r#"[{ "name": "Test", "title": "HEADER TEST", "mapper": "HiROM", "ram_size": 8192 }]"#,
+        )
+        .unwrap();
+        let c = Cartridge::load_with_db(&rom, &[], None, &db).unwrap();
+        assert_eq!(c.mapper, Mapper::HiROM);
+        assert!(c.has_ram());
+        assert_eq!(c.get_ram_size(), 8192);
+
+        let c = Cartridge::load_with_db(&rom, &[], None, &BoardDatabase::new()).unwrap();
+        assert_eq!(c.mapper, Mapper::LoROM);
+        assert_eq!(c.get_ram_size(), 0);
+    }
+
+    #[test]
+    fn superfx_by_checksum() {
+        let mut rom = build_rom(0x20000, 0x7FC0, 0x20);
+        rom[0x7FC0 + HDR_CHIPSET_OFFSET] = 0x13;
+        let crc = crc32fast::hash(&rom);
+
+        let err = Cartridge::load_with_db(&rom, &[], None, &BoardDatabase::new())
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err.downcast::<CartridgeError>(),
+            Ok(CartridgeError::UnknownSuperFX(_, c)) if c == crc
+        ));
+
+        // Matched by checksum, regardless of the title
+        let db = BoardDatabase::from_json(&format!(
+            r#"[{{ "name": "Test", "crc32": "{:08X}", "title": "OTHER TITLE",
+                   "mapper": "SuperFX2", "gsu_map": "SuperFX2", "gsu_ram_size": 65536 }}]"#,
+            crc
+        ))
+        .unwrap();
+        let c = Cartridge::load_with_db(&rom, &[], None, &db).unwrap();
+        assert_eq!(c.mapper, Mapper::SuperFX2);
+    }
+
+    #[test]
+    fn co_battery_ram() {
+        let c = Cartridge::load(&build_rom(0x20000, 0x7FC0, 0x20), None).unwrap();
+        assert!(c.co_battery_ram().is_none());
+
+        let mut rom = build_rom(0x20000, 0x7FC0, 0x20);
+        let db = BoardDatabase::from_json(
+            r#"[{ "name": "Test", "title": "HEADER TEST", "mapper": "SuperFX1",
+                  "gsu_map": "SuperFX1", "gsu_ram_size": 131072 }]"#,
+        )
+        .unwrap();
+
+        // SuperFX without battery
+        rom[0x7FC0 + HDR_CHIPSET_OFFSET] = 0x14;
+        let c = Cartridge::load_with_db(&rom, &[], None, &db).unwrap();
+        assert!(c.co_superfx.is_some());
+        assert!(c.co_battery_ram().is_none());
+
+        // SuperFX with battery
+        rom[0x7FC0 + HDR_CHIPSET_OFFSET] = 0x15;
+        let mut c = Cartridge::load_with_db(&rom, &[], None, &db).unwrap();
+        let bram = c.co_battery_ram_mut().unwrap();
+        assert_eq!(bram.battery_ram_size(), 0x20000);
+        bram.load_battery_ram(&[1, 2, 3]);
+        assert_eq!(c.read(0x700001), Some(2));
+        c.write(0x710000, 0xAA);
+        let save = c.co_battery_ram().unwrap().save_battery_ram();
+        assert_eq!(save.len(), 0x20000);
+        assert_eq!(save[..3], [1, 2, 3]);
+        assert_eq!(save[0x10000], 0xAA);
+    }
+
+    #[test]
+    fn cx4_rom_size() {
+        let mut rom = build_rom(0x20000, 0x7FC0, 0x20);
+        rom[0x7FC0 + HDR_CHIPSET_OFFSET] = 0xF3;
+        rom[0x7FC0 - 1] = 0x10;
+
+        let err = Cartridge::load(&rom, Some(&[0; 0x1000])).err().unwrap();
+        assert!(matches!(
+            err.downcast::<CartridgeError>(),
+            Ok(CartridgeError::InvalidCoRomSize("Cx4", 0x1000, _))
+        ));
+
+        let c = Cartridge::load(&rom, Some(&[0; cx4_cpu::DATA_ROM_SIZE])).unwrap();
+        assert_eq!(c.mapper, Mapper::Cx4);
+    }
+
+    #[test]
+    fn copier_hint() {
+        // Both locations look equally plausible
+        let mut rom = build_rom(0x20000, 0x7FC0, 0x20);
+        rom.copy_within(0x7FC0..0x8000, 0xFFC0);
+        rom[0xFFC0 + HDR_MAPMODE_OFFSET] = 0x21;
+        rom[0x8000] = rom[0];
+        assert_eq!(
+            Cartridge::score_header(&rom, 0x7FC0).unwrap().total(),
+            Cartridge::score_header(&rom, 0xFFC0).unwrap().total()
+        );
+        assert_eq!(Cartridge::detect_header(&rom).unwrap().offset, 0x7FC0);
+
+        let mut hdr = vec![0; copier::COPIER_HEADER_SIZE];
+        hdr[2] = 0x30;
+        hdr[8..11].copy_from_slice(&[0xAA, 0xBB, 0x04]);
+        hdr.extend(&rom);
+        let c = Cartridge::load(&hdr, None).unwrap();
+        assert_eq!(c.mapper, Mapper::HiROM);
+    }
+
+    #[test]
+    fn interleaved() {
+        let rom = build_rom(0x40000, 0xFFC0, 0x21);
+        let il = copier::interleave(&rom);
+        assert!(!Cartridge::is_interleaved(&rom));
+        assert!(Cartridge::is_interleaved(&il));
+
+        let c = Cartridge::load(&il, None).unwrap();
+        assert_eq!(c.mapper, Mapper::HiROM);
+        assert_eq!(c.rom, rom);
+    }
+
+    #[test]
+    fn sufami_turbo() {
+        let bios = build_rom(0x40000, 0x7FC0, 0x20);
+        let mut slot = vec![0; 0x20000];
+        slot[..14].copy_from_slice(b"BANDAI SFC-ADX");
+        slot[0x37] = 1;
+        slot[0x8000] = 0xAB;
+
+        let mut c = Cartridge::load_sufami(&bios, Some(&slot), None).unwrap();
+        assert_eq!(c.mapper, Mapper::SufamiTurbo);
+        assert!(!c.has_ram());
+        assert_eq!(c.read(0x008000), Some(bios[0]));
+        assert_eq!(c.read(0x218000), Some(0xAB));
+        assert_eq!(c.read(0x408000), None);
+        assert_eq!(c.write(0x608000, 0x12), Some(()));
+        assert_eq!(c.read(0x608800), Some(0x12));
+    }
+
+    #[test]
+    fn no_header() {
+        let err = Cartridge::load(&[0; 0x20000], None).err().unwrap();
+        let Ok(CartridgeError::NoHeader(scores)) = err.downcast::<CartridgeError>() else {
+            panic!("Wrong error");
+        };
+        assert_eq!(scores.len(), 2);
+    }
+
+    #[test]
+    fn illogical_size() {
+        let err = Cartridge::load(&[0; 0x20001], None).err().unwrap();
+        assert!(matches!(
+            err.downcast::<CartridgeError>(),
+            Ok(CartridgeError::IllogicalSize(0x20001))
+        ));
+    }
+}
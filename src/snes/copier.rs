@@ -0,0 +1,226 @@
+use std::fmt;
+
+/// Size of a copier header
+pub const COPIER_HEADER_SIZE: usize = 0x200;
+
+const SWC_MAGIC: [u8; 3] = [0xAA, 0xBB, 0x04];
+const SWC_MAGIC_OFFSET: usize = 8;
+const UFO_MAGIC: &[u8] = b"SUPERUFO";
+const UFO_MAGIC_OFFSET: usize = 8;
+const GD3_MAGIC: &[u8] = b"GAME DOCTOR SF 3";
+
+// SWC emulation mode byte (offset 2)
+const SWC_EMU_MULTI: u8 = 1 << 6;
+const SWC_EMU_HIROM: u8 = 1 << 4;
+const SWC_EMU_SRAM_SHIFT: u8 = 2;
+const SWC_EMU_SRAM_MASK: u8 = 0x03;
+
+/// Known FIG emulation bytes (offset 4, 5)
+const FIG_EMU: [[u8; 2]; 8] = [
+    [0x77, 0x83],
+    [0x00, 0x80],
+    [0x47, 0x83],
+    [0x11, 0x02],
+    [0xDD, 0x82],
+    [0xDD, 0x02],
+    [0xF7, 0x83],
+    [0xFD, 0x82],
+];
+
+/// Size of a 32KB block in an interleaved dump
+const INTERLEAVE_BLOCK: usize = 0x8000;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CopierFormat {
+    /// Super Wild Card / Super Magicom (.swc, .smc)
+    SWC,
+    /// Pro Fighter (.fig)
+    FIG,
+    /// Super UFO (.ufo)
+    UFO,
+    /// Game Doctor SF3/6/7 (.gd3)
+    GD3,
+    /// Header present but not recognized (or zeroed)
+    Unknown,
+}
+
+/// Information parsed from a copier header
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct CopierHeader {
+    pub format: CopierFormat,
+
+    /// Memory map hint (true = HiROM, false = LoROM), if the header has one
+    pub hirom: Option<bool>,
+
+    /// SRAM size hint, in bytes
+    pub sram_size: Option<usize>,
+
+    /// File is part of a multi-file (split) image
+    pub multi_file: bool,
+}
+
+impl CopierHeader {
+    /// Parses a copier header
+    pub fn parse(hdr: &[u8]) -> Self {
+        let mut h = Self {
+            format: CopierFormat::Unknown,
+            hirom: None,
+            sram_size: None,
+            multi_file: false,
+        };
+        if hdr.len() < COPIER_HEADER_SIZE {
+            return h;
+        }
+
+        if hdr[SWC_MAGIC_OFFSET..].starts_with(&SWC_MAGIC) {
+            h.format = CopierFormat::SWC;
+            h.hirom = Some(hdr[2] & SWC_EMU_HIROM != 0);
+            h.multi_file = hdr[2] & SWC_EMU_MULTI != 0;
+            h.sram_size = match (hdr[2] >> SWC_EMU_SRAM_SHIFT) & SWC_EMU_SRAM_MASK {
+                0 => Some(32 * 1024),
+                1 => Some(8 * 1024),
+                2 => Some(2 * 1024),
+                _ => Some(0),
+            };
+        } else if hdr[UFO_MAGIC_OFFSET..].starts_with(UFO_MAGIC) {
+            h.format = CopierFormat::UFO;
+            h.multi_file = hdr[2] & 0x40 != 0;
+            // Bank type: 0 = HiROM, 1 = LoROM
+            h.hirom = Some(hdr[0x12] == 0);
+            h.sram_size = match hdr[0x13] {
+                0 => Some(0),
+                s @ 1..=4 => Some(2048 << (s * 2 - 2)),
+                _ => None,
+            };
+        } else if hdr.starts_with(GD3_MAGIC) {
+            // GD3 stores HiROM images interleaved; the memory map is
+            // figured out after de-interleaving.
+            h.format = CopierFormat::GD3;
+        } else if matches!(hdr[2], 0x00 | 0x40)
+            && matches!(hdr[3], 0x00 | 0x80)
+            && FIG_EMU.contains(&[hdr[4], hdr[5]])
+            && hdr[6..].iter().all(|&b| b == 0)
+        {
+            h.format = CopierFormat::FIG;
+            h.multi_file = hdr[2] & 0x40 != 0;
+            h.hirom = Some(hdr[3] & 0x80 != 0);
+            h.sram_size = match [hdr[4], hdr[5]] {
+                [0x77, 0x83] | [0x47, 0x83] | [0xDD, 0x82] | [0xF7, 0x83] | [0xFD, 0x82] => Some(0),
+                _ => None,
+            };
+        }
+
+        h
+    }
+
+    /// Suggested cartridge header location, based on the memory map hint
+    pub fn header_offset_hint(&self) -> Option<usize> {
+        match self.hirom {
+            Some(true) => Some(0xFFC0),
+            Some(false) => Some(0x7FC0),
+            None => None,
+        }
+    }
+}
+
+impl fmt::Display for CopierHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.format)?;
+        match self.hirom {
+            Some(true) => write!(f, ", HiROM")?,
+            Some(false) => write!(f, ", LoROM")?,
+            None => (),
+        }
+        if let Some(sz) = self.sram_size {
+            write!(f, ", {} KB SRAM", sz / 1024)?;
+        }
+        if self.multi_file {
+            write!(f, ", multi-file")?;
+        }
+        Ok(())
+    }
+}
+
+/// De-interleaves a HiROM image as dumped by some copiers, which store
+/// the upper halves of all 64KB banks followed by the lower halves.
+/// The length of the image must be a multiple of 64KB.
+pub fn deinterleave(rom: &[u8]) -> Vec<u8> {
+    let banks = rom.len() / (INTERLEAVE_BLOCK * 2);
+    let mut out = Vec::with_capacity(rom.len());
+    for bank in 0..banks {
+        out.extend_from_slice(&rom[(banks + bank) * INTERLEAVE_BLOCK..][..INTERLEAVE_BLOCK]);
+        out.extend_from_slice(&rom[bank * INTERLEAVE_BLOCK..][..INTERLEAVE_BLOCK]);
+    }
+    out
+}
+
+/// Interleaves a HiROM image, the inverse of `deinterleave()`.
+pub fn interleave(rom: &[u8]) -> Vec<u8> {
+    let banks = rom.len() / (INTERLEAVE_BLOCK * 2);
+    let mut out = Vec::with_capacity(rom.len());
+    for bank in 0..banks {
+        out.extend_from_slice(&rom[(bank * 2 + 1) * INTERLEAVE_BLOCK..][..INTERLEAVE_BLOCK]);
+    }
+    for bank in 0..banks {
+        out.extend_from_slice(&rom[(bank * 2) * INTERLEAVE_BLOCK..][..INTERLEAVE_BLOCK]);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swc() {
+        let mut hdr = [0; COPIER_HEADER_SIZE];
+        hdr[0] = 0x80;
+        hdr[2] = 0x30 | (1 << SWC_EMU_SRAM_SHIFT);
+        hdr[8..11].copy_from_slice(&SWC_MAGIC);
+        let h = CopierHeader::parse(&hdr);
+        assert_eq!(h.format, CopierFormat::SWC);
+        assert_eq!(h.hirom, Some(true));
+        assert_eq!(h.sram_size, Some(8 * 1024));
+        assert!(!h.multi_file);
+        assert_eq!(h.header_offset_hint(), Some(0xFFC0));
+    }
+
+    #[test]
+    fn fig() {
+        let mut hdr = [0; COPIER_HEADER_SIZE];
+        hdr[0] = 0x80;
+        hdr[4..6].copy_from_slice(&[0x77, 0x83]);
+        let h = CopierHeader::parse(&hdr);
+        assert_eq!(h.format, CopierFormat::FIG);
+        assert_eq!(h.hirom, Some(false));
+        assert_eq!(h.sram_size, Some(0));
+    }
+
+    #[test]
+    fn ufo_gd3_unknown() {
+        let mut hdr = [0; COPIER_HEADER_SIZE];
+        hdr[8..16].copy_from_slice(UFO_MAGIC);
+        hdr[0x12] = 1;
+        let h = CopierHeader::parse(&hdr);
+        assert_eq!(h.format, CopierFormat::UFO);
+        assert_eq!(h.hirom, Some(false));
+
+        let mut hdr = [0; COPIER_HEADER_SIZE];
+        hdr[..16].copy_from_slice(GD3_MAGIC);
+        assert_eq!(CopierHeader::parse(&hdr).format, CopierFormat::GD3);
+
+        let h = CopierHeader::parse(&[0; COPIER_HEADER_SIZE]);
+        assert_eq!(h.format, CopierFormat::Unknown);
+        assert_eq!(h.header_offset_hint(), None);
+    }
+
+    #[test]
+    fn interleave_roundtrip() {
+        let rom: Vec<u8> = (0..0x40000).map(|i| (i / INTERLEAVE_BLOCK) as u8).collect();
+        let il = interleave(&rom);
+        assert_eq!(il[0], 1);
+        assert_eq!(il[INTERLEAVE_BLOCK], 3);
+        assert_eq!(il[INTERLEAVE_BLOCK * 4], 0);
+        assert_eq!(deinterleave(&il), rom);
+    }
+}
@@ -0,0 +1,199 @@
+use serde::{Deserialize, Serialize};
+
+/// Size of an 8M (8 Mbit) memory pack
+pub const PACK_SIZE_8M: usize = 0x100000;
+
+/// Size of an erase block
+const BLOCK_SIZE: usize = 0x10000;
+
+// Status register bits
+const STATUS_READY: u8 = 1 << 7;
+const STATUS_ERASE_ERROR: u8 = 1 << 5;
+const STATUS_PROGRAM_ERROR: u8 = 1 << 4;
+
+/// Chip identification, read at $xxFF00 in ID mode
+const CHIP_ID: [u8; 8] = [0x4D, 0x00, 0x50, 0x00, 0x00, 0x00, 0x2A, 0x00];
+const CHIP_ID_OFFSET: usize = 0xFF00;
+
+// Commands
+const CMD_READ_ARRAY: u8 = 0xFF;
+const CMD_READ_ARRAY_ALT: u8 = 0x00;
+const CMD_PROGRAM: u8 = 0x10;
+const CMD_PROGRAM_ALT: u8 = 0x40;
+const CMD_BLOCK_ERASE: u8 = 0x20;
+const CMD_CHIP_ERASE: u8 = 0xA7;
+const CMD_CLEAR_STATUS: u8 = 0x50;
+const CMD_READ_STATUS: u8 = 0x70;
+const CMD_READ_EXT_STATUS: u8 = 0x71;
+const CMD_READ_ID: u8 = 0x90;
+const CMD_READ_ID_ALT: u8 = 0x75;
+const CMD_CONFIRM: u8 = 0xD0;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+enum FlashMode {
+    ReadArray,
+    ReadStatus,
+    ReadId,
+    /// Next write programs a byte
+    Program,
+    /// Waiting for confirmation of a block erase
+    BlockErase,
+    /// Waiting for confirmation of a chip erase
+    ChipErase,
+}
+
+/// Satellaview memory pack (8M flash)
+#[derive(Serialize, Deserialize)]
+pub struct MemoryPack {
+    data: Vec<u8>,
+    mode: FlashMode,
+    status: u8,
+
+    /// Contents were modified since loading
+    dirty: bool,
+}
+
+impl MemoryPack {
+    /// Creates a memory pack from an image
+    pub fn new(image: &[u8]) -> Self {
+        let mut data = image.to_vec();
+        if data.is_empty() {
+            data.resize(PACK_SIZE_8M, 0xFF);
+        }
+        Self {
+            data,
+            mode: FlashMode::ReadArray,
+            status: STATUS_READY,
+            dirty: false,
+        }
+    }
+
+    /// Creates an empty (erased) memory pack
+    pub fn blank() -> Self {
+        Self::new(&[])
+    }
+
+    /// Contents of the flash
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns true if the contents were modified since
+    /// loading or the last call to `clear_dirty()`.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn clear_dirty(&mut self) {
+        self.dirty = false;
+    }
+
+    fn mirror(&self, offset: usize) -> usize {
+        offset % self.data.len()
+    }
+
+    pub fn read(&self, offset: usize) -> u8 {
+        match self.mode {
+            FlashMode::ReadStatus
+            | FlashMode::Program
+            | FlashMode::BlockErase
+            | FlashMode::ChipErase => self.status,
+            FlashMode::ReadId => {
+                let idx = (offset & 0xFFFF).wrapping_sub(CHIP_ID_OFFSET);
+                CHIP_ID.get(idx).copied().unwrap_or(0)
+            }
+            FlashMode::ReadArray => self.data[self.mirror(offset)],
+        }
+    }
+
+    pub fn write(&mut self, offset: usize, val: u8) {
+        match self.mode {
+            FlashMode::Program => {
+                // Programming can only clear bits
+                let addr = self.mirror(offset);
+                self.data[addr] &= val;
+                self.dirty = true;
+                if self.data[addr] != val {
+                    self.status |= STATUS_PROGRAM_ERROR;
+                }
+                self.mode = FlashMode::ReadStatus;
+                return;
+            }
+            FlashMode::BlockErase | FlashMode::ChipErase => {
+                if val == CMD_CONFIRM {
+                    let range = if self.mode == FlashMode::BlockErase {
+                        let start = self.mirror(offset) & !(BLOCK_SIZE - 1);
+                        start..(start + BLOCK_SIZE).min(self.data.len())
+                    } else {
+                        0..self.data.len()
+                    };
+                    self.data[range].fill(0xFF);
+                    self.dirty = true;
+                } else {
+                    self.status |= STATUS_ERASE_ERROR | STATUS_PROGRAM_ERROR;
+                }
+                self.mode = FlashMode::ReadStatus;
+                return;
+            }
+            _ => (),
+        }
+
+        match val {
+            CMD_READ_ARRAY | CMD_READ_ARRAY_ALT => self.mode = FlashMode::ReadArray,
+            CMD_READ_STATUS | CMD_READ_EXT_STATUS => self.mode = FlashMode::ReadStatus,
+            CMD_READ_ID | CMD_READ_ID_ALT => self.mode = FlashMode::ReadId,
+            CMD_PROGRAM | CMD_PROGRAM_ALT => self.mode = FlashMode::Program,
+            CMD_BLOCK_ERASE => self.mode = FlashMode::BlockErase,
+            CMD_CHIP_ERASE => self.mode = FlashMode::ChipErase,
+            CMD_CLEAR_STATUS => self.status = STATUS_READY,
+            // Chip enable/resume and unknown commands
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn program_and_erase() {
+        let mut p = MemoryPack::blank();
+        assert_eq!(p.data().len(), PACK_SIZE_8M);
+        assert_eq!(p.read(0x12345), 0xFF);
+
+        p.write(0x12345, CMD_PROGRAM);
+        p.write(0x12345, 0x5A);
+        assert_eq!(p.read(0x12345), STATUS_READY);
+        assert!(p.is_dirty());
+        p.write(0, CMD_READ_ARRAY);
+        assert_eq!(p.read(0x12345), 0x5A);
+
+        // Cannot set bits by programming
+        p.write(0x12345, CMD_PROGRAM);
+        p.write(0x12345, 0xA5);
+        assert_ne!(p.read(0x12345) & STATUS_PROGRAM_ERROR, 0);
+        p.write(0, CMD_CLEAR_STATUS);
+        p.write(0, CMD_READ_ARRAY);
+        assert_eq!(p.read(0x12345), 0x00);
+
+        p.write(0x10000, CMD_BLOCK_ERASE);
+        p.write(0x10000, CMD_CONFIRM);
+        assert_eq!(p.read(0), STATUS_READY);
+        p.write(0, CMD_READ_ARRAY);
+        assert_eq!(p.read(0x12345), 0xFF);
+    }
+
+    #[test]
+    fn chip_erase_and_id() {
+        let mut p = MemoryPack::new(&[0; PACK_SIZE_8M]);
+        p.write(0, CMD_CHIP_ERASE);
+        p.write(0, CMD_CONFIRM);
+        p.write(0, CMD_READ_ARRAY);
+        assert!(p.data().iter().all(|&b| b == 0xFF));
+
+        p.write(0, CMD_READ_ID);
+        assert_eq!(p.read(0xC0FF00), 0x4D);
+        assert_eq!(p.read(0xC0FF06), 0x2A);
+    }
+}
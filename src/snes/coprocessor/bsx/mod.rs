@@ -0,0 +1,311 @@
+pub mod flash;
+pub mod receiver;
+
+use std::cell::RefCell;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::bus::{Address, BusMember};
+
+use flash::MemoryPack;
+use receiver::{Receiver, RECEIVER_BASE, RECEIVER_END};
+
+/// Size of the PSRAM in the BS-X cartridge
+pub const PSRAM_SIZE: usize = 512 * 1024;
+const PSRAM_MASK: usize = PSRAM_SIZE - 1;
+
+/// Size of the battery backed SRAM in the BS-X cartridge
+pub const SRAM_SIZE: usize = 32 * 1024;
+
+// MMC registers ($00-$0F:5000), only bit 7 is used.
+/// Cartridge area maps: 0 = memory pack, 1 = PSRAM
+const MMC_MAP_PSRAM: usize = 0x01;
+/// Cartridge area layout: 0 = LoROM, 1 = HiROM
+const MMC_MAP_HIROM: usize = 0x02;
+/// PSRAM at $60-$6F
+const MMC_PSRAM_60: usize = 0x03;
+/// Disable PSRAM at $40-$4F
+const MMC_PSRAM_40_DISABLE: usize = 0x05;
+/// Disable PSRAM at $50-$5F
+const MMC_PSRAM_50_DISABLE: usize = 0x06;
+/// BIOS ROM at $00-$1F:8000-FFFF
+const MMC_BIOS_00: usize = 0x07;
+/// BIOS ROM at $80-$9F:8000-FFFF
+const MMC_BIOS_80: usize = 0x08;
+/// Writing bit 7 commits the other registers
+const MMC_COMMIT: usize = 0x0E;
+
+const MMC_BIT: u8 = 0x80;
+
+/// Memory region an address of the BS-X cartridge is mapped to
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum BsxRegion {
+    /// MMC register
+    Mmc(usize),
+    /// BIOS ROM (offset)
+    Bios(usize),
+    /// Battery backed SRAM (offset)
+    Sram(usize),
+    /// PSRAM (offset)
+    Psram(usize),
+    /// Memory pack (offset)
+    Pack(usize),
+    /// Satellite receiver register
+    Receiver(usize),
+}
+
+#[derive(Serialize, Deserialize)]
+struct BsxState {
+    /// MMC registers as written
+    mmc: [u8; 16],
+    /// MMC registers in effect
+    mmc_active: [u8; 16],
+    psram: Vec<u8>,
+    pack: Option<MemoryPack>,
+    receiver: Receiver,
+}
+
+/// Satellaview BS-X base cartridge: MMC, PSRAM, memory pack slot
+/// and satellite receiver.
+#[derive(Serialize, Deserialize)]
+pub struct BSX {
+    state: RefCell<BsxState>,
+}
+
+impl Default for BSX {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BSX {
+    pub fn new() -> Self {
+        let mut mmc = [0; 16];
+        mmc[MMC_BIOS_00] = MMC_BIT;
+        mmc[MMC_BIOS_80] = MMC_BIT;
+        Self {
+            state: RefCell::new(BsxState {
+                mmc,
+                mmc_active: mmc,
+                psram: vec![0; PSRAM_SIZE],
+                pack: None,
+                receiver: Receiver::new(),
+            }),
+        }
+    }
+
+    /// Inserts a memory pack in the slot
+    pub fn insert_pack(&mut self, pack: MemoryPack) {
+        self.state.get_mut().pack = Some(pack);
+    }
+
+    /// Removes the memory pack from the slot
+    pub fn eject_pack(&mut self) -> Option<MemoryPack> {
+        self.state.get_mut().pack.take()
+    }
+
+    /// Returns the contents of the memory pack if it was modified,
+    /// and marks it as unmodified.
+    pub fn take_modified_pack(&mut self) -> Option<Vec<u8>> {
+        let pack = self.state.get_mut().pack.as_mut()?;
+        if !pack.is_dirty() {
+            return None;
+        }
+        pack.clear_dirty();
+        Some(pack.data().to_vec())
+    }
+
+    /// Sets the directory the satellite receiver receives broadcasts from
+    pub fn set_broadcast_dir(&mut self, dir: Option<PathBuf>) {
+        self.state.get_mut().receiver.set_broadcast_dir(dir);
+    }
+
+    /// Sets the current time (Unix time, in seconds) of the satellite broadcast
+    pub fn set_time(&mut self, time: i64) {
+        self.state.get_mut().receiver.set_time(time);
+    }
+
+    /// Advances the time of the satellite broadcast by the specified
+    /// amount of seconds
+    pub fn advance_time(&mut self, secs: i64) {
+        self.state.get_mut().receiver.advance_time(secs);
+    }
+
+    fn mmc(&self, reg: usize) -> bool {
+        self.state.borrow().mmc_active[reg] & MMC_BIT != 0
+    }
+
+    /// Determines where an address is mapped to, based on the
+    /// current MMC configuration.
+    pub fn map(&self, fulladdr: Address) -> Option<BsxRegion> {
+        let (bank, addr) = ((fulladdr >> 16) as usize, (fulladdr & 0xFFFF) as usize);
+
+        match (bank, addr) {
+            // MMC registers
+            (0x00..=0x0F, 0x5000) => return Some(BsxRegion::Mmc(bank)),
+
+            // Satellite receiver
+            (0x00..=0x3F | 0x80..=0xBF, RECEIVER_BASE..=RECEIVER_END) => {
+                return Some(BsxRegion::Receiver(addr))
+            }
+
+            // SRAM
+            (0x10..=0x17, 0x5000..=0x5FFF) => {
+                return Some(BsxRegion::Sram((bank - 0x10) * 0x1000 + (addr - 0x5000)))
+            }
+
+            // PSRAM, always mapped
+            (0x20..=0x3F, 0x6000..=0x7FFF) => {
+                return Some(BsxRegion::Psram(
+                    ((bank - 0x20) * 0x2000 + (addr - 0x6000)) & PSRAM_MASK,
+                ))
+            }
+            (0x70..=0x77, _) => {
+                return Some(BsxRegion::Psram(
+                    ((bank - 0x70) * 0x10000 + addr) & PSRAM_MASK,
+                ))
+            }
+
+            // BIOS ROM (LoROM)
+            (0x00..=0x1F, 0x8000..=0xFFFF) if self.mmc(MMC_BIOS_00) => {
+                return Some(BsxRegion::Bios(bank * 0x8000 + (addr - 0x8000)))
+            }
+            (0x80..=0x9F, 0x8000..=0xFFFF) if self.mmc(MMC_BIOS_80) => {
+                return Some(BsxRegion::Bios((bank - 0x80) * 0x8000 + (addr - 0x8000)))
+            }
+
+            // Switchable PSRAM
+            (0x40..=0x4F, _) if !self.mmc(MMC_PSRAM_40_DISABLE) => {
+                return Some(BsxRegion::Psram(
+                    ((bank - 0x40) * 0x10000 + addr) & PSRAM_MASK,
+                ))
+            }
+            (0x50..=0x5F, _) if !self.mmc(MMC_PSRAM_50_DISABLE) => {
+                return Some(BsxRegion::Psram(
+                    ((bank - 0x50) * 0x10000 + addr) & PSRAM_MASK,
+                ))
+            }
+            (0x60..=0x6F, _) if self.mmc(MMC_PSRAM_60) => {
+                return Some(BsxRegion::Psram(
+                    ((bank - 0x60) * 0x10000 + addr) & PSRAM_MASK,
+                ))
+            }
+            _ => (),
+        }
+
+        // Cartridge area, maps either the memory pack or PSRAM
+        let offset = if self.mmc(MMC_MAP_HIROM) {
+            match (bank, addr) {
+                (0x00..=0x3F | 0x80..=0xBF, 0x8000..=0xFFFF) => (bank & 0x3F) * 0x10000 + addr,
+                (0x40..=0x7D, _) => (bank - 0x40) * 0x10000 + addr,
+                (0xC0..=0xFF, _) => (bank - 0xC0) * 0x10000 + addr,
+                _ => return None,
+            }
+        } else {
+            match (bank, addr) {
+                (0x00..=0x7D | 0x80..=0xFF, 0x8000..=0xFFFF) => {
+                    (bank & 0x7F) * 0x8000 + (addr - 0x8000)
+                }
+                _ => return None,
+            }
+        };
+        if self.mmc(MMC_MAP_PSRAM) {
+            Some(BsxRegion::Psram(offset & PSRAM_MASK))
+        } else {
+            Some(BsxRegion::Pack(offset))
+        }
+    }
+}
+
+impl BusMember<Address> for BSX {
+    fn read(&self, fulladdr: Address) -> Option<u8> {
+        let region = self.map(fulladdr)?;
+        let mut state = self.state.borrow_mut();
+
+        match region {
+            BsxRegion::Mmc(reg) => Some(state.mmc[reg] & MMC_BIT),
+            BsxRegion::Psram(offset) => Some(state.psram[offset]),
+            BsxRegion::Pack(offset) => state.pack.as_ref().map(|p| p.read(offset)),
+            BsxRegion::Receiver(addr) => Some(state.receiver.read(addr)),
+            // Handled by the cartridge
+            BsxRegion::Bios(_) | BsxRegion::Sram(_) => None,
+        }
+    }
+
+    fn write(&mut self, fulladdr: Address, val: u8) -> Option<()> {
+        let region = self.map(fulladdr)?;
+        let state = self.state.get_mut();
+
+        match region {
+            BsxRegion::Mmc(reg) => {
+                state.mmc[reg] = val;
+                if reg == MMC_COMMIT && val & MMC_BIT != 0 {
+                    state.mmc_active = state.mmc;
+                }
+                Some(())
+            }
+            BsxRegion::Psram(offset) => Some(state.psram[offset] = val),
+            BsxRegion::Pack(offset) => state.pack.as_mut().map(|p| p.write(offset, val)),
+            BsxRegion::Receiver(addr) => Some(state.receiver.write(addr, val)),
+            // Handled by the cartridge
+            BsxRegion::Bios(_) | BsxRegion::Sram(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_map() {
+        let bsx = BSX::new();
+        assert_eq!(bsx.map(0x008000), Some(BsxRegion::Bios(0)));
+        assert_eq!(bsx.map(0x9F8000), Some(BsxRegion::Bios(0xF8000)));
+        assert_eq!(bsx.map(0x208000), Some(BsxRegion::Pack(0x100000)));
+        assert_eq!(bsx.map(0x400000), Some(BsxRegion::Psram(0)));
+        assert_eq!(bsx.map(0x600000), None);
+        assert_eq!(bsx.map(0x105000), Some(BsxRegion::Sram(0)));
+        assert_eq!(bsx.map(0x7E0000), None);
+        assert_eq!(bsx.map(0x002100), None);
+        assert_eq!(bsx.map(0x002188), Some(BsxRegion::Receiver(0x2188)));
+    }
+
+    #[test]
+    fn mmc_commit() {
+        let mut bsx = BSX::new();
+        // Map PSRAM in HiROM layout, disable BIOS
+        bsx.write(0x015000, 0x80);
+        bsx.write(0x025000, 0x80);
+        bsx.write(0x075000, 0x00);
+        bsx.write(0x085000, 0x00);
+        assert_eq!(bsx.read(0x025000), Some(0x80));
+        assert_eq!(bsx.map(0x008000), Some(BsxRegion::Bios(0)));
+
+        bsx.write(0x0E5000, 0x80);
+        assert_eq!(bsx.map(0x008000), Some(BsxRegion::Psram(0x8000)));
+        assert_eq!(bsx.map(0xC10000), Some(BsxRegion::Psram(0x10000)));
+
+        bsx.write(0xC10000, 0xAB);
+        assert_eq!(bsx.read(0x410000), Some(0xAB));
+        assert_eq!(bsx.read(0x710000), Some(0xAB));
+    }
+
+    #[test]
+    fn memory_pack() {
+        let mut bsx = BSX::new();
+        assert_eq!(bsx.read(0xC08000), None);
+
+        bsx.insert_pack(MemoryPack::new(&[0x12; flash::PACK_SIZE_8M]));
+        assert_eq!(bsx.read(0xC08000), Some(0x12));
+        assert!(bsx.take_modified_pack().is_none());
+
+        bsx.write(0xC08000, 0x10);
+        bsx.write(0xC08000, 0x02);
+        bsx.write(0xC08000, 0xFF);
+        assert_eq!(bsx.read(0xC08000), Some(0x02));
+        assert_eq!(bsx.take_modified_pack().unwrap()[0], 0x02);
+        assert!(bsx.take_modified_pack().is_none());
+    }
+}
@@ -0,0 +1,311 @@
+use std::fs;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::snes::coprocessor::spc7110::rtc::civil_from_days;
+
+/// First receiver register
+pub const RECEIVER_BASE: usize = 0x2188;
+/// Last receiver register
+pub const RECEIVER_END: usize = 0x219F;
+
+/// Base register of each stream
+const STREAM_BASE: [usize; 2] = [0x2188, 0x218E];
+
+// Stream register offsets
+const REG_CHANNEL_LO: usize = 0;
+const REG_CHANNEL_HI: usize = 1;
+const REG_QUEUE: usize = 2;
+const REG_PREFIX: usize = 3;
+const REG_DATA: usize = 4;
+const REG_STATUS: usize = 5;
+
+/// Data bytes per packet
+const PACKET_SIZE: usize = 22;
+
+// Prefix bits
+const PREFIX_FIRST: u8 = 0x10;
+const PREFIX_LAST: u8 = 0x80;
+
+/// Channel that broadcasts the time
+const CHANNEL_TIME: u16 = 0;
+
+/// A stream of packets received from a logical channel
+#[derive(Default, Serialize, Deserialize)]
+struct Stream {
+    channel: u16,
+    /// Sequence number of the current file on this channel
+    count: u32,
+    data: Vec<u8>,
+    pos: usize,
+    /// Packets left to be received
+    queue: usize,
+    loaded: bool,
+    first: bool,
+    prefix_latch: bool,
+    data_latch: bool,
+    status: u8,
+}
+
+impl Stream {
+    fn reset(&mut self) {
+        self.count = 0;
+        self.loaded = false;
+        self.queue = 0;
+    }
+}
+
+/// Satellaview satellite receiver ($2188-$219F).
+///
+/// Instead of a satellite, channels are received from files in a
+/// local broadcast directory, named BSXcccc-n.bin (cccc = logical
+/// channel in hex, n = sequence number starting at 0), which is the
+/// naming used by existing BS-X data dumps.
+#[derive(Serialize, Deserialize)]
+pub struct Receiver {
+    broadcast_dir: Option<PathBuf>,
+    streams: [Stream; 2],
+
+    /// Time channel packet and read position
+    time_packet: [u8; PACKET_SIZE],
+    time_pos: usize,
+    /// Current time (Unix time, in seconds), broadcast on the time channel
+    time: i64,
+
+    /// Remaining (unemulated) registers
+    regs: [u8; RECEIVER_END - RECEIVER_BASE + 1],
+}
+
+impl Default for Receiver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Receiver {
+    pub fn new() -> Self {
+        Self {
+            broadcast_dir: None,
+            streams: Default::default(),
+            time_packet: [0; PACKET_SIZE],
+            time_pos: 0,
+            time: 0,
+            regs: [0; RECEIVER_END - RECEIVER_BASE + 1],
+        }
+    }
+
+    /// Sets the directory to receive broadcasts from
+    pub fn set_broadcast_dir(&mut self, dir: Option<PathBuf>) {
+        self.broadcast_dir = dir;
+    }
+
+    /// Sets the current time (Unix time, in seconds)
+    pub fn set_time(&mut self, time: i64) {
+        self.time = time;
+    }
+
+    /// Advances the clock by the specified amount of seconds
+    pub fn advance_time(&mut self, secs: i64) {
+        self.time += secs;
+    }
+
+    /// Loads the next file of a stream from the broadcast directory.
+    /// Wraps around to the first file at the end.
+    fn load_next(&mut self, s: usize) {
+        let Some(dir) = self.broadcast_dir.as_ref() else {
+            return;
+        };
+        let stream = &mut self.streams[s];
+        for count in [stream.count, 0] {
+            let filename = dir.join(format!("BSX{:04X}-{}.bin", stream.channel, count));
+            if let Ok(data) = fs::read(filename) {
+                stream.queue = data.len().div_ceil(PACKET_SIZE);
+                stream.data = data;
+                stream.pos = 0;
+                stream.count = count + 1;
+                stream.loaded = true;
+                stream.first = true;
+                return;
+            }
+        }
+        stream.loaded = false;
+    }
+
+    /// Builds the packet broadcast on the time channel
+    fn latch_time(&mut self) {
+        let t = self.time;
+        let days = t.div_euclid(86400);
+        let secs = t.rem_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+
+        self.time_packet = [0; PACKET_SIZE];
+        self.time_packet[5] = 0x01;
+        self.time_packet[6] = 0x01;
+        self.time_packet[10] = (secs % 60) as u8;
+        self.time_packet[11] = ((secs / 60) % 60) as u8;
+        self.time_packet[12] = (secs / 3600) as u8;
+        self.time_packet[13] = ((days + 4).rem_euclid(7) + 1) as u8;
+        self.time_packet[14] = day as u8;
+        self.time_packet[15] = month as u8;
+        self.time_packet[16..18].copy_from_slice(&(year as u16).to_le_bytes());
+    }
+
+    fn read_stream(&mut self, s: usize, reg: usize) -> u8 {
+        match reg {
+            REG_CHANNEL_LO => self.streams[s].channel as u8,
+            REG_CHANNEL_HI => (self.streams[s].channel >> 8) as u8,
+            REG_QUEUE => {
+                let stream = &self.streams[s];
+                if !stream.prefix_latch || !stream.data_latch {
+                    return 0;
+                }
+                if stream.channel == CHANNEL_TIME {
+                    return 1;
+                }
+                if stream.queue == 0 {
+                    self.load_next(s);
+                }
+                let stream = &self.streams[s];
+                if stream.loaded {
+                    stream.queue.min(0x7F) as u8
+                } else {
+                    0
+                }
+            }
+            REG_PREFIX => {
+                if !self.streams[s].prefix_latch {
+                    return 0;
+                }
+                if self.streams[s].channel == CHANNEL_TIME {
+                    self.time_pos = 0;
+                    return PREFIX_FIRST | PREFIX_LAST;
+                }
+                let stream = &mut self.streams[s];
+                if !stream.loaded || stream.queue == 0 {
+                    return 0;
+                }
+                let mut v = 0;
+                if stream.first {
+                    v |= PREFIX_FIRST;
+                    stream.first = false;
+                }
+                stream.queue -= 1;
+                if stream.queue == 0 {
+                    v |= PREFIX_LAST;
+                }
+                stream.status |= v;
+                v
+            }
+            REG_DATA => {
+                if self.streams[s].channel == CHANNEL_TIME {
+                    if self.time_pos == 0 {
+                        self.latch_time();
+                    }
+                    let v = self.time_packet[self.time_pos];
+                    self.time_pos = (self.time_pos + 1) % PACKET_SIZE;
+                    return v;
+                }
+                let stream = &mut self.streams[s];
+                if !stream.loaded || !stream.data_latch {
+                    return 0xFF;
+                }
+                let v = stream.data.get(stream.pos).copied().unwrap_or(0);
+                stream.pos += 1;
+                v
+            }
+            REG_STATUS => std::mem::take(&mut self.streams[s].status),
+            _ => unreachable!(),
+        }
+    }
+
+    fn write_stream(&mut self, s: usize, reg: usize, val: u8) {
+        let stream = &mut self.streams[s];
+        match reg {
+            REG_CHANNEL_LO => {
+                stream.channel = (stream.channel & 0xFF00) | val as u16;
+                stream.reset();
+            }
+            REG_CHANNEL_HI => {
+                stream.channel = (stream.channel & 0x00FF) | ((val as u16) << 8);
+                stream.reset();
+            }
+            REG_PREFIX => stream.prefix_latch = val != 0,
+            REG_DATA => stream.data_latch = val != 0,
+            _ => (),
+        }
+    }
+
+    /// Finds the stream and register for an address
+    fn stream_reg(addr: usize) -> Option<(usize, usize)> {
+        STREAM_BASE
+            .iter()
+            .enumerate()
+            .find(|(_, &base)| (base..=(base + REG_STATUS)).contains(&addr))
+            .map(|(s, &base)| (s, addr - base))
+    }
+
+    pub fn read(&mut self, addr: usize) -> u8 {
+        match Self::stream_reg(addr) {
+            Some((s, reg)) => self.read_stream(s, reg),
+            None => self.regs[addr - RECEIVER_BASE],
+        }
+    }
+
+    pub fn write(&mut self, addr: usize, val: u8) {
+        match Self::stream_reg(addr) {
+            Some((s, reg)) => self.write_stream(s, reg, val),
+            None => self.regs[addr - RECEIVER_BASE] = val,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stream_from_file() {
+        let dir = std::env::temp_dir().join(format!("siena_bsx_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let data: Vec<u8> = (0..30).collect();
+        fs::write(dir.join("BSX0121-0.bin"), &data).unwrap();
+
+        let mut r = Receiver::new();
+        r.set_broadcast_dir(Some(dir.clone()));
+        r.write(0x2188, 0x21);
+        r.write(0x2189, 0x01);
+        r.write(0x218B, 1);
+        r.write(0x218C, 1);
+        assert_eq!(r.read(0x218A), 2);
+        assert_eq!(r.read(0x218B), PREFIX_FIRST);
+        assert_eq!(r.read(0x218C), 0);
+        assert_eq!(r.read(0x218C), 1);
+        assert_eq!(r.read(0x218B), PREFIX_LAST);
+        assert_eq!(r.read(0x218D), PREFIX_FIRST | PREFIX_LAST);
+        assert_eq!(r.read(0x218D), 0);
+
+        // Other stream is not affected
+        r.write(0x218E, 0x22);
+        r.write(0x218F, 0x01);
+        r.write(0x2191, 1);
+        r.write(0x2192, 1);
+        assert_eq!(r.read(0x2190), 0);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn time_channel() {
+        let mut r = Receiver::new();
+        // 2001-02-03 (Sat) 04:05:06
+        r.set_time(981_173_106);
+        r.write(0x218B, 1);
+        r.write(0x218C, 1);
+        assert_eq!(r.read(0x218A), 1);
+        assert_eq!(r.read(0x218B), PREFIX_FIRST | PREFIX_LAST);
+        let packet: Vec<u8> = (0..PACKET_SIZE).map(|_| r.read(0x218C)).collect();
+        assert_eq!(packet[10..16], [6, 5, 4, 7, 3, 2]);
+        assert_eq!(u16::from_le_bytes([packet[16], packet[17]]), 2001);
+    }
+}
@@ -0,0 +1,73 @@
+use std::cell::RefCell;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::bus::{Address, BusMember};
+use crate::cpu_hg51b::cpu::CpuHg51b;
+use crate::tickable::{Tickable, Ticks};
+
+/// Capcom Cx4 co-processor
+#[derive(Serialize, Deserialize)]
+pub struct Cx4 {
+    /// HG51B169 CPU core
+    pub cpu: RefCell<CpuHg51b>,
+}
+
+impl Cx4 {
+    pub fn new(rom: &[u8], rom_mask: usize) -> Self {
+        Self {
+            cpu: RefCell::new(CpuHg51b::new(rom, rom_mask)),
+        }
+    }
+
+    pub fn load_data_rom(&mut self, rom: &[u8]) {
+        let mut cpu = self.cpu.borrow_mut();
+        cpu.load_data_rom(rom)
+    }
+
+    pub fn get_int(&self) -> bool {
+        let cpu = self.cpu.borrow();
+        cpu.get_int()
+    }
+}
+
+impl Tickable for Cx4 {
+    fn tick(&mut self, ticks: Ticks) -> Result<Ticks> {
+        let mut cpu = self.cpu.borrow_mut();
+
+        cpu.tick(ticks)
+    }
+}
+
+impl BusMember<Address> for Cx4 {
+    fn read(&self, fulladdr: Address) -> Option<u8> {
+        let (_bank, addr) = ((fulladdr >> 16) as usize, (fulladdr & 0xFFFF) as usize);
+        let cpu = self.cpu.borrow();
+
+        match addr {
+            // Data RAM
+            0x6000..=0x6BFF => cpu.read_dram(addr),
+
+            // I/O registers
+            0x7C00..=0x7FFF => Some(cpu.read_io(addr)),
+
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, fulladdr: Address, val: u8) -> Option<()> {
+        let (_bank, addr) = ((fulladdr >> 16) as usize, (fulladdr & 0xFFFF) as usize);
+        let mut cpu = self.cpu.borrow_mut();
+
+        match addr {
+            // Data RAM
+            0x6000..=0x6BFF => cpu.write_dram(addr, val),
+
+            // I/O registers
+            0x7C00..=0x7FFF => Some(cpu.write_io(addr, val)),
+
+            _ => None,
+        }
+    }
+}
@@ -1,4 +1,25 @@
+pub mod bsx;
+pub mod cx4;
 pub mod dsp1;
+pub mod msu1;
+pub mod obc1;
 pub mod sa1;
 pub mod sgb;
+pub mod spc7110;
+pub mod sufami;
 pub mod superfx;
+
+/// Co-processors that keep (battery backed) cartridge RAM
+/// themselves, which needs to be persisted in the save file.
+pub trait BatteryRam {
+    /// Size of the battery backed RAM, in bytes
+    fn battery_ram_size(&self) -> usize;
+
+    /// Loads the contents of the battery backed RAM
+    /// (e.g. from a save file).
+    fn load_battery_ram(&mut self, data: &[u8]);
+
+    /// Returns the contents of the battery backed RAM
+    /// (e.g. to write to a save file).
+    fn save_battery_ram(&self) -> Vec<u8>;
+}
@@ -0,0 +1,436 @@
+use std::cell::RefCell;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+
+use crate::bus::{Address, BusMember};
+
+/// Sample rate of MSU-1 PCM tracks
+pub const PCM_SAMPLE_RATE: u32 = 44100;
+
+/// Magic at the start of a PCM track
+const PCM_MAGIC: &[u8] = b"MSU1";
+/// Size of the PCM track header (magic + loop point)
+const PCM_HEADER_SIZE: usize = 8;
+
+/// Chip identification, read at $2002-$2007
+const IDENT: &[u8; 6] = b"S-MSU1";
+/// Chip revision, in the status register
+const REVISION: u8 = 1;
+
+// Registers
+const REG_FIRST: usize = 0x2000;
+const REG_LAST: usize = 0x2007;
+
+const REG_STATUS: usize = 0x2000;
+const REG_READ: usize = 0x2001;
+const REG_SEEK_LAST: usize = 0x2003;
+const REG_TRACK_LO: usize = 0x2004;
+const REG_TRACK_HI: usize = 0x2005;
+const REG_VOLUME: usize = 0x2006;
+const REG_CONTROL: usize = 0x2007;
+
+// Status register bits
+const STATUS_TRACK_MISSING: u8 = 1 << 3;
+const STATUS_PLAYING: u8 = 1 << 4;
+const STATUS_REPEAT: u8 = 1 << 5;
+
+// Control register bits
+const CONTROL_PLAY: u8 = 1 << 0;
+const CONTROL_REPEAT: u8 = 1 << 1;
+
+/// MSU-1 audio playback, mixed into the APU output.
+#[derive(Default)]
+pub struct Msu1Audio {
+    /// PCM file of the current track
+    track: Option<File>,
+    /// Length of the track (in stereo samples)
+    frames: usize,
+    /// Loop point (in stereo samples)
+    loop_point: usize,
+    /// Position of the next sample to read from the track (in stereo samples)
+    pos: usize,
+    /// Samples read from the track, but not yet played (interleaved stereo)
+    buf: Vec<i16>,
+    buf_pos: usize,
+
+    /// Samples the output is interpolated between
+    cur: Option<[i16; 2]>,
+    next: Option<[i16; 2]>,
+    /// Resampling accumulator
+    frac: u32,
+
+    volume: u8,
+    playing: bool,
+    repeat: bool,
+}
+
+impl Msu1Audio {
+    /// Stereo samples read from the track file at once
+    const CHUNK_FRAMES: usize = 4096;
+
+    /// Loads a PCM track. Returns false if the track is not valid.
+    fn load_track(&mut self, file: Option<File>) -> bool {
+        self.playing = false;
+        self.track = None;
+        self.frames = 0;
+        self.rewind();
+
+        let Some(mut file) = file else {
+            return false;
+        };
+        let mut header = [0; PCM_HEADER_SIZE];
+        if file.read_exact(&mut header).is_err() || !header.starts_with(PCM_MAGIC) {
+            return false;
+        }
+        let len = file.metadata().map_or(0, |m| m.len() as usize);
+        self.loop_point = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+        self.frames = len.saturating_sub(PCM_HEADER_SIZE) / 4;
+        self.track = Some(file);
+        self.rewind();
+        true
+    }
+
+    /// Moves the read position to the specified stereo sample
+    fn seek(&mut self, frame: usize) {
+        self.pos = frame;
+        self.buf.clear();
+        self.buf_pos = 0;
+        if let Some(track) = self.track.as_mut() {
+            let _ = track.seek(SeekFrom::Start((PCM_HEADER_SIZE + frame * 4) as u64));
+        }
+    }
+
+    /// Rewinds to the start of the track
+    fn rewind(&mut self) {
+        self.seek(0);
+        self.frac = 0;
+        self.cur = self.next_frame();
+        self.next = self.next_frame();
+    }
+
+    /// Reads the next stereo sample from the track, continuing at the
+    /// loop point at the end if repeating.
+    fn next_frame(&mut self) -> Option<[i16; 2]> {
+        if self.pos >= self.frames {
+            if !self.repeat || self.frames == 0 {
+                return None;
+            }
+            self.seek(if self.loop_point < self.frames {
+                self.loop_point
+            } else {
+                0
+            });
+        }
+
+        if self.buf_pos >= self.buf.len() {
+            self.buf.clear();
+            self.buf_pos = 0;
+            let mut data = vec![0; Self::CHUNK_FRAMES.min(self.frames - self.pos) * 4];
+            self.track.as_mut()?.read_exact(&mut data).ok()?;
+            self.buf.extend(
+                data.chunks_exact(2)
+                    .map(|s| i16::from_le_bytes([s[0], s[1]])),
+            );
+        }
+
+        let frame = [self.buf[self.buf_pos], self.buf[self.buf_pos + 1]];
+        self.buf_pos += 2;
+        self.pos += 1;
+        Some(frame)
+    }
+
+    /// Mixes the playing track into the (interleaved stereo) output
+    /// buffer, resampling to the given output sample rate.
+    pub fn mix(&mut self, out: &mut [i16], rate: u32) {
+        if !self.playing {
+            return;
+        }
+
+        for frame in out.chunks_exact_mut(2) {
+            let Some(cur) = self.cur else {
+                // Stop and rewind to the start of the track
+                self.playing = false;
+                self.rewind();
+                return;
+            };
+            let next = self.next.unwrap_or(cur);
+
+            for (ch, s) in frame.iter_mut().enumerate() {
+                // Linear interpolation between the surrounding samples
+                let (a, b) = (i64::from(cur[ch]), i64::from(next[ch]));
+                let v = a + (b - a) * i64::from(self.frac) / i64::from(rate);
+                let v = v * i64::from(self.volume) / 255;
+                *s = (i64::from(*s) + v).clamp(i16::MIN.into(), i16::MAX.into()) as i16;
+            }
+
+            self.frac += PCM_SAMPLE_RATE;
+            while self.frac >= rate {
+                self.frac -= rate;
+                self.cur = self.next;
+                self.next = self.next_frame();
+            }
+        }
+    }
+}
+
+/// MSU-1 enhanced audio/data chip.
+///
+/// The data ROM is read from `<rom>.msu`, audio tracks from
+/// `<rom>-<track>.pcm`.
+pub struct Msu1 {
+    /// ROM filename without extension
+    base: PathBuf,
+
+    data: Option<RefCell<BufReader<File>>>,
+    seek: u32,
+    track: u16,
+    track_missing: bool,
+
+    audio: Arc<Mutex<Msu1Audio>>,
+}
+
+impl Msu1 {
+    /// Creates an MSU-1 for the given ROM, if the data file
+    /// (`<rom>.msu`) exists.
+    pub fn open(rom_filename: &Path) -> Result<Option<Self>> {
+        let data_filename = rom_filename.with_extension("msu");
+        if !data_filename.exists() {
+            return Ok(None);
+        }
+        Ok(Some(Self::new(
+            rom_filename.with_extension(""),
+            Some(File::open(data_filename)?),
+        )))
+    }
+
+    /// Creates an MSU-1 with tracks named after `base`.
+    pub fn new(base: PathBuf, data: Option<File>) -> Self {
+        Self {
+            base,
+            data: data.map(|f| RefCell::new(BufReader::new(f))),
+            seek: 0,
+            track: 0,
+            track_missing: false,
+            audio: Arc::new(Mutex::new(Msu1Audio::default())),
+        }
+    }
+
+    /// Gets a (reference counted) copy of the audio playback state,
+    /// to mix into the audio output.
+    pub fn get_audio(&self) -> Arc<Mutex<Msu1Audio>> {
+        Arc::clone(&self.audio)
+    }
+
+    fn track_filename(&self, track: u16) -> PathBuf {
+        let mut s = OsString::from(self.base.as_os_str());
+        s.push(format!("-{}.pcm", track));
+        PathBuf::from(s)
+    }
+
+    /// Opens the selected track. Samples are read from the file as the
+    /// track plays, so this does not block on loading the whole track.
+    fn select_track(&mut self) {
+        let file = File::open(self.track_filename(self.track)).ok();
+        self.track_missing = !self.audio.lock().unwrap().load_track(file);
+    }
+
+    fn read_data(&self) -> u8 {
+        let Some(data) = self.data.as_ref() else {
+            return 0;
+        };
+        let mut b = [0];
+        match data.borrow_mut().read_exact(&mut b) {
+            Ok(()) => b[0],
+            Err(_) => 0,
+        }
+    }
+}
+
+impl BusMember<Address> for Msu1 {
+    fn read(&self, fulladdr: Address) -> Option<u8> {
+        let (bank, addr) = ((fulladdr >> 16) as usize, (fulladdr & 0xFFFF) as usize);
+
+        match (bank, addr) {
+            (0x00..=0x3F | 0x80..=0xBF, REG_FIRST..=REG_LAST) => Some(match addr {
+                REG_STATUS => {
+                    let audio = self.audio.lock().unwrap();
+                    let mut v = REVISION;
+                    if self.track_missing {
+                        v |= STATUS_TRACK_MISSING;
+                    }
+                    if audio.playing {
+                        v |= STATUS_PLAYING;
+                    }
+                    if audio.repeat {
+                        v |= STATUS_REPEAT;
+                    }
+                    v
+                }
+                REG_READ => self.read_data(),
+                _ => IDENT[addr - REG_READ - 1],
+            }),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, fulladdr: Address, val: u8) -> Option<()> {
+        let (bank, addr) = ((fulladdr >> 16) as usize, (fulladdr & 0xFFFF) as usize);
+
+        match (bank, addr) {
+            (0x00..=0x3F | 0x80..=0xBF, REG_FIRST..=REG_SEEK_LAST) => {
+                let shift = (addr - REG_FIRST) * 8;
+                self.seek = (self.seek & !(0xFF << shift)) | ((val as u32) << shift);
+                if addr == REG_SEEK_LAST {
+                    if let Some(data) = self.data.as_ref() {
+                        // Seeking past the end is not an error, reads return 0.
+                        let _ = data.borrow_mut().seek(SeekFrom::Start(self.seek.into()));
+                    }
+                }
+                Some(())
+            }
+            (0x00..=0x3F | 0x80..=0xBF, REG_TRACK_LO) => {
+                self.track = (self.track & 0xFF00) | val as u16;
+                Some(())
+            }
+            (0x00..=0x3F | 0x80..=0xBF, REG_TRACK_HI) => {
+                self.track = (self.track & 0x00FF) | ((val as u16) << 8);
+                self.select_track();
+                Some(())
+            }
+            (0x00..=0x3F | 0x80..=0xBF, REG_VOLUME) => {
+                self.audio.lock().unwrap().volume = val;
+                Some(())
+            }
+            (0x00..=0x3F | 0x80..=0xBF, REG_CONTROL) => {
+                let mut audio = self.audio.lock().unwrap();
+                if !self.track_missing {
+                    audio.playing = val & CONTROL_PLAY != 0;
+                    audio.repeat = val & CONTROL_REPEAT != 0;
+                }
+                Some(())
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn fixture_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("siena_msu1_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn pcm(loop_point: u32, samples: &[i16]) -> Vec<u8> {
+        let mut v = PCM_MAGIC.to_vec();
+        v.extend(loop_point.to_le_bytes());
+        for s in samples {
+            v.extend(s.to_le_bytes());
+        }
+        v
+    }
+
+    #[test]
+    fn data_port() {
+        let dir = fixture_dir("data");
+        let rom = dir.join("game.sfc");
+        assert!(Msu1::open(&rom).unwrap().is_none());
+        fs::write(dir.join("game.msu"), (0..=255).collect::<Vec<u8>>()).unwrap();
+
+        let mut msu = Msu1::open(&rom).unwrap().unwrap();
+        let ident: Vec<u8> = (0x2002..=0x2007).map(|a| msu.read(a).unwrap()).collect();
+        assert_eq!(ident, IDENT);
+        assert_eq!(msu.read(0x002000), Some(REVISION));
+        assert_eq!(msu.read(0x402000), None);
+
+        assert_eq!(msu.read(0x002001), Some(0));
+        assert_eq!(msu.read(0x802001), Some(1));
+        msu.write(0x002000, 0x80);
+        msu.write(0x002001, 0x00);
+        msu.write(0x002002, 0x00);
+        msu.write(0x002003, 0x00);
+        assert_eq!(msu.read(0x002001), Some(0x80));
+        assert_eq!(msu.read(0x002001), Some(0x81));
+
+        msu.write(0x002002, 0x01);
+        msu.write(0x002003, 0x00);
+        assert_eq!(msu.read(0x002001), Some(0));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn audio_tracks() {
+        let dir = fixture_dir("audio");
+        let rom = dir.join("game.sfc");
+        fs::write(dir.join("game.msu"), []).unwrap();
+        fs::write(dir.join("game-1.pcm"), pcm(1, &[100, -100, 200, -200])).unwrap();
+
+        let mut msu = Msu1::open(&rom).unwrap().unwrap();
+        let audio = msu.get_audio();
+
+        // Missing track
+        msu.write(0x002004, 2);
+        msu.write(0x002005, 0);
+        assert_ne!(msu.read(0x002000).unwrap() & STATUS_TRACK_MISSING, 0);
+        msu.write(0x002007, CONTROL_PLAY);
+        assert_eq!(msu.read(0x002000).unwrap() & STATUS_PLAYING, 0);
+
+        // Play once, at the PCM sample rate
+        msu.write(0x002004, 1);
+        msu.write(0x002005, 0);
+        msu.write(0x002006, 0xFF);
+        msu.write(0x002007, CONTROL_PLAY);
+        assert_eq!(
+            msu.read(0x002000).unwrap() & (STATUS_PLAYING | STATUS_TRACK_MISSING),
+            STATUS_PLAYING
+        );
+        let mut out = [1; 6];
+        audio.lock().unwrap().mix(&mut out, PCM_SAMPLE_RATE);
+        assert_eq!(out, [101, -99, 201, -199, 1, 1]);
+        assert_eq!(msu.read(0x002000).unwrap() & STATUS_PLAYING, 0);
+
+        // Repeat from the loop point, at half volume
+        msu.write(0x002006, 0x80);
+        msu.write(0x002007, CONTROL_PLAY | CONTROL_REPEAT);
+        let mut out = [0; 8];
+        audio.lock().unwrap().mix(&mut out, PCM_SAMPLE_RATE);
+        assert_eq!(out, [50, -50, 100, -100, 100, -100, 100, -100]);
+        assert_ne!(msu.read(0x002000).unwrap() & STATUS_REPEAT, 0);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn audio_interpolation() {
+        let dir = fixture_dir("interpolation");
+        let rom = dir.join("game.sfc");
+        fs::write(dir.join("game.msu"), []).unwrap();
+        fs::write(dir.join("game-1.pcm"), pcm(0, &[0, 0, 1000, -1000])).unwrap();
+
+        let mut msu = Msu1::open(&rom).unwrap().unwrap();
+        msu.write(0x002004, 1);
+        msu.write(0x002005, 0);
+        msu.write(0x002006, 0xFF);
+        msu.write(0x002007, CONTROL_PLAY);
+
+        // At twice the PCM sample rate, every other sample is in between
+        let mut out = [0; 8];
+        msu.get_audio()
+            .lock()
+            .unwrap()
+            .mix(&mut out, PCM_SAMPLE_RATE * 2);
+        assert_eq!(out, [0, 0, 500, -500, 1000, -1000, 1000, -1000]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
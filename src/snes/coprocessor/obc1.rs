@@ -0,0 +1,151 @@
+use serde::{Deserialize, Serialize};
+
+/// Size of the SRAM on OBC1 boards, which the register window and
+/// object tables are mapped into
+pub const SRAM_SIZE: usize = 0x2000;
+
+/// Offset of the OBC1 register window in SRAM ($7FF0-$7FF7)
+const REG_BASE: usize = 0x1FF0;
+
+/// Register offsets within the register window
+const REG_ATTR: usize = 0x04;
+const REG_BASESEL: usize = 0x05;
+const REG_INDEX: usize = 0x06;
+
+/// Offset of the packed 2-bit attribute table from the base
+const ATTR_TABLE_OFFSET: usize = 0x200;
+
+/// OBC1 object controller
+///
+/// The OBC1 sits between the S-CPU and the 8 KB SRAM. It does not have any
+/// state of its own; the registers are backed by the SRAM itself, so the
+/// current base/index are derived from the SRAM contents on every access.
+#[derive(Serialize, Deserialize, Default)]
+pub struct OBC1 {}
+
+impl OBC1 {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    /// Base address of the active OAM table in SRAM (selected by $7FF5 bit 0)
+    fn base(ram: &[u8]) -> usize {
+        if ram[REG_BASE + REG_BASESEL] & 1 != 0 {
+            0x1800
+        } else {
+            0x1C00
+        }
+    }
+
+    /// Object index (selected by $7FF6)
+    fn index(ram: &[u8]) -> usize {
+        (ram[REG_BASE + REG_INDEX] & 0x7F) as usize
+    }
+
+    /// Bit position of the 2-bit attribute of the current object
+    /// within its attribute byte
+    fn attr_shift(ram: &[u8]) -> usize {
+        ((ram[REG_BASE + REG_INDEX] & 0x03) as usize) << 1
+    }
+
+    /// Translates an access to a register in the register window to
+    /// the SRAM address it actually accesses.
+    fn translate(ram: &[u8], reg: usize) -> usize {
+        match reg {
+            // Object X, Y, tile, attributes
+            0x00..=0x03 => Self::base(ram) + (Self::index(ram) << 2) + reg,
+            // Packed 2-bit attributes
+            REG_ATTR => Self::base(ram) + (Self::index(ram) >> 2) + ATTR_TABLE_OFFSET,
+            _ => REG_BASE + reg,
+        }
+    }
+
+    /// Reads from the SRAM window at $6000-$7FFF.
+    pub fn read(&self, ram: &[u8], addr: usize) -> u8 {
+        let addr = addr & 0x1FFF;
+        match addr {
+            0x1FF0..=0x1FF4 => ram[Self::translate(ram, addr - REG_BASE)],
+            _ => ram[addr],
+        }
+    }
+
+    /// Writes to the SRAM window at $6000-$7FFF.
+    pub fn write(&mut self, ram: &mut [u8], addr: usize, val: u8) {
+        let addr = addr & 0x1FFF;
+        match addr {
+            0x1FF0..=0x1FF3 => {
+                let a = Self::translate(ram, addr - REG_BASE);
+                ram[a] = val;
+            }
+            0x1FF4 => {
+                let a = Self::translate(ram, REG_ATTR);
+                let shift = Self::attr_shift(ram);
+                ram[a] = (ram[a] & !(0x03 << shift)) | ((val & 0x03) << shift);
+            }
+            // Base select, index and the remaining registers are
+            // stored in SRAM as-is.
+            _ => ram[addr] = val,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ram() -> Vec<u8> {
+        vec![0; 0x2000]
+    }
+
+    #[test]
+    fn obc1_object_regs() {
+        let mut ram = ram();
+        let mut obc1 = OBC1::new();
+
+        // Base 0x1C00, object 5
+        obc1.write(&mut ram, 0x7FF5, 0);
+        obc1.write(&mut ram, 0x7FF6, 5);
+        for i in 0..4 {
+            obc1.write(&mut ram, 0x7FF0 + i, 0x10 + i as u8);
+        }
+        assert_eq!(ram[0x1C14..0x1C18], [0x10, 0x11, 0x12, 0x13]);
+        assert_eq!(obc1.read(&ram, 0x7FF2), 0x12);
+
+        // Base 0x1800
+        obc1.write(&mut ram, 0x7FF5, 1);
+        obc1.write(&mut ram, 0x7FF0, 0xAA);
+        assert_eq!(ram[0x1814], 0xAA);
+        assert_eq!(obc1.read(&ram, 0x7FF0), 0xAA);
+    }
+
+    #[test]
+    fn obc1_attr_packing() {
+        let mut ram = ram();
+        let mut obc1 = OBC1::new();
+
+        for idx in 4..8 {
+            obc1.write(&mut ram, 0x7FF6, idx);
+            obc1.write(&mut ram, 0x7FF4, idx & 0x03);
+        }
+        // Objects 4-7 share byte 0x1C00 + 0x200 + 1
+        assert_eq!(ram[0x1E01], 0b11_10_01_00);
+
+        obc1.write(&mut ram, 0x7FF6, 6);
+        obc1.write(&mut ram, 0x7FF4, 0xFC);
+        assert_eq!(ram[0x1E01], 0b11_00_01_00);
+        assert_eq!(obc1.read(&ram, 0x7FF4), 0b11_00_01_00);
+    }
+
+    #[test]
+    fn obc1_passthrough() {
+        let mut ram = ram();
+        let mut obc1 = OBC1::new();
+
+        obc1.write(&mut ram, 0x6123, 0x55);
+        assert_eq!(ram[0x0123], 0x55);
+        assert_eq!(obc1.read(&ram, 0x6123), 0x55);
+
+        obc1.write(&mut ram, 0x7FF7, 0x66);
+        assert_eq!(ram[0x1FF7], 0x66);
+    }
+}
@@ -5,6 +5,7 @@ use std::cell::RefCell;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
+use super::BatteryRam;
 use crate::bus::{Address, Bus, BusMember};
 use crate::cpu_65816::cpu::Cpu65816;
 use crate::tickable::{Tickable, Ticks};
@@ -22,10 +23,13 @@ pub struct SA1 {
 
     /// SA-1 CPU control shadow register
     pub ccnt: u8,
+
+    /// Size of the (battery backed) BW-RAM on the cartridge
+    bwram_size: usize,
 }
 
 impl SA1 {
-    pub fn new(rom: &[u8], rom_mask: usize) -> Self {
+    pub fn new(rom: &[u8], rom_mask: usize, bwram_size: usize) -> Self {
         let mut cpu = Cpu65816::new(Sa1Bus::new(rom.to_owned(), rom_mask));
 
         // Address of CRV
@@ -38,6 +42,7 @@ impl SA1 {
         Self {
             cpu: RefCell::new(cpu),
             ccnt: CCNT_DEFAULT,
+            bwram_size: bwram_size.min(BWRAM_SIZE),
         }
     }
 
@@ -48,6 +53,21 @@ impl SA1 {
     }
 }
 
+impl BatteryRam for SA1 {
+    fn battery_ram_size(&self) -> usize {
+        self.bwram_size
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.bwram_size);
+        self.cpu.get_mut().bus.bwram[..len].copy_from_slice(&data[..len]);
+    }
+
+    fn save_battery_ram(&self) -> Vec<u8> {
+        self.cpu.borrow().bus.bwram[..self.bwram_size].to_vec()
+    }
+}
+
 impl Tickable for SA1 {
     fn tick(&mut self, ticks: Ticks) -> Result<Ticks> {
         let mut cpu = self.cpu.borrow_mut();
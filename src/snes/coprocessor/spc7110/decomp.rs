@@ -0,0 +1,323 @@
+use serde::{Deserialize, Serialize};
+
+/// Arithmetic coder constants
+const HALF: u8 = 0x55;
+const MAX: u16 = 0xFF;
+
+/// Initial most-recently-used list of colors
+const COLORMAP_INIT: u64 = 0xFEDCBA9876543210;
+
+/// Probability model state
+struct ModelState {
+    /// Probability of the more probable symbol (MPS)
+    probability: u8,
+    /// Next state after output of {MPS, LPS}
+    next: [u8; 2],
+}
+
+const fn ms(probability: u8, mps: u8, lps: u8) -> ModelState {
+    ModelState {
+        probability,
+        next: [mps, lps],
+    }
+}
+
+/// Probability model state machine
+const EVOLUTION: [ModelState; 53] = [
+    ms(0x5A, 1, 1),
+    ms(0x25, 2, 6),
+    ms(0x11, 3, 8),
+    ms(0x08, 4, 10),
+    ms(0x03, 5, 12),
+    ms(0x01, 5, 15),
+    ms(0x5A, 7, 7),
+    ms(0x3F, 8, 19),
+    ms(0x2C, 9, 21),
+    ms(0x20, 10, 22),
+    ms(0x17, 11, 23),
+    ms(0x11, 12, 25),
+    ms(0x0C, 13, 26),
+    ms(0x09, 14, 28),
+    ms(0x07, 15, 29),
+    ms(0x05, 16, 31),
+    ms(0x04, 17, 32),
+    ms(0x03, 18, 34),
+    ms(0x02, 5, 35),
+    ms(0x5A, 20, 20),
+    ms(0x48, 21, 39),
+    ms(0x3A, 22, 40),
+    ms(0x2E, 23, 42),
+    ms(0x26, 24, 44),
+    ms(0x1F, 25, 45),
+    ms(0x19, 26, 46),
+    ms(0x15, 27, 25),
+    ms(0x11, 28, 26),
+    ms(0x0E, 29, 26),
+    ms(0x0B, 30, 27),
+    ms(0x09, 31, 28),
+    ms(0x08, 32, 29),
+    ms(0x07, 33, 30),
+    ms(0x05, 34, 31),
+    ms(0x04, 35, 33),
+    ms(0x04, 36, 33),
+    ms(0x03, 37, 34),
+    ms(0x02, 38, 35),
+    ms(0x02, 5, 36),
+    ms(0x58, 40, 39),
+    ms(0x4D, 41, 47),
+    ms(0x43, 42, 48),
+    ms(0x3B, 43, 49),
+    ms(0x34, 44, 50),
+    ms(0x2E, 45, 51),
+    ms(0x29, 46, 44),
+    ms(0x25, 24, 45),
+    ms(0x56, 48, 47),
+    ms(0x4F, 49, 47),
+    ms(0x47, 50, 48),
+    ms(0x41, 51, 49),
+    ms(0x3C, 52, 50),
+    ms(0x37, 43, 51),
+];
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy)]
+struct Context {
+    /// Current model state
+    prediction: u8,
+    /// If 1, exchange the role of MPS and LPS
+    swap: u8,
+}
+
+/// Inverse morton code transform: unpack big-endian packed pixels.
+/// Returns odd bits in lower half, even bits in upper half.
+fn deinterleave(data: u64, bits: u32) -> u32 {
+    let mut data = data & ((1u64 << bits) - 1);
+    data = 0x5555555555555555 & ((data << bits) | (data >> 1));
+    data = 0x3333333333333333 & (data | (data >> 1));
+    data = 0x0F0F0F0F0F0F0F0F & (data | (data >> 2));
+    data = 0x00FF00FF00FF00FF & (data | (data >> 4));
+    data = 0x0000FFFF0000FFFF & (data | (data >> 8));
+    (data | (data >> 16)) as u32
+}
+
+/// Extracts a nibble from a list and moves it to the front (low 4 bits).
+fn move_to_front(list: u64, nibble: u64) -> u64 {
+    let mut mask: u64 = !15;
+    for n in (0..64).step_by(4) {
+        if (list >> n) & 15 == nibble {
+            return (list & mask) + ((list << 4) & !mask) + nibble;
+        }
+        mask <<= 4;
+    }
+    list
+}
+
+/// SPC7110 decompression unit, a context-adaptive binary arithmetic
+/// decoder for 1bpp (mode 0), 2bpp (mode 1) and 4bpp (mode 2) tile data.
+#[derive(Serialize, Deserialize)]
+pub struct Decompressor {
+    /// Bits per pixel
+    pub bpp: usize,
+    /// Data ROM read offset
+    offset: usize,
+    /// Bits remaining in input
+    bits: u32,
+    /// Arithmetic range (8-bits, but Max + 1 = 256)
+    range: u16,
+    /// Input data from the data ROM
+    input: u16,
+    output: u8,
+    pixels: u64,
+    /// Most recently used colors
+    colormap: u64,
+    /// Decompressed row after calling decode()
+    pub result: u32,
+    context: [[Context; 15]; 5],
+}
+
+impl Default for Decompressor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decompressor {
+    pub fn new() -> Self {
+        Self {
+            bpp: 1,
+            offset: 0,
+            bits: 8,
+            range: MAX + 1,
+            input: 0,
+            output: 0,
+            pixels: 0,
+            colormap: COLORMAP_INIT,
+            result: 0,
+            context: [[Context::default(); 15]; 5],
+        }
+    }
+
+    fn read(&mut self, read: &impl Fn(usize) -> u8) -> u8 {
+        let v = read(self.offset);
+        self.offset += 1;
+        v
+    }
+
+    /// Starts decompression of a new stream in the given mode.
+    pub fn initialize(&mut self, mode: u8, origin: usize, read: &impl Fn(usize) -> u8) {
+        self.context = [[Context::default(); 15]; 5];
+        self.bpp = 1 << mode;
+        self.offset = origin;
+        self.bits = 8;
+        self.range = MAX + 1;
+        self.input = self.read(read) as u16;
+        self.input = self.input << 8 | self.read(read) as u16;
+        self.output = 0;
+        self.pixels = 0;
+        self.colormap = COLORMAP_INIT;
+    }
+
+    /// Decodes the next row of 8 pixels into `result`.
+    pub fn decode(&mut self, read: &impl Fn(usize) -> u8) {
+        for pixel in 0..8 {
+            let mut map = self.colormap;
+            let mut diff = 0;
+
+            if self.bpp > 1 {
+                let (pa, pb, pc) = if self.bpp == 2 {
+                    (
+                        (self.pixels >> 2) & 3,
+                        (self.pixels >> 14) & 3,
+                        (self.pixels >> 16) & 3,
+                    )
+                } else {
+                    (
+                        self.pixels & 15,
+                        (self.pixels >> 28) & 15,
+                        (self.pixels >> 32) & 15,
+                    )
+                };
+
+                if pa != pb || pb != pc {
+                    let m = pa ^ pb ^ pc;
+                    // No match; all pixels differ
+                    diff = 4;
+                    if m ^ pc == 0 {
+                        // a == b; pixel c differs
+                        diff = 3;
+                    }
+                    if m ^ pa == 0 {
+                        // c == b; pixel a differs
+                        diff = 2;
+                    }
+                    if m ^ pb == 0 {
+                        // c == a; pixel b differs
+                        diff = 1;
+                    }
+                }
+
+                self.colormap = move_to_front(self.colormap, pa);
+
+                map = move_to_front(map, pc);
+                map = move_to_front(map, pb);
+                map = move_to_front(map, pa);
+            }
+
+            for plane in 0..self.bpp {
+                let bit = if self.bpp > 1 {
+                    1 << plane
+                } else {
+                    1 << (pixel & 3)
+                };
+                let history = (bit - 1) & self.output as usize;
+                let mut set = 0;
+
+                if self.bpp == 1 {
+                    set = (pixel >= 4) as usize;
+                }
+                if self.bpp == 2 {
+                    set = diff;
+                }
+                if plane >= 2 && history <= 1 {
+                    set = diff;
+                }
+
+                let ctx = &mut self.context[set][bit + history - 1];
+                let model = &EVOLUTION[ctx.prediction as usize];
+                let lps_offset = (self.range - model.probability as u16) as u8;
+                // LPS if true
+                let symbol = self.input >= (lps_offset as u16) << 8;
+
+                self.output = self.output << 1 | (symbol as u8 ^ ctx.swap);
+
+                if !symbol {
+                    self.range = lps_offset as u16;
+                } else {
+                    self.range -= lps_offset as u16;
+                    self.input = self.input.wrapping_sub((lps_offset as u16) << 8);
+                }
+
+                // Scale back into [0.75 ... 1.5]
+                while self.range <= MAX / 2 {
+                    ctx.prediction = model.next[symbol as usize];
+
+                    self.range <<= 1;
+                    self.input <<= 1;
+
+                    self.bits -= 1;
+                    if self.bits == 0 {
+                        self.bits = 8;
+                        let v = read(self.offset);
+                        self.offset += 1;
+                        self.input = self.input.wrapping_add(v as u16);
+                    }
+                }
+
+                if symbol && model.probability > HALF {
+                    ctx.swap ^= 1;
+                }
+            }
+
+            let mut index = self.output as u64 & ((1 << self.bpp) - 1);
+            if self.bpp == 1 {
+                index ^= (self.pixels >> 15) & 1;
+            }
+
+            self.pixels = self.pixels << self.bpp | ((map >> (4 * index)) & 15);
+        }
+
+        self.result = match self.bpp {
+            1 => self.pixels as u32,
+            2 => deinterleave(self.pixels, 16),
+            _ => deinterleave(deinterleave(self.pixels, 32) as u64, 32),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_move_to_front() {
+        assert_eq!(move_to_front(COLORMAP_INIT, 0), COLORMAP_INIT);
+        assert_eq!(move_to_front(COLORMAP_INIT, 3), 0xFEDCBA9876542103);
+        assert_eq!(move_to_front(COLORMAP_INIT, 15), 0xEDCBA9876543210F);
+    }
+
+    #[test]
+    fn test_deinterleave() {
+        // Bits alternate between the two planes
+        assert_eq!(deinterleave(0xAAAA, 16), 0x00FF);
+        assert_eq!(deinterleave(0x5555, 16), 0xFF00);
+    }
+
+    #[test]
+    fn decode_zeroes() {
+        // An all-zero stream decodes to the most probable symbols
+        let read = |_| 0;
+        let mut d = Decompressor::new();
+        d.initialize(0, 0, &read);
+        d.decode(&read);
+        assert_eq!(d.result & 0xFF, 0);
+    }
+}
@@ -0,0 +1,614 @@
+pub mod decomp;
+pub mod rtc;
+
+use std::cell::RefCell;
+
+use serde::{Deserialize, Serialize};
+
+use crate::bus::{Address, BusMember};
+
+use decomp::Decompressor;
+use rtc::Rtc4513;
+
+/// Size of the program ROM at the start of the ROM image.
+/// The data ROM immediately follows it.
+pub const PROGRAM_ROM_SIZE: usize = 0x100000;
+
+/// Mirrors an address into a (not necessarily power of 2) sized area
+fn mirror(mut addr: usize, mut size: usize) -> usize {
+    if size == 0 {
+        return 0;
+    }
+    let mut base = 0;
+    let mut mask = 1 << 23;
+    while addr >= size {
+        while addr & mask == 0 {
+            mask >>= 1;
+        }
+        addr -= mask;
+        if size > mask {
+            size -= mask;
+            base += mask;
+        }
+        mask >>= 1;
+    }
+    base + addr
+}
+
+/// Gets byte n of a multi-byte register
+fn get_byte(v: u32, n: usize) -> u8 {
+    (v >> (n * 8)) as u8
+}
+
+/// Sets byte n of a multi-byte register
+fn set_byte(v: u32, n: usize, val: u8) -> u32 {
+    (v & !(0xFF << (n * 8))) | ((val as u32) << (n * 8))
+}
+
+/// Reads from the data ROM, honoring the data ROM size
+/// configuration in $4834.
+fn datarom_read(rom: &[u8], rom_ctrl: u8, addr: usize) -> u8 {
+    if rom.is_empty() {
+        return 0;
+    }
+    let size = 1 << (rom_ctrl & 3);
+    let mask = 0x100000 * size - 1;
+    if rom_ctrl & 3 != 3 && addr & 0x400000 != 0 {
+        return 0;
+    }
+    rom[mirror(addr & mask, rom.len())]
+}
+
+/// Internal SPC7110 state
+#[derive(Serialize, Deserialize)]
+struct Spc7110State {
+    data_rom: Vec<u8>,
+    decomp: Decompressor,
+    rtc: Option<Rtc4513>,
+
+    /// $4801-$4803: decompression table address
+    dcu_table: u32,
+    /// $4804: decompression table index
+    dcu_index: u8,
+    /// $4805-$4806: decompression start offset
+    dcu_seek: u16,
+    /// $4807: rows to skip between tile rows
+    dcu_skip: u8,
+    /// $4809-$480A: decompression counter
+    dcu_counter: u16,
+    /// $480B: decompression control
+    dcu_ctrl: u8,
+    /// $480C: decompression status
+    dcu_status: u8,
+    /// Mode and ROM address loaded from the table
+    dcu_mode: u8,
+    dcu_addr: usize,
+    /// Decompressed tile buffer
+    dcu_tile: [u8; 32],
+    dcu_offset: usize,
+
+    /// $4810: data port value
+    dp_value: u8,
+    /// $4811-$4813: data port offset
+    dp_offset: u32,
+    /// $4814-$4815: data port adjust
+    dp_adjust: u16,
+    /// $4816-$4817: data port stride
+    dp_stride: u16,
+    /// $4818: data port mode
+    dp_mode: u8,
+
+    /// $4820-$4823: multiplicand/dividend
+    alu_a: u32,
+    /// $4824-$4825: multiplier
+    alu_mul: u16,
+    /// $4826-$4827: divisor
+    alu_div: u16,
+    /// $4828-$482B: product/quotient
+    alu_result: u32,
+    /// $482C-$482D: remainder
+    alu_rem: u16,
+    /// $482E: signed mode
+    alu_signed: u8,
+    /// $482F: ALU status
+    alu_status: u8,
+
+    /// $4830: SRAM enable
+    sram_ctrl: u8,
+    /// $4831-$4833: data ROM bank for $D0-$DF, $E0-$EF, $F0-$FF
+    banks: [u8; 3],
+    /// $4834: data ROM size
+    rom_ctrl: u8,
+}
+
+impl Spc7110State {
+    fn new(data_rom: Vec<u8>, rtc: bool) -> Self {
+        Self {
+            data_rom,
+            decomp: Decompressor::new(),
+            rtc: if rtc { Some(Rtc4513::new()) } else { None },
+            dcu_table: 0,
+            dcu_index: 0,
+            dcu_seek: 0,
+            dcu_skip: 0,
+            dcu_counter: 0,
+            dcu_ctrl: 0,
+            dcu_status: 0,
+            dcu_mode: 0,
+            dcu_addr: 0,
+            dcu_tile: [0; 32],
+            dcu_offset: 0,
+            dp_value: 0,
+            dp_offset: 0,
+            dp_adjust: 0,
+            dp_stride: 0,
+            dp_mode: 0,
+            alu_a: 0,
+            alu_mul: 0,
+            alu_div: 0,
+            alu_result: 0,
+            alu_rem: 0,
+            alu_signed: 0,
+            alu_status: 0,
+            sram_ctrl: 0,
+            banks: [0, 1, 2],
+            rom_ctrl: 0,
+        }
+    }
+
+    fn datarom_read(&self, addr: usize) -> u8 {
+        datarom_read(&self.data_rom, self.rom_ctrl, addr)
+    }
+
+    /// Loads the mode and ROM address of the compressed data from
+    /// the table in data ROM and starts decompression.
+    fn dcu_begin(&mut self) {
+        let entry = self.dcu_table as usize + ((self.dcu_index as usize) << 2);
+        self.dcu_mode = self.datarom_read(entry);
+        self.dcu_addr = (self.datarom_read(entry + 1) as usize) << 16
+            | (self.datarom_read(entry + 2) as usize) << 8
+            | self.datarom_read(entry + 3) as usize;
+
+        if self.dcu_mode > 2 {
+            // Invalid mode
+            return;
+        }
+
+        let (rom, rom_ctrl) = (&self.data_rom, self.rom_ctrl);
+        let read = |addr| datarom_read(rom, rom_ctrl, addr);
+        self.decomp.initialize(self.dcu_mode, self.dcu_addr, &read);
+        self.decomp.decode(&read);
+
+        let seek = if self.dcu_ctrl & 2 != 0 {
+            self.dcu_seek
+        } else {
+            0
+        };
+        for _ in 0..seek {
+            self.decomp.decode(&read);
+        }
+
+        self.dcu_status |= 0x80;
+        self.dcu_offset = 0;
+    }
+
+    /// Reads the next decompressed byte, in SNES planar tile format.
+    fn dcu_read(&mut self) -> u8 {
+        if self.dcu_status & 0x80 == 0 {
+            return 0;
+        }
+
+        if self.dcu_offset == 0 {
+            let (rom, rom_ctrl) = (&self.data_rom, self.rom_ctrl);
+            let read = |addr| datarom_read(rom, rom_ctrl, addr);
+
+            for row in 0..8 {
+                let r = self.decomp.result;
+                match self.decomp.bpp {
+                    1 => {
+                        self.dcu_tile[row] = r as u8;
+                    }
+                    2 => {
+                        self.dcu_tile[row * 2] = r as u8;
+                        self.dcu_tile[row * 2 + 1] = (r >> 8) as u8;
+                    }
+                    _ => {
+                        self.dcu_tile[row * 2] = r as u8;
+                        self.dcu_tile[row * 2 + 1] = (r >> 8) as u8;
+                        self.dcu_tile[row * 2 + 16] = (r >> 16) as u8;
+                        self.dcu_tile[row * 2 + 17] = (r >> 24) as u8;
+                    }
+                }
+
+                let skip = if self.dcu_ctrl & 1 != 0 {
+                    self.dcu_skip
+                } else {
+                    1
+                };
+                for _ in 0..skip {
+                    self.decomp.decode(&read);
+                }
+            }
+        }
+
+        let v = self.dcu_tile[self.dcu_offset];
+        self.dcu_offset = (self.dcu_offset + 1) & (8 * self.decomp.bpp - 1);
+        v
+    }
+
+    /// Adjust value, sign extended if selected in $4818
+    fn dp_adjust_value(&self) -> u32 {
+        if self.dp_mode & 8 != 0 {
+            self.dp_adjust as i16 as u32
+        } else {
+            self.dp_adjust as u32
+        }
+    }
+
+    /// Latches the data ROM byte at the current data port pointer
+    fn dp_read(&mut self) {
+        let adjust = if self.dp_mode & 2 != 0 {
+            self.dp_adjust_value()
+        } else {
+            0
+        };
+        self.dp_value = self.datarom_read(self.dp_offset.wrapping_add(adjust) as usize);
+    }
+
+    /// Advances the data port after a read of $4810
+    fn dp_increment(&mut self) {
+        let stride = match (self.dp_mode & 1 != 0, self.dp_mode & 4 != 0) {
+            (false, _) => 1,
+            (true, false) => self.dp_stride as u32,
+            (true, true) => self.dp_stride as i16 as u32,
+        };
+        if self.dp_mode & 0x10 == 0 {
+            self.dp_offset = self.dp_offset.wrapping_add(stride) & 0xFFFFFF;
+        } else {
+            self.dp_adjust = self.dp_adjust_value().wrapping_add(stride) as u16;
+        }
+        self.dp_read();
+    }
+
+    /// Adds the adjust value to the offset, if the trigger
+    /// (selected by $4818 bits 5-6) matches.
+    fn dp_apply_adjust(&mut self, trigger: u8) {
+        if self.dp_mode >> 5 != trigger {
+            return;
+        }
+        self.dp_offset = self.dp_offset.wrapping_add(self.dp_adjust_value()) & 0xFFFFFF;
+        self.dp_read();
+    }
+
+    fn alu_multiply(&mut self) {
+        self.alu_result = if self.alu_signed & 1 != 0 {
+            (self.alu_mul as i16 as i32).wrapping_mul(self.alu_a as u16 as i16 as i32) as u32
+        } else {
+            (self.alu_mul as u32).wrapping_mul(self.alu_a & 0xFFFF)
+        };
+        self.alu_status &= 0x7F;
+    }
+
+    fn alu_divide(&mut self) {
+        if self.alu_signed & 1 != 0 {
+            let dividend = self.alu_a as i32;
+            let divisor = self.alu_div as i16 as i32;
+            if divisor != 0 {
+                self.alu_result = dividend.wrapping_div(divisor) as u32;
+                self.alu_rem = dividend.wrapping_rem(divisor) as u16;
+            } else {
+                self.alu_result = 0;
+                self.alu_rem = dividend as u16;
+            }
+        } else {
+            let dividend = self.alu_a;
+            let divisor = self.alu_div as u32;
+            if let Some(quotient) = dividend.checked_div(divisor) {
+                self.alu_result = quotient;
+                self.alu_rem = (dividend % divisor) as u16;
+            } else {
+                self.alu_result = 0;
+                self.alu_rem = dividend as u16;
+            }
+        }
+        self.alu_status &= 0x7F;
+    }
+
+    fn read_reg(&mut self, reg: usize) -> Option<u8> {
+        Some(match reg {
+            // Decompression unit
+            0x4800 => {
+                self.dcu_counter = self.dcu_counter.wrapping_sub(1);
+                self.dcu_read()
+            }
+            0x4801..=0x4803 => get_byte(self.dcu_table, reg - 0x4801),
+            0x4804 => self.dcu_index,
+            0x4805..=0x4806 => get_byte(self.dcu_seek.into(), reg - 0x4805),
+            0x4807 => self.dcu_skip,
+            0x4808 => 0,
+            0x4809..=0x480A => get_byte(self.dcu_counter.into(), reg - 0x4809),
+            0x480B => self.dcu_ctrl,
+            0x480C => self.dcu_status,
+
+            // Data port
+            0x4810 => {
+                let v = self.dp_value;
+                self.dp_increment();
+                v
+            }
+            0x4811..=0x4813 => get_byte(self.dp_offset, reg - 0x4811),
+            0x4814..=0x4815 => get_byte(self.dp_adjust.into(), reg - 0x4814),
+            0x4816..=0x4817 => get_byte(self.dp_stride.into(), reg - 0x4816),
+            0x4818 => self.dp_mode,
+            0x481A => {
+                self.dp_apply_adjust(3);
+                0
+            }
+
+            // ALU
+            0x4820..=0x4823 => get_byte(self.alu_a, reg - 0x4820),
+            0x4824..=0x4825 => get_byte(self.alu_mul.into(), reg - 0x4824),
+            0x4826..=0x4827 => get_byte(self.alu_div.into(), reg - 0x4826),
+            0x4828..=0x482B => get_byte(self.alu_result, reg - 0x4828),
+            0x482C..=0x482D => get_byte(self.alu_rem.into(), reg - 0x482C),
+            0x482E => self.alu_signed,
+            0x482F => self.alu_status,
+
+            // Memory control unit
+            0x4830 => self.sram_ctrl,
+            0x4831..=0x4833 => self.banks[reg - 0x4831],
+            0x4834 => self.rom_ctrl,
+
+            // RTC-4513
+            0x4840..=0x4842 => self.rtc.as_mut()?.read(reg),
+
+            _ => return None,
+        })
+    }
+
+    fn write_reg(&mut self, reg: usize, val: u8) -> Option<()> {
+        match reg {
+            // Decompression unit
+            0x4801..=0x4803 => self.dcu_table = set_byte(self.dcu_table, reg - 0x4801, val),
+            0x4804 => self.dcu_index = val,
+            0x4805 => self.dcu_seek = set_byte(self.dcu_seek.into(), 0, val) as u16,
+            0x4806 => {
+                self.dcu_seek = set_byte(self.dcu_seek.into(), 1, val) as u16;
+                self.dcu_status &= 0x7F;
+                self.dcu_begin();
+            }
+            0x4807 => self.dcu_skip = val,
+            0x4808 => (),
+            0x4809..=0x480A => {
+                self.dcu_counter = set_byte(self.dcu_counter.into(), reg - 0x4809, val) as u16
+            }
+            0x480B => self.dcu_ctrl = val & 0x03,
+
+            // Data port
+            0x4811..=0x4812 => self.dp_offset = set_byte(self.dp_offset, reg - 0x4811, val),
+            0x4813 => {
+                self.dp_offset = set_byte(self.dp_offset, 2, val & 0x7F);
+                self.dp_read();
+            }
+            0x4814 => {
+                self.dp_adjust = set_byte(self.dp_adjust.into(), 0, val) as u16;
+                self.dp_apply_adjust(1);
+            }
+            0x4815 => {
+                self.dp_adjust = set_byte(self.dp_adjust.into(), 1, val) as u16;
+                self.dp_apply_adjust(2);
+            }
+            0x4816..=0x4817 => {
+                self.dp_stride = set_byte(self.dp_stride.into(), reg - 0x4816, val) as u16
+            }
+            0x4818 => {
+                self.dp_mode = val & 0x7F;
+                self.dp_read();
+            }
+
+            // ALU
+            0x4820..=0x4823 => self.alu_a = set_byte(self.alu_a, reg - 0x4820, val),
+            0x4824 => self.alu_mul = set_byte(self.alu_mul.into(), 0, val) as u16,
+            0x4825 => {
+                self.alu_mul = set_byte(self.alu_mul.into(), 1, val) as u16;
+                self.alu_status |= 0x81;
+                self.alu_multiply();
+            }
+            0x4826 => self.alu_div = set_byte(self.alu_div.into(), 0, val) as u16,
+            0x4827 => {
+                self.alu_div = set_byte(self.alu_div.into(), 1, val) as u16;
+                self.alu_status |= 0x80;
+                self.alu_divide();
+            }
+            0x482E => self.alu_signed = val & 0x01,
+
+            // Memory control unit
+            0x4830 => self.sram_ctrl = val & 0x87,
+            0x4831..=0x4833 => self.banks[reg - 0x4831] = val & 0x07,
+            0x4834 => self.rom_ctrl = val & 0x07,
+
+            // RTC-4513
+            0x4840..=0x4842 => self.rtc.as_mut()?.write(reg, val),
+
+            _ => return None,
+        }
+        Some(())
+    }
+}
+
+/// Epson SPC7110 data decompression/memory controller
+#[derive(Serialize, Deserialize)]
+pub struct SPC7110 {
+    state: RefCell<Spc7110State>,
+}
+
+impl SPC7110 {
+    pub fn new(data_rom: Vec<u8>, rtc: bool) -> Self {
+        Self {
+            state: RefCell::new(Spc7110State::new(data_rom, rtc)),
+        }
+    }
+
+    /// SRAM is mapped in ($4830 bit 7)
+    pub fn sram_enabled(&self) -> bool {
+        self.state.borrow().sram_ctrl & 0x80 != 0
+    }
+
+    pub fn has_rtc(&self) -> bool {
+        self.state.borrow().rtc.is_some()
+    }
+
+    /// Sets the current time (Unix time, in seconds) of the RTC, if any
+    pub fn set_time(&mut self, time: i64) {
+        if let Some(rtc) = self.state.get_mut().rtc.as_mut() {
+            rtc.set_time(time);
+        }
+    }
+
+    /// Advances the RTC, if any, by the specified amount of seconds
+    pub fn advance_time(&mut self, secs: i64) {
+        if let Some(rtc) = self.state.get_mut().rtc.as_mut() {
+            rtc.advance_time(secs);
+        }
+    }
+}
+
+impl BusMember<Address> for SPC7110 {
+    fn read(&self, fulladdr: Address) -> Option<u8> {
+        let (bank, addr) = ((fulladdr >> 16) as usize, (fulladdr & 0xFFFF) as usize);
+        let mut state = self.state.borrow_mut();
+
+        match (bank, addr) {
+            // I/O registers
+            (0x00..=0x3F | 0x80..=0xBF, 0x4800..=0x4842) => state.read_reg(addr),
+
+            // Decompression port mirror
+            (0x50, _) => state.read_reg(0x4800),
+            (0x58, _) => state.read_reg(0x4808),
+
+            // Bank switched data ROM
+            (0x10..=0x3F | 0x90..=0xBF, 0x8000..=0xFFFF) | (0xD0..=0xFF, _) => {
+                let bankreg = state.banks[((bank >> 4) & 3) - 1] as usize;
+                let offset = ((bank & 0x0F) << 16 | addr) + bankreg * 0x100000;
+                Some(state.datarom_read(offset))
+            }
+
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, fulladdr: Address, val: u8) -> Option<()> {
+        let (bank, addr) = ((fulladdr >> 16) as usize, (fulladdr & 0xFFFF) as usize);
+        let mut state = self.state.borrow_mut();
+
+        match (bank, addr) {
+            // I/O registers
+            (0x00..=0x3F | 0x80..=0xBF, 0x4800..=0x4842) => state.write_reg(addr, val),
+
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spc7110() -> SPC7110 {
+        SPC7110::new(
+            (0..0x300000).map(|i| (i >> 16) as u8 ^ i as u8).collect(),
+            false,
+        )
+    }
+
+    fn write16(s: &mut SPC7110, addr: Address, val: u16) {
+        s.write(addr, val as u8);
+        s.write(addr + 1, (val >> 8) as u8);
+    }
+
+    fn read32(s: &SPC7110, addr: Address) -> u32 {
+        (0..4).fold(0, |acc, i| {
+            acc | (s.read(addr + i).unwrap() as u32) << (i * 8)
+        })
+    }
+
+    #[test]
+    fn alu_unsigned() {
+        let mut s = spc7110();
+        write16(&mut s, 0x4820, 1234);
+        write16(&mut s, 0x4824, 5678);
+        assert_eq!(read32(&s, 0x4828), 1234 * 5678);
+        assert_eq!(s.read(0x482F), Some(0x01));
+
+        write16(&mut s, 0x4820, 0x5678);
+        write16(&mut s, 0x4822, 0x1234);
+        write16(&mut s, 0x4826, 1000);
+        assert_eq!(read32(&s, 0x4828), 0x12345678 / 1000);
+        assert_eq!(
+            s.read(0x482C).unwrap() as u32 | (s.read(0x482D).unwrap() as u32) << 8,
+            0x12345678 % 1000
+        );
+    }
+
+    #[test]
+    fn alu_signed() {
+        let mut s = spc7110();
+        s.write(0x482E, 1);
+        write16(&mut s, 0x4820, (-3i16) as u16);
+        write16(&mut s, 0x4824, 7);
+        assert_eq!(read32(&s, 0x4828) as i32, -21);
+
+        write16(&mut s, 0x4820, (-100i32) as u16);
+        write16(&mut s, 0x4822, ((-100i32) >> 16) as u16);
+        write16(&mut s, 0x4826, 7);
+        assert_eq!(read32(&s, 0x4828) as i32, -14);
+
+        // Division by zero
+        write16(&mut s, 0x4826, 0);
+        assert_eq!(read32(&s, 0x4828), 0);
+    }
+
+    #[test]
+    fn data_port() {
+        let mut s = spc7110();
+        let expect = |i: usize| (i >> 16) as u8 ^ i as u8;
+
+        s.write(0x4818, 0);
+        s.write(0x4811, 0x10);
+        s.write(0x4812, 0x00);
+        s.write(0x4813, 0x01);
+        assert_eq!(s.read(0x4810), Some(expect(0x010010)));
+        assert_eq!(s.read(0x4810), Some(expect(0x010011)));
+        assert_eq!(s.read(0x4811), Some(0x12));
+
+        // Stride
+        write16(&mut s, 0x4816, 0x100);
+        s.write(0x4818, 0x01);
+        assert_eq!(s.read(0x4810), Some(expect(0x010012)));
+        assert_eq!(s.read(0x4810), Some(expect(0x010112)));
+
+        // Adjust applied on write to $4814
+        s.write(0x4818, 0x20);
+        s.write(0x4814, 0x08);
+        assert_eq!(s.read(0x4813), Some(0x01));
+        assert_eq!(s.read(0x4811), Some(0x1A));
+    }
+
+    #[test]
+    fn bank_switching() {
+        let mut s = spc7110();
+        let expect = |i: usize| (i >> 16) as u8 ^ i as u8;
+
+        // 4 MB data ROM
+        s.write(0x4834, 2);
+        assert_eq!(s.read(0xD01234), Some(expect(0x001234)));
+        assert_eq!(s.read(0xE01234), Some(expect(0x101234)));
+        assert_eq!(s.read(0x2F9234), Some(expect(0x1F9234)));
+
+        s.write(0x4831, 2);
+        assert_eq!(s.read(0xD31234), Some(expect(0x231234)));
+        assert!(!s.sram_enabled());
+        s.write(0x4830, 0x80);
+        assert!(s.sram_enabled());
+    }
+}
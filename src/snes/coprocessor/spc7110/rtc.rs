@@ -0,0 +1,299 @@
+use serde::{Deserialize, Serialize};
+
+/// Commands that can be sent after selecting the chip
+const CMD_WRITE: u8 = 0x03;
+const CMD_READ: u8 = 0x0C;
+
+/// Register (nibble) indices
+const REG_SECLO: usize = 0x00;
+const REG_SECHI: usize = 0x01;
+const REG_MINLO: usize = 0x02;
+const REG_MINHI: usize = 0x03;
+const REG_HOURLO: usize = 0x04;
+const REG_HOURHI: usize = 0x05;
+const REG_DAYLO: usize = 0x06;
+const REG_DAYHI: usize = 0x07;
+const REG_MONTHLO: usize = 0x08;
+const REG_MONTHHI: usize = 0x09;
+const REG_YEARLO: usize = 0x0A;
+const REG_YEARHI: usize = 0x0B;
+const REG_WEEKDAY: usize = 0x0C;
+const REG_CTRLF: usize = 0x0F;
+
+/// Control register F: 24-hour mode
+const CTRLF_24H: u8 = 1 << 2;
+/// Hour high nibble: PM flag (12-hour mode)
+const HOURHI_PM: u8 = 1 << 2;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+enum RtcState {
+    /// Waiting for a command
+    Mode,
+    /// Waiting for the start address
+    Seek,
+    Read,
+    Write,
+}
+
+/// Converts days since the Unix epoch to (year, month, day).
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = (z - era * 146097) as u64;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let y = yoe as i64 + era * 400;
+    (if m <= 2 { y + 1 } else { y }, m, d)
+}
+
+/// Converts (year, month, day) to days since the Unix epoch.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = (y - era * 400) as u64;
+    let mp = if month > 2 { month - 3 } else { month + 9 } as u64;
+    let doy = (153 * mp + 2) / 5 + day as u64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe as i64 - 719468
+}
+
+/// Epson RTC-4513 real-time clock, as used by the SPC7110 in
+/// Far East of Eden Zero.
+///
+/// The clock runs on emulated time: it is set by the frontend and advanced
+/// by the emulator, so emulation does not depend on the host clock.
+#[derive(Serialize, Deserialize)]
+pub struct Rtc4513 {
+    chipselect: u8,
+    state: RtcState,
+    /// Last command/data written
+    mdr: u8,
+    /// Current register index
+    offset: usize,
+    /// Register nibbles; the time registers are latched at the start of a read
+    regs: [u8; 16],
+    /// Time registers were written since the chip was selected
+    dirty: bool,
+    /// Current time (Unix time, in seconds)
+    time: i64,
+}
+
+impl Default for Rtc4513 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Rtc4513 {
+    pub fn new() -> Self {
+        let mut regs = [0; 16];
+        regs[REG_CTRLF] = CTRLF_24H;
+        Self {
+            chipselect: 0,
+            state: RtcState::Mode,
+            mdr: 0,
+            offset: 0,
+            regs,
+            dirty: false,
+            time: 0,
+        }
+    }
+
+    /// Sets the current time (Unix time, in seconds)
+    pub fn set_time(&mut self, time: i64) {
+        self.time = time;
+    }
+
+    /// Advances the clock by the specified amount of seconds
+    pub fn advance_time(&mut self, secs: i64) {
+        self.time += secs;
+    }
+
+    fn bcd(lo: u8, hi: u8) -> u32 {
+        (lo & 0x0F) as u32 + (hi as u32 * 10)
+    }
+
+    /// Latches the emulated time into the time registers.
+    fn latch(&mut self) {
+        let t = self.time;
+        let days = t.div_euclid(86400);
+        let secs = t.rem_euclid(86400) as u32;
+        let (year, month, day) = civil_from_days(days);
+        let (hour, minute, second) = (secs / 3600, (secs / 60) % 60, secs % 60);
+
+        let (hour, pm) = if self.regs[REG_CTRLF] & CTRLF_24H != 0 {
+            (hour, false)
+        } else {
+            (hour % 12, hour >= 12)
+        };
+        let year = year.rem_euclid(100) as u32;
+
+        self.regs[REG_SECLO] = (second % 10) as u8;
+        self.regs[REG_SECHI] = (second / 10) as u8;
+        self.regs[REG_MINLO] = (minute % 10) as u8;
+        self.regs[REG_MINHI] = (minute / 10) as u8;
+        self.regs[REG_HOURLO] = (hour % 10) as u8;
+        self.regs[REG_HOURHI] = (hour / 10) as u8 | if pm { HOURHI_PM } else { 0 };
+        self.regs[REG_DAYLO] = (day % 10) as u8;
+        self.regs[REG_DAYHI] = (day / 10) as u8;
+        self.regs[REG_MONTHLO] = (month % 10) as u8;
+        self.regs[REG_MONTHHI] = (month / 10) as u8;
+        self.regs[REG_YEARLO] = (year % 10) as u8;
+        self.regs[REG_YEARHI] = (year / 10) as u8;
+        self.regs[REG_WEEKDAY] = (days + 4).rem_euclid(7) as u8;
+    }
+
+    /// Sets the clock from the (written) time registers.
+    fn commit(&mut self) {
+        let r = &self.regs;
+        let second = Self::bcd(r[REG_SECLO], r[REG_SECHI] & 0x07);
+        let minute = Self::bcd(r[REG_MINLO], r[REG_MINHI] & 0x07);
+        let mut hour = Self::bcd(r[REG_HOURLO], r[REG_HOURHI] & 0x03);
+        if r[REG_CTRLF] & CTRLF_24H == 0 && r[REG_HOURHI] & HOURHI_PM != 0 {
+            hour += 12;
+        }
+        let day = Self::bcd(r[REG_DAYLO], r[REG_DAYHI] & 0x03).max(1);
+        let month = Self::bcd(r[REG_MONTHLO], r[REG_MONTHHI] & 0x01).clamp(1, 12);
+        let year = Self::bcd(r[REG_YEARLO], r[REG_YEARHI]) as i64;
+        let year = if year >= 90 { 1900 + year } else { 2000 + year };
+
+        self.time =
+            days_from_civil(year, month, day) * 86400 + (hour * 3600 + minute * 60 + second) as i64;
+    }
+
+    fn reset(&mut self) {
+        if self.dirty {
+            self.commit();
+            self.dirty = false;
+        }
+        self.state = RtcState::Mode;
+        self.offset = 0;
+    }
+
+    /// Reads from the RTC registers ($4840-$4842)
+    pub fn read(&mut self, addr: usize) -> u8 {
+        match addr & 3 {
+            0 => self.chipselect,
+            1 => {
+                if self.chipselect != 1 {
+                    return 0;
+                }
+                match self.state {
+                    RtcState::Write => self.mdr,
+                    RtcState::Read => {
+                        let v = self.regs[self.offset & 0x0F];
+                        self.offset += 1;
+                        v
+                    }
+                    _ => 0,
+                }
+            }
+            // Always ready
+            2 => 0x80,
+            _ => 0,
+        }
+    }
+
+    /// Writes to the RTC registers ($4840-$4842)
+    pub fn write(&mut self, addr: usize, val: u8) {
+        match addr & 3 {
+            0 => {
+                self.chipselect = val;
+                if self.chipselect != 1 {
+                    self.reset();
+                }
+            }
+            1 if self.chipselect == 1 => {
+                let val = val & 0x0F;
+                match self.state {
+                    RtcState::Mode => {
+                        if val != CMD_WRITE && val != CMD_READ {
+                            return;
+                        }
+                        self.state = RtcState::Seek;
+                        self.mdr = val;
+                    }
+                    RtcState::Seek => {
+                        if self.mdr == CMD_WRITE {
+                            self.state = RtcState::Write;
+                        } else {
+                            self.state = RtcState::Read;
+                        }
+                        self.latch();
+                        self.offset = val as usize;
+                        self.mdr = val;
+                    }
+                    RtcState::Write => {
+                        let reg = self.offset & 0x0F;
+                        self.regs[reg] = val;
+                        if reg <= REG_WEEKDAY || reg == REG_CTRLF {
+                            self.dirty = true;
+                        }
+                        self.offset += 1;
+                        self.mdr = val;
+                    }
+                    RtcState::Read => (),
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_regs(rtc: &mut Rtc4513) -> [u8; 13] {
+        rtc.write(0, 1);
+        rtc.write(1, CMD_READ);
+        rtc.write(1, 0);
+        let mut out = [0; 13];
+        for r in out.iter_mut() {
+            *r = rtc.read(1);
+        }
+        rtc.write(0, 0);
+        out
+    }
+
+    #[test]
+    fn civil_roundtrip() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 2, 29), 11016);
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+    }
+
+    #[test]
+    fn set_and_read_time() {
+        let mut rtc = Rtc4513::new();
+        rtc.write(0, 1);
+        rtc.write(1, CMD_WRITE);
+        rtc.write(1, 0);
+        // 1999-12-31 (Fri) 23:59:30
+        for v in [0, 3, 9, 5, 3, 2, 1, 3, 2, 1, 9, 9, 5] {
+            rtc.write(1, v);
+        }
+        rtc.write(0, 0);
+
+        let regs = read_regs(&mut rtc);
+        assert_eq!(&regs[..], &[0, 3, 9, 5, 3, 2, 1, 3, 2, 1, 9, 9, 5]);
+
+        // Runs on emulated time only
+        rtc.advance_time(30);
+        let regs = read_regs(&mut rtc);
+        assert_eq!(&regs[..], &[0, 0, 0, 0, 0, 0, 1, 0, 1, 0, 0, 0, 6]);
+    }
+
+    #[test]
+    fn status_ready() {
+        let mut rtc = Rtc4513::new();
+        assert_eq!(rtc.read(2), 0x80);
+        // Ignores commands without chip select
+        rtc.write(1, CMD_READ);
+        assert_eq!(rtc.state, RtcState::Mode);
+    }
+}
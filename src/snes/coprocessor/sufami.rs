@@ -0,0 +1,188 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use super::BatteryRam;
+use crate::bus::{Address, BusMember};
+
+/// Magic at the start of a Sufami Turbo cartridge
+const SLOT_MAGIC: &[u8] = b"BANDAI SFC-ADX";
+/// Header offsets in a Sufami Turbo cartridge
+const SLOT_HDR_TITLE: usize = 0x10;
+const SLOT_HDR_TITLE_SIZE: usize = 14;
+const SLOT_HDR_SRAM_SIZE: usize = 0x37;
+
+/// A cartridge in one of the Sufami Turbo slots
+#[derive(Serialize, Deserialize)]
+pub struct SufamiSlot {
+    rom: Vec<u8>,
+    rom_mask: usize,
+    sram: Vec<u8>,
+}
+
+impl SufamiSlot {
+    pub fn new(rom: &[u8]) -> Result<Self> {
+        if rom.len() < SLOT_HDR_SRAM_SIZE + 1 || !rom.starts_with(SLOT_MAGIC) {
+            bail!("Not a Sufami Turbo cartridge");
+        }
+        let sram_size = rom[SLOT_HDR_SRAM_SIZE] as usize * 2048;
+        let rom_mask = rom.len().next_power_of_two() - 1;
+        let mut rom = rom.to_vec();
+        rom.resize(rom_mask + 1, 0xFF);
+
+        Ok(Self {
+            rom,
+            rom_mask,
+            sram: vec![0; sram_size],
+        })
+    }
+
+    /// Title of the cartridge
+    pub fn get_title(&self) -> String {
+        String::from_utf8_lossy(&self.rom[SLOT_HDR_TITLE..(SLOT_HDR_TITLE + SLOT_HDR_TITLE_SIZE)])
+            .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+            .to_string()
+    }
+
+    fn read_rom(&self, offset: usize) -> u8 {
+        self.rom[offset & self.rom_mask]
+    }
+
+    fn read_sram(&self, offset: usize) -> Option<u8> {
+        if self.sram.is_empty() {
+            return None;
+        }
+        Some(self.sram[offset % self.sram.len()])
+    }
+
+    fn write_sram(&mut self, offset: usize, val: u8) -> Option<()> {
+        if self.sram.is_empty() {
+            return None;
+        }
+        let len = self.sram.len();
+        Some(self.sram[offset % len] = val)
+    }
+}
+
+impl BatteryRam for SufamiSlot {
+    fn battery_ram_size(&self) -> usize {
+        self.sram.len()
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.sram.len());
+        self.sram[..len].copy_from_slice(&data[..len]);
+    }
+
+    fn save_battery_ram(&self) -> Vec<u8> {
+        self.sram.clone()
+    }
+}
+
+/// Sufami Turbo adapter, with slots A and B. The BIOS
+/// is the ROM of the base cartridge itself.
+#[derive(Serialize, Deserialize, Default)]
+pub struct SufamiTurbo {
+    pub slots: [Option<SufamiSlot>; 2],
+}
+
+impl SufamiTurbo {
+    pub const SLOT_A: usize = 0;
+    pub const SLOT_B: usize = 1;
+
+    pub fn new(slot_a: Option<SufamiSlot>, slot_b: Option<SufamiSlot>) -> Self {
+        Self {
+            slots: [slot_a, slot_b],
+        }
+    }
+
+    /// Offset of an address within a LoROM area
+    fn lorom_offset(bank: usize, addr: usize) -> usize {
+        (bank & 0x1F) * 0x8000 + (addr - 0x8000)
+    }
+}
+
+impl BusMember<Address> for SufamiTurbo {
+    fn read(&self, fulladdr: Address) -> Option<u8> {
+        let (bank, addr) = ((fulladdr >> 16) as usize, (fulladdr & 0xFFFF) as usize);
+        let offset = Self::lorom_offset(bank, addr);
+
+        match (bank & 0x7F, addr) {
+            // Slot ROMs
+            (0x20..=0x3F, 0x8000..=0xFFFF) => {
+                Some(self.slots[Self::SLOT_A].as_ref()?.read_rom(offset))
+            }
+            (0x40..=0x5F, 0x8000..=0xFFFF) => {
+                Some(self.slots[Self::SLOT_B].as_ref()?.read_rom(offset))
+            }
+
+            // Slot SRAMs
+            (0x60..=0x63, 0x8000..=0xFFFF) => self.slots[Self::SLOT_A].as_ref()?.read_sram(offset),
+            (0x70..=0x73, 0x8000..=0xFFFF) => self.slots[Self::SLOT_B].as_ref()?.read_sram(offset),
+            _ => None,
+        }
+    }
+
+    fn write(&mut self, fulladdr: Address, val: u8) -> Option<()> {
+        let (bank, addr) = ((fulladdr >> 16) as usize, (fulladdr & 0xFFFF) as usize);
+
+        match (bank & 0x7F, addr) {
+            // Slot SRAMs
+            (0x60..=0x63, 0x8000..=0xFFFF) => self.slots[Self::SLOT_A]
+                .as_mut()?
+                .write_sram(Self::lorom_offset(bank, addr), val),
+            (0x70..=0x73, 0x8000..=0xFFFF) => self.slots[Self::SLOT_B]
+                .as_mut()?
+                .write_sram(Self::lorom_offset(bank, addr), val),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slot_rom(title: &str, sram_kb: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x40000];
+        rom[..SLOT_MAGIC.len()].copy_from_slice(SLOT_MAGIC);
+        rom[SLOT_HDR_TITLE..(SLOT_HDR_TITLE + title.len())].copy_from_slice(title.as_bytes());
+        rom[SLOT_HDR_SRAM_SIZE] = sram_kb / 2;
+        rom[0x8000] = 0xAB;
+        rom
+    }
+
+    #[test]
+    fn slots() {
+        let a = SufamiSlot::new(&slot_rom("SLOT A", 8)).unwrap();
+        let b = SufamiSlot::new(&slot_rom("SLOT B", 0)).unwrap();
+        assert_eq!(a.get_title(), "SLOT A");
+        assert_eq!(a.battery_ram_size(), 8 * 1024);
+        assert!(SufamiSlot::new(&[0; 0x1000]).is_err());
+
+        let mut st = SufamiTurbo::new(Some(a), Some(b));
+        assert_eq!(st.read(0x208000), Some(b'B'));
+        assert_eq!(st.read(0xA18000), Some(0xAB));
+        assert_eq!(st.read(0x418000), Some(0xAB));
+
+        // Slot A SRAM (mirrored), slot B has none
+        assert_eq!(st.write(0x608000, 0x12), Some(()));
+        assert_eq!(st.read(0xE0A000), Some(0x12));
+        assert_eq!(st.write(0x708000, 0x12), None);
+        assert_eq!(st.read(0x708000), None);
+
+        assert_eq!(
+            st.slots[SufamiTurbo::SLOT_A]
+                .as_ref()
+                .unwrap()
+                .save_battery_ram()[0],
+            0x12
+        );
+    }
+
+    #[test]
+    fn empty_slot() {
+        let st = SufamiTurbo::new(None, None);
+        assert_eq!(st.read(0x208000), None);
+        assert_eq!(st.read(0x608000), None);
+    }
+}
@@ -3,6 +3,7 @@ use std::cell::RefCell;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
+use super::BatteryRam;
 use crate::bus::{Address, BusMember};
 use crate::cpu_gsu::cpu::{CpuGsu, GsuAddress, GsuMap, CACHE_LINE_SIZE};
 use crate::cpu_gsu::regs::{Flag, Register};
@@ -28,6 +29,22 @@ impl SuperFX {
     }
 }
 
+impl BatteryRam for SuperFX {
+    fn battery_ram_size(&self) -> usize {
+        let cpu = self.cpu.borrow();
+        cpu.ram.len().min(cpu.ram_mask.saturating_add(1))
+    }
+
+    fn load_battery_ram(&mut self, data: &[u8]) {
+        let len = data.len().min(self.battery_ram_size());
+        self.cpu.get_mut().ram[..len].copy_from_slice(&data[..len]);
+    }
+
+    fn save_battery_ram(&self) -> Vec<u8> {
+        self.cpu.borrow().ram[..self.battery_ram_size()].to_vec()
+    }
+}
+
 impl Tickable for SuperFX {
     fn tick(&mut self, ticks: Ticks) -> Result<Ticks> {
         let mut cpu = self.cpu.borrow_mut();
@@ -3,14 +3,20 @@ use std::sync::{Arc, Mutex};
 
 use crate::bus::Bus;
 use crate::cpu_65816::cpu::Cpu65816;
-use crate::frontend::Renderer;
+use crate::frontend::filter::Picture;
+use crate::frontend::{DisplayBuffer, Renderer};
 #[cfg(not(feature = "apu_blargg"))]
 use crate::snes::apu::apu::Apu;
+#[cfg(not(feature = "apu_blargg"))]
+use crate::snes::apu::spc::{Id666, SpcFile};
 #[cfg(feature = "apu_blargg")]
 use crate::snes::apu_blargg::Apu;
 use crate::snes::bus::mainbus::{BusTrace, Mainbus};
 use crate::snes::cartridge::{empty_ram, Cartridge, VideoFormat};
 use crate::snes::joypad::{Joypad, JoypadEvent, JoypadEventSender, JOYPAD_COUNT};
+use crate::snes::ppu::debug::DebugView;
+use crate::snes::ppu::ppu::RenderMode;
+use crate::snes::ppu::sprites::OAMEntry;
 use crate::tickable::{Tickable, Ticks};
 
 use anyhow::{anyhow, Result};
@@ -28,6 +34,39 @@ pub enum Schedule {
     SuperFX = 4,
     SA1 = 5,
     SuperGameboy = 6,
+    Cx4 = 7,
+}
+
+/// SPC700 clock (24.576 MHz oscillator / 24), in Hz
+const SPC700_CLOCK: Ticks = 1_024_000;
+/// DSP-1 clock (on-cartridge oscillator), in Hz
+const DSP1_CLOCK: Ticks = 7_600_000;
+/// Cx4 clock (on-cartridge oscillator), in Hz
+const CX4_CLOCK: Ticks = 20_000_000;
+
+/// Converts cycles of a component that runs off its own oscillator
+/// to master clock ticks, carrying over the remainder so no time is
+/// lost to rounding.
+struct ClockRatio {
+    master: Ticks,
+    clock: Ticks,
+    remainder: Ticks,
+}
+
+impl ClockRatio {
+    fn new(master: Ticks, clock: Ticks) -> Self {
+        Self {
+            master,
+            clock,
+            remainder: 0,
+        }
+    }
+
+    fn master_ticks(&mut self, cycles: Ticks) -> Ticks {
+        let ticks = cycles * self.master + self.remainder;
+        self.remainder = ticks % self.clock;
+        ticks / self.clock
+    }
 }
 
 pub struct Emulator<T>
@@ -38,6 +77,14 @@ where
     joypad_senders: Option<[JoypadEventSender; JOYPAD_COUNT]>,
     schedule_next: EnumMap<Schedule, Ticks>,
     schedule_ticks: Ticks,
+
+    spc700_clock: ClockRatio,
+    dsp1_clock: ClockRatio,
+    cx4_clock: ClockRatio,
+
+    /// Master cycles in a second, and when the cartridge time advances next
+    second_ticks: Ticks,
+    next_second: Ticks,
 }
 
 impl<T> Emulator<T>
@@ -49,6 +96,7 @@ where
         apu_ipl: &[u8],
         renderer: T,
         ovr_videoformat: Option<VideoFormat>,
+        render_mode: RenderMode,
     ) -> Result<Self> {
         // Set up joypad inputs
         let (joypads, joypad_senders) = Joypad::new_channel_all();
@@ -57,12 +105,6 @@ where
         // Determine video format (PAL/NTSC)
         let videoformat = ovr_videoformat.unwrap_or(cartridge.get_video_format());
 
-        // Determine frame rate limit based on the video format
-        let fps = match videoformat {
-            VideoFormat::NTSC => 60,
-            VideoFormat::PAL => 50,
-        };
-
         // Initialize S-CPU bus
         let bus = Mainbus::<T>::new(
             cartridge,
@@ -71,8 +113,8 @@ where
             joypads,
             apu_ipl,
             false,
-            fps,
             videoformat,
+            render_mode,
         );
 
         println!(
@@ -85,11 +127,19 @@ where
         // Initialize S-CPU
         let cpu = Cpu65816::<Mainbus<T>>::new(bus);
 
+        let master_clock = videoformat.master_clock();
         let mut emu = Self {
             cpu,
             joypad_senders: Some(joypad_senders),
             schedule_next: EnumMap::default(),
             schedule_ticks: 0,
+
+            spc700_clock: ClockRatio::new(master_clock, SPC700_CLOCK),
+            dsp1_clock: ClockRatio::new(master_clock, DSP1_CLOCK),
+            cx4_clock: ClockRatio::new(master_clock, CX4_CLOCK),
+
+            second_ticks: master_clock,
+            next_second: master_clock,
         };
 
         // Initialize scheduling for co-processors
@@ -105,6 +155,9 @@ where
         if emu.cpu.bus.cartridge.co_sgb.is_none() {
             emu.schedule_next[Schedule::SuperGameboy] = Ticks::MAX;
         }
+        if emu.cpu.bus.cartridge.co_cx4.is_none() {
+            emu.schedule_next[Schedule::Cx4] = Ticks::MAX;
+        }
 
         Ok(emu)
     }
@@ -112,7 +165,6 @@ where
     pub fn testmode(&mut self) {
         self.cpu.bus.ppu.single_threaded();
         self.schedule_next[Schedule::SPC700] = Ticks::MAX;
-        self.set_fps_limit(0);
     }
 
     pub fn load_state(&mut self, json: &str) -> Result<()> {
@@ -125,8 +177,24 @@ where
         let mut new_cpu: Cpu65816<Mainbus<T>> = Deserialize::deserialize(&mut deserializer)?;
         // ..and move all the non-serializable stuff over.
         new_cpu.bus.ppu.renderer = std::mem::replace(&mut self.cpu.bus.ppu.renderer, None);
+        new_cpu.bus.ppu.update_color_profile();
         new_cpu.bus.joypads = std::mem::replace(&mut self.cpu.bus.joypads, None);
         new_cpu.bus.cartridge.ram = std::mem::replace(&mut self.cpu.bus.cartridge.ram, empty_ram());
+        new_cpu.bus.cartridge.co_msu1 = self.cpu.bus.cartridge.co_msu1.take();
+        if let (Some(new_cx4), Some(cx4)) = (
+            new_cpu.bus.cartridge.co_cx4.as_mut(),
+            self.cpu.bus.cartridge.co_cx4.as_mut(),
+        ) {
+            new_cx4.cpu.get_mut().rom = std::mem::take(&mut cx4.cpu.get_mut().rom);
+        }
+        new_cpu.bus.apu.lock().unwrap().set_msu1(
+            new_cpu
+                .bus
+                .cartridge
+                .co_msu1
+                .as_ref()
+                .map(|m| m.get_audio()),
+        );
 
         self.cpu = new_cpu;
         Ok(())
@@ -143,8 +211,70 @@ where
         }
     }
 
-    pub fn set_fps_limit(&mut self, new_limit: u64) {
-        self.cpu.bus.ppu.set_fps_limit(new_limit);
+    /// Sets the current date and time (Unix time, in seconds) for
+    /// cartridges that keep or receive the time. From then on, the
+    /// time advances with the emulation.
+    pub fn set_time(&mut self, time: i64) {
+        self.cpu.bus.cartridge.set_time(time);
+    }
+
+    /// Frames completed since power on
+    pub fn get_frame_count(&self) -> u64 {
+        self.cpu.bus.ppu.get_frame_count()
+    }
+
+    /// Runs the emulation until the PPU completed a frame. The emulation
+    /// runs as fast as possible; pacing is up to the frontend.
+    pub fn run_frame(&mut self) -> Result<()> {
+        let frame = self.get_frame_count();
+        while self.get_frame_count() == frame {
+            self.tick()?;
+        }
+        Ok(())
+    }
+
+    /// Renders audio output of the APU, as interleaved stereo samples
+    /// at `Apu::SAMPLE_RATE`.
+    pub fn render_audio(&mut self, out: &mut [i16]) {
+        self.cpu.bus.apu.lock().unwrap().render(out);
+    }
+
+    /// Renders a view of PPU memory (VRAM, CGRAM, OAM), for debugging
+    pub fn render_debug_view(&self, view: DebugView) -> Result<Picture> {
+        self.cpu.bus.ppu.render_debug_view(view)
+    }
+
+    /// Gets the frame buffer of the renderer, holding the last completed
+    /// frame between `run_frame()` calls, and the height of the picture in it.
+    pub fn get_frame(&mut self) -> (DisplayBuffer, usize) {
+        self.cpu.bus.ppu.get_frame()
+    }
+
+    /// Captures the current picture at the resolution the PPU produced
+    /// it in. Use `Picture::save_png` to write it to a file.
+    pub fn screenshot(&mut self) -> Picture {
+        self.cpu.bus.ppu.screenshot()
+    }
+
+    /// Gets all decoded sprite entries in OAM
+    pub fn get_oam_entries(&self) -> Vec<OAMEntry> {
+        self.cpu.bus.ppu.get_oam_entries()
+    }
+
+    /// Gets the PPU debug layer mask (`DBG_*` in `snes::ppu::state`)
+    pub fn get_dbg_layermask(&self) -> u16 {
+        self.cpu.bus.ppu.get_dbg_layermask()
+    }
+
+    /// Sets the PPU debug layer mask (`DBG_*` in `snes::ppu::state`)
+    pub fn set_dbg_layermask(&mut self, mask: u16) {
+        self.cpu.bus.ppu.set_dbg_layermask(mask);
+    }
+
+    /// Toggles layers/effects in the PPU debug layer mask
+    pub fn toggle_dbg_layer(&mut self, mask: u16) {
+        let v = self.get_dbg_layermask() ^ mask;
+        self.set_dbg_layermask(v);
     }
 
     pub fn set_trace_bus(&mut self, v: BusTrace) {
@@ -167,10 +297,37 @@ where
         }
     }
 
+    pub fn get_cartridge(&self) -> &Cartridge {
+        &self.cpu.bus.cartridge
+    }
+
+    pub fn get_cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cpu.bus.cartridge
+    }
+
     pub fn get_apu(&mut self) -> Arc<Mutex<Apu>> {
         self.cpu.bus.get_apu()
     }
 
+    /// Captures the state of the APU as an SPC file, tagged with
+    /// the title of the cartridge and the dump date (Unix time).
+    #[cfg(not(feature = "apu_blargg"))]
+    pub fn save_spc(&self, time: i64) -> SpcFile {
+        let mut spc = self.cpu.bus.apu.lock().unwrap().save_spc();
+        spc.tags = Some(Id666 {
+            game_title: self.cpu.bus.cartridge.get_title(),
+            dumper: "Siena".to_string(),
+            dump_date: Id666::format_date(time),
+            ..Default::default()
+        });
+        spc
+    }
+
+    /// Video format (PAL/NTSC) the system runs in
+    pub fn get_videoformat(&self) -> VideoFormat {
+        self.cpu.bus.ppu.get_videoformat()
+    }
+
     pub fn set_verbose_cpu(&mut self, v: bool) {
         self.cpu.verbose = v;
     }
@@ -195,6 +352,13 @@ where
         }
     }
 
+    pub fn toggle_verbose_cx4(&mut self) {
+        if let Some(cx4) = self.cpu.bus.cartridge.co_cx4.as_mut() {
+            let mut cpu = cx4.cpu.borrow_mut();
+            cpu.verbose = !cpu.verbose;
+        }
+    }
+
     pub fn toggle_verbose_gsu(&mut self) {
         if let Some(sfx) = self.cpu.bus.cartridge.co_superfx.as_mut() {
             let mut gsu = sfx.cpu.borrow_mut();
@@ -211,7 +375,7 @@ where
     pub fn tick(&mut self) -> Result<()> {
         // The scheduler assumes a base clock, which is the SNES master clock,
         // monotonically increasing. This is not based on time; the wall clock time
-        // speed of the emulator is regulated by the frame rate limiter.
+        // speed of the emulator is regulated by the frontend.
         //
         // Every cycle-based component receives time to do a step when it
         // needs to run (based on a divider from the master clock).
@@ -244,6 +408,12 @@ where
             self.schedule_ticks += 1;
         }
 
+        // The cartridge time runs on emulated time
+        if self.schedule_ticks >= self.next_second {
+            self.next_second += self.second_ticks;
+            self.cpu.bus.cartridge.advance_time(1);
+        }
+
         Ok(())
     }
 
@@ -263,18 +433,20 @@ where
                 Ok(cpu_ticks + pause_ticks)
             }
             Schedule::PPU => {
-                // 5.3 MHz
+                // 5.3 MHz (4 master cycles per dot, some dots take 6)
+                let dot_ticks = self.cpu.bus.ppu.get_dot_master_cycles();
 
                 // Tick the bus too to make sure we're hitting all
                 // the HDMA slots.
                 self.cpu.bus.tick(1)?;
 
-                Ok(self.cpu.bus.ppu.tick(1)? * 4)
+                Ok(self.cpu.bus.ppu.tick(1)? * dot_ticks)
             }
             Schedule::SPC700 => {
                 // 1.024 MHz
                 let mut apu = self.cpu.bus.apu.lock().unwrap();
-                Ok(apu.tick(1)? * 21)
+                let cycles = apu.tick(1)?;
+                Ok(self.spc700_clock.master_ticks(cycles))
             }
             Schedule::SA1 => {
                 // ~10.74 MHz
@@ -293,14 +465,19 @@ where
                     * 1)
             }
             Schedule::DSP1 => {
-                // 7.6 - 8 MHz ?
-                // 2 master cycles leans towards faster, but works well..
-                Ok(self.cpu.bus.cartridge.co_dsp1.as_mut().unwrap().tick(1)? * 2)
+                // 7.6 MHz
+                let cycles = self.cpu.bus.cartridge.co_dsp1.as_mut().unwrap().tick(1)?;
+                Ok(self.dsp1_clock.master_ticks(cycles))
             }
             Schedule::SuperGameboy => {
                 // Divider handled by co-processor as it is configurable
                 Ok(self.cpu.bus.cartridge.co_sgb.as_mut().unwrap().tick(1)?)
             }
+            Schedule::Cx4 => {
+                // 20 MHz
+                let cycles = self.cpu.bus.cartridge.co_cx4.as_mut().unwrap().tick(1)?;
+                Ok(self.cx4_clock.master_ticks(cycles))
+            }
         }
     }
 }
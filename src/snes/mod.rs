@@ -3,9 +3,12 @@ pub mod apu;
 #[cfg(feature = "apu_blargg")]
 pub mod apu_blargg;
 
+pub mod boarddb;
 pub mod bus;
 pub mod cartridge;
+pub mod copier;
 pub mod coprocessor;
 pub mod emulator;
 pub mod joypad;
+pub mod patch;
 pub mod ppu;
@@ -0,0 +1,512 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use thiserror::Error;
+
+use super::cartridge::Cartridge;
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const BPS_MAGIC: &[u8] = b"BPS1";
+const UPS_MAGIC: &[u8] = b"UPS1";
+
+/// Size of the CRC32 footer of BPS/UPS patches
+const FOOTER_SIZE: usize = 12;
+
+/// Largest output size accepted from BPS/UPS patches, well above any
+/// cartridge, so a corrupt size field cannot exhaust memory.
+const MAX_TARGET_SIZE: usize = 64 * 1024 * 1024;
+
+/// Extensions of patches that are picked up automatically
+/// next to the ROM.
+const PATCH_EXTENSIONS: [&str; 3] = ["ips", "bps", "ups"];
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PatchFormat {
+    IPS,
+    BPS,
+    UPS,
+}
+
+#[derive(Debug, Error)]
+pub enum PatchError {
+    #[error("Unknown patch format")]
+    UnknownFormat,
+    #[error("Patch is truncated")]
+    Truncated,
+    #[error("Patch is corrupt (checksum {actual:08X}, expected {expected:08X})")]
+    PatchChecksum { expected: u32, actual: u32 },
+    #[error("Patch does not apply to this ROM (checksum {actual:08X}, expected {expected:08X})")]
+    SourceChecksum { expected: u32, actual: u32 },
+    #[error("Patched ROM is wrong (checksum {actual:08X}, expected {expected:08X})")]
+    TargetChecksum { expected: u32, actual: u32 },
+    #[error("Patch reads outside of the ROM")]
+    OutOfBounds,
+}
+
+/// Detects the format of a patch by its magic
+pub fn detect_format(patch: &[u8]) -> Option<PatchFormat> {
+    if patch.starts_with(IPS_MAGIC) {
+        Some(PatchFormat::IPS)
+    } else if patch.starts_with(BPS_MAGIC) {
+        Some(PatchFormat::BPS)
+    } else if patch.starts_with(UPS_MAGIC) {
+        Some(PatchFormat::UPS)
+    } else {
+        None
+    }
+}
+
+/// Finds patches next to a ROM file with the same name
+/// (e.g. game.sfc -> game.ips, game.bps, game.ups).
+pub fn find_patches(rom_path: &Path) -> Vec<PathBuf> {
+    PATCH_EXTENSIONS
+        .iter()
+        .map(|ext| rom_path.with_extension(ext))
+        .filter(|p| p.is_file())
+        .collect()
+}
+
+/// Applies a patch to a ROM image as loaded from disk.
+///
+/// A copier header, if present, is kept in place so the patched
+/// image can be passed to `Cartridge::load` as usual. IPS patches
+/// are applied to the ROM without copier header. BPS/UPS patches
+/// are applied to whichever of the two matches the source checksum.
+pub fn apply_patch(image: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    let hdr_size = Cartridge::copier_header_size(image.len()).unwrap_or(0);
+    let (header, rom) = image.split_at(hdr_size);
+
+    let apply = match detect_format(patch).ok_or(PatchError::UnknownFormat)? {
+        PatchFormat::IPS => {
+            let mut out = header.to_vec();
+            out.extend(apply_ips(rom, patch)?);
+            return Ok(out);
+        }
+        PatchFormat::BPS => apply_bps,
+        PatchFormat::UPS => apply_ups,
+    };
+
+    match apply(rom, patch) {
+        Ok(patched) => {
+            let mut out = header.to_vec();
+            out.extend(patched);
+            Ok(out)
+        }
+        // Patch may have been created against the image including copier header
+        Err(PatchError::SourceChecksum { .. }) if hdr_size > 0 => Ok(apply(image, patch)?),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Applies an IPS patch, including RLE records and the truncate extension
+pub fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if !patch.starts_with(IPS_MAGIC) {
+        return Err(PatchError::UnknownFormat);
+    }
+    let mut out = rom.to_vec();
+    let mut p = IPS_MAGIC.len();
+    let get = |from: usize, len: usize| patch.get(from..(from + len)).ok_or(PatchError::Truncated);
+    let be = |b: &[u8]| b.iter().fold(0usize, |acc, &v| (acc << 8) | v as usize);
+
+    loop {
+        let offset = get(p, 3)?;
+        if offset == IPS_EOF {
+            p += 3;
+            break;
+        }
+        let offset = be(offset);
+        let size = be(get(p + 3, 2)?);
+        p += 5;
+
+        let (len, data) = if size == 0 {
+            // RLE record
+            let len = be(get(p, 2)?);
+            let val = get(p + 2, 1)?[0];
+            p += 3;
+            (len, vec![val; len])
+        } else {
+            let data = get(p, size)?.to_vec();
+            p += size;
+            (size, data)
+        };
+
+        if out.len() < offset + len {
+            out.resize(offset + len, 0);
+        }
+        out[offset..(offset + len)].copy_from_slice(&data);
+    }
+
+    // Truncate extension
+    if let Ok(size) = get(p, 3) {
+        out.truncate(be(size));
+    }
+
+    Ok(out)
+}
+
+/// Decodes a BPS/UPS variable length number
+fn decode_number(patch: &[u8], p: &mut usize) -> Result<usize, PatchError> {
+    let mut data: usize = 0;
+    let mut shift: usize = 1;
+    loop {
+        let x = *patch.get(*p).ok_or(PatchError::Truncated)?;
+        *p += 1;
+        data = data.wrapping_add((x & 0x7F) as usize * shift);
+        if x & 0x80 != 0 {
+            return Ok(data);
+        }
+        shift <<= 7;
+        data = data.wrapping_add(shift);
+    }
+}
+
+/// Reads and verifies the CRC32 footer of a BPS/UPS patch.
+/// Returns (source CRC, target CRC).
+fn read_footer(patch: &[u8]) -> Result<(u32, u32), PatchError> {
+    if patch.len() < FOOTER_SIZE + 4 {
+        return Err(PatchError::Truncated);
+    }
+    let footer = &patch[(patch.len() - FOOTER_SIZE)..];
+    let crc = |i: usize| u32::from_le_bytes(footer[(i * 4)..(i * 4 + 4)].try_into().unwrap());
+
+    let actual = crc32fast::hash(&patch[..(patch.len() - 4)]);
+    if actual != crc(2) {
+        return Err(PatchError::PatchChecksum {
+            expected: crc(2),
+            actual,
+        });
+    }
+    Ok((crc(0), crc(1)))
+}
+
+/// Applies a BPS patch, verifying the source and target CRC32
+pub fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if !patch.starts_with(BPS_MAGIC) {
+        return Err(PatchError::UnknownFormat);
+    }
+    let (source_crc, target_crc) = read_footer(patch)?;
+    let actual = crc32fast::hash(rom);
+    if actual != source_crc {
+        return Err(PatchError::SourceChecksum {
+            expected: source_crc,
+            actual,
+        });
+    }
+
+    let mut p = BPS_MAGIC.len();
+    let _source_size = decode_number(patch, &mut p)?;
+    let target_size = decode_number(patch, &mut p)?;
+    if target_size > MAX_TARGET_SIZE {
+        return Err(PatchError::OutOfBounds);
+    }
+    let metadata_size = decode_number(patch, &mut p)?;
+    p = p.checked_add(metadata_size).ok_or(PatchError::Truncated)?;
+
+    let mut out: Vec<u8> = Vec::with_capacity(target_size.min(rom.len() + patch.len()));
+    let (mut source_offset, mut target_offset) = (0isize, 0isize);
+    let end = patch.len() - FOOTER_SIZE;
+
+    while p < end {
+        let data = decode_number(patch, &mut p)?;
+        let len = (data >> 2) + 1;
+        if len > target_size - out.len() {
+            return Err(PatchError::OutOfBounds);
+        }
+        match data & 3 {
+            // SourceRead
+            0 => {
+                let pos = out.len();
+                out.extend(rom.get(pos..(pos + len)).ok_or(PatchError::OutOfBounds)?);
+            }
+            // TargetRead
+            1 => {
+                let next = p.checked_add(len).ok_or(PatchError::Truncated)?;
+                out.extend(patch[..end].get(p..next).ok_or(PatchError::Truncated)?);
+                p = next;
+            }
+            // SourceCopy
+            2 => {
+                let delta = decode_number(patch, &mut p)?;
+                let delta = if delta & 1 != 0 {
+                    -((delta >> 1) as isize)
+                } else {
+                    (delta >> 1) as isize
+                };
+                source_offset = source_offset
+                    .checked_add(delta)
+                    .ok_or(PatchError::OutOfBounds)?;
+                let from = usize::try_from(source_offset).map_err(|_| PatchError::OutOfBounds)?;
+                let to = from.checked_add(len).ok_or(PatchError::OutOfBounds)?;
+                out.extend(rom.get(from..to).ok_or(PatchError::OutOfBounds)?);
+                source_offset = to as isize;
+            }
+            // TargetCopy (may overlap, so byte by byte)
+            _ => {
+                let delta = decode_number(patch, &mut p)?;
+                let delta = if delta & 1 != 0 {
+                    -((delta >> 1) as isize)
+                } else {
+                    (delta >> 1) as isize
+                };
+                target_offset = target_offset
+                    .checked_add(delta)
+                    .ok_or(PatchError::OutOfBounds)?;
+                for _ in 0..len {
+                    let from =
+                        usize::try_from(target_offset).map_err(|_| PatchError::OutOfBounds)?;
+                    let v = *out.get(from).ok_or(PatchError::OutOfBounds)?;
+                    out.push(v);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    let actual = crc32fast::hash(&out);
+    if out.len() != target_size || actual != target_crc {
+        return Err(PatchError::TargetChecksum {
+            expected: target_crc,
+            actual,
+        });
+    }
+    Ok(out)
+}
+
+/// Applies a UPS patch, verifying the input and output CRC32.
+/// UPS patches are reversible; applying a patch to its target
+/// restores the source.
+pub fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    if !patch.starts_with(UPS_MAGIC) {
+        return Err(PatchError::UnknownFormat);
+    }
+    let (source_crc, target_crc) = read_footer(patch)?;
+
+    let mut p = UPS_MAGIC.len();
+    let source_size = decode_number(patch, &mut p)?;
+    let target_size = decode_number(patch, &mut p)?;
+
+    let actual = crc32fast::hash(rom);
+    let (out_size, expected_crc) = if actual == source_crc {
+        (target_size, target_crc)
+    } else if actual == target_crc {
+        (source_size, source_crc)
+    } else {
+        return Err(PatchError::SourceChecksum {
+            expected: source_crc,
+            actual,
+        });
+    };
+
+    if out_size > MAX_TARGET_SIZE {
+        return Err(PatchError::OutOfBounds);
+    }
+    let mut out = rom.to_vec();
+    out.resize(out_size, 0);
+    let end = patch.len() - FOOTER_SIZE;
+    let mut offset = 0usize;
+
+    while p < end {
+        offset = offset
+            .checked_add(decode_number(patch, &mut p)?)
+            .ok_or(PatchError::OutOfBounds)?;
+        loop {
+            let x = *patch.get(p).ok_or(PatchError::Truncated)?;
+            p += 1;
+            if let Some(v) = out.get_mut(offset) {
+                *v ^= x;
+            }
+            offset = offset.checked_add(1).ok_or(PatchError::OutOfBounds)?;
+            if x == 0 {
+                break;
+            }
+        }
+    }
+
+    let actual = crc32fast::hash(&out);
+    if actual != expected_crc {
+        return Err(PatchError::TargetChecksum {
+            expected: expected_crc,
+            actual,
+        });
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_number(mut v: usize) -> Vec<u8> {
+        let mut out = vec![];
+        loop {
+            let x = (v & 0x7F) as u8;
+            v >>= 7;
+            if v == 0 {
+                out.push(x | 0x80);
+                return out;
+            }
+            out.push(x);
+            v -= 1;
+        }
+    }
+
+    fn add_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend(crc32fast::hash(source).to_le_bytes());
+        patch.extend(crc32fast::hash(target).to_le_bytes());
+        patch.extend(crc32fast::hash(&patch).to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn ips() {
+        let rom = vec![0u8; 16];
+        let mut patch = b"PATCH".to_vec();
+        // Normal record
+        patch.extend([0x00, 0x00, 0x02, 0x00, 0x02, 0xAA, 0xBB]);
+        // RLE record, expanding the ROM
+        patch.extend([0x00, 0x00, 0x0E, 0x00, 0x00, 0x00, 0x04, 0xCC]);
+        patch.extend(b"EOF");
+
+        let out = apply_ips(&rom, &patch).unwrap();
+        assert_eq!(out.len(), 18);
+        assert_eq!(&out[0..4], &[0, 0, 0xAA, 0xBB]);
+        assert_eq!(&out[14..18], &[0xCC; 4]);
+
+        // Truncate extension
+        patch.extend([0x00, 0x00, 0x08]);
+        assert_eq!(apply_ips(&rom, &patch).unwrap().len(), 8);
+
+        assert!(matches!(
+            apply_ips(&rom, &patch[..10]),
+            Err(PatchError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn bps() {
+        let source = b"Hello, world!".to_vec();
+        let target = b"Hello, world, world!!".to_vec();
+
+        let mut patch = BPS_MAGIC.to_vec();
+        patch.extend(encode_number(source.len()));
+        patch.extend(encode_number(target.len()));
+        patch.extend(encode_number(0));
+        // SourceRead "Hello, world"
+        patch.extend(encode_number((12 - 1) << 2));
+        // SourceCopy ", world" from offset 5
+        patch.extend(encode_number(((7 - 1) << 2) | 2));
+        patch.extend(encode_number(5 << 1));
+        // TargetRead "!"
+        patch.extend(encode_number(1));
+        patch.push(b'!');
+        // TargetCopy "!" from the previous byte
+        patch.extend(encode_number(3));
+        patch.extend(encode_number(19 << 1));
+        let patch = add_footer(patch, &source, &target);
+
+        assert_eq!(apply_bps(&source, &patch).unwrap(), target);
+        assert!(matches!(
+            apply_bps(&target, &patch),
+            Err(PatchError::SourceChecksum { .. })
+        ));
+
+        let mut corrupt = patch.clone();
+        corrupt[6] ^= 1;
+        assert!(matches!(
+            apply_bps(&source, &corrupt),
+            Err(PatchError::PatchChecksum { .. })
+        ));
+    }
+
+    #[test]
+    fn bps_bounds() {
+        let source = b"Hello, world!".to_vec();
+        let bps = |target_size: usize, metadata_size: usize, cmds: &[u8]| {
+            let mut patch = BPS_MAGIC.to_vec();
+            patch.extend(encode_number(source.len()));
+            patch.extend(encode_number(target_size));
+            patch.extend(encode_number(metadata_size));
+            patch.extend(cmds);
+            add_footer(patch, &source, &[])
+        };
+
+        // Huge target size
+        assert!(matches!(
+            apply_bps(&source, &bps(usize::MAX >> 1, 0, &[])),
+            Err(PatchError::OutOfBounds)
+        ));
+        // Metadata size overflowing the patch position
+        assert!(matches!(
+            apply_bps(&source, &bps(4, usize::MAX - 1, &[])),
+            Err(PatchError::Truncated)
+        ));
+        // TargetRead beyond the end of the patch
+        assert!(matches!(
+            apply_bps(&source, &bps(4, 0, &encode_number((3 << 2) | 1))),
+            Err(PatchError::Truncated)
+        ));
+        // Command writing beyond the target size
+        assert!(matches!(
+            apply_bps(&source, &bps(4, 0, &encode_number(7 << 2))),
+            Err(PatchError::OutOfBounds)
+        ));
+        // SourceCopy with an offset overflowing the source
+        let mut cmds = encode_number(2);
+        cmds.extend(encode_number(usize::MAX - 1));
+        assert!(matches!(
+            apply_bps(&source, &bps(4, 0, &cmds)),
+            Err(PatchError::OutOfBounds)
+        ));
+    }
+
+    #[test]
+    fn ups() {
+        let source = vec![1, 2, 3, 4, 5, 6];
+        let target = vec![1, 2, 0x13, 4, 5, 6, 7];
+
+        let mut patch = UPS_MAGIC.to_vec();
+        patch.extend(encode_number(source.len()));
+        patch.extend(encode_number(target.len()));
+        patch.extend(encode_number(2));
+        patch.extend([0x10, 0x00]);
+        patch.extend(encode_number(2));
+        patch.extend([0x07, 0x00]);
+        let patch = add_footer(patch, &source, &target);
+
+        assert_eq!(apply_ups(&source, &patch).unwrap(), target);
+        // Reverse
+        assert_eq!(apply_ups(&target, &patch).unwrap(), source);
+    }
+
+    #[test]
+    fn ups_bounds() {
+        let source = vec![1, 2, 3, 4];
+
+        // XOR run overflowing the offset
+        let mut patch = UPS_MAGIC.to_vec();
+        patch.extend(encode_number(source.len()));
+        patch.extend(encode_number(source.len()));
+        patch.extend(encode_number(usize::MAX));
+        patch.extend([0x01, 0x00]);
+        let patch = add_footer(patch, &source, &source);
+        assert!(matches!(
+            apply_ups(&source, &patch),
+            Err(PatchError::OutOfBounds)
+        ));
+    }
+
+    #[test]
+    fn copier_header_kept() {
+        let mut image = vec![0xEE; 0x200];
+        image.extend(vec![0u8; 0x400]);
+        let mut patch = b"PATCH".to_vec();
+        patch.extend([0x00, 0x00, 0x00, 0x00, 0x01, 0x55]);
+        patch.extend(b"EOF");
+
+        let out = apply_patch(&image, &patch).unwrap();
+        assert_eq!(out.len(), image.len());
+        assert_eq!(out[0], 0xEE);
+        assert_eq!(out[0x200], 0x55);
+    }
+}
@@ -20,13 +20,53 @@ macro_rules! write_m7x_13b {
     }};
 }
 
+impl PPUState {
+    /// Writes a byte to OAM at the specified address, through the
+    /// write buffer of the lower table.
+    pub(super) fn oam_write(&mut self, oaddr: u16, val: u8) {
+        // Deal with the upper table mirrors
+        let addr = if oaddr >= 0x200 {
+            (oaddr & 0x21F) as usize
+        } else {
+            oaddr as usize
+        };
+
+        let even = addr % 2 == 0;
+        if even {
+            self.oam_writebuf = val;
+        }
+        if !even && addr <= 0x1FF {
+            self.oam[addr - 1] = self.oam_writebuf;
+            self.oam[addr] = val;
+        }
+        if addr > 0x1FF {
+            self.oam[addr] = val;
+        }
+    }
+
+    /// Writes a byte to CGRAM at the specified address and advances
+    /// CGADD.
+    pub(super) fn cgram_write(&mut self, addr: u8, val: u8) {
+        let msb = self.cgadd_msb.get();
+        let valw = self.cgram[addr as usize];
+
+        if msb {
+            self.cgram[addr as usize] = valw & 0xFF | ((val as CgramWord) << 8);
+
+            self.cgadd.set(self.cgadd.get().wrapping_add(1));
+            self.cgadd_msb.set(false);
+        } else {
+            self.cgram[addr as usize] = valw & 0xFF00 | val as CgramWord;
+            self.cgadd_msb.set(true);
+        }
+    }
+}
+
 impl BusMember<Address> for PPUState {
     fn read(&self, fulladdr: Address) -> Option<u8> {
         let (_bank, addr) = ((fulladdr >> 16) as usize, (fulladdr & 0xFFFF) as usize);
 
         match addr {
-            // BGxSC - BGx Screen Base and Screen Size
-            0x2107..=0x210A => Some(self.bgxsc[addr - 0x2107]),
             // MPYL - Signed Multiply Result (lower 8bit) (R)
             0x2134 => {
                 let res = i32::from(self.m7a as i16) * i32::from(self.m7b_8b);
@@ -109,25 +149,7 @@ impl BusMember<Address> for PPUState {
             // OAMDATA - OAM Data Write (W)
             0x2104 => {
                 let oaddr = self.oamadd_addr.get();
-
-                // Deal with the upper table mirrors
-                let addr = if oaddr >= 0x200 {
-                    (oaddr & 0x21F) as usize
-                } else {
-                    oaddr as usize
-                };
-
-                let even = addr % 2 == 0;
-                if even {
-                    self.oam_writebuf = val;
-                }
-                if !even && addr <= 0x1FF {
-                    self.oam[addr - 1] = self.oam_writebuf;
-                    self.oam[addr] = val;
-                }
-                if addr > 0x1FF {
-                    self.oam[addr] = val;
-                }
+                self.oam_write(oaddr, val);
                 Some(self.oamadd_addr.set((oaddr + 1) & 0x3FF))
             }
             // BGMODE - BG Mode and BG Character Size
@@ -202,22 +224,7 @@ impl BusMember<Address> for PPUState {
                 Some(())
             }
             // CGDATA - Palette CGRAM Data Write
-            0x2122 => {
-                let addr = self.cgadd.get();
-                let msb = self.cgadd_msb.get();
-                let valw = self.cgram[addr as usize];
-
-                if msb {
-                    self.cgram[addr as usize] = valw & 0xFF | ((val as CgramWord) << 8);
-
-                    self.cgadd.set(addr.wrapping_add(1));
-                    self.cgadd_msb.set(false);
-                } else {
-                    self.cgram[addr as usize] = valw & 0xFF00 | val as CgramWord;
-                    self.cgadd_msb.set(true);
-                }
-                Some(())
-            }
+            0x2122 => Some(self.cgram_write(self.cgadd.get(), val)),
             // Window BG1/BG2 Mask Settings (W)
             0x2123 => Some(self.w12sel = val),
             // W34SEL - Window BG3/BG4 Mask Settings (W)
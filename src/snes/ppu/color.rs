@@ -1,10 +1,13 @@
 use std::cmp::min;
 
-use num_traits::int::PrimInt;
-use num_traits::AsPrimitive;
 use serde::{Deserialize, Serialize};
 
-use crate::frontend::Color;
+use crate::frontend::{Color, ColorProfile};
+
+/// Gamma of a CRT television
+const CRT_GAMMA: f64 = 2.5;
+/// Gamma of a typical (sRGB) host display
+const HOST_GAMMA: f64 = 2.2;
 
 /// RGB555 SNES-native color
 #[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
@@ -29,9 +32,9 @@ impl SnesColor {
         )
     }
 
-    /// Convert to a (host)-native color (RGB888)
+    /// Convert to a (host)-native color (RGB888), linearly and at
+    /// full brightness. Use `ColorTable` for other color profiles.
     pub fn to_native(&self) -> Color {
-        // TODO better put this in the frontend? I'm not sure..
         (
             self.r() << 3, // Red, 5-bit
             self.g() << 3, // Green, 5-bit
@@ -69,22 +72,6 @@ impl SnesColor {
         Self(((u16::from(val) & 0x1F) << 10) | (self.0 & !(0x1F << 10)))
     }
 
-    /// Apply brightness (0 - 15)
-    pub fn apply_brightness<T: PrimInt + AsPrimitive<u32>>(&self, brightness: T) -> Self {
-        if brightness == T::zero() {
-            return Self::BLACK;
-        }
-
-        let brightness: u32 = (brightness + T::one()).as_();
-        debug_assert!(brightness <= 16);
-        let t = self.0 as u32;
-
-        Self(
-            (((((t & 0x7C1F) * brightness) & 0x7C1F0) | (((t & 0x3E0) * brightness) & 0x3E00)) >> 4)
-                as u16,
-        )
-    }
-
     /// Color math add
     pub fn cm_add(&self, other: &SnesColor, div2: bool) -> SnesColor {
         let div = if div2 { 2 } else { 1 };
@@ -105,3 +92,61 @@ impl SnesColor {
         )
     }
 }
+
+/// Lookup table to convert 5-bit color intensities to 8-bit host
+/// intensities for every master brightness level, in a color profile.
+#[derive(Clone)]
+pub struct ColorTable {
+    profile: ColorProfile,
+    table: [[u8; 32]; 16],
+}
+
+impl ColorTable {
+    pub fn new(profile: ColorProfile) -> Self {
+        let mut table = [[0; 32]; 16];
+        for (brightness, row) in table.iter_mut().enumerate() {
+            // Master brightness scales the analog output linearly, in
+            // steps of 1/16th. Brightness 0 is black.
+            let level = if brightness == 0 {
+                0.0
+            } else {
+                (brightness as f64 + 1.0) / 16.0
+            };
+
+            for (c, out) in row.iter_mut().enumerate() {
+                let v = match profile {
+                    ColorProfile::Linear => f64::from((c as u8) << 3) * level,
+                    ColorProfile::BitReplicate => {
+                        f64::from(((c as u8) << 3) | ((c as u8) >> 2)) * level
+                    }
+                    ColorProfile::Crt => {
+                        255.0 * (c as f64 / 31.0 * level).powf(CRT_GAMMA / HOST_GAMMA)
+                    }
+                };
+                *out = v.round() as u8;
+            }
+        }
+        Self { profile, table }
+    }
+
+    pub fn get_profile(&self) -> ColorProfile {
+        self.profile
+    }
+
+    /// Converts a color to a host color at the given master
+    /// brightness (0 - 15)
+    pub fn to_native(&self, color: SnesColor, brightness: u8) -> Color {
+        let row = &self.table[usize::from(brightness & 0x0F)];
+        (
+            row[usize::from(color.r())],
+            row[usize::from(color.g())],
+            row[usize::from(color.b())],
+        )
+    }
+}
+
+impl Default for ColorTable {
+    fn default() -> Self {
+        Self::new(ColorProfile::default())
+    }
+}
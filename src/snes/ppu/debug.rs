@@ -0,0 +1,213 @@
+use super::color::SnesColor;
+use super::ppu::*;
+use super::sprites::OAM_ENTRIES;
+use super::state::*;
+use super::tile::*;
+use crate::frontend::filter::Picture;
+use crate::frontend::Color;
+
+use anyhow::{bail, Result};
+
+use std::fmt;
+use std::ops::Range;
+
+/// Tiles per row in a tile sheet
+const SHEET_TILES_WIDTH: usize = 32;
+/// Size of a color in the palette view, in pixels
+const PALETTE_CELL: usize = 8;
+/// Sprites per row in the sprite view
+const SPRITES_WIDTH: usize = 16;
+/// Size of a sprite in the sprite view, in pixels (largest sprite size)
+const SPRITE_CELL: usize = 64;
+/// Size of the mode 7 tilemap, in pixels
+const MODE7_MAP_SIZE: usize = 1024;
+
+/// Views of PPU memory, for debugging
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum DebugView {
+    /// VRAM as a sheet of tiles of the specified depth, using
+    /// the specified palette (in palettes of the tile depth).
+    Tiles(BPP, u8),
+    /// Tilemap of a background layer (0 - 3), at its configured size.
+    /// In mode 7, BG1 is the mode 7 tilemap.
+    Tilemap(usize),
+    /// Palette (CGRAM)
+    Palette,
+    /// All sprites in OAM
+    Sprites,
+}
+
+impl fmt::Display for DebugView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tiles(bpp, palette) => {
+                write!(f, "tiles-{}bpp-pal{}", bpp.num_bitplanes(), palette)
+            }
+            Self::Tilemap(bg) => write!(f, "tilemap-bg{}", bg + 1),
+            Self::Palette => write!(f, "palette"),
+            Self::Sprites => write!(f, "sprites"),
+        }
+    }
+}
+
+/// A tile anywhere in VRAM, not flipped
+struct VramTile {
+    data_range: Range<usize>,
+    bpp: BPP,
+}
+
+impl<'tdata> Tile<'tdata> for VramTile {
+    fn get_vram_range(&self) -> Range<usize> {
+        self.data_range.clone()
+    }
+    fn get_tile_flip_x(&self) -> bool {
+        false
+    }
+    fn get_tile_flip_y(&self) -> bool {
+        false
+    }
+    fn get_tile_bpp(&self) -> BPP {
+        self.bpp
+    }
+    fn get_tile_palette(&self) -> u8 {
+        0
+    }
+}
+
+impl PPUState {
+    /// Renders a debug view of the PPU memory
+    pub fn render_debug_view(&self, view: DebugView) -> Result<Picture> {
+        match view {
+            DebugView::Tiles(bpp, palette) => Ok(self.render_debug_tiles(bpp, palette)),
+            DebugView::Tilemap(bg) if bg == 0 && self.get_screen_mode() == 7 => {
+                Ok(self.render_debug_mode7_tilemap())
+            }
+            DebugView::Tilemap(bg) => self.render_debug_tilemap(bg),
+            DebugView::Palette => Ok(self.render_debug_palette()),
+            DebugView::Sprites => Ok(self.render_debug_sprites()),
+        }
+    }
+
+    /// Converts a color for a debug view, at full brightness
+    fn debug_color(&self, color: SnesColor) -> Color {
+        self.colortable.to_native(color, 15)
+    }
+
+    fn render_debug_tiles(&self, bpp: BPP, palette: u8) -> Picture {
+        let len = TILE_HEIGHT * bpp.num_bitplanes() / VRAM_WORDSIZE;
+        let tiles = VRAM_WORDS / len;
+        let base = (usize::from(palette) * usize::from(bpp.entries_per_palette())) & 0xFF;
+        let mut img = Picture::blank(
+            SHEET_TILES_WIDTH * TILE_WIDTH,
+            tiles / SHEET_TILES_WIDTH * TILE_HEIGHT,
+        );
+
+        for tilenr in 0..tiles {
+            let tile = VramTile {
+                data_range: (tilenr * len)..((tilenr + 1) * len),
+                bpp,
+            };
+            let (tx, ty) = (
+                (tilenr % SHEET_TILES_WIDTH) * TILE_WIDTH,
+                (tilenr / SHEET_TILES_WIDTH) * TILE_HEIGHT,
+            );
+            for y in 0..TILE_HEIGHT {
+                for (x, &c) in tile.get_coloridcs_y(y, &self.vram).iter().enumerate() {
+                    let color = self.cgram_to_color(((base + usize::from(c)) & 0xFF) as u8);
+                    img.set(tx + x, ty + y, self.debug_color(color));
+                }
+            }
+        }
+        img
+    }
+
+    fn render_debug_tilemap(&self, bg: usize) -> Result<Picture> {
+        let layers = match self.get_screen_mode() {
+            0 => 4,
+            1 => 3,
+            2..=5 => 2,
+            _ => 1,
+        };
+        if bg >= layers {
+            bail!(
+                "BG{} is not available in mode {}",
+                bg + 1,
+                self.get_screen_mode()
+            );
+        }
+
+        let (tilewidth, tileheight) = self.get_bg_tile_size(bg);
+        let (tiles_x, tiles_y) = match self.get_tilemap_dimensions(bg) {
+            TilemapDimensions::D32x32 => (32, 32),
+            TilemapDimensions::D64x32 => (64, 32),
+            TilemapDimensions::D32x64 => (32, 64),
+            TilemapDimensions::D64x64 => (64, 64),
+        };
+        let mut img = Picture::blank(tiles_x * tilewidth, tiles_y * tileheight);
+
+        for y in 0..img.height {
+            for x in 0..img.width {
+                let entry = self.get_tilemap_entry_xy(bg, x, y, 0, 0);
+                let (px_x, px_y) = (x % tilewidth, y % tileheight);
+                let tile = self.get_bg_tile(bg, &entry, px_x, px_y);
+                let c = tile.get_coloridx(px_x % TILE_WIDTH, px_y % TILE_HEIGHT, &self.vram);
+                img.set(x, y, self.debug_color(self.cindex_to_color(bg, &tile, c)));
+            }
+        }
+        Ok(img)
+    }
+
+    fn render_debug_mode7_tilemap(&self) -> Picture {
+        let mut img = Picture::blank(MODE7_MAP_SIZE, MODE7_MAP_SIZE);
+
+        for y in 0..MODE7_MAP_SIZE {
+            for x in 0..MODE7_MAP_SIZE {
+                // VRAM coordinates are 8-bit fixed point
+                let c = self.mode7_vram_to_color((x << 8) as i32, (y << 8) as i32);
+                img.set(x, y, self.debug_color(self.cgram_to_color(c)));
+            }
+        }
+        img
+    }
+
+    fn render_debug_palette(&self) -> Picture {
+        let mut img = Picture::blank(16 * PALETTE_CELL, 16 * PALETTE_CELL);
+
+        for y in 0..img.height {
+            for x in 0..img.width {
+                let idx = (y / PALETTE_CELL) * 16 + (x / PALETTE_CELL);
+                img.set(x, y, self.debug_color(self.cgram_to_color(idx as u8)));
+            }
+        }
+        img
+    }
+
+    fn render_debug_sprites(&self) -> Picture {
+        let mut img = Picture::blank(
+            SPRITES_WIDTH * SPRITE_CELL,
+            OAM_ENTRIES / SPRITES_WIDTH * SPRITE_CELL,
+        );
+
+        for (idx, e) in self.get_oam_entries().iter().enumerate() {
+            let (sx, sy) = (
+                (idx % SPRITES_WIDTH) * SPRITE_CELL,
+                (idx / SPRITES_WIDTH) * SPRITE_CELL,
+            );
+            for y in 0..e.height {
+                for x in 0..e.width {
+                    let tile = self.get_sprite_tile(e, x / TILE_WIDTH, y / TILE_HEIGHT);
+                    let c = tile.get_coloridx(x % TILE_WIDTH, y % TILE_HEIGHT, &self.vram);
+                    if c == 0 {
+                        continue;
+                    }
+                    img.set(
+                        sx + x,
+                        sy + y,
+                        self.debug_color(self.sprite_cindex_to_color(&tile, c)),
+                    );
+                }
+            }
+        }
+        img
+    }
+}
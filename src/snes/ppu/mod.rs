@@ -1,5 +1,6 @@
 pub mod bus;
 pub mod color;
+pub mod debug;
 pub mod ppu;
 pub mod render;
 pub mod render_m7;
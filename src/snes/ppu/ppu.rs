@@ -1,22 +1,29 @@
+use super::color::ColorTable;
+use super::debug::DebugView;
+use super::sprites::{OAMEntry, SpriteLine, OAM_ENTRIES};
 use super::state::PPUState;
 
 use anyhow::Result;
+use arrayvec::ArrayVec;
 use rusty_pool::ThreadPool;
 use serde::{Deserialize, Serialize};
 
 use crate::bus::{Address, BusMember};
-use crate::frontend::Renderer;
+use crate::frontend::filter::Picture;
+use crate::frontend::{Color, DisplayBuffer, Renderer};
 use crate::snes::cartridge::VideoFormat;
 use crate::tickable::{Tickable, Ticks};
 
 use std::cell::Cell;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use std::thread::sleep;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 pub const SCREEN_WIDTH: usize = 512;
-pub const SCREEN_HEIGHT: usize = 448;
+/// Height of the frame buffer, which fits an overscan frame. Use
+/// `Renderer::set_active_height` to determine the height of the
+/// current picture.
+pub const SCREEN_HEIGHT: usize = 478;
 
 // The entire screen should be shifted up by 1 scanline
 const SCANLINE_OUTPUT_OFFSET: isize = -1;
@@ -37,12 +44,59 @@ const VMAIN_INC_MASK: u8 = 0x03;
 const VMAIN_TRANSLATE_MASK: u8 = 0x03;
 const VMAIN_TRANSLATE_SHIFT: u8 = 2;
 
-fn _default_none<T>() -> Option<T> {
-    None
+/// Selects how the PPU produces the picture
+#[derive(Debug, Eq, PartialEq, Copy, Clone, clap::ValueEnum, Serialize, Deserialize)]
+pub enum RenderMode {
+    /// Renders entire scanlines at once, on a thread pool. Fast, but
+    /// register writes during the visible part of a scanline are not
+    /// visible until the next scanline.
+    Scanline,
+    /// Renders pixel by pixel on the emulation thread, in step with
+    /// the master clock, so mid-scanline raster effects are visible.
+    Dot,
+}
+
+/// Location of a scanline in the frame buffer
+#[derive(Clone, Copy)]
+struct OutputLine {
+    /// Scanline as seen by the backgrounds
+    scanline_bg: usize,
+    /// Line in the frame buffer
+    line: usize,
+    /// Line is duplicated to the line below (non-high res)
+    double: bool,
+}
+
+/// Scanline currently being output by the dot renderer
+struct DotLine {
+    output: OutputLine,
+    sprites: SpriteLine,
+    buffer: DisplayBuffer,
+    /// Line rendered from the PPU state as it is at the current dot,
+    /// with the CGRAM address of every pixel. Discarded when a register
+    /// is written, so the remainder of the line is rendered using the
+    /// new state.
+    pixels: Option<(ArrayVec<Color, SCREEN_WIDTH>, ArrayVec<u8, SCREEN_WIDTH>)>,
+}
+
+/// Writes a pixel to the frame buffer
+fn put_pixel(buffer: &DisplayBuffer, line: usize, x: usize, color: Color, double: bool) {
+    let idx_upper = ((line * SCREEN_WIDTH) + x) * 4;
+    buffer[idx_upper].store(color.2, Ordering::Release);
+    buffer[idx_upper + 1].store(color.1, Ordering::Release);
+    buffer[idx_upper + 2].store(color.0, Ordering::Release);
+
+    if double {
+        // Outside of high res, duplicate every line to scale up
+        let idx_lower = (((line + 1) * SCREEN_WIDTH) + x) * 4;
+        buffer[idx_lower].store(color.2, Ordering::Release);
+        buffer[idx_lower + 1].store(color.1, Ordering::Release);
+        buffer[idx_lower + 2].store(color.0, Ordering::Release);
+    }
 }
 
-fn _default_now() -> Instant {
-    Instant::now()
+fn _default_none<T>() -> Option<T> {
+    None
 }
 
 #[derive(Serialize, Deserialize)]
@@ -54,26 +108,26 @@ pub struct PPU<TRenderer: Renderer> {
 
     videoformat: VideoFormat,
 
-    /// Timestamp when the last frame was completed
-    #[serde(skip, default = "_default_now")]
-    last_frame: Instant,
-
-    /// The desired speed at which to run the PPU (and with that the emulator)
-    /// in microseconds per frame.
-    desired_frametime: u64,
-
-    pub(super) cycles: usize,
+    /// Current dot in the scanline
+    pub(super) hcounter: usize,
+    /// Current scanline
+    pub(super) vcounter: usize,
     pub(super) last_scanline: usize,
     pub(super) intreq_vblank: bool,
     pub(super) intreq_hblank: bool,
     pub(super) vblank: bool,
     pub(super) hblank: bool,
 
-    state: PPUState,
+    pub(super) state: PPUState,
+
+    render_mode: RenderMode,
 
     #[serde(skip)]
     pool: ThreadPool,
 
+    #[serde(skip)]
+    dot_line: Option<DotLine>,
+
     // H/V latches
     /// Current value latched in OPHCT
     pub(super) hlatch: Cell<u16>,
@@ -92,40 +146,66 @@ pub struct PPU<TRenderer: Renderer> {
 
     pub(super) interlace_frame: bool,
 
-    vi_count: u64,
-    #[serde(skip, default = "Instant::now")]
-    vi_time: Instant,
+    /// Sprite range-over flag, for STAT77.6
+    pub(super) obj_range_over: bool,
+    /// Sprite time-over flag, for STAT77.7
+    pub(super) obj_time_over: bool,
+
+    /// PPU1 open bus, last value read from a PPU1 register
+    pub(super) ppu1_mdr: Cell<u8>,
+    /// PPU2 open bus, last value read from a PPU2 register
+    pub(super) ppu2_mdr: Cell<u8>,
+
+    /// Frames completed since power on
+    #[serde(default)]
+    frame_count: u64,
 }
 
 impl<TRenderer> PPU<TRenderer>
 where
     TRenderer: Renderer,
 {
-    pub const CYCLES_PER_SCANLINE: usize = 341; // including H-blank
-    pub const SCANLINES_PER_FRAME: usize = 262; // including V-blank
-    pub const VBLANK_START: usize = 225;
+    pub const CYCLES_PER_SCANLINE: usize = 340; // including H-blank
+    pub const SCANLINES_PER_FRAME_NTSC: usize = 262; // including V-blank
+    pub const SCANLINES_PER_FRAME_PAL: usize = 312; // including V-blank
     pub const VISIBLE_LINES: usize = 224;
+    pub const VISIBLE_LINES_OVERSCAN: usize = 239;
 
     pub const LINE_HBLANK_START: usize = 274;
     pub const H_RENDER: usize = 22;
 
-    pub fn new(renderer: TRenderer, fps: u64, videoformat: VideoFormat) -> Self {
-        let desired_frametime = if fps == 0 { 0 } else { 1_000_000 / fps };
+    /// Chip version of PPU1 (5C77), in STAT77
+    pub const PPU1_VERSION: u8 = 1;
+    /// Chip version of PPU2 (5C78), in STAT78
+    pub const PPU2_VERSION: u8 = 3;
+
+    /// Dots that take 6 master cycles rather than 4
+    const LONG_DOTS: [usize; 2] = [323, 327];
+    /// Master cycles per dot
+    const DOT_CYCLES: Ticks = 4;
+    const LONG_DOT_CYCLES: Ticks = 6;
+
+    pub fn new(renderer: TRenderer, videoformat: VideoFormat, render_mode: RenderMode) -> Self {
+        let mut state = PPUState::new();
+        state.colortable = ColorTable::new(renderer.get_color_profile());
 
         Self {
             vram: vec![0; VRAM_WORDS],
 
             renderer: Some(renderer),
             videoformat,
-            cycles: 0,
+            hcounter: 0,
+            vcounter: 0,
             last_scanline: 0,
             intreq_vblank: false,
             intreq_hblank: false,
             vblank: false,
             hblank: false,
 
-            state: PPUState::new(),
+            state,
+            render_mode,
             pool: ThreadPool::default(),
+            dot_line: None,
 
             hlatch: Cell::new(0),
             vlatch: Cell::new(0),
@@ -137,41 +217,140 @@ where
             vmain: 0,
             vram_prefetch: Cell::new(0),
             interlace_frame: false,
-
-            last_frame: Instant::now(),
-            desired_frametime,
-
-            vi_count: 0,
-            vi_time: Instant::now(),
+            obj_range_over: false,
+            obj_time_over: false,
+            ppu1_mdr: Cell::new(0),
+            ppu2_mdr: Cell::new(0),
+            frame_count: 0,
         }
     }
 
+    pub fn get_videoformat(&self) -> VideoFormat {
+        self.videoformat
+    }
+
+    /// Position in the frame, in dots (assuming regular scanlines)
     pub fn get_cycles(&self) -> Ticks {
-        self.cycles
+        self.vcounter * Self::CYCLES_PER_SCANLINE + self.hcounter
     }
 
     pub fn single_threaded(&mut self) {
         self.pool = ThreadPool::new(1, 1, Duration::from_secs(60));
     }
 
-    pub fn set_fps_limit(&mut self, fps: u64) {
-        self.desired_frametime = if fps == 0 { 0 } else { 1_000_000 / fps };
+    /// Frames completed since power on
+    pub fn get_frame_count(&self) -> u64 {
+        self.frame_count
     }
 
     pub fn get_current_scanline(&self) -> usize {
-        self.cycles / Self::CYCLES_PER_SCANLINE
+        self.vcounter
     }
 
     pub fn get_current_h(&self) -> usize {
-        self.cycles % Self::CYCLES_PER_SCANLINE
+        self.hcounter
+    }
+
+    /// Scanlines in the current frame, including V-blank.
+    pub fn get_scanlines_per_frame(&self) -> usize {
+        let lines = match self.videoformat {
+            VideoFormat::NTSC => Self::SCANLINES_PER_FRAME_NTSC,
+            VideoFormat::PAL => Self::SCANLINES_PER_FRAME_PAL,
+        };
+
+        // Interlaced even frames have one extra scanline
+        if self.state.in_interlace() && !self.interlace_frame {
+            lines + 1
+        } else {
+            lines
+        }
+    }
+
+    /// Scanline 240 of non-interlaced odd frames is 4 master cycles
+    /// short (NTSC only).
+    fn is_short_line(&self) -> bool {
+        matches!(self.videoformat, VideoFormat::NTSC)
+            && !self.state.in_interlace()
+            && self.interlace_frame
+            && self.vcounter == 240
+    }
+
+    /// Scanline 311 of interlaced odd frames is 4 master cycles
+    /// long (PAL only).
+    fn is_long_line(&self) -> bool {
+        matches!(self.videoformat, VideoFormat::PAL)
+            && self.state.in_interlace()
+            && self.interlace_frame
+            && self.vcounter == 311
+    }
+
+    /// Dots in the current scanline, including H-blank.
+    pub fn get_current_line_dots(&self) -> usize {
+        if self.is_long_line() {
+            Self::CYCLES_PER_SCANLINE + 1
+        } else {
+            Self::CYCLES_PER_SCANLINE
+        }
+    }
+
+    /// Master cycles the current dot takes. Two dots in each scanline
+    /// are longer, except for the short scanline.
+    pub fn get_dot_master_cycles(&self) -> Ticks {
+        if !self.is_short_line() && Self::LONG_DOTS.contains(&self.hcounter) {
+            Self::LONG_DOT_CYCLES
+        } else {
+            Self::DOT_CYCLES
+        }
+    }
+
+    /// Visible scanlines in the current frame, which depends on
+    /// the overscan setting.
+    pub fn get_visible_lines(&self) -> usize {
+        if self.state.in_overscan() {
+            Self::VISIBLE_LINES_OVERSCAN
+        } else {
+            Self::VISIBLE_LINES
+        }
+    }
+
+    /// Scanline at which V-blank starts
+    pub fn get_vblank_start(&self) -> usize {
+        self.get_visible_lines() + 1
+    }
+
+    /// Height of the current picture in the frame buffer
+    pub fn get_active_height(&self) -> usize {
+        self.get_visible_lines() * 2
     }
 
     pub fn in_vblank(&self) -> bool {
-        self.last_scanline >= Self::VBLANK_START
+        self.last_scanline >= self.get_vblank_start()
     }
 
     pub fn in_hblank(&self) -> bool {
-        self.cycles % Self::CYCLES_PER_SCANLINE >= Self::LINE_HBLANK_START
+        self.hcounter >= Self::LINE_HBLANK_START
+    }
+
+    /// Returns if the PPU is drawing the picture, during which
+    /// it has VRAM, CGRAM and OAM to itself.
+    fn in_active_display(&self) -> bool {
+        !self.state.in_force_blank() && !self.in_vblank()
+    }
+
+    /// Returns if the PPU is fetching colors from CGRAM.
+    fn in_cgram_render(&self) -> bool {
+        self.in_active_display()
+            && self.get_current_scanline() > 0
+            && (Self::H_RENDER..Self::LINE_HBLANK_START).contains(&self.get_current_h())
+    }
+
+    /// OAM address the PPU is accessing at the current dot during
+    /// active display. Range evaluation visits a sprite every 2 dots,
+    /// starting at the highest priority sprite.
+    fn get_oam_render_addr(&self) -> u16 {
+        let sprite =
+            (self.state.get_first_sprite() + self.get_current_h().min(255) / 2) % OAM_ENTRIES;
+        (sprite * 4) as u16
     }
 
     pub fn get_clr_intreq_vblank(&mut self) -> bool {
@@ -186,14 +365,73 @@ where
         v
     }
 
-    pub fn render_scanline(&mut self, scanline: usize, output_offset: isize) {
-        let mut t_state = self.state.clone();
-        let t_buffer = self.renderer.as_mut().unwrap().get_buffer();
+    /// Picks up the color profile of the renderer, if it changed
+    pub fn update_color_profile(&mut self) {
+        let profile = self.renderer.as_ref().unwrap().get_color_profile();
+        if profile != self.state.colortable.get_profile() {
+            self.state.colortable = ColorTable::new(profile);
+        }
+    }
+
+    /// Debug layer mask (see DBG_* in state.rs)
+    pub fn get_dbg_layermask(&self) -> u16 {
+        self.state.dbg_layermask
+    }
 
+    pub fn set_dbg_layermask(&mut self, mask: u16) {
+        self.state.dbg_layermask = mask;
+    }
+
+    /// Renders a debug view of the current PPU memory
+    pub fn render_debug_view(&self, view: DebugView) -> Result<Picture> {
+        let mut state = self.state.clone();
+        state.vram = Arc::new(self.vram.clone());
+        state.render_debug_view(view)
+    }
+
+    pub fn get_oam_entries(&self) -> Vec<OAMEntry> {
+        self.state.get_oam_entries()
+    }
+
+    /// Gets the frame buffer of the renderer (`SCREEN_WIDTH` wide) and the
+    /// height of the picture in it. Between frames, it holds the last
+    /// completed frame.
+    pub fn get_frame(&mut self) -> (DisplayBuffer, usize) {
+        let height = self.get_active_height();
+        (self.renderer.as_mut().unwrap().get_buffer(), height)
+    }
+
+    /// Captures the picture in the frame buffer of the renderer, at the
+    /// resolution it was produced in (e.g. 256x224 for low resolution).
+    pub fn screenshot(&mut self) -> Picture {
+        let (buffer, height) = self.get_frame();
+        Picture::from_displaybuffer(&buffer, SCREEN_WIDTH, height).to_native()
+    }
+
+    pub fn get_render_mode(&self) -> RenderMode {
+        self.render_mode
+    }
+
+    /// Evaluates the sprites to draw on the specified scanline and
+    /// updates the sprite overflow flags.
+    fn evaluate_sprites(&mut self, scanline: usize) -> SpriteLine {
         // Sprites are always rendered on "full" pixels, even in highres.
-        let scanline_sprites = scanline;
+        // Sprites are actually offset 1 scanline down, because on the original hardware
+        // the sprites for the NEXT scanline are fetched during the CURRENT scanline.
+        // On scanline 0, the sprites at Y 0 are fetched to be drawn at scanline 1.
+        // This is also why scanline 0 is never rendered.
+        let sprites = self.state.evaluate_sprites(scanline - 1);
+        if !self.state.in_force_blank() {
+            self.obj_range_over |= sprites.range_over;
+            self.obj_time_over |= sprites.time_over;
+        }
+        sprites
+    }
 
-        let scanline_bg = if t_state.in_highres_v() {
+    /// Determines where the specified scanline goes in the frame buffer.
+    /// Returns None if the scanline is not output in this frame.
+    fn get_output_line(&self, scanline: usize, output_offset: isize) -> Option<OutputLine> {
+        let scanline_bg = if self.state.in_highres_v() {
             // If vertical high res is enabled, every other scanline is updated
             // every other frame.
             scanline * 2 + if self.interlace_frame { 0 } else { 1 }
@@ -204,7 +442,7 @@ where
         };
 
         let output_line = usize::try_from((scanline_bg as isize) + output_offset).unwrap();
-        let upper_line = if t_state.in_highres_v() {
+        let upper_line = if self.state.in_highres_v() {
             output_line
         } else {
             output_line * 2
@@ -213,28 +451,99 @@ where
         // Frames in which the 'even' scanlines are updated, we skip the
         // last line because it falls off the edge of the frame
         // ('odd line' frames render one extra line)
-        if t_state.in_highres_v() && !self.interlace_frame && output_line >= SCREEN_HEIGHT {
-            return;
+        if self.state.in_highres_v()
+            && !self.interlace_frame
+            && output_line >= self.get_active_height()
+        {
+            return None;
         }
 
+        Some(OutputLine {
+            scanline_bg,
+            line: upper_line,
+            double: !self.state.in_highres_v(),
+        })
+    }
+
+    pub fn render_scanline(&mut self, scanline: usize, output_offset: isize) {
+        let mut t_state = self.state.clone();
+        let t_buffer = self.renderer.as_mut().unwrap().get_buffer();
+
+        let sprites = self.evaluate_sprites(scanline);
+        let Some(output) = self.get_output_line(scanline, output_offset) else {
+            return;
+        };
+
         self.pool.execute(move || {
-            let line = t_state.render_scanline(scanline_bg, scanline_sprites);
+            let line = t_state.render_scanline(output.scanline_bg, &sprites);
             for (x, color) in line.into_iter().enumerate() {
-                let idx_upper = ((upper_line * SCREEN_WIDTH) + x) * 4;
-                t_buffer[idx_upper].store(color.2.into(), Ordering::Release);
-                t_buffer[idx_upper + 1].store(color.1.into(), Ordering::Release);
-                t_buffer[idx_upper + 2].store(color.0.into(), Ordering::Release);
-
-                if !t_state.in_highres_v() {
-                    // Outside of high res, duplicate every line to scale up
-                    let lower_line = upper_line + 1;
-                    let idx_lower = ((lower_line * SCREEN_WIDTH) + x) * 4;
-                    t_buffer[idx_lower].store(color.2.into(), Ordering::Release);
-                    t_buffer[idx_lower + 1].store(color.1.into(), Ordering::Release);
-                    t_buffer[idx_lower + 2].store(color.0.into(), Ordering::Release);
+                put_pixel(&t_buffer, output.line, x, color, output.double);
+            }
+        });
+    }
+
+    /// Renders the pixel at the current dot (dot renderer)
+    fn render_dot(&mut self) {
+        let h = self.get_current_h();
+
+        if h == Self::H_RENDER {
+            let line = self.get_current_scanline();
+            self.dot_line = None;
+            if self.is_visible_line(line) {
+                let sprites = self.evaluate_sprites(line);
+                if let Some(output) = self.get_output_line(line, SCANLINE_OUTPUT_OFFSET) {
+                    self.dot_line = Some(DotLine {
+                        output,
+                        sprites,
+                        buffer: self.renderer.as_mut().unwrap().get_buffer(),
+                        pixels: None,
+                    });
                 }
             }
+        }
+
+        if !(Self::H_RENDER..(Self::H_RENDER + SCREEN_WIDTH / 2)).contains(&h) {
+            return;
+        }
+        // Every dot outputs two pixels in the (high res) frame buffer
+        let x = (h - Self::H_RENDER) * 2;
+        let Some(dl) = self.dot_line.as_mut() else {
+            return;
+        };
+        let (pixels, _) = dl.pixels.get_or_insert_with(|| {
+            self.state
+                .clone()
+                .render_scanline_cgaddr(dl.output.scanline_bg, &dl.sprites)
         });
+        for x in x..(x + 2) {
+            put_pixel(&dl.buffer, dl.output.line, x, pixels[x], dl.output.double);
+        }
+    }
+
+    /// CGRAM address the dot renderer is fetching at the current dot,
+    /// if it is outputting pixels.
+    fn get_cgram_render_addr(&mut self) -> Option<u8> {
+        let h = self.get_current_h();
+        if !(Self::H_RENDER..(Self::H_RENDER + SCREEN_WIDTH / 2)).contains(&h) {
+            return None;
+        }
+        let dl = self.dot_line.as_mut()?;
+        let (_, cgaddr) = dl.pixels.get_or_insert_with(|| {
+            self.state
+                .clone()
+                .render_scanline_cgaddr(dl.output.scanline_bg, &dl.sprites)
+        });
+        // The second pixel of a dot is the main screen in high res
+        Some(cgaddr[(h - Self::H_RENDER) * 2 + 1])
+    }
+
+    /// Scanline 0 is discarded by the original hardware, so
+    /// scanline 1 becomes the top of the frame. However, H/V-interrupts,
+    /// HDMA, etc are still executed for scanline 0, so we only discard
+    /// it here and shift the whole frame up by 1.
+    fn is_visible_line(&self, line: usize) -> bool {
+        (line as isize) < ((self.get_visible_lines() as isize) - SCANLINE_OUTPUT_OFFSET)
+            && (line as isize) + SCANLINE_OUTPUT_OFFSET >= 0
     }
 
     pub(super) fn vram_autoinc(&self, upper: bool, write: bool) {
@@ -286,21 +595,25 @@ where
     TRenderer: Renderer,
 {
     fn tick(&mut self, ticks: Ticks) -> Result<Ticks> {
-        self.cycles =
-            (self.cycles + ticks) % (Self::CYCLES_PER_SCANLINE * Self::SCANLINES_PER_FRAME);
-
-        if self.get_current_h() == Self::H_RENDER {
-            let line = self.get_current_scanline();
+        for _ in 0..ticks {
+            self.hcounter += 1;
+            if self.hcounter >= self.get_current_line_dots() {
+                self.hcounter = 0;
+                self.vcounter += 1;
+                if self.vcounter >= self.get_scanlines_per_frame() {
+                    self.vcounter = 0;
+                }
+            }
+        }
 
-            // Scanline 0 is discarded by the original hardware, so
-            // scanline 1 becomes the top of the frame. However, H/V-interrupts,
-            // HDMA, etc are still executed for scanline 0, so we only discard
-            // it here and shift the whole frame up by 1.
-            if (line as isize) < ((Self::VISIBLE_LINES as isize) - SCANLINE_OUTPUT_OFFSET)
-                && (line as isize) + SCANLINE_OUTPUT_OFFSET >= 0
-            {
-                self.render_scanline(line, SCANLINE_OUTPUT_OFFSET);
+        match self.render_mode {
+            RenderMode::Scanline => {
+                let line = self.get_current_scanline();
+                if self.get_current_h() == Self::H_RENDER && self.is_visible_line(line) {
+                    self.render_scanline(line, SCANLINE_OUTPUT_OFFSET);
+                }
             }
+            RenderMode::Dot => self.render_dot(),
         }
 
         if self.in_hblank() {
@@ -324,7 +637,7 @@ where
                     self.intreq_vblank = true;
 
                     // Reload OAMADD (only outside of force blank)
-                    if self.state.inidisp & 0x80 == 0 {
+                    if !self.state.in_force_blank() {
                         self.state.oamadd_addr.set(self.state.oamadd_reload.get());
                     }
                 }
@@ -335,6 +648,12 @@ where
                 // Toggle interlace frame bit in STAT78
                 self.interlace_frame = !self.interlace_frame;
 
+                // Reset sprite overflow flags in STAT77 (only outside of force blank)
+                if !self.state.in_force_blank() {
+                    self.obj_range_over = false;
+                    self.obj_time_over = false;
+                }
+
                 // Roll over the VRAM buffer so any changes during the last frame
                 // reflect in the next frame.
                 self.state.vram = Arc::new(self.vram.clone());
@@ -344,25 +663,13 @@ where
                 self.pool.join();
 
                 // Present frame to the screen
+                let height = self.get_active_height();
                 let renderer = self.renderer.as_mut().unwrap();
+                renderer.set_active_height(height);
                 renderer.update()?;
+                self.update_color_profile();
 
-                // Sync to desired framerate
-                let frametime = self.last_frame.elapsed().as_micros() as u64;
-                if frametime < self.desired_frametime {
-                    sleep(Duration::from_micros(self.desired_frametime - frametime));
-                }
-                self.last_frame = Instant::now();
-
-                self.vi_count += 1;
-                if self.vi_time.elapsed().as_secs() >= 2 {
-                    println!(
-                        "PPU: {:0.2} VBlank/sec",
-                        self.vi_count as f32 / self.vi_time.elapsed().as_secs_f32()
-                    );
-                    self.vi_count = 0;
-                    self.vi_time = Instant::now();
-                }
+                self.frame_count += 1;
             }
         }
 
@@ -378,22 +685,43 @@ where
         let (_bank, addr) = ((fulladdr >> 16) as usize, (fulladdr & 0xFFFF) as usize);
 
         match addr {
-            // VMAIN - VRAM Address Increment Mode
-            0x2115 => Some(self.vmain),
-            // VMADDL - VRAM Address (lower 8bit)
-            0x2116 => Some(self.vmadd.get() as u8),
-            // VMADDH - VRAM Address (upper 8bit)
-            0x2117 => Some((self.vmadd.get() >> 8) as u8),
+            // Write-only registers on PPU1 read the PPU1 open bus,
+            // the others the CPU open bus.
+            0x2104..=0x2106
+            | 0x2108..=0x210A
+            | 0x2114..=0x2116
+            | 0x2118..=0x211A
+            | 0x2124..=0x2126
+            | 0x2128..=0x212A => Some(self.ppu1_mdr.get()),
+            // MPYL/MPYM/MPYH, RDOAM
+            0x2134..=0x2136 | 0x2138 => {
+                let v = self.state.read(fulladdr)?;
+                self.ppu1_mdr.set(v);
+                Some(v)
+            }
             // RDVRAML - VRAM Data Read (lower 8bit)
             0x2139 => {
                 let v = self.vram_prefetch.get() as u8;
                 self.vram_autoinc(false, false);
+                self.ppu1_mdr.set(v);
                 Some(v)
             }
             // RDVRAMH - VRAM Data Read (upper 8bit)
             0x213A => {
                 let v = (self.vram_prefetch.get() >> 8) as u8;
                 self.vram_autoinc(true, false);
+                self.ppu1_mdr.set(v);
+                Some(v)
+            }
+            // RDCGRAM - Palette CGRAM Data Read
+            0x213B => {
+                let msb = self.state.cgadd_msb.get();
+                let mut v = self.state.read(fulladdr)?;
+                if msb {
+                    // Bit 7 of the upper byte is open bus
+                    v = (v & 0x7F) | (self.ppu2_mdr.get() & 0x80);
+                }
+                self.ppu2_mdr.set(v);
                 Some(v)
             }
             // SLHV - Latch H/V-Counter by Software (R)
@@ -411,9 +739,11 @@ where
                 let v = if !self.hlatch_msb.get() {
                     self.hlatch.get() as u8
                 } else {
-                    (self.hlatch.get() >> 8) as u8
+                    // Bits 1-7 are open bus
+                    (self.ppu2_mdr.get() & 0xFE) | ((self.hlatch.get() >> 8) as u8 & 0x01)
                 };
                 self.hlatch_msb.set(!self.hlatch_msb.get());
+                self.ppu2_mdr.set(v);
 
                 Some(v)
             }
@@ -422,16 +752,33 @@ where
                 let v = if !self.vlatch_msb.get() {
                     self.vlatch.get() as u8
                 } else {
-                    (self.vlatch.get() >> 8) as u8
+                    // Bits 1-7 are open bus
+                    (self.ppu2_mdr.get() & 0xFE) | ((self.vlatch.get() >> 8) as u8 & 0x01)
                 };
                 self.vlatch_msb.set(!self.vlatch_msb.get());
+                self.ppu2_mdr.set(v);
 
                 Some(v)
             }
+            // STAT77 - PPU1 Status and Version Number (R)
+            0x213E => {
+                let mut val = Self::PPU1_VERSION;
+
+                if self.obj_time_over {
+                    val |= 1 << 7;
+                }
+                if self.obj_range_over {
+                    val |= 1 << 6;
+                }
+                // Bit 5 is master/slave mode (always master), bit 4 is open bus
+                val |= self.ppu1_mdr.get() & (1 << 4);
+                self.ppu1_mdr.set(val);
+
+                Some(val)
+            }
             // STAT78 - PPU2 Status and Version Number (R)
             0x213F => {
-                // PPU2 version
-                let mut val = 1;
+                let mut val = Self::PPU2_VERSION;
 
                 if self.interlace_frame {
                     val |= 1 << 7;
@@ -440,10 +787,12 @@ where
                     val |= 1 << 6;
                 }
                 // Bit 5 is open bus
+                val |= self.ppu2_mdr.get() & (1 << 5);
                 val |= match self.videoformat {
                     VideoFormat::NTSC => 0,
                     VideoFormat::PAL => 1 << 4,
                 };
+                self.ppu2_mdr.set(val);
 
                 // Read clears all latches
                 self.hlatch_msb.set(false);
@@ -452,13 +801,33 @@ where
 
                 Some(val)
             }
-            _ => self.state.read(fulladdr),
+            _ => None,
         }
     }
 
     fn write(&mut self, fulladdr: Address, val: u8) -> Option<()> {
         let (_bank, addr) = ((fulladdr >> 16) as usize, (fulladdr & 0xFFFF) as usize);
 
+        // CGDATA writes during rendering go to the address the PPU is
+        // accessing, but CGADD still increments. Only the dot renderer
+        // knows which color is fetched at this dot; the scanline
+        // renderer writes to CGADD as usual.
+        let cgram_render_addr =
+            if addr == 0x2122 && self.render_mode == RenderMode::Dot && self.in_cgram_render() {
+                self.get_cgram_render_addr()
+            } else {
+                None
+            };
+
+        // Render the remainder of the line using the new state
+        if let Some(dl) = self.dot_line.as_mut() {
+            dl.pixels = None;
+        }
+
+        if let Some(cgaddr) = cgram_render_addr {
+            return Some(self.state.cgram_write(cgaddr, val));
+        }
+
         match addr {
             // VMAIN - VRAM Address Increment Mode
             0x2115 => Some(self.vmain = val),
@@ -481,6 +850,11 @@ where
                 let addr = usize::from(self.vram_addr_translate(self.vmadd.get())) & VRAM_ADDRMASK;
                 self.vram_autoinc(false, true);
 
+                // VRAM is only accessible during V-blank or force blank
+                if self.in_active_display() {
+                    return Some(());
+                }
+
                 let cur = self.vram[addr];
                 Some(self.vram[addr] = (cur & 0xFF00) | val as u16)
             }
@@ -489,9 +863,23 @@ where
                 let addr = usize::from(self.vram_addr_translate(self.vmadd.get())) & VRAM_ADDRMASK;
                 self.vram_autoinc(true, true);
 
+                if self.in_active_display() {
+                    return Some(());
+                }
+
                 let cur = self.vram[addr];
                 Some(self.vram[addr] = (cur & 0xFF) | (val as u16) << 8)
             }
+            // OAMDATA - OAM Data Write (W)
+            0x2104 if self.in_active_display() => {
+                // The write goes to the address the PPU is accessing,
+                // but OAMADD still increments and determines which byte
+                // of the word is written.
+                let oaddr = self.state.oamadd_addr.get();
+                self.state
+                    .oam_write(self.get_oam_render_addr() | (oaddr & 1), val);
+                Some(self.state.oamadd_addr.set((oaddr + 1) & 0x3FF))
+            }
             _ => self.state.write(fulladdr, val),
         }
     }
@@ -1,6 +1,6 @@
 use super::color::SnesColor;
 use super::ppu::SCREEN_WIDTH;
-use super::sprites::{SpriteTile, OAM_ENTRIES};
+use super::sprites::{SpriteLine, SpriteTile};
 use super::state::*;
 use super::tile::*;
 use crate::frontend::Color;
@@ -26,6 +26,9 @@ pub struct RenderState {
     /// Layer that produced the pixel
     pub layer: [u8; SCREEN_WIDTH],
 
+    /// CGRAM address the color was fetched from
+    pub cgaddr: [u8; SCREEN_WIDTH],
+
     /// Layer mask for the window
     pub windowlayermask: u8,
 
@@ -48,6 +51,7 @@ impl RenderState {
             palette: [0; SCREEN_WIDTH],
             paletted: [backdrop; SCREEN_WIDTH],
             layer: [LAYER_BACKDROP; SCREEN_WIDTH],
+            cgaddr: [0; SCREEN_WIDTH],
             layermask,
             window,
             windowlayermask,
@@ -64,6 +68,17 @@ pub struct WindowState {
     sprites: WindowLine,
 }
 
+impl WindowState {
+    /// All windows disabled
+    fn none() -> Self {
+        Self {
+            bg: [[false; SCREEN_WIDTH]; 4],
+            math: [false; SCREEN_WIDTH],
+            sprites: [false; SCREEN_WIDTH],
+        }
+    }
+}
+
 const WINAREA_OUTER: u8 = 1 << 0;
 const WINAREA_ENABLE: u8 = 1 << 1;
 const WINAREA_MASK: u8 = 0x03;
@@ -91,15 +106,26 @@ impl PPUState {
         SnesColor::from(self.cgram[addr as usize])
     }
 
+    /// First CGRAM address of the palette of a background tile
     #[inline(always)]
-    fn cindex_to_color<'a>(&self, bg: usize, tile: &impl Tile<'a>, idx: u8) -> SnesColor {
+    fn tile_palette_base<'a>(&self, bg: usize, tile: &impl Tile<'a>) -> u8 {
         let paletteidx = tile.get_tile_palette();
-        let palette = match tile.get_tile_bpp() {
+        match tile.get_tile_bpp() {
             BPP::Two if self.get_screen_mode() == 0 => bg as u8 * 32 + paletteidx * 4,
             BPP::Two => paletteidx * 4,
             BPP::Four => paletteidx * 16,
             BPP::Eight => 0,
-        };
+        }
+    }
+
+    #[inline(always)]
+    pub(super) fn cindex_to_color<'a>(
+        &self,
+        bg: usize,
+        tile: &impl Tile<'a>,
+        idx: u8,
+    ) -> SnesColor {
+        let palette = self.tile_palette_base(bg, tile);
         if self.cgwsel & (1 << 0) != 0 && self.get_layer_bpp(bg) == BPP::Eight {
             Self::directcolor(idx, palette)
         } else {
@@ -107,9 +133,19 @@ impl PPUState {
         }
     }
 
-    fn sprite_cindex_to_color(&self, tile: &SpriteTile, idx: u8) -> SnesColor {
-        let palette = 128 + (tile.oam.palette() * 16);
-        self.cgram_to_color(palette + idx)
+    /// CGRAM address of a color index in a background tile
+    #[inline(always)]
+    pub(super) fn cindex_to_cgaddr<'a>(&self, bg: usize, tile: &impl Tile<'a>, idx: u8) -> u8 {
+        self.tile_palette_base(bg, tile) + idx
+    }
+
+    pub(super) fn sprite_cindex_to_color(&self, tile: &SpriteTile, idx: u8) -> SnesColor {
+        self.cgram_to_color(Self::sprite_cindex_to_cgaddr(tile, idx))
+    }
+
+    /// CGRAM address of a color index in a sprite tile
+    pub(super) fn sprite_cindex_to_cgaddr(tile: &SpriteTile, idx: u8) -> u8 {
+        128 + (tile.oam.palette() * 16) + idx
     }
 
     fn adjust_offsets_opt(
@@ -214,6 +250,7 @@ impl PPUState {
                 }
                 state.idx[x] = c;
                 state.paletted[x] = self.cindex_to_color(bg, &tile, c);
+                state.cgaddr[x] = self.cindex_to_cgaddr(bg, &tile, c);
                 state.layer[x] = bg as u8;
                 x += 1;
             } else {
@@ -237,6 +274,7 @@ impl PPUState {
                     }
                     state.idx[x] = c[ix];
                     state.paletted[x] = self.cindex_to_color(bg, &tile, c[ix]);
+                    state.cgaddr[x] = self.cindex_to_cgaddr(bg, &tile, c[ix]);
                     state.layer[x] = bg as u8;
                     x += 1;
                 }
@@ -244,74 +282,63 @@ impl PPUState {
         }
     }
 
-    fn render_scanline_sprites(&mut self, scanline: usize, state: &mut RenderState, priority: u8) {
-        if state.layermask & (1 << LAYER_SPRITES) == 0 {
+    fn render_scanline_sprites(
+        &mut self,
+        sprites: &SpriteLine,
+        state: &mut RenderState,
+        priority: u8,
+    ) {
+        if state.layermask & (1 << LAYER_SPRITES) == 0
+            || self.dbg_layermask & (DBG_OBJ_PRIO0 << priority) != 0
+        {
             return;
         }
 
-        // Sprites are actually offset 1 scanline down, because on the original hardware
-        // the sprites for the NEXT scanline are fetched during the CURRENT scanline.
-        // On scanline 0, the sprites at Y 0 are fetched to be drawn at scanline 1.
-        // This is also why scanline 0 is never rendered.
-        let scanline = scanline - 1;
-
         let scale = self.get_screen_mode_scale_sprites();
 
-        for idx in 0..OAM_ENTRIES {
-            let prio_idx = if self.oam_priority {
-                // Priority rotation enabled
-                (idx + self.oamadd_reload.get() as usize) % OAM_ENTRIES
-            } else {
-                idx
-            };
-            let e = self.get_oam_entry(prio_idx);
-
+        // Sprites are in order of priority, so the first opaque
+        // pixel drawn wins.
+        for s in &sprites.sprites {
+            let e = &s.oam;
             if e.priority != priority {
                 continue;
             }
 
-            if !(e.y..(e.y + e.height)).contains(&scanline) {
-                continue;
-            }
+            // Sub-tile and coordinate within the tile vertically
+            let (t_y, in_y) = (s.row / TILE_HEIGHT, s.row % TILE_HEIGHT);
 
-            for x in e.x..(e.x + e.width as i32) {
-                if (x * scale as i32) >= state.idx.len() as i32 || x < 0 {
-                    // Outside of visible area.
-                    continue;
-                }
+            for &t_x in &s.tiles {
+                let sprite = self.get_sprite_tile(e, t_x, t_y);
 
-                if state.window.sprites[x as usize]
-                    && state.windowlayermask & (1 << LAYER_SPRITES) != 0
-                {
-                    // Masked by window.
-                    continue;
-                }
+                for in_x in 0..TILE_WIDTH {
+                    let x = e.x + (t_x * TILE_WIDTH + in_x) as i32;
+                    if (x * scale as i32) >= state.idx.len() as i32 || x < 0 {
+                        // Outside of visible area.
+                        continue;
+                    }
 
-                // Coordinates within the tile
-                let (in_x, in_y) = (
-                    ((x - e.x) % TILE_WIDTH as i32) as usize,
-                    ((scanline - e.y) % TILE_HEIGHT) as usize,
-                );
-                // Sub-tile coordinates
-                let (t_x, t_y) = (
-                    ((x - e.x) / TILE_WIDTH as i32) as usize,
-                    ((scanline - e.y) / TILE_HEIGHT) as usize,
-                );
-                // Should be positive from here on
-                let x = (x as usize) * scale;
-
-                let sprite = self.get_sprite_tile(&e, t_x, t_y);
-
-                let coloridx = sprite.get_coloridx(in_x, in_y, &self.vram);
-                if coloridx == 0 || state.idx[x] != 0 {
-                    continue;
-                }
+                    if state.window.sprites[x as usize]
+                        && state.windowlayermask & (1 << LAYER_SPRITES) != 0
+                    {
+                        // Masked by window.
+                        continue;
+                    }
+
+                    // Should be positive from here on
+                    let x = (x as usize) * scale;
+
+                    let coloridx = sprite.get_coloridx(in_x, in_y, &self.vram);
+                    if coloridx == 0 || state.idx[x] != 0 {
+                        continue;
+                    }
 
-                for ix in x..(x + scale) {
-                    state.idx[ix] = coloridx;
-                    state.palette[ix] = sprite.oam.palette();
-                    state.paletted[ix] = self.sprite_cindex_to_color(&sprite, coloridx);
-                    state.layer[ix] = LAYER_SPRITES;
+                    for ix in x..(x + scale) {
+                        state.idx[ix] = coloridx;
+                        state.palette[ix] = sprite.oam.palette();
+                        state.paletted[ix] = self.sprite_cindex_to_color(&sprite, coloridx);
+                        state.cgaddr[ix] = Self::sprite_cindex_to_cgaddr(&sprite, coloridx);
+                        state.layer[ix] = LAYER_SPRITES;
+                    }
                 }
             }
         }
@@ -320,7 +347,7 @@ impl PPUState {
     fn render_scanline_screen(
         &mut self,
         scanline_bg: usize,
-        scanline_sprites: usize,
+        sprites: &SpriteLine,
         layermask: u8,
         backdrop: SnesColor,
         windows: WindowState,
@@ -333,25 +360,25 @@ impl PPUState {
             0 => {
                 // 4 layers, 2bpp (4 colors)
                 // Sprites with priority 3
-                self.render_scanline_sprites(scanline_sprites, &mut state, 3);
+                self.render_scanline_sprites(sprites, &mut state, 3);
                 // BG1 tiles with priority 1
                 self.render_scanline_bglayer(scanline_bg, 0, &mut state, true);
                 // BG2 tiles with priority 1
                 self.render_scanline_bglayer(scanline_bg, 1, &mut state, true);
                 // Sprites with priority 2
-                self.render_scanline_sprites(scanline_sprites, &mut state, 2);
+                self.render_scanline_sprites(sprites, &mut state, 2);
                 // BG1 tiles with priority 0
                 self.render_scanline_bglayer(scanline_bg, 0, &mut state, false);
                 // BG2 tiles with priority 0
                 self.render_scanline_bglayer(scanline_bg, 1, &mut state, false);
                 // Sprites with priority 1
-                self.render_scanline_sprites(scanline_sprites, &mut state, 1);
+                self.render_scanline_sprites(sprites, &mut state, 1);
                 // BG3 tiles with priority 1
                 self.render_scanline_bglayer(scanline_bg, 2, &mut state, true);
                 // BG4 tiles with priority 1
                 self.render_scanline_bglayer(scanline_bg, 3, &mut state, true);
                 // Sprites with priority 0
-                self.render_scanline_sprites(scanline_sprites, &mut state, 0);
+                self.render_scanline_sprites(sprites, &mut state, 0);
                 // BG3 tiles with priority 0
                 self.render_scanline_bglayer(scanline_bg, 2, &mut state, false);
                 // BG4 tiles with priority 0
@@ -364,25 +391,25 @@ impl PPUState {
                     self.render_scanline_bglayer(scanline_bg, 2, &mut state, true);
                 }
                 // Sprites with priority 3
-                self.render_scanline_sprites(scanline_sprites, &mut state, 3);
+                self.render_scanline_sprites(sprites, &mut state, 3);
                 // BG1 tiles with priority 1
                 self.render_scanline_bglayer(scanline_bg, 0, &mut state, true);
                 // BG2 tiles with priority 1
                 self.render_scanline_bglayer(scanline_bg, 1, &mut state, true);
                 // Sprites with priority 2
-                self.render_scanline_sprites(scanline_sprites, &mut state, 2);
+                self.render_scanline_sprites(sprites, &mut state, 2);
                 // BG1 tiles with priority 0
                 self.render_scanline_bglayer(scanline_bg, 0, &mut state, false);
                 // BG2 tiles with priority 0
                 self.render_scanline_bglayer(scanline_bg, 1, &mut state, false);
                 // Sprites with priority 1
-                self.render_scanline_sprites(scanline_sprites, &mut state, 1);
+                self.render_scanline_sprites(sprites, &mut state, 1);
                 // BG3 tiles with priority 1 if bit 3 of $2105 is clear
                 if !bg3_prio {
                     self.render_scanline_bglayer(scanline_bg, 2, &mut state, true);
                 }
                 // Sprites with priority 0
-                self.render_scanline_sprites(scanline_sprites, &mut state, 0);
+                self.render_scanline_sprites(sprites, &mut state, 0);
                 // BG3 tiles with priority 0
                 self.render_scanline_bglayer(scanline_bg, 2, &mut state, false);
             }
@@ -390,19 +417,19 @@ impl PPUState {
                 // 2 layers, bg1: 4bpp (16 colors), bg2: 4bpp (16 colors)
                 // bg3: Offset-per-tile
                 // Sprites with priority 3
-                self.render_scanline_sprites(scanline_sprites, &mut state, 3);
+                self.render_scanline_sprites(sprites, &mut state, 3);
                 // BG1 tiles with priority 1
                 self.render_scanline_bglayer(scanline_bg, 0, &mut state, true);
                 // Sprites with priority 2
-                self.render_scanline_sprites(scanline_sprites, &mut state, 2);
+                self.render_scanline_sprites(sprites, &mut state, 2);
                 // BG2 tiles with priority 1
                 self.render_scanline_bglayer(scanline_bg, 1, &mut state, true);
                 // Sprites with priority 1
-                self.render_scanline_sprites(scanline_sprites, &mut state, 1);
+                self.render_scanline_sprites(sprites, &mut state, 1);
                 // BG1 tiles with priority 0
                 self.render_scanline_bglayer(scanline_bg, 0, &mut state, false);
                 // Sprites with priority 0
-                self.render_scanline_sprites(scanline_sprites, &mut state, 0);
+                self.render_scanline_sprites(sprites, &mut state, 0);
                 // BG2 tiles with priority 0
                 self.render_scanline_bglayer(scanline_bg, 1, &mut state, false);
             }
@@ -410,19 +437,19 @@ impl PPUState {
                 // 2 layers, bg1: 8bpp (256 colors)
                 // bg2: 4bpp (16 colors)
                 // Sprites with priority 3
-                self.render_scanline_sprites(scanline_sprites, &mut state, 3);
+                self.render_scanline_sprites(sprites, &mut state, 3);
                 // BG1 tiles with priority 1
                 self.render_scanline_bglayer(scanline_bg, 0, &mut state, true);
                 // Sprites with priority 2
-                self.render_scanline_sprites(scanline_sprites, &mut state, 2);
+                self.render_scanline_sprites(sprites, &mut state, 2);
                 // BG2 tiles with priority 1
                 self.render_scanline_bglayer(scanline_bg, 1, &mut state, true);
                 // Sprites with priority 1
-                self.render_scanline_sprites(scanline_sprites, &mut state, 1);
+                self.render_scanline_sprites(sprites, &mut state, 1);
                 // BG1 tiles with priority 0
                 self.render_scanline_bglayer(scanline_bg, 0, &mut state, false);
                 // Sprites with priority 0
-                self.render_scanline_sprites(scanline_sprites, &mut state, 0);
+                self.render_scanline_sprites(sprites, &mut state, 0);
                 // BG2 tiles with priority 0
                 self.render_scanline_bglayer(scanline_bg, 1, &mut state, false);
             }
@@ -430,19 +457,19 @@ impl PPUState {
                 // 2 layers, bg1: 8bpp (256 colors)
                 // bg2: 2bpp (16 colors)
                 // Sprites with priority 3
-                self.render_scanline_sprites(scanline_sprites, &mut state, 3);
+                self.render_scanline_sprites(sprites, &mut state, 3);
                 // BG1 tiles with priority 1
                 self.render_scanline_bglayer(scanline_bg, 0, &mut state, true);
                 // Sprites with priority 2
-                self.render_scanline_sprites(scanline_sprites, &mut state, 2);
+                self.render_scanline_sprites(sprites, &mut state, 2);
                 // BG2 tiles with priority 1
                 self.render_scanline_bglayer(scanline_bg, 1, &mut state, true);
                 // Sprites with priority 1
-                self.render_scanline_sprites(scanline_sprites, &mut state, 1);
+                self.render_scanline_sprites(sprites, &mut state, 1);
                 // BG1 tiles with priority 0
                 self.render_scanline_bglayer(scanline_bg, 0, &mut state, false);
                 // Sprites with priority 0
-                self.render_scanline_sprites(scanline_sprites, &mut state, 0);
+                self.render_scanline_sprites(sprites, &mut state, 0);
                 // BG2 tiles with priority 0
                 self.render_scanline_bglayer(scanline_bg, 1, &mut state, false);
             }
@@ -450,49 +477,49 @@ impl PPUState {
                 // 2 layers, bg1: 4bpp (16 colors)
                 // bg2: 2bpp (4 colors)
                 // Sprites with priority 3
-                self.render_scanline_sprites(scanline_sprites, &mut state, 3);
+                self.render_scanline_sprites(sprites, &mut state, 3);
                 // BG1 tiles with priority 1
                 self.render_scanline_bglayer(scanline_bg, 0, &mut state, true);
                 // Sprites with priority 2
-                self.render_scanline_sprites(scanline_sprites, &mut state, 2);
+                self.render_scanline_sprites(sprites, &mut state, 2);
                 // BG2 tiles with priority 1
                 self.render_scanline_bglayer(scanline_bg, 1, &mut state, true);
                 // Sprites with priority 1
-                self.render_scanline_sprites(scanline_sprites, &mut state, 1);
+                self.render_scanline_sprites(sprites, &mut state, 1);
                 // BG1 tiles with priority 0
                 self.render_scanline_bglayer(scanline_bg, 0, &mut state, false);
                 // Sprites with priority 0
-                self.render_scanline_sprites(scanline_sprites, &mut state, 0);
+                self.render_scanline_sprites(sprites, &mut state, 0);
                 // BG2 tiles with priority 0
                 self.render_scanline_bglayer(scanline_bg, 1, &mut state, false);
             }
             6 => {
                 // 1 layer, bg1: 4bpp (16 colors)
                 // Sprites with priority 3
-                self.render_scanline_sprites(scanline_sprites, &mut state, 3);
+                self.render_scanline_sprites(sprites, &mut state, 3);
                 // BG1 tiles with priority 1
                 self.render_scanline_bglayer(scanline_bg, 0, &mut state, true);
                 // Sprites with priority 2
-                self.render_scanline_sprites(scanline_sprites, &mut state, 2);
+                self.render_scanline_sprites(sprites, &mut state, 2);
                 // Sprites with priority 1
-                self.render_scanline_sprites(scanline_sprites, &mut state, 1);
+                self.render_scanline_sprites(sprites, &mut state, 1);
                 // BG1 tiles with priority 0
                 self.render_scanline_bglayer(scanline_bg, 0, &mut state, false);
                 // Sprites with priority 0
-                self.render_scanline_sprites(scanline_sprites, &mut state, 0);
+                self.render_scanline_sprites(sprites, &mut state, 0);
             }
             7 => {
                 // TODO extbg
                 // Sprites with priority 3
-                self.render_scanline_sprites(scanline_sprites, &mut state, 3);
+                self.render_scanline_sprites(sprites, &mut state, 3);
                 // Sprites with priority 2
-                self.render_scanline_sprites(scanline_sprites, &mut state, 2);
+                self.render_scanline_sprites(sprites, &mut state, 2);
                 // Sprites with priority 1
-                self.render_scanline_sprites(scanline_sprites, &mut state, 1);
+                self.render_scanline_sprites(sprites, &mut state, 1);
                 // BG1
                 self.render_scanline_mode7(scanline_bg, 0, &mut state);
                 // Sprites with priority 0
-                self.render_scanline_sprites(scanline_sprites, &mut state, 0);
+                self.render_scanline_sprites(sprites, &mut state, 0);
             }
             _ => unreachable!(),
         }
@@ -503,9 +530,19 @@ impl PPUState {
     pub fn render_scanline(
         &mut self,
         scanline_bg: usize,
-        scanline_sprites: usize,
+        sprites: &SpriteLine,
     ) -> ArrayVec<Color, SCREEN_WIDTH> {
-        let brightness = (self.inidisp & 0x0F) as usize;
+        self.render_scanline_cgaddr(scanline_bg, sprites).0
+    }
+
+    /// Renders a scanline, also returning the CGRAM address the main
+    /// screen color of every pixel was fetched from.
+    pub fn render_scanline_cgaddr(
+        &mut self,
+        scanline_bg: usize,
+        sprites: &SpriteLine,
+    ) -> (ArrayVec<Color, SCREEN_WIDTH>, ArrayVec<u8, SCREEN_WIDTH>) {
+        let brightness = self.inidisp & 0x0F;
         let pseudo_highres = self.setini & (1 << 3) != 0;
         let scale = if pseudo_highres {
             1
@@ -513,29 +550,38 @@ impl PPUState {
             self.get_screen_mode_scale_bg()
         };
         let mut out: ArrayVec<Color, SCREEN_WIDTH> = ArrayVec::new();
+        let mut cgaddr: ArrayVec<u8, SCREEN_WIDTH> = ArrayVec::new();
 
-        if brightness == 0 || self.inidisp & 0x80 != 0 {
-            // Force blank or no brightness
-            return ArrayVec::from_iter(
-                iter::repeat(SnesColor::BLACK.to_native()).take(SCREEN_WIDTH),
+        if self.in_force_blank() {
+            return (
+                ArrayVec::from_iter(iter::repeat_n(SnesColor::BLACK.to_native(), SCREEN_WIDTH)),
+                ArrayVec::from_iter(iter::repeat_n(0, SCREEN_WIDTH)),
             );
         }
 
-        let windows = self.render_windows();
+        let windows = if self.dbg_layermask & DBG_WINDOWS == 0 {
+            self.render_windows()
+        } else {
+            WindowState::none()
+        };
         let mainscreen = self.render_scanline_screen(
             scanline_bg,
-            scanline_sprites,
-            self.tm & !self.dbg_layermask,
-            self.cgram_to_color(0),
+            sprites,
+            self.dbg_screen_mask(self.tm),
+            if self.dbg_layermask & DBG_BACKDROP == 0 {
+                self.cgram_to_color(0)
+            } else {
+                SnesColor::BLACK
+            },
             windows.clone(),
             self.tmw,
         );
         let subscreen = self.render_scanline_screen(
             scanline_bg,
-            scanline_sprites,
+            sprites,
             if pseudo_highres || self.cgwsel & (1 << 1) != 0 {
                 // Enable backdrop + bg + obj
-                self.ts & !self.dbg_layermask
+                self.dbg_screen_mask(self.ts)
             } else {
                 // Backdrop only
                 0
@@ -582,11 +628,12 @@ impl PPUState {
             // Outside of high-res modes we scale up horizontally by two
             // to stretch the image to the high-res resolution.
             for _ in 0..scale {
-                out.push(pixel.apply_brightness(brightness).to_native());
+                out.push(self.colortable.to_native(pixel, brightness));
+                cgaddr.push(mainscreen.cgaddr[if pseudo_highres { x / 2 } else { x }]);
             }
         }
 
-        out
+        (out, cgaddr)
     }
 
     fn apply_colormath(
@@ -607,6 +654,10 @@ impl PPUState {
 
         let mut pixel = mainclr;
 
+        if self.dbg_layermask & DBG_COLORMATH != 0 {
+            return pixel;
+        }
+
         if force_main_black == 3
             || (in_window && force_main_black == 2)
             || (!in_window && force_main_black == 1)
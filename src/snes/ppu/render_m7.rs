@@ -13,7 +13,7 @@ impl PPUState {
 
     /// Maps mode 7 VRAM coordinate to a color
     #[inline(always)]
-    fn mode7_vram_to_color(&self, vram_x: i32, vram_y: i32) -> u8 {
+    pub(super) fn mode7_vram_to_color(&self, vram_x: i32, vram_y: i32) -> u8 {
         let screenover = (self.m7sel >> 6) & 0x03;
         let overflow = (vram_x >> 18) | (vram_y >> 18) != 0;
 
@@ -118,6 +118,7 @@ impl PPUState {
                 } else {
                     self.cgram_to_color(c)
                 };
+                state.cgaddr[x] = c;
                 state.layer[x] = bg as u8;
             }
             (vram_x, vram_y) = self.mode7_next_vramxy(vram_x, vram_y);
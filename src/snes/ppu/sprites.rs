@@ -4,11 +4,20 @@ use super::tile::*;
 
 use crate::util::sign_extend;
 
+use arrayvec::ArrayVec;
+
 use std::ops::Range;
 
 pub const OAM_ENTRIES: usize = 128;
 
-#[derive(Debug)]
+/// Maximum amount of sprites on a scanline (range-over beyond this)
+pub const SPRITES_PER_LINE: usize = 32;
+/// Maximum amount of 8x8 sprite tiles on a scanline (time-over beyond this)
+pub const SPRITE_TILES_PER_LINE: usize = 34;
+/// Maximum amount of tiles in a row of a sprite
+const SPRITE_MAX_TILES: usize = 8;
+
+#[derive(Debug, Clone)]
 pub struct OAMEntry {
     pub x: i32,
     pub y: usize,
@@ -28,6 +37,16 @@ impl OAMEntry {
     pub fn palette(&self) -> u8 {
         (self.attr >> 1) & 0x07
     }
+    /// Returns the row of the sprite on the given scanline, if the sprite
+    /// intersects it. Sprites wrap around vertically.
+    pub fn get_row(&self, scanline: usize) -> Option<usize> {
+        let row = scanline.wrapping_sub(self.y) & 0xFF;
+        if row < self.height {
+            Some(row)
+        } else {
+            None
+        }
+    }
     pub fn get_tileidx(&self, tile_x: usize, tile_y: usize) -> usize {
         debug_assert!(tile_x < self.width / TILE_WIDTH);
         debug_assert!(tile_y < self.height / TILE_HEIGHT);
@@ -51,6 +70,27 @@ impl OAMEntry {
     }
 }
 
+/// A sprite selected to be drawn on a scanline
+#[derive(Debug)]
+pub struct LineSprite {
+    pub oam: OAMEntry,
+    /// Row of the sprite on the scanline
+    pub row: usize,
+    /// Tiles (horizontal index) fetched in time to be drawn
+    pub tiles: ArrayVec<usize, SPRITE_MAX_TILES>,
+}
+
+/// Result of the sprite evaluation for a scanline
+#[derive(Debug, Default)]
+pub struct SpriteLine {
+    /// Sprites on the scanline, in order of priority
+    pub sprites: ArrayVec<LineSprite, SPRITES_PER_LINE>,
+    /// More than 32 sprites were on the scanline
+    pub range_over: bool,
+    /// More than 34 sprite tiles were on the scanline
+    pub time_over: bool,
+}
+
 /// A sprite tile is a single 8 x 8 pixel segment of a sprite.
 pub struct SpriteTile<'a> {
     data_range: Range<usize>,
@@ -120,6 +160,75 @@ impl PPUState {
         }
     }
 
+    /// Retrieve all sprite entries from OAM
+    pub fn get_oam_entries(&self) -> Vec<OAMEntry> {
+        (0..OAM_ENTRIES).map(|i| self.get_oam_entry(i)).collect()
+    }
+
+    /// Index of the OAM entry with the highest priority. With priority
+    /// rotation enabled (OAMADD bit 15), this is selected by OAMADD.
+    pub fn get_first_sprite(&self) -> usize {
+        if self.oam_priority {
+            (usize::from(self.oamadd_reload.get()) >> 2) & (OAM_ENTRIES - 1)
+        } else {
+            0
+        }
+    }
+
+    /// Evaluates which sprites (and tiles) are drawn on a scanline,
+    /// in the same way the hardware does.
+    ///
+    /// First, OAM is scanned starting at the highest priority sprite
+    /// for (up to 32) sprites that are in range of the scanline. Then,
+    /// the tiles of those sprites are fetched, starting with the last
+    /// sprite found, up to 34 tiles. Only tiles that are (partially)
+    /// visible horizontally are fetched.
+    pub fn evaluate_sprites(&self, scanline: usize) -> SpriteLine {
+        let mut line = SpriteLine::default();
+        let first = self.get_first_sprite();
+
+        // Range evaluation
+        for i in 0..OAM_ENTRIES {
+            let oam = self.get_oam_entry((first + i) % OAM_ENTRIES);
+            let Some(row) = oam.get_row(scanline) else {
+                continue;
+            };
+            // Sprites completely off-screen to the left are not in range,
+            // except at X = -256.
+            if oam.x != -256 && oam.x <= -(oam.width as i32) {
+                continue;
+            }
+            if line.sprites.is_full() {
+                line.range_over = true;
+                break;
+            }
+            line.sprites.push(LineSprite {
+                oam,
+                row,
+                tiles: ArrayVec::new(),
+            });
+        }
+
+        // Time evaluation
+        let mut tiles = 0;
+        'time: for sprite in line.sprites.iter_mut().rev() {
+            for t_x in 0..(sprite.oam.width / TILE_WIDTH) {
+                let x = sprite.oam.x + (t_x * TILE_WIDTH) as i32;
+                if x <= -(TILE_WIDTH as i32) || x >= 256 {
+                    continue;
+                }
+                if tiles == SPRITE_TILES_PER_LINE {
+                    line.time_over = true;
+                    break 'time;
+                }
+                sprite.tiles.push(t_x);
+                tiles += 1;
+            }
+        }
+
+        line
+    }
+
     /// Retrieve a pixel data reference to a specific sprite tile.
     /// One tile is an 8x8 pixel segment of a sprite.
     pub fn get_sprite_tile<'a>(
@@ -8,7 +8,7 @@ use num_traits::{FromPrimitive, ToPrimitive};
 use serbia::serbia;
 use serde::{Deserialize, Serialize};
 
-use super::color::SnesColor;
+use super::color::{ColorTable, SnesColor};
 use super::ppu::*;
 use super::tile::{Tile, TILE_HEIGHT, TILE_WIDTH};
 
@@ -24,6 +24,24 @@ const OAM_SIZE: usize = 512 + 32;
 /// BG3 contains offset-per-tile data for mode 2, 4, 6
 const OPT_BG: usize = 2;
 
+// Debug layer mask (dbg_layermask) bits. A set bit hides the layer
+// or disables the effect, regardless of the PPU registers.
+pub const DBG_BG1: u16 = 1 << 0;
+pub const DBG_BG2: u16 = 1 << 1;
+pub const DBG_BG3: u16 = 1 << 2;
+pub const DBG_BG4: u16 = 1 << 3;
+pub const DBG_OBJ: u16 = 1 << 4;
+/// Sprites with priority 0, shift left by priority for the others
+pub const DBG_OBJ_PRIO0: u16 = 1 << 8;
+pub const DBG_OBJ_PRIO1: u16 = DBG_OBJ_PRIO0 << 1;
+pub const DBG_OBJ_PRIO2: u16 = DBG_OBJ_PRIO0 << 2;
+pub const DBG_OBJ_PRIO3: u16 = DBG_OBJ_PRIO0 << 3;
+pub const DBG_COLORMATH: u16 = 1 << 12;
+pub const DBG_WINDOWS: u16 = 1 << 13;
+pub const DBG_BACKDROP: u16 = 1 << 14;
+/// Mask for the bits that correspond to TM/TS
+const DBG_TM_MASK: u16 = 0x1F;
+
 #[derive(FromPrimitive)]
 pub enum TilemapDimensions {
     D32x32 = 0,
@@ -104,8 +122,12 @@ impl BPP {
 #[serbia]
 #[derive(Clone, Serialize, Deserialize)]
 pub struct PPUState {
-    // Debug toggles to mask certain bg/obj layers
-    pub dbg_layermask: u8,
+    /// Debug toggles to mask certain layers/effects (DBG_*)
+    pub dbg_layermask: u16,
+
+    /// Conversion to host colors, in the color profile of the renderer
+    #[serde(skip)]
+    pub(super) colortable: ColorTable,
 
     pub(super) vram: Vram,
 
@@ -200,6 +222,7 @@ impl PPUState {
     pub fn new() -> Self {
         Self {
             dbg_layermask: 0,
+            colortable: ColorTable::default(),
 
             vram: Arc::new(vec![0; VRAM_WORDS]),
 
@@ -283,9 +306,30 @@ impl PPUState {
         }
     }
 
+    /// Returns if interlace is enabled (SETINI).
+    pub(super) fn in_interlace(&self) -> bool {
+        self.setini & 1 != 0
+    }
+
+    /// Main/sub screen layer mask with the debug layer mask applied
+    pub(super) fn dbg_screen_mask(&self, tmts: u8) -> u8 {
+        tmts & !((self.dbg_layermask & DBG_TM_MASK) as u8)
+    }
+
+    /// Returns if the display is forced blank (INIDISP bit 7), which
+    /// gives free access to VRAM, CGRAM and OAM.
+    pub(super) fn in_force_blank(&self) -> bool {
+        self.inidisp & 0x80 != 0
+    }
+
+    /// Returns if overscan (239 visible scanlines) is enabled.
+    pub(super) fn in_overscan(&self) -> bool {
+        self.setini & (1 << 2) != 0
+    }
+
     /// Returns if the PPU is currently in vertical high res mode.
     pub(super) fn in_highres_v(&self) -> bool {
-        self.in_highres_h() && self.setini & 1 != 0
+        self.in_highres_h() && self.in_interlace()
     }
 
     /// Returns the horizontal scaling factor of the active PPU mode,
@@ -1,15 +1,24 @@
 use crate::bus::BusMember;
-use crate::frontend::NullRenderer;
+use crate::frontend::test::TestRenderer;
+use crate::frontend::{ColorProfile, NullRenderer, Renderer};
 use crate::snes::cartridge::VideoFormat;
+use crate::tickable::{Tickable, Ticks};
 
-use super::ppu::PPU;
-use super::state::PPUState;
+use super::color::{ColorTable, SnesColor};
+use super::debug::DebugView;
+use super::ppu::{RenderMode, PPU, SCREEN_HEIGHT, SCREEN_WIDTH};
+use super::sprites::SpriteLine;
+use super::state::{PPUState, BPP, DBG_BACKDROP, DBG_COLORMATH};
+
+use std::sync::atomic::Ordering;
 
 fn ppustate() -> PPUState {
     PPUState::new()
 }
 fn ppu() -> PPU<NullRenderer> {
-    PPU::new(NullRenderer {}, 0, VideoFormat::PAL)
+    let mut p = PPU::new(NullRenderer {}, VideoFormat::PAL, RenderMode::Scanline);
+    p.write(0x2100, 0x80); // INIDISP - force blank, for access to PPU memory
+    p
 }
 
 #[test]
@@ -296,3 +305,511 @@ fn vram_addr_translate() {
         0b1111110000000000
     );
 }
+
+/// Runs the PPU until the start of the next frame.
+/// Returns the amount of scanlines and master cycles in the frame.
+fn run_frame(p: &mut PPU<TestRenderer>) -> (usize, Ticks) {
+    let (mut lines, mut master) = (0, 0);
+    loop {
+        master += p.get_dot_master_cycles();
+        p.tick(1).unwrap();
+        if p.get_current_h() == 0 {
+            lines += 1;
+            if p.get_current_scanline() == 0 {
+                return (lines, master);
+            }
+        }
+    }
+}
+
+fn frame_timing(videoformat: VideoFormat, interlace: bool) -> [(usize, Ticks); 2] {
+    let (renderer, _) = TestRenderer::new_test(SCREEN_WIDTH, SCREEN_HEIGHT);
+    let mut p = PPU::new(renderer, videoformat, RenderMode::Scanline);
+    p.single_threaded();
+    p.write(0x2133, if interlace { 1 } else { 0 }); // SETINI
+    [run_frame(&mut p), run_frame(&mut p)]
+}
+
+#[test]
+fn frame_timing_ntsc() {
+    // Scanline 240 of every other frame is short
+    assert_eq!(
+        frame_timing(VideoFormat::NTSC, false),
+        [(262, 262 * 1364), (262, 262 * 1364 - 4)]
+    );
+    // Even frames have an extra scanline
+    assert_eq!(
+        frame_timing(VideoFormat::NTSC, true),
+        [(263, 263 * 1364), (262, 262 * 1364)]
+    );
+}
+
+#[test]
+fn frame_timing_pal() {
+    assert_eq!(
+        frame_timing(VideoFormat::PAL, false),
+        [(312, 312 * 1364), (312, 312 * 1364)]
+    );
+    // Even frames have an extra scanline, scanline 311 of odd frames is long
+    assert_eq!(
+        frame_timing(VideoFormat::PAL, true),
+        [(313, 313 * 1364), (312, 312 * 1364 + 4)]
+    );
+}
+
+/// Sets up an OAM entry (small sprite)
+fn set_sprite(s: &mut PPUState, idx: usize, x: u8, y: u8) {
+    s.oam[idx * 4] = x;
+    s.oam[idx * 4 + 1] = y;
+}
+
+fn sprites_offscreen() -> PPUState {
+    let mut s = ppustate();
+    for idx in 0..128 {
+        set_sprite(&mut s, idx, 0, 0xF0);
+    }
+    s
+}
+
+#[test]
+fn sprite_range_over() {
+    let mut s = sprites_offscreen();
+    for idx in 0..33 {
+        set_sprite(&mut s, idx, idx as u8, 10);
+    }
+    let line = s.evaluate_sprites(10);
+    assert!(line.range_over);
+    assert!(!line.time_over);
+    assert_eq!(line.sprites.len(), 32);
+    assert_eq!(line.sprites[0].oam.x, 0);
+    assert_eq!(line.sprites[31].oam.x, 31);
+
+    let line = s.evaluate_sprites(9);
+    assert!(!line.range_over);
+    assert!(line.sprites.is_empty());
+
+    // Sprites wrap around vertically
+    set_sprite(&mut s, 100, 0, 0xFC);
+    assert_eq!(s.evaluate_sprites(2).sprites[0].row, 6);
+}
+
+#[test]
+fn sprite_time_over() {
+    let mut s = sprites_offscreen();
+    // Small sprites 16x16
+    s.obsel = 3 << 5;
+    for idx in 0..18 {
+        set_sprite(&mut s, idx, 0, 0);
+    }
+    let line = s.evaluate_sprites(0);
+    assert!(!line.range_over);
+    assert!(line.time_over);
+    // Tiles are fetched starting at the last sprite
+    assert!(line.sprites[0].tiles.is_empty());
+    assert_eq!(line.sprites[1].tiles.len(), 2);
+    assert_eq!(line.sprites[17].tiles.len(), 2);
+
+    // Exactly 34 tiles
+    set_sprite(&mut s, 17, 0, 0xF0);
+    assert!(!s.evaluate_sprites(0).time_over);
+
+    // Tiles off-screen are not fetched
+    set_sprite(&mut s, 0, 0xF8, 0);
+    let line = s.evaluate_sprites(0);
+    assert!(!line.time_over);
+    assert_eq!(line.sprites[0].tiles.as_slice(), &[0]);
+}
+
+#[test]
+fn sprite_priority_rotation() {
+    let mut s = sprites_offscreen();
+    for idx in 0..128 {
+        set_sprite(&mut s, idx, idx as u8, 0);
+    }
+    assert_eq!(s.evaluate_sprites(0).sprites[0].oam.x, 0);
+
+    // OAMADDL = 5, OAMADDH = priority rotation
+    s.write(0x2102, 5);
+    s.write(0x2103, 0x80);
+    assert_eq!(s.get_first_sprite(), 2);
+    let line = s.evaluate_sprites(0);
+    assert_eq!(line.sprites[0].oam.x, 2);
+    assert_eq!(line.sprites[31].oam.x, 33);
+}
+
+#[test]
+fn stat77() {
+    let (renderer, _) = TestRenderer::new_test(SCREEN_WIDTH, SCREEN_HEIGHT);
+    let mut p = PPU::new(renderer, VideoFormat::NTSC, RenderMode::Scanline);
+    p.single_threaded();
+    assert_eq!(p.read(0x213E), Some(0x01));
+
+    // 33 sprites on scanline 10
+    for idx in 0..33 {
+        p.write(0x2102, (idx * 2) as u8);
+        p.write(0x2103, 0);
+        p.write(0x2104, 0);
+        p.write(0x2104, 10);
+    }
+    p.write(0x2100, 0x0F); // INIDISP
+    while p.get_current_scanline() != 12 {
+        p.tick(1).unwrap();
+    }
+    assert_eq!(p.read(0x213E), Some(0x41));
+
+    // Reset at the end of VBlank
+    while p.get_current_scanline() != 0 {
+        p.tick(1).unwrap();
+    }
+    assert_eq!(p.read(0x213E), Some(0x01));
+}
+
+/// Renders a frame with the backdrop color changed halfway scanline 10.
+/// Returns the colors (BGR) at the left and right side of that line.
+fn raster_split(render_mode: RenderMode) -> ([u8; 3], [u8; 3]) {
+    let (renderer, _) = TestRenderer::new_test(SCREEN_WIDTH, SCREEN_HEIGHT);
+    let mut p = PPU::new(renderer, VideoFormat::NTSC, render_mode);
+    p.single_threaded();
+    p.write(0x2100, 0x0F); // INIDISP - full brightness
+    p.write(0x2121, 0); // CGADD
+    p.write(0x2122, 0x1F); // CGDATA - red
+    p.write(0x2122, 0x00);
+
+    while p.get_current_scanline() != 10 || p.get_current_h() != PPU::<TestRenderer>::H_RENDER + 128
+    {
+        p.tick(1).unwrap();
+    }
+    p.write(0x2121, 0); // CGADD
+    p.write(0x2122, 0x00); // CGDATA - blue
+    p.write(0x2122, 0x7C);
+    run_frame(&mut p);
+
+    let buffer = p.renderer.as_mut().unwrap().get_buffer();
+    let pixel = |x: usize| {
+        // Scanline 0 is not output
+        let idx = ((9 * 2 * SCREEN_WIDTH) + x) * 4;
+        [0, 1, 2].map(|i| buffer[idx + i].load(Ordering::Acquire))
+    };
+    (pixel(0), pixel(SCREEN_WIDTH - 1))
+}
+
+#[test]
+fn render_mode_raster_effect() {
+    // Scanline renderer renders the entire line before the write
+    let (left, right) = raster_split(RenderMode::Scanline);
+    assert!(left[2] > 0 && left[0] == 0);
+    assert_eq!(left, right);
+
+    // Dot renderer applies the write from the next pixel onwards
+    let (left, right) = raster_split(RenderMode::Dot);
+    assert!(left[2] > 0 && left[0] == 0);
+    assert!(right[0] > 0 && right[2] == 0);
+}
+
+/// Runs the PPU until V-blank starts, returns the scanline.
+fn vblank_start(p: &mut PPU<TestRenderer>) -> usize {
+    while p.in_vblank() {
+        p.tick(1).unwrap();
+    }
+    while !p.in_vblank() {
+        p.tick(1).unwrap();
+    }
+    p.get_current_scanline()
+}
+
+#[test]
+fn overscan() {
+    let (renderer, _) = TestRenderer::new_test(SCREEN_WIDTH, SCREEN_HEIGHT);
+    let mut p = PPU::new(renderer, VideoFormat::NTSC, RenderMode::Scanline);
+    p.single_threaded();
+
+    assert_eq!(vblank_start(&mut p), 225);
+    assert_eq!(p.get_active_height(), 448);
+
+    p.write(0x2133, 1 << 2); // SETINI - overscan
+    assert_eq!(vblank_start(&mut p), 240);
+    assert_eq!(p.get_active_height(), 478);
+
+    // Frame length is not affected
+    run_frame(&mut p);
+    assert_eq!(run_frame(&mut p).0, 262);
+}
+
+#[test]
+fn dbg_layermask() {
+    let mut s = ppustate();
+    s.write(0x2100, 0x0F); // INIDISP - full brightness
+    s.write(0x2121, 0); // CGADD
+    s.write(0x2122, 0x1F); // CGDATA - red backdrop
+    s.write(0x2122, 0x00);
+    s.write(0x2131, 1 << 5); // CGADSUB - add to backdrop
+    s.write(0x2132, 0x80 | 0x1F); // COLDATA - blue
+
+    let sprites = SpriteLine::default();
+    let pixel = |s: &mut PPUState| s.render_scanline(1, &sprites)[0];
+
+    let (r, _, b) = pixel(&mut s);
+    assert!(r > 0 && b > 0);
+
+    s.dbg_layermask = DBG_COLORMATH;
+    let (r, _, b) = pixel(&mut s);
+    assert!(r > 0 && b == 0);
+
+    s.dbg_layermask |= DBG_BACKDROP;
+    assert_eq!(pixel(&mut s), (0, 0, 0));
+}
+
+#[test]
+fn debug_views() {
+    let mut p = ppu();
+    p.write(0x2121, 5); // CGADD
+    p.write(0x2122, 0x1F); // CGDATA - red
+    p.write(0x2122, 0x00);
+
+    // Palette grid
+    let img = p.render_debug_view(DebugView::Palette).unwrap();
+    assert_eq!((img.width, img.height), (128, 128));
+    assert_eq!(img.get(5 * 8, 0), (0xF8, 0, 0));
+    assert_eq!(img.get(4 * 8, 0), (0, 0, 0));
+
+    // Tile 1, 2bpp: top-left pixel color 1, with palette 1 (colors 4-7)
+    p.write(0x2116, 8); // VMADDL
+    p.write(0x2117, 0); // VMADDH
+    p.write(0x2118, 0x80); // VMDATAL
+    let img = p.render_debug_view(DebugView::Tiles(BPP::Two, 1)).unwrap();
+    assert_eq!((img.width, img.height), (256, 1024));
+    assert_eq!(img.get(8, 0), (0xF8, 0, 0));
+    assert_eq!(img.get(9, 0), (0, 0, 0));
+
+    // Mode 0 has 4 layers, mode 1 has 3
+    p.write(0x2105, 1); // BGMODE
+    assert!(p.render_debug_view(DebugView::Tilemap(2)).is_ok());
+    assert!(p.render_debug_view(DebugView::Tilemap(3)).is_err());
+
+    assert_eq!(
+        p.render_debug_view(DebugView::Sprites)
+            .unwrap()
+            .pixels
+            .len(),
+        1024 * 512
+    );
+}
+
+#[test]
+fn vram_write_active_display() {
+    let (renderer, _) = TestRenderer::new_test(SCREEN_WIDTH, SCREEN_HEIGHT);
+    let mut p = PPU::new(renderer, VideoFormat::NTSC, RenderMode::Scanline);
+    p.single_threaded();
+    p.write(0x2100, 0x0F); // INIDISP - display enabled
+    p.write(0x2115, 0x80); // VMAIN - high 1 word
+
+    // Ignored, but the address still increments
+    p.write(0x2118, 0xAA); // VMDATAL
+    p.write(0x2119, 0xBB); // VMDATAH
+    assert_eq!(p.vram[0], 0);
+    assert_eq!(p.vmadd.get(), 1);
+
+    vblank_start(&mut p);
+    p.write(0x2118, 0xAA); // VMDATAL
+    p.write(0x2119, 0xBB); // VMDATAH
+    assert_eq!(p.vram[1], 0xBBAA);
+
+    // Force blank
+    while p.in_vblank() {
+        p.tick(1).unwrap();
+    }
+    p.write(0x2100, 0x8F); // INIDISP
+    p.write(0x2118, 0xCC); // VMDATAL
+    p.write(0x2119, 0xDD); // VMDATAH
+    assert_eq!(p.vram[2], 0xDDCC);
+}
+
+#[test]
+fn oam_cgram_write_active_display() {
+    let (renderer, _) = TestRenderer::new_test(SCREEN_WIDTH, SCREEN_HEIGHT);
+    let mut p = PPU::new(renderer, VideoFormat::NTSC, RenderMode::Scanline);
+    p.single_threaded();
+    p.write(0x2100, 0x0F); // INIDISP - display enabled
+    while p.get_current_scanline() != 10 || p.get_current_h() != 100 {
+        p.tick(1).unwrap();
+    }
+
+    // OAM write lands at the sprite being evaluated (100 / 2 = 50)
+    p.write(0x2102, 0x10); // OAMADDL
+    p.write(0x2103, 0x00); // OAMADDH
+    p.write(0x2104, 0xAA); // OAMDATA
+    p.write(0x2104, 0xBB); // OAMDATA
+    assert_eq!(p.state.oam[0x20..0x22], [0, 0]);
+    assert_eq!(p.state.oam[50 * 4..50 * 4 + 2], [0xAA, 0xBB]);
+    assert_eq!(p.state.oamadd_addr.get(), 0x22);
+
+    // Scanline renderer does not know the color being output,
+    // CGRAM write lands at CGADD
+    p.write(0x2121, 0x10); // CGADD
+    p.write(0x2122, 0x1F); // CGDATA
+    p.write(0x2122, 0x00); // CGDATA
+    assert_eq!(p.state.cgram[0x10], 0x001F);
+    assert_eq!(p.state.cgadd.get(), 0x11);
+}
+
+#[test]
+fn cgram_write_dot_render() {
+    let (renderer, _) = TestRenderer::new_test(SCREEN_WIDTH, SCREEN_HEIGHT);
+    let mut p = PPU::new(renderer, VideoFormat::NTSC, RenderMode::Dot);
+    p.single_threaded();
+    p.write(0x2100, 0x80); // INIDISP - force blank
+    p.write(0x2105, 0x00); // BGMODE - mode 0
+    p.write(0x2107, 0x04); // BG1SC - tilemap at 0x400
+    p.write(0x212C, 0x01); // TM - BG1
+    p.write(0x2115, 0x80); // VMAIN - increment after high byte
+    p.write(0x2116, 0x00); // VMADDL
+    p.write(0x2117, 0x00); // VMADDH
+    for _ in 0..8 {
+        // Tile 0, all pixels color 3
+        p.write(0x2118, 0xFF);
+        p.write(0x2119, 0xFF);
+    }
+    p.write(0x2100, 0x0F); // INIDISP - display enabled
+                           // VRAM changes are visible from the next frame
+    run_frame(&mut p);
+    while p.get_current_scanline() != 10 || p.get_current_h() != PPU::<TestRenderer>::H_RENDER + 100
+    {
+        p.tick(1).unwrap();
+    }
+
+    // CGRAM write lands at the color being output
+    p.write(0x2121, 0x10); // CGADD
+    p.write(0x2122, 0x1F); // CGDATA
+    p.write(0x2122, 0x00); // CGDATA
+    assert_eq!(p.state.cgram[0x10], 0);
+    assert_eq!(p.state.cgram[0], 0);
+    assert_eq!(p.state.cgram[3], 0x001F);
+    assert_eq!(p.state.cgadd.get(), 0x11);
+
+    // Not in H-blank
+    while p.get_current_h() != PPU::<TestRenderer>::LINE_HBLANK_START {
+        p.tick(1).unwrap();
+    }
+    p.write(0x2122, 0xE0); // CGDATA
+    p.write(0x2122, 0x03); // CGDATA
+    assert_eq!(p.state.cgram[0x11], 0x03E0);
+}
+
+#[test]
+fn ppu_openbus() {
+    let mut p = ppu();
+
+    // Write-only PPU1 registers read the last value read from PPU1
+    p.write(0x211B, 0x02); // M7A
+    p.write(0x211B, 0x00);
+    p.write(0x211C, 0x21); // M7B
+    assert_eq!(p.read(0x2134), Some(0x42)); // MPYL
+    for addr in [0x2104, 0x2106, 0x210A, 0x2115, 0x2118, 0x2126, 0x212A] {
+        assert_eq!(p.read(addr), Some(0x42));
+    }
+    // Other write-only registers read the CPU open bus
+    for addr in [0x2100, 0x2107, 0x2117, 0x2121, 0x2133] {
+        assert_eq!(p.read(addr), None);
+    }
+
+    // STAT77: bit 4 from PPU1 open bus, master/slave (0), version
+    p.write(0x211B, 0x30); // M7A
+    p.write(0x211B, 0x00);
+    p.write(0x211C, 0x01); // M7B
+    assert_eq!(p.read(0x2134), Some(0x30)); // MPYL
+    assert_eq!(
+        p.read(0x213E),
+        Some(0x10 | PPU::<NullRenderer>::PPU1_VERSION)
+    );
+
+    // RDCGRAM: upper bit 7 from PPU2 open bus
+    p.write(0x2121, 0); // CGADD
+    p.write(0x2122, 0xFF); // CGDATA
+    p.write(0x2122, 0xFF);
+    p.write(0x2121, 0); // CGADD
+    assert_eq!(p.read(0x213B), Some(0xFF));
+    assert_eq!(p.read(0x213B), Some(0xFF));
+    p.write(0x2121, 0); // CGADD
+    p.write(0x2122, 0x00); // CGDATA
+    p.write(0x2122, 0x7F);
+    p.write(0x2121, 0); // CGADD
+    assert_eq!(p.read(0x213B), Some(0x00));
+    assert_eq!(p.read(0x213B), Some(0x7F));
+
+    // OPHCT: upper bits 1-7 from PPU2 open bus
+    while p.get_current_h() != 0x12C {
+        p.tick(1).unwrap();
+    }
+    p.read(0x2137); // SLHV
+    assert_eq!(p.read(0x213C), Some(0x2C)); // OPHCT
+    assert_eq!(p.read(0x213C), Some(0x2D));
+
+    // STAT78: bit 5 from PPU2 open bus, PAL, version
+    p.write(0x2121, 0); // CGADD
+    p.read(0x213B); // RDCGRAM (0x00)
+    assert_eq!(
+        p.read(0x213F),
+        Some((1 << 6) | (1 << 4) | PPU::<NullRenderer>::PPU2_VERSION)
+    );
+}
+
+#[test]
+fn colortable_full_brightness() {
+    let c = SnesColor::from_rgb5(0x1F, 0x10, 0);
+    let t = ColorTable::new(ColorProfile::Linear);
+    assert_eq!(t.to_native(c, 15), c.to_native());
+    assert_eq!(t.to_native(c, 15), (0xF8, 0x80, 0));
+
+    let t = ColorTable::new(ColorProfile::BitReplicate);
+    assert_eq!(t.to_native(c, 15), (0xFF, 0x84, 0));
+
+    let t = ColorTable::new(ColorProfile::Crt);
+    assert_eq!(t.to_native(c, 15).0, 0xFF);
+    assert_eq!(t.to_native(c, 15).2, 0);
+    assert!(t.to_native(c, 15).1 < 0x84);
+}
+
+#[test]
+fn colortable_brightness() {
+    let c = SnesColor::from_rgb5(0x1F, 0x1F, 0x1F);
+    for profile in [
+        ColorProfile::Linear,
+        ColorProfile::BitReplicate,
+        ColorProfile::Crt,
+    ] {
+        let t = ColorTable::new(profile);
+        for b in 1..16 {
+            assert!(t.to_native(c, b).0 > t.to_native(c, b - 1).0);
+        }
+        // Brightness 0 is black
+        assert_eq!(t.to_native(c, 0), t.to_native(SnesColor::BLACK, 15));
+        assert_eq!(t.to_native(c, 0).0, 0);
+    }
+
+    // Not quantized to 5 bits
+    let t = ColorTable::new(ColorProfile::Linear);
+    assert_eq!(t.to_native(SnesColor::from_rgb5(0x1F, 0, 0), 7).0, 124);
+}
+
+#[test]
+fn screenshot_native() {
+    let (renderer, _) = TestRenderer::new_test(SCREEN_WIDTH, SCREEN_HEIGHT);
+    let mut p = PPU::new(renderer, VideoFormat::NTSC, RenderMode::Scanline);
+    p.single_threaded();
+    p.write(0x2121, 0x00); // CGADD
+    p.write(0x2122, 0x1F); // CGDATA - backdrop red
+    p.write(0x2122, 0x00); // CGDATA
+    p.write(0x2100, 0x0F); // INIDISP - display enabled
+    vblank_start(&mut p);
+    vblank_start(&mut p);
+
+    let picture = p.screenshot();
+    assert_eq!((picture.width, picture.height), (256, 224));
+    assert_eq!(picture.get(0, 0), (0xF8, 0, 0));
+
+    p.write(0x2133, 0x04); // SETINI - overscan
+    vblank_start(&mut p);
+    vblank_start(&mut p);
+    let picture = p.screenshot();
+    assert_eq!((picture.width, picture.height), (256, 239));
+}